@@ -0,0 +1,39 @@
+#!/bin/bash
+# Benchmark script for the NDJSON bulk import endpoint
+#
+# Run it once against a default build and once against a build with
+# `cargo run --release --features simd` to compare parsing throughput.
+
+# Configuration
+ROWS=${ROWS:-2000000}
+HOST="http://localhost:8080"
+FILE="/tmp/users_import.ndjson"
+
+# Generate the import file (roughly 150 bytes per row)
+if [ ! -f "$FILE" ]; then
+    echo "Generating $ROWS rows into $FILE"
+    for i in $(seq 1 $ROWS); do
+        echo "{\"name\":\"Imported User $i with a reasonably long display name\",\"email\":\"import$i-$RANDOM@example.com\",\"age\":$((i % 90 + 10))}"
+    done > "$FILE"
+fi
+
+size_bytes=$(stat -c %s "$FILE")
+size_mb=$(echo "scale=2; $size_bytes / 1048576" | bc -l)
+echo "Import file: $FILE (${size_mb} MB)"
+
+# Run the import
+time=$(curl -s -X POST -w "%{time_total}" -o /tmp/import_result.json \
+  -H "Content-Type: application/x-ndjson" \
+  --data-binary @"$FILE" \
+  $HOST/users/import)
+
+throughput=$(echo "scale=2; $size_mb / $time" | bc -l)
+
+# Print summary
+echo ""
+echo "IMPORT BENCHMARK SUMMARY"
+echo "========================"
+echo "Rows: $ROWS"
+echo "Total time: ${time}s"
+echo "Throughput: ${throughput} MB/s"
+echo "Result: $(jq -c '{imported, skipped, errors: (.errors | length)}' /tmp/import_result.json)"
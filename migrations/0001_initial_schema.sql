@@ -0,0 +1,213 @@
+-- The schema as the application created it before versioned migrations. Every
+-- statement is idempotent, so databases set up by those versions take it as is.
+
+-- Create users table
+CREATE TABLE IF NOT EXISTS users (
+    id UUID PRIMARY KEY,
+    username VARCHAR(32),
+    name VARCHAR(100) NOT NULL,
+    email VARCHAR(255) NOT NULL UNIQUE,
+    age SMALLINT,
+    role VARCHAR(16) NOT NULL DEFAULT 'user',
+    password_hash TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    origin_region VARCHAR(32),
+    updated_region VARCHAR(32)
+);
+
+-- Columns added after the table was first created
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    ADD COLUMN IF NOT EXISTS username VARCHAR(32),
+    ADD COLUMN IF NOT EXISTS role VARCHAR(16) NOT NULL DEFAULT 'user',
+    ADD COLUMN IF NOT EXISTS password_hash TEXT,
+    ADD COLUMN IF NOT EXISTS origin_region VARCHAR(32),
+    ADD COLUMN IF NOT EXISTS updated_region VARCHAR(32);
+
+-- Full-text index for GET /users/search
+CREATE INDEX IF NOT EXISTS idx_users_search ON users USING GIN (to_tsvector('simple', name || ' ' || email));
+
+-- Usernames are unique ignoring case
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_username_lower ON users (lower(username));
+
+-- Previous usernames, so GET /users/by-username/{old} can redirect to the current one
+CREATE TABLE IF NOT EXISTS username_history (
+    username VARCHAR(32) NOT NULL,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    renamed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_username_history_lower ON username_history (lower(username));
+
+CREATE OR REPLACE FUNCTION record_username_change() RETURNS trigger AS $$
+BEGIN
+    IF NEW.username IS NULL THEN
+        DELETE FROM username_history WHERE user_id = NEW.id;
+    ELSIF OLD.username IS NOT NULL AND lower(OLD.username) <> lower(NEW.username) THEN
+        INSERT INTO username_history (username, user_id) VALUES (OLD.username, NEW.id);
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS users_record_username_change ON users;
+CREATE TRIGGER users_record_username_change
+    AFTER UPDATE OF username ON users
+    FOR EACH ROW EXECUTE FUNCTION record_username_change();
+
+-- Writes are stamped with the region in the connection's app.region setting (REGION)
+CREATE INDEX IF NOT EXISTS idx_users_origin_region ON users (origin_region);
+
+CREATE OR REPLACE FUNCTION stamp_user_region() RETURNS trigger AS $$
+DECLARE
+    region VARCHAR(32) := NULLIF(current_setting('app.region', true), '');
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        NEW.origin_region := COALESCE(NEW.origin_region, region);
+    ELSE
+        NEW.origin_region := COALESCE(OLD.origin_region, region);
+    END IF;
+    NEW.updated_region := region;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS users_stamp_region ON users;
+CREATE TRIGGER users_stamp_region
+    BEFORE INSERT OR UPDATE ON users
+    FOR EACH ROW EXECUTE FUNCTION stamp_user_region();
+
+-- Change log backing differential sync (GET/POST /sync/users)
+CREATE TABLE IF NOT EXISTS user_changes (
+    seq BIGSERIAL PRIMARY KEY,
+    user_id UUID NOT NULL,
+    op VARCHAR(10) NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_changes_user_id ON user_changes(user_id, seq);
+
+CREATE OR REPLACE FUNCTION record_user_change() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        INSERT INTO user_changes (user_id, op) VALUES (OLD.id, 'delete');
+        RETURN OLD;
+    END IF;
+    INSERT INTO user_changes (user_id, op) VALUES (NEW.id, lower(TG_OP));
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS users_record_change ON users;
+CREATE TRIGGER users_record_change
+    AFTER INSERT OR UPDATE OR DELETE ON users
+    FOR EACH ROW EXECUTE FUNCTION record_user_change();
+
+-- External (OAuth/OIDC) identities linked to local users
+CREATE TABLE IF NOT EXISTS identities (
+    provider VARCHAR(64) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (provider, subject)
+);
+
+CREATE INDEX IF NOT EXISTS idx_identities_user_id ON identities(user_id);
+
+-- Single-use refresh tokens, hashed. A login starts a family, reusing a used token revokes it
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    id UUID PRIMARY KEY,
+    family_id UUID NOT NULL,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash BYTEA NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_refresh_tokens_family_id ON refresh_tokens(family_id);
+
+-- Consent per user and purpose, revoked rows are kept as history
+CREATE TABLE IF NOT EXISTS consents (
+    id BIGSERIAL PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    purpose VARCHAR(64) NOT NULL,
+    source VARCHAR(255) NOT NULL,
+    granted_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_consents_active ON consents(user_id, purpose) WHERE revoked_at IS NULL;
+CREATE INDEX IF NOT EXISTS idx_consents_user_id ON consents(user_id, granted_at);
+
+-- Deletes and email changes held for a second admin (APPROVALS_REQUIRED=true).
+-- No foreign key to users: the record of an approved delete outlives the user.
+CREATE TABLE IF NOT EXISTS approvals (
+    id UUID PRIMARY KEY,
+    action VARCHAR(32) NOT NULL,
+    user_id UUID NOT NULL,
+    changes JSONB,
+    requested_by VARCHAR(255) NOT NULL,
+    requested_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    decided_by VARCHAR(255),
+    decided_at TIMESTAMPTZ
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_approvals_pending ON approvals(user_id, action) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS idx_approvals_status ON approvals(status, requested_at);
+
+-- Updates and deletes deferred with POST /users/{id}/schedule, kept after they ran
+CREATE TABLE IF NOT EXISTS scheduled_operations (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL,
+    action VARCHAR(16) NOT NULL,
+    changes JSONB,
+    execute_at TIMESTAMPTZ NOT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    finished_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_scheduled_operations_due ON scheduled_operations(execute_at) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS idx_scheduled_operations_user_id ON scheduled_operations(user_id, execute_at);
+
+-- Keys for machine clients (X-Api-Key), only the SHA-256 of the secret is stored
+CREATE TABLE IF NOT EXISTS api_keys (
+    id UUID PRIMARY KEY,
+    name VARCHAR(100) NOT NULL,
+    prefix VARCHAR(16) NOT NULL,
+    key_hash BYTEA NOT NULL UNIQUE,
+    created_by VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ
+);
+
+-- White-label settings for server-rendered pages and email (GET/PUT /admin/tenant-settings)
+CREATE TABLE IF NOT EXISTS tenant_settings (
+    tenant VARCHAR(64) PRIMARY KEY,
+    logo_url TEXT,
+    primary_color VARCHAR(7),
+    support_email VARCHAR(255),
+    email_footer TEXT,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Progress of the data backfills, so they resume after a restart (GET /admin/backfills)
+CREATE TABLE IF NOT EXISTS backfills (
+    name VARCHAR(64) PRIMARY KEY,
+    cursor UUID,
+    processed BIGINT NOT NULL DEFAULT 0,
+    changed BIGINT NOT NULL DEFAULT 0,
+    total BIGINT NOT NULL DEFAULT 0,
+    status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    error TEXT,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    finished_at TIMESTAMPTZ
+);
@@ -0,0 +1,24 @@
+-- Hash-chained record of every admin API call. Each row's hash covers the
+-- previous row's, so editing or removing a row breaks the chain from there on.
+CREATE TABLE admin_ledger (
+    seq BIGINT PRIMARY KEY,
+    recorded_at TIMESTAMPTZ NOT NULL,
+    actor TEXT NOT NULL,
+    method VARCHAR(16) NOT NULL,
+    path TEXT NOT NULL,
+    status SMALLINT NOT NULL,
+    request_id VARCHAR(128),
+    prev_hash BYTEA NOT NULL,
+    hash BYTEA NOT NULL
+);
+
+-- Rows are only ever appended
+CREATE FUNCTION refuse_admin_ledger_change() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'admin_ledger is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER admin_ledger_append_only
+    BEFORE UPDATE OR DELETE OR TRUNCATE ON admin_ledger
+    FOR EACH STATEMENT EXECUTE FUNCTION refuse_admin_ledger_change();
@@ -0,0 +1,21 @@
+-- Deletes only stamp deleted_at, so a deleted user can be restored. The row
+-- keeps its email and username, nobody else can take them while it exists.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
+-- Sync clients see a soft delete as a delete and a restore as a new user
+CREATE OR REPLACE FUNCTION record_user_change() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        INSERT INTO user_changes (user_id, op) VALUES (OLD.id, 'delete');
+        RETURN OLD;
+    END IF;
+    IF TG_OP = 'UPDATE' AND OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        INSERT INTO user_changes (user_id, op) VALUES (NEW.id, 'delete');
+    ELSIF TG_OP = 'UPDATE' AND OLD.deleted_at IS NOT NULL AND NEW.deleted_at IS NULL THEN
+        INSERT INTO user_changes (user_id, op) VALUES (NEW.id, 'insert');
+    ELSE
+        INSERT INTO user_changes (user_id, op) VALUES (NEW.id, lower(TG_OP));
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,16 @@
+-- Counts the writes to each user, for optimistic concurrency: an update names
+-- the version it was based on and fails when the row has moved past it. Every
+-- UPDATE of the row counts, whoever makes it, so writes from outside the
+-- server are noticed as well.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS version INTEGER NOT NULL DEFAULT 1;
+
+CREATE FUNCTION bump_user_version() RETURNS trigger AS $$
+BEGIN
+    NEW.version := OLD.version + 1;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER users_bump_version
+    BEFORE UPDATE ON users
+    FOR EACH ROW EXECUTE FUNCTION bump_user_version();
@@ -0,0 +1,18 @@
+-- Role the queries of POST /admin/query run as (SET LOCAL ROLE), so they can
+-- only read what is granted here: the users table without password hashes. It
+-- can't log in and has none of the server's own privileges. Creating a role
+-- needs CREATEROLE; without it the migration leaves a notice and the role has
+-- to be made by hand, see the README.
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_roles WHERE rolname = 'hello_world_query') THEN
+        CREATE ROLE hello_world_query NOLOGIN;
+    END IF;
+    EXECUTE format('GRANT hello_world_query TO %I', current_user);
+    EXECUTE format('GRANT USAGE ON SCHEMA %I TO hello_world_query', current_schema());
+    GRANT SELECT (id, username, name, email, age, role, created_at, updated_at, origin_region, updated_region, deleted_at, version)
+        ON users TO hello_world_query;
+EXCEPTION WHEN insufficient_privilege THEN
+    RAISE NOTICE 'Role hello_world_query not set up, POST /admin/query needs it: %', SQLERRM;
+END
+$$;
@@ -0,0 +1,39 @@
+-- 0004 skipped updates that left the row as it was, but the version trigger
+-- from 0005 runs before it and bumps the version on every UPDATE, so OLD and
+-- NEW always differed. Compare the rows without the columns every write
+-- stamps (version, updated_at, updated_region), so an update that changes no
+-- data, like an import upserting an identical user, isn't recorded.
+CREATE OR REPLACE FUNCTION record_user_audit() RETURNS trigger AS $$
+DECLARE
+    action VARCHAR(16);
+BEGIN
+    IF TG_OP = 'UPDATE'
+        AND to_jsonb(OLD) - 'version' - 'updated_at' - 'updated_region'
+            = to_jsonb(NEW) - 'version' - 'updated_at' - 'updated_region' THEN
+        RETURN NEW;
+    END IF;
+
+    action := CASE
+        WHEN TG_OP = 'INSERT' THEN 'create'
+        WHEN TG_OP = 'DELETE' THEN 'purge'
+        WHEN OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN 'delete'
+        WHEN OLD.deleted_at IS NOT NULL AND NEW.deleted_at IS NULL THEN 'restore'
+        ELSE 'update'
+    END;
+
+    INSERT INTO audit_log (user_id, action, actor, request_id, before, after)
+    VALUES (
+        CASE WHEN TG_OP = 'DELETE' THEN OLD.id ELSE NEW.id END,
+        action,
+        NULLIF(current_setting('app.actor', true), ''),
+        NULLIF(current_setting('app.request_id', true), ''),
+        CASE WHEN TG_OP = 'INSERT' THEN NULL ELSE to_jsonb(OLD) - 'password_hash' END,
+        CASE WHEN TG_OP = 'DELETE' THEN NULL ELSE to_jsonb(NEW) - 'password_hash' END
+    );
+
+    IF TG_OP = 'DELETE' THEN
+        RETURN OLD;
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,33 @@
+use alloc::string::String;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// A key machine clients send in the X-Api-Key header. Only a hash of the
+// secret is stored; `prefix` is its first few characters, to tell keys apart.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+// POST /admin/api-keys body
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IssueApiKeyRequest {
+    // What the key is for, e.g. "nightly export cron"
+    pub name: String,
+}
+
+// Response to issuing a key, the only time the secret is shown
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IssuedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
@@ -0,0 +1,80 @@
+use alloc::string::String;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::user::UpdateUserRequest;
+
+// Changes that need a second admin while approvals are required
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalAction {
+    Delete,
+    ChangeEmail,
+}
+
+impl ApprovalAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Delete => "delete",
+            Self::ChangeEmail => "change_email",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "delete" => Some(Self::Delete),
+            "change_email" => Some(Self::ChangeEmail),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApprovalStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl ApprovalStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Approved => "approved",
+            Self::Rejected => "rejected",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(Self::Pending),
+            "approved" => Some(Self::Approved),
+            "rejected" => Some(Self::Rejected),
+            _ => None,
+        }
+    }
+}
+
+// A held delete or email change, carried out once another admin approves it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Approval {
+    pub id: Uuid,
+    pub action: ApprovalAction,
+    #[serde(with = "crate::public_id")]
+    pub user_id: Uuid,
+    // The whole update for email changes, applied as sent on approval
+    pub changes: Option<UpdateUserRequest>,
+    pub requested_by: String,
+    pub requested_at: DateTime<Utc>,
+    pub status: ApprovalStatus,
+    pub decided_by: Option<String>,
+    pub decided_at: Option<DateTime<Utc>>,
+}
+
+// GET /admin/approvals query parameters
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApprovalListQuery {
+    pub status: Option<ApprovalStatus>,
+}
@@ -0,0 +1,74 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::user::User;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Create,
+    Update,
+    // Soft delete, the user can still be restored
+    Delete,
+    Restore,
+    // The row was removed for good, by a replace or a retention policy
+    Purge,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Create => "create",
+            Self::Update => "update",
+            Self::Delete => "delete",
+            Self::Restore => "restore",
+            Self::Purge => "purge",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "create" => Some(Self::Create),
+            "update" => Some(Self::Update),
+            "delete" => Some(Self::Delete),
+            "restore" => Some(Self::Restore),
+            "purge" => Some(Self::Purge),
+            _ => None,
+        }
+    }
+}
+
+// One write to a user. `before` is None for creates, `after` for purges.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditEntry {
+    pub id: i64,
+    #[serde(with = "crate::public_id")]
+    pub user_id: Uuid,
+    pub action: AuditAction,
+    // `admin:<name>` or `user:<id>`, None for writes made outside a request or
+    // by an anonymous client
+    pub actor: Option<String>,
+    pub request_id: Option<String>,
+    pub recorded_at: DateTime<Utc>,
+    pub before: Option<User>,
+    pub after: Option<User>,
+}
+
+// GET /audit and GET /users/{id}/audit query parameters
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditQuery {
+    // Only entries older than this one, the previous page's next_before
+    pub before: Option<i64>,
+    pub limit: Option<u32>,
+}
+
+// Newest first. Pass next_before as ?before= for the following page, it is
+// None on the last one.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditPage {
+    pub items: Vec<AuditEntry>,
+    pub next_before: Option<i64>,
+}
@@ -0,0 +1,24 @@
+use alloc::string::String;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// Consent a user gave for one purpose, e.g. "marketing". Revoking keeps the
+// record with revoked_at set, a later grant starts a new one, so the history
+// of what was agreed to and when is preserved.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Consent {
+    pub purpose: String,
+    pub granted_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    // Where the consent was collected, e.g. "signup-form"
+    pub source: String,
+}
+
+// POST /users/{id}/consents body, grants or revokes consent for a purpose
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConsentRequest {
+    pub purpose: String,
+    pub granted: bool,
+    pub source: String,
+}
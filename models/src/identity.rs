@@ -0,0 +1,23 @@
+use alloc::string::String;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// An external (OAuth/OIDC) identity linked to a local user. The provider and
+// subject pair is unique, so an identity belongs to at most one user.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Identity {
+    pub provider: String,
+    pub subject: String,
+    #[serde(with = "crate::public_id")]
+    pub user_id: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+// POST /users/{id}/identities body
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinkIdentityRequest {
+    pub provider: String,
+    pub subject: String,
+}
@@ -0,0 +1,20 @@
+// Request and response types of the user API, shared by the server and the
+// WASM frontend so both compile against the same structs. Only serde types
+// live here; anything touching the database stays in the server crate.
+#![no_std]
+
+extern crate alloc;
+
+pub mod api_key;
+pub mod approval;
+pub mod audit;
+pub mod consent;
+pub mod identity;
+pub mod password;
+pub mod public_id;
+pub mod schedule;
+pub mod sort;
+pub mod sync;
+pub mod tenant;
+pub mod user;
+pub mod validate;
@@ -0,0 +1,130 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+// Longest password accepted, hashing much longer input is only useful to attackers
+pub const MAX_PASSWORD_LEN: usize = 128;
+
+// Shortest local part or domain label that counts as part of the email in a
+// password; shorter ones would rule out too many passwords by accident
+const MIN_EMAIL_PART_LEN: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CharClass {
+    Lowercase,
+    Uppercase,
+    Digit,
+    Symbol,
+}
+
+impl CharClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Lowercase => "lowercase",
+            Self::Uppercase => "uppercase",
+            Self::Digit => "digit",
+            Self::Symbol => "symbol",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "lowercase" => Some(Self::Lowercase),
+            "uppercase" => Some(Self::Uppercase),
+            "digit" => Some(Self::Digit),
+            "symbol" => Some(Self::Symbol),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Self::Lowercase => c.is_lowercase(),
+            Self::Uppercase => c.is_uppercase(),
+            Self::Digit => c.is_numeric(),
+            Self::Symbol => !c.is_alphanumeric() && !c.is_whitespace(),
+        }
+    }
+}
+
+// Rules for new passwords, as served by GET /auth/password-policy. The
+// frontend renders the requirements and runs `problems` while the user types;
+// the breach check is only done by the server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PasswordPolicy {
+    // In characters, not bytes
+    pub min_length: usize,
+    pub max_length: usize,
+    // Each class needs at least one character
+    pub required_classes: Vec<CharClass>,
+    // Passwords found in known breaches are refused
+    pub breach_check: bool,
+    // Passwords containing the account's email, its local part or domain are refused
+    pub disallow_email: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 12,
+            max_length: MAX_PASSWORD_LEN,
+            required_classes: Vec::new(),
+            breach_check: true,
+            disallow_email: true,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    // Every local rule `password` breaks, empty when it's acceptable
+    pub fn problems(&self, password: &str, email: Option<&str>) -> Vec<String> {
+        let mut problems = Vec::new();
+        let length = password.chars().count();
+        if length < self.min_length {
+            problems.push(format!("must be at least {} characters", self.min_length));
+        } else if length > self.max_length {
+            problems.push(format!("must be at most {} characters", self.max_length));
+        }
+        for class in &self.required_classes {
+            if !password.chars().any(|c| class.matches(c)) {
+                problems.push(format!("must contain a {} character", class.as_str()));
+            }
+        }
+        if self.disallow_email && email.is_some_and(|email| contains_email(password, email)) {
+            problems.push("must not contain your email address".into());
+        }
+        problems
+    }
+}
+
+fn contains_email(password: &str, email: &str) -> bool {
+    let password = password.to_lowercase();
+    let email = email.to_lowercase();
+    let (local, domain) = email.split_once('@').unwrap_or((&email, ""));
+    // The last domain label is the TLD, which is too generic to count
+    let labels = domain.rsplit_once('.').map(|(labels, _)| labels).unwrap_or(domain);
+    // "ada.lovelace" is caught as "ada.lovelace", "adalovelace", "ada" and "lovelace"
+    let joined: String = local.chars().filter(|c| c.is_alphanumeric()).collect();
+    let words = local.split(|c: char| !c.is_alphanumeric());
+    let mut parts = [local, joined.as_str()].into_iter().chain(words).chain(labels.split('.'));
+    parts.any(|part| part.chars().count() >= MIN_EMAIL_PART_LEN && password.contains(part))
+}
+
+// POST /auth/password-policy/check body
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PasswordCheckRequest {
+    pub password: String,
+    // The account's email, for the email rule
+    pub email: Option<String>,
+}
+
+// POST /auth/login body
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoginRequest {
+    pub email: String,
+    pub password: String,
+}
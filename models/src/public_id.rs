@@ -0,0 +1,136 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use uuid::Uuid;
+
+// How user ids are written in API output. Ids are always stored as UUIDs, the
+// format only changes what clients see; either form is accepted as input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdFormat {
+    // Plain UUIDs, the default
+    Uuid,
+    // `usr_` followed by the UUID in fixed-width base62
+    Prefixed,
+}
+
+pub const PREFIX: &str = "usr_";
+
+const ALPHABET: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+// 62^22 > 2^128, so every UUID fits in 22 digits
+const ENCODED_LEN: usize = 22;
+
+static FORMAT: AtomicU8 = AtomicU8::new(0);
+
+// Set once at startup, before any ids are serialized
+pub fn set_format(format: IdFormat) {
+    FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+pub fn format() -> IdFormat {
+    match FORMAT.load(Ordering::Relaxed) {
+        0 => IdFormat::Uuid,
+        _ => IdFormat::Prefixed,
+    }
+}
+
+// The id as clients should see it
+pub fn encode(id: &Uuid) -> String {
+    match format() {
+        IdFormat::Uuid => id.to_string(),
+        IdFormat::Prefixed => {
+            let mut value = id.as_u128();
+            let mut digits = [b'0'; ENCODED_LEN];
+            for digit in digits.iter_mut().rev() {
+                *digit = ALPHABET[(value % 62) as usize];
+                value /= 62;
+            }
+
+            let mut encoded = String::with_capacity(PREFIX.len() + ENCODED_LEN);
+            encoded.push_str(PREFIX);
+            encoded.extend(digits.iter().map(|&d| d as char));
+            encoded
+        }
+    }
+}
+
+// Accepts both a plain UUID and the prefixed form, whatever the output format
+pub fn decode(value: &str) -> Option<Uuid> {
+    let digits = match value.strip_prefix(PREFIX) {
+        Some(digits) => digits,
+        None => return Uuid::parse_str(value).ok(),
+    };
+    if digits.len() != ENCODED_LEN {
+        return None;
+    }
+
+    let mut id: u128 = 0;
+    for c in digits.bytes() {
+        let digit = ALPHABET.iter().position(|&a| a == c)? as u128;
+        id = id.checked_mul(62)?.checked_add(digit)?;
+    }
+    Some(Uuid::from_u128(id))
+}
+
+// `#[serde(with = "crate::public_id")]` for Uuid fields
+pub fn serialize<S: Serializer>(id: &Uuid, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&encode(id))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Uuid, D::Error> {
+    PublicId::deserialize(deserializer).map(|id| id.0)
+}
+
+// `#[serde(with = "crate::public_id::vec")]` for Vec<Uuid> fields
+pub mod vec {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(ids: &[Uuid], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(ids.iter().map(encode))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Uuid>, D::Error> {
+        let ids = Vec::<PublicId>::deserialize(deserializer)?;
+        Ok(ids.into_iter().map(|id| id.0).collect())
+    }
+}
+
+// A user id in its public form, for path segments and anywhere else a bare
+// id is deserialized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicId(pub Uuid);
+
+impl fmt::Display for PublicId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&encode(&self.0))
+    }
+}
+
+impl Serialize for PublicId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize(&self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PublicId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = PublicId;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "a UUID or a {}-prefixed id", PREFIX)
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<PublicId, E> {
+                decode(value)
+                    .map(PublicId)
+                    .ok_or_else(|| E::invalid_value(de::Unexpected::Str(value), &self))
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
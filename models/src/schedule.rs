@@ -0,0 +1,90 @@
+use alloc::string::String;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::user::UpdateUserRequest;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduledAction {
+    Update,
+    Delete,
+}
+
+impl ScheduledAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Update => "update",
+            Self::Delete => "delete",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "update" => Some(Self::Update),
+            "delete" => Some(Self::Delete),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleStatus {
+    Pending,
+    // Claimed by the scheduler and being carried out
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl ScheduleStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Running => "running",
+            Self::Done => "done",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(Self::Pending),
+            "running" => Some(Self::Running),
+            "done" => Some(Self::Done),
+            "failed" => Some(Self::Failed),
+            "cancelled" => Some(Self::Cancelled),
+            _ => None,
+        }
+    }
+}
+
+// POST /users/{id}/schedule body. Updates carry the changes to apply, as in
+// PUT /users/{id}; deletes carry none.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleRequest {
+    pub action: ScheduledAction,
+    pub changes: Option<UpdateUserRequest>,
+    pub execute_at: DateTime<Utc>,
+}
+
+// A change to a user that the scheduler carries out at `execute_at`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScheduledOperation {
+    pub id: Uuid,
+    #[serde(with = "crate::public_id")]
+    pub user_id: Uuid,
+    pub action: ScheduledAction,
+    pub changes: Option<UpdateUserRequest>,
+    pub execute_at: DateTime<Utc>,
+    pub status: ScheduleStatus,
+    // Why a failed operation couldn't be carried out
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
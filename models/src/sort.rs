@@ -0,0 +1,94 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+// Most keys accepted in a single sort specification
+const MAX_SORT_KEYS: usize = 5;
+
+// Fields users can be sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    Id,
+    Name,
+    Email,
+    Age,
+    CreatedAt,
+    UpdatedAt,
+    // Users created in this instance's region before the others
+    LocalRegion,
+}
+
+// A field some resource can be sorted by, named as in ?sort=
+pub trait SortableField: Copy + PartialEq {
+    fn parse(name: &str) -> Option<Self>;
+}
+
+impl SortableField for SortField {
+    fn parse(name: &str) -> Option<Self> {
+        SortField::parse(name)
+    }
+}
+
+impl SortField {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "id" => Some(Self::Id),
+            "name" => Some(Self::Name),
+            "email" => Some(Self::Email),
+            "age" => Some(Self::Age),
+            "created_at" => Some(Self::CreatedAt),
+            "updated_at" => Some(Self::UpdatedAt),
+            "local_region" => Some(Self::LocalRegion),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey<F = SortField> {
+    pub field: F,
+    pub descending: bool,
+}
+
+// Validated multi-key sort specification, parsed from `?sort=name,-created_at`
+// where a leading `-` sorts that key in descending order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortSpec<F = SortField> {
+    pub keys: Vec<SortKey<F>>,
+}
+
+impl<F> Default for SortSpec<F> {
+    fn default() -> Self {
+        Self { keys: Vec::new() }
+    }
+}
+
+impl<F: SortableField> SortSpec<F> {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut keys: Vec<SortKey<F>> = Vec::new();
+
+        for part in spec.split(',').map(str::trim) {
+            let (name, descending) = match part.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (part.strip_prefix('+').unwrap_or(part), false),
+            };
+
+            if name.is_empty() {
+                return Err("Empty sort key".to_string());
+            }
+
+            let field = F::parse(name).ok_or_else(|| format!("Unknown sort field: {}", name))?;
+            if keys.iter().any(|key| key.field == field) {
+                return Err(format!("Sort field given more than once: {}", name));
+            }
+
+            keys.push(SortKey { field, descending });
+        }
+
+        if keys.len() > MAX_SORT_KEYS {
+            return Err(format!("At most {} sort keys are allowed", MAX_SORT_KEYS));
+        }
+
+        Ok(Self { keys })
+    }
+}
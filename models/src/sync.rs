@@ -0,0 +1,100 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::user::User;
+
+// GET /sync/users query parameters
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncQuery {
+    pub since_token: Option<String>,
+}
+
+// Changes since the client's token, and the token to send next time
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SyncResponse {
+    pub created: Vec<User>,
+    pub updated: Vec<User>,
+    #[serde(with = "crate::public_id::vec")]
+    pub deleted: Vec<Uuid>,
+    pub next_token: String,
+}
+
+// How to resolve a client change to a record that also changed on the server
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictStrategy {
+    #[default]
+    ServerWins,
+    ClientWins,
+    Merge,
+}
+
+// POST /sync/users body
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncPushRequest {
+    pub since_token: String,
+    #[serde(default)]
+    pub strategy: ConflictStrategy,
+    pub changes: Vec<ClientChange>,
+}
+
+// A change made on the client while offline. Updates may carry the record as the
+// client last saw it (`base`), which the merge strategy uses to keep server-side
+// edits to fields the client didn't touch.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ClientChange {
+    Create {
+        #[serde(with = "crate::public_id")]
+        id: Uuid,
+        name: String,
+        email: String,
+        age: Option<u8>,
+    },
+    Update {
+        #[serde(with = "crate::public_id")]
+        id: Uuid,
+        base: Option<UserSnapshot>,
+        name: Option<String>,
+        email: Option<String>,
+        age: Option<u8>,
+    },
+    Delete {
+        #[serde(with = "crate::public_id")]
+        id: Uuid,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserSnapshot {
+    pub name: String,
+    pub email: String,
+    pub age: Option<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncStatus {
+    Applied,
+    Merged,
+    Conflict,
+    NotFound,
+}
+
+// Outcome of one client change, with the server copy of the record where there is one
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncResult {
+    #[serde(with = "crate::public_id")]
+    pub id: Uuid,
+    pub status: SyncStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<User>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncPushResponse {
+    pub results: Vec<SyncResult>,
+    pub next_token: String,
+}
@@ -0,0 +1,19 @@
+use alloc::string::String;
+use serde::{Deserialize, Serialize};
+
+// White-label settings of the tenant running this instance, used by the
+// server-rendered pages and outgoing email. Unset fields fall back to the
+// built-in look.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TenantSettings {
+    #[serde(default)]
+    pub logo_url: Option<String>,
+    // CSS hex color, `#rrggbb`
+    #[serde(default)]
+    pub primary_color: Option<String>,
+    #[serde(default)]
+    pub support_email: Option<String>,
+    #[serde(default)]
+    pub email_footer: Option<String>,
+}
@@ -0,0 +1,259 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use chrono::{DateTime, Utc};
+use core::fmt::Display;
+use core::str::FromStr;
+use serde::{Deserialize, Deserializer, Serialize};
+use uuid::Uuid;
+
+// User model
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct User {
+    #[serde(with = "crate::public_id")]
+    pub id: Uuid,
+    // Public handle, unique ignoring case. Users created before usernames existed have none.
+    pub username: Option<String>,
+    pub name: String,
+    pub email: String,
+    pub age: Option<u8>,
+    // Older payloads have no role, those users are plain users
+    #[serde(default)]
+    pub role: Role,
+    // Argon2id PHC string, None for users who can't log in with a password
+    #[serde(skip)]
+    pub password_hash: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    // Region of the instance that created the user and of the one that wrote it
+    // last, for telling apart concurrent writes in an active-active deployment.
+    // None for rows written before regions were configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin_region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_region: Option<String>,
+    // When the user was deleted, they can be restored at POST /users/{id}/restore.
+    // Deleted users are only listed with include_deleted=true.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    // Goes up by one with every write to the user, updates name the version
+    // they were made against. 0 in payloads from before versions.
+    #[serde(default)]
+    pub version: u32,
+}
+
+// What a user may do through the API while roles are enforced: admins write,
+// every role reads
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    #[default]
+    User,
+    Readonly,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Admin => "admin",
+            Self::User => "user",
+            Self::Readonly => "readonly",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "admin" => Some(Self::Admin),
+            "user" => Some(Self::User),
+            "readonly" => Some(Self::Readonly),
+            _ => None,
+        }
+    }
+}
+
+// PUT /admin/users/{id}/role body
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetRoleRequest {
+    pub role: Role,
+}
+
+// Creation DTO
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateUserRequest {
+    pub username: Option<String>,
+    pub name: String,
+    pub email: String,
+    pub age: Option<u8>,
+    // Lets the user log in at POST /auth/login, only stored as a hash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+// Update DTO
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateUserRequest {
+    pub username: Option<String>,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub age: Option<u8>,
+    // The version of the user the update was made against, the update fails
+    // as a conflict when the user has changed since. None updates regardless.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+}
+
+impl UpdateUserRequest {
+    // Whether it changes any field, the version isn't one
+    pub fn is_empty(&self) -> bool {
+        self.username.is_none() && self.name.is_none() && self.email.is_none() && self.age.is_none()
+    }
+}
+
+// User list query parameters
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListUsersQuery {
+    pub sort: Option<String>,
+    // Either one switches the response to a UserPage
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+    // Either one switches to keyset pagination by id and a UserCursorPage
+    pub after: Option<String>,
+    pub limit: Option<u32>,
+}
+
+// One keyset page of the user list, ordered by id. Pass next_cursor as
+// ?after= to get the following page; it is None on the last one. Cursors are
+// opaque and expire, they're only good for following the pages.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserCursorPage {
+    pub items: Vec<User>,
+    pub next_cursor: Option<String>,
+}
+
+// GET /users filter parameters, every one given has to match. Blank values
+// count as not given, that's what an empty field of an HTML form sends.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct UserFilter {
+    #[serde(default, deserialize_with = "blank_as_none")]
+    pub email: Option<String>,
+    #[serde(default, deserialize_with = "blank_as_none")]
+    pub min_age: Option<u8>,
+    #[serde(default, deserialize_with = "blank_as_none")]
+    pub max_age: Option<u8>,
+    // Case-insensitive substring of the name
+    #[serde(default, deserialize_with = "blank_as_none")]
+    pub name_contains: Option<String>,
+    // Region the user was created in, `local` for this instance's region
+    #[serde(default, deserialize_with = "blank_as_none")]
+    pub region: Option<String>,
+    // Also list deleted users, admins only
+    #[serde(default, deserialize_with = "blank_as_false")]
+    pub include_deleted: bool,
+}
+
+fn blank_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(value) if !value.trim().is_empty() => value.parse().map(Some).map_err(serde::de::Error::custom),
+        _ => Ok(None),
+    }
+}
+
+fn blank_as_false<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    Ok(blank_as_none(deserializer)?.unwrap_or_default())
+}
+
+impl UserFilter {
+    // Whether any field is filtered on, include_deleted aside
+    pub fn has_conditions(&self) -> bool {
+        self.email.is_some() || self.min_age.is_some() || self.max_age.is_some() || self.name_contains.is_some() || self.region.is_some()
+    }
+
+    // Nothing filtered, every user that isn't deleted
+    pub fn is_empty(&self) -> bool {
+        !self.has_conditions() && !self.include_deleted
+    }
+}
+
+// One page of the user list, `page` counts from 1
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserPage {
+    pub total: i64,
+    pub page: u32,
+    pub per_page: u32,
+    pub items: Vec<User>,
+}
+
+// Typeahead query, GET /users/suggest?q=
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SuggestQuery {
+    pub q: String,
+}
+
+// One typeahead result
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Suggestion {
+    #[serde(with = "crate::public_id")]
+    pub id: Uuid,
+    pub name: String,
+}
+
+// Search query, GET /users/search?q=&limit=
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    pub limit: Option<u32>,
+}
+
+// Batch read DTO
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchGetRequest {
+    #[serde(with = "crate::public_id::vec")]
+    pub ids: Vec<Uuid>,
+}
+
+// Bulk delete DTO
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeleteUsersRequest {
+    #[serde(with = "crate::public_id::vec")]
+    pub ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QrTarget {
+    #[default]
+    Verify,
+    Profile,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+// QR code query parameters
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QrQuery {
+    #[serde(default)]
+    pub target: QrTarget,
+    #[serde(default)]
+    pub format: QrFormat,
+}
+
+// Signed verification link parameters
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyQuery {
+    pub exp: u64,
+    pub sig: String,
+}
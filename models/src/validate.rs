@@ -0,0 +1,287 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::api_key::IssueApiKeyRequest;
+use crate::audit::AuditQuery;
+use crate::consent::ConsentRequest;
+use crate::identity::LinkIdentityRequest;
+use crate::schedule::{ScheduleRequest, ScheduledAction};
+use crate::tenant::TenantSettings;
+use crate::user::{CreateUserRequest, SearchQuery, UpdateUserRequest, UserFilter};
+
+// Column sizes of the users, identities and consents tables
+pub const MAX_NAME_LEN: usize = 100;
+pub const MAX_EMAIL_LEN: usize = 255;
+pub const MAX_PROVIDER_LEN: usize = 64;
+pub const MAX_SUBJECT_LEN: usize = 255;
+pub const MIN_USERNAME_LEN: usize = 3;
+pub const MAX_USERNAME_LEN: usize = 32;
+pub const MAX_REGION_LEN: usize = 32;
+pub const MAX_PURPOSE_LEN: usize = 64;
+pub const MAX_SOURCE_LEN: usize = 255;
+pub const MAX_AGE: u8 = 150;
+pub const MAX_URL_LEN: usize = 2048;
+pub const MAX_FOOTER_LEN: usize = 2000;
+pub const MAX_SEARCH_LIMIT: u32 = 100;
+pub const MAX_AUDIT_LIMIT: u32 = 200;
+
+// Usernames that could pass for the service itself, its staff or its routes.
+// Compared ignoring case.
+pub const RESERVED_USERNAMES: &[&str] = &[
+    "admin", "administrator", "anonymous", "api", "billing", "by-username", "help", "info",
+    "login", "logout", "mail", "me", "moderator", "null", "owner", "postmaster", "register",
+    "root", "security", "self", "settings", "signup", "staff", "status", "support", "system",
+    "undefined", "user", "users", "webmaster", "www",
+];
+
+// One rule a request field broke
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+// Input rules for request bodies. Lives next to the types so the frontend can
+// run the same checks before submitting a form.
+pub trait Validate {
+    fn validate(&self) -> Result<(), Vec<FieldError>>;
+}
+
+// Collects field errors while a request is checked
+#[derive(Default)]
+struct Errors(Vec<FieldError>);
+
+impl Errors {
+    fn add(&mut self, field: &str, message: String) {
+        self.0.push(FieldError { field: field.into(), message });
+    }
+
+    fn text(&mut self, field: &str, value: &str, max_len: usize) {
+        if value.trim().is_empty() {
+            self.add(field, "must not be empty".into());
+        } else if value.len() > max_len {
+            self.add(field, format!("must be at most {} bytes", max_len));
+        }
+    }
+
+    // Display names: text without control characters or surrounding whitespace
+    fn name(&mut self, field: &str, value: &str) {
+        self.text(field, value, MAX_NAME_LEN);
+        if value.chars().any(char::is_control) {
+            self.add(field, "must not contain control characters".into());
+        } else if !value.trim().is_empty() && value.trim() != value {
+            self.add(field, "must not start or end with whitespace".into());
+        }
+    }
+
+    fn email(&mut self, field: &str, value: &str) {
+        self.text(field, value, MAX_EMAIL_LEN);
+        if !value.trim().is_empty() && !is_email(value) {
+            self.add(field, "must be an email address".into());
+        }
+    }
+
+    // A slug of ASCII letters, digits, `-` and `_` that starts and ends with a
+    // letter or digit, and isn't reserved
+    fn username(&mut self, field: &str, value: &str) {
+        let bytes = value.as_bytes();
+        if bytes.len() < MIN_USERNAME_LEN || bytes.len() > MAX_USERNAME_LEN {
+            self.add(field, format!("must be {} to {} characters", MIN_USERNAME_LEN, MAX_USERNAME_LEN));
+        } else if !bytes.iter().all(|b| b.is_ascii_alphanumeric() || *b == b'-' || *b == b'_') {
+            self.add(field, "may only contain letters, digits, - and _".into());
+        } else if !bytes[0].is_ascii_alphanumeric() || !bytes[bytes.len() - 1].is_ascii_alphanumeric() {
+            self.add(field, "must start and end with a letter or digit".into());
+        } else if RESERVED_USERNAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(value)) {
+            self.add(field, "is reserved".into());
+        }
+    }
+
+    fn url(&mut self, field: &str, value: &str) {
+        self.text(field, value, MAX_URL_LEN);
+        if !value.starts_with("https://") && !value.starts_with("http://") {
+            self.add(field, "must be an http or https URL".into());
+        }
+    }
+
+    fn color(&mut self, field: &str, value: &str) {
+        let hex = value.strip_prefix('#').unwrap_or("");
+        if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            self.add(field, "must be a hex color like #2d3e50".into());
+        }
+    }
+
+    fn age(&mut self, field: &str, value: u8) {
+        if value > MAX_AGE {
+            self.add(field, format!("must be at most {}", MAX_AGE));
+        }
+    }
+
+    fn finish(self) -> Result<(), Vec<FieldError>> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(self.0)
+        }
+    }
+}
+
+// local@domain.tld: one @, no whitespace, and a domain of non-empty dot-separated
+// labels. Anything stricter rejects real addresses, delivery is the real check.
+fn is_email(value: &str) -> bool {
+    let (local, domain) = match value.split_once('@') {
+        Some(parts) => parts,
+        None => return false,
+    };
+    !local.is_empty()
+        && !domain.contains('@')
+        && !value.chars().any(|c| c.is_whitespace() || c.is_control())
+        && domain.contains('.')
+        && domain.split('.').all(|label| {
+            !label.is_empty() && !label.starts_with('-') && !label.ends_with('-')
+        })
+}
+
+impl Validate for CreateUserRequest {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Errors::default();
+        if let Some(username) = &self.username {
+            errors.username("username", username);
+        }
+        errors.name("name", &self.name);
+        errors.email("email", &self.email);
+        if let Some(age) = self.age {
+            errors.age("age", age);
+        }
+        errors.finish()
+    }
+}
+
+impl Validate for UpdateUserRequest {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Errors::default();
+        if let Some(username) = &self.username {
+            errors.username("username", username);
+        }
+        if let Some(name) = &self.name {
+            errors.name("name", name);
+        }
+        if let Some(email) = &self.email {
+            errors.email("email", email);
+        }
+        if let Some(age) = self.age {
+            errors.age("age", age);
+        }
+        errors.finish()
+    }
+}
+
+// The time isn't checked here, whether it's in the future depends on the server clock
+impl Validate for ScheduleRequest {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Errors::default();
+        match (self.action, &self.changes) {
+            (ScheduledAction::Update, Some(changes)) => {
+                for error in changes.validate().err().unwrap_or_default() {
+                    errors.add(&format!("changes.{}", error.field), error.message);
+                }
+            }
+            (ScheduledAction::Update, None) => errors.add("changes", "is required for updates".into()),
+            (ScheduledAction::Delete, Some(_)) => errors.add("changes", "must not be set for deletes".into()),
+            (ScheduledAction::Delete, None) => {}
+        }
+        errors.finish()
+    }
+}
+
+impl Validate for UserFilter {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Errors::default();
+        if let Some(email) = &self.email {
+            errors.text("email", email, MAX_EMAIL_LEN);
+        }
+        if let Some(name) = &self.name_contains {
+            errors.text("name_contains", name, MAX_NAME_LEN);
+        }
+        if let Some(region) = &self.region {
+            errors.text("region", region, MAX_REGION_LEN);
+        }
+        if let (Some(min), Some(max)) = (self.min_age, self.max_age) {
+            if min > max {
+                errors.add("max_age", "must not be less than min_age".into());
+            }
+        }
+        errors.finish()
+    }
+}
+
+impl Validate for SearchQuery {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Errors::default();
+        errors.text("q", &self.q, MAX_EMAIL_LEN);
+        if let Some(limit) = self.limit {
+            if limit == 0 || limit > MAX_SEARCH_LIMIT {
+                errors.add("limit", format!("must be between 1 and {}", MAX_SEARCH_LIMIT));
+            }
+        }
+        errors.finish()
+    }
+}
+
+impl Validate for AuditQuery {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Errors::default();
+        if let Some(limit) = self.limit {
+            if limit == 0 || limit > MAX_AUDIT_LIMIT {
+                errors.add("limit", format!("must be between 1 and {}", MAX_AUDIT_LIMIT));
+            }
+        }
+        errors.finish()
+    }
+}
+
+impl Validate for LinkIdentityRequest {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Errors::default();
+        errors.text("provider", &self.provider, MAX_PROVIDER_LEN);
+        errors.text("subject", &self.subject, MAX_SUBJECT_LEN);
+        errors.finish()
+    }
+}
+
+impl Validate for ConsentRequest {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Errors::default();
+        errors.text("purpose", &self.purpose, MAX_PURPOSE_LEN);
+        errors.text("source", &self.source, MAX_SOURCE_LEN);
+        errors.finish()
+    }
+}
+
+impl Validate for IssueApiKeyRequest {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Errors::default();
+        errors.name("name", &self.name);
+        errors.finish()
+    }
+}
+
+impl Validate for TenantSettings {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Errors::default();
+        if let Some(logo_url) = &self.logo_url {
+            errors.url("logo_url", logo_url);
+        }
+        if let Some(color) = &self.primary_color {
+            errors.color("primary_color", color);
+        }
+        if let Some(email) = &self.support_email {
+            errors.email("support_email", email);
+        }
+        if let Some(footer) = &self.email_footer {
+            errors.text("email_footer", footer, MAX_FOOTER_LEN);
+        }
+        errors.finish()
+    }
+}
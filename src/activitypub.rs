@@ -0,0 +1,46 @@
+use serde_json::{json, Value};
+
+use crate::models::public_id;
+use crate::models::user::User;
+
+pub const ACTIVITY_JSON: &str = "application/activity+json";
+pub const LD_JSON: &str = "application/ld+json; profile=\"https://www.w3.org/ns/activitystreams\"";
+
+// Pick the response media type for an actor document from the client's ranked
+// Accept list. Plain JSON and wildcards get activity+json; None means 406.
+pub fn negotiate(accepted: &[String]) -> Option<&'static str> {
+    if accepted.is_empty() {
+        return Some(ACTIVITY_JSON);
+    }
+
+    accepted.iter().find_map(|mime| match mime.as_str() {
+        "application/activity+json" | "application/json" | "application/*" | "*/*" => Some(ACTIVITY_JSON),
+        "application/ld+json" => Some(LD_JSON),
+        _ => None,
+    })
+}
+
+// JSON-LD Person document describing a user. The public key is a placeholder
+// until users get signing keys; federating tools only need the shape for now.
+pub fn actor_document(user: &User, base_url: &str) -> Value {
+    let public_id = public_id::encode(&user.id);
+    let actor_id = format!("{}/users/{}/actor", base_url, public_id);
+
+    json!({
+        "@context": [
+            "https://www.w3.org/ns/activitystreams",
+            "https://w3id.org/security/v1"
+        ],
+        "id": actor_id,
+        "type": "Person",
+        "name": user.name,
+        "url": format!("{}/users/{}", base_url, public_id),
+        "inbox": format!("{}/inbox", actor_id),
+        "outbox": format!("{}/outbox", actor_id),
+        "publicKey": {
+            "id": format!("{}#main-key", actor_id),
+            "owner": actor_id,
+            "publicKeyPem": ""
+        }
+    })
+}
@@ -0,0 +1,129 @@
+use actix_web::web;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::egress;
+use crate::health::RequestStats;
+use crate::repositories::user_repo::CachedUserRepository;
+
+// How often the monitor looks at the database and the error rate
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+// Error rates over a handful of requests are noise
+const MIN_REQUESTS_FOR_RATE: u64 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookKind {
+    Slack,
+    Teams,
+}
+
+#[derive(Debug, Clone)]
+pub struct AlertConfig {
+    pub webhook_url: String,
+    pub kind: WebhookKind,
+    pub cooldown: Duration,
+    pub error_rate_percent: f64,
+}
+
+// Posts operational alerts to a Slack or Teams incoming webhook. Each kind of
+// alert is sent at most once per cooldown so a flapping condition doesn't
+// flood the channel. Without a webhook alerts are only logged.
+pub struct Alerter {
+    config: Option<AlertConfig>,
+    last_sent: Mutex<HashMap<&'static str, Instant>>,
+}
+
+impl Alerter {
+    pub fn new(config: Option<AlertConfig>) -> Self {
+        Self {
+            config,
+            last_sent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn alert(&self, key: &'static str, message: &str) {
+        log::error!(target: "alert", "[{}] {}", key, message);
+
+        let config = match &self.config {
+            Some(config) => config,
+            None => return,
+        };
+
+        {
+            let mut last_sent = self.last_sent.lock();
+            if last_sent.get(key).is_some_and(|sent| sent.elapsed() < config.cooldown) {
+                log::debug!("Alert {} suppressed, still cooling down", key);
+                return;
+            }
+            last_sent.insert(key, Instant::now());
+        }
+
+        let text = format!("[{}] {}", env!("CARGO_PKG_NAME"), message);
+        let body = match config.kind {
+            WebhookKind::Slack => serde_json::json!({ "text": text }),
+            WebhookKind::Teams => serde_json::json!({
+                "@type": "MessageCard",
+                "@context": "https://schema.org/extensions",
+                "summary": key,
+                "text": text,
+            }),
+        };
+
+        let client = match egress::webhook_client(&config.webhook_url).await {
+            Ok(client) => client,
+            Err(e) => {
+                log::warn!("Not delivering alert {}: {}", key, e);
+                return;
+            }
+        };
+        let result = client
+            .post(&config.webhook_url)
+            .json(&body)
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            log::warn!("Failed to deliver alert {}: {}", key, e);
+        }
+    }
+
+    // Watch for the database going away (and coming back) and for error-rate spikes
+    pub fn spawn_monitor(alerter: web::Data<Alerter>, users: web::Data<CachedUserRepository>, stats: web::Data<RequestStats>) {
+        let threshold = alerter.config.as_ref().map_or(5.0, |c| c.error_rate_percent);
+
+        actix_web::rt::spawn(async move {
+            let mut ticker = actix_web::rt::time::interval(CHECK_INTERVAL);
+            let mut database_down = false;
+
+            loop {
+                ticker.tick().await;
+
+                match users.ping().await {
+                    Err(e) if !database_down => {
+                        database_down = true;
+                        alerter.alert("database", &format!("Database unreachable: {}", e)).await;
+                    }
+                    Ok(()) if database_down => {
+                        database_down = false;
+                        alerter.alert("database_recovered", "Database reachable again").await;
+                    }
+                    _ => {}
+                }
+
+                let counts = stats.recent();
+                if counts.requests >= MIN_REQUESTS_FOR_RATE {
+                    let rate = counts.errors as f64 * 100.0 / counts.requests as f64;
+                    if rate > threshold {
+                        let message = format!(
+                            "Server error rate {:.1}% ({} of {} requests in the last {} minutes)",
+                            rate, counts.errors, counts.requests, stats.window_secs() / 60
+                        );
+                        alerter.alert("error_rate", &message).await;
+                    }
+                }
+            }
+        });
+    }
+}
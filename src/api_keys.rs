@@ -0,0 +1,144 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, HttpMessage};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use sha2::{Digest, Sha256};
+use std::rc::Rc;
+
+use crate::errors::{AppError, Context};
+use crate::middleware::rate_limit::RateLimiter;
+use crate::models::api_key::ApiKey;
+use crate::repositories::api_key_repo::ApiKeyRepository;
+
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+// Marks strings as keys of this service, so secret scanners and people can spot them
+const KEY_PREFIX: &str = "hwk_";
+// Characters of the key stored in the clear to tell keys apart
+const SHOWN_LEN: usize = 12;
+
+// A new random key and what gets stored of it: the shown prefix and the hash
+pub fn generate() -> (String, String, Vec<u8>) {
+    let key = format!("{}{}", KEY_PREFIX, URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>()));
+    let prefix = key[..SHOWN_LEN].to_string();
+    let key_hash = hash(&key);
+    (key, prefix, key_hash)
+}
+
+// Keys are 256 random bits, so a plain SHA-256 is enough to make the stored
+// hashes useless to someone reading the table
+pub fn hash(key: &str) -> Vec<u8> {
+    Sha256::digest(key.as_bytes()).to_vec()
+}
+
+// Paths that work without a key even when keys are required. The admin routes
+// are guarded by the proxy that sets X-Admin-User, and are where keys come from;
+// browser logins can't send a key.
+pub fn exempt(path: &str) -> bool {
+    path == "/health"
+        || path.starts_with("/health/")
+        || path == "/status"
+        || path == "/metrics"
+        || path.starts_with("/admin/")
+        || path.starts_with("/auth/")
+}
+
+// Checks the X-Api-Key header of every request. A key that is unknown or
+// revoked is always refused; a missing one only when keys are required. The
+// matching key is put in the request extensions for handlers that want it.
+// With rate limits on, failed keys count against the sender's address before
+// any lookup, see RateLimiter::check_key_attempt.
+#[derive(Clone)]
+pub struct ApiKeyAuth {
+    required: bool,
+    repo: web::Data<ApiKeyRepository>,
+    limiter: Option<RateLimiter>,
+}
+
+impl ApiKeyAuth {
+    pub fn new(required: bool, repo: web::Data<ApiKeyRepository>, limiter: Option<RateLimiter>) -> Self {
+        Self { required, repo, limiter }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ApiKeyAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Transform = ApiKeyMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ApiKeyMiddleware {
+            service: Rc::new(service),
+            required: self.required,
+            repo: self.repo.clone(),
+            limiter: self.limiter.clone(),
+        }))
+    }
+}
+
+pub struct ApiKeyMiddleware<S> {
+    service: Rc<S>,
+    required: bool,
+    repo: web::Data<ApiKeyRepository>,
+    limiter: Option<RateLimiter>,
+}
+
+impl<S, B> Service<ServiceRequest> for ApiKeyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let required = self.required && !exempt(req.path());
+        let repo = self.repo.clone();
+        let limiter = self.limiter.clone();
+        let key = req
+            .headers()
+            .get(API_KEY_HEADER)
+            .map(|value| value.to_str().map(str::trim).unwrap_or_default().to_string());
+
+        Box::pin(async move {
+            match key {
+                Some(key) => {
+                    if let Some(limiter) = &limiter {
+                        limiter.check_key_attempt(req.request())?;
+                    }
+                    let api_key = repo.authenticate(&hash(&key)).await.context("Failed to check API key")?;
+                    match api_key {
+                        Some(api_key) => {
+                            log::debug!("Request {} {} with API key {} ({})", req.method(), req.path(), api_key.name, api_key.id);
+                            req.extensions_mut().insert::<ApiKey>(api_key);
+                        }
+                        None => {
+                            log::warn!(target: "audit", "Refused {} {} with an unknown or revoked API key", req.method(), req.path());
+                            if let Some(limiter) = &limiter {
+                                limiter.key_failed(req.request());
+                            }
+                            return Err(AppError::unauthorized("Invalid API key").into());
+                        }
+                    }
+                }
+                None if required => {
+                    return Err(AppError::unauthorized(format!("The {} header is required", API_KEY_HEADER)).into());
+                }
+                None => {}
+            }
+            Ok(service.call(req).await?.map_into_boxed_body())
+        })
+    }
+}
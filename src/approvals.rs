@@ -0,0 +1,169 @@
+use actix_web::{web, HttpRequest};
+use std::error::Error as StdError;
+use uuid::Uuid;
+
+use crate::models::approval::{Approval, ApprovalAction, ApprovalStatus};
+use crate::models::user::{UpdateUserRequest, User};
+use crate::repositories::approval_repo::ApprovalRepository;
+use crate::repositories::user_repo::CachedUserRepository;
+
+// Names the admin making a request, set by the authenticating proxy in front of the API
+pub const ADMIN_HEADER: &str = "X-Admin-User";
+
+pub enum Requested {
+    Created(Approval),
+    // The same kind of change is already waiting for this user
+    AlreadyPending(Approval),
+}
+
+pub enum Decided {
+    Done(Approval),
+    NotFound,
+    // Decided before, or approved by the admin who asked for it
+    NotAllowed(Approval),
+    // The email was taken while the change waited
+    EmailTaken,
+}
+
+// Four-eyes rule for deletes and email changes. While approvals are required
+// these changes are held as requests and only carried out once a second
+// admin approves them.
+pub struct Approvals {
+    required: bool,
+    repo: ApprovalRepository,
+    users: web::Data<CachedUserRepository>,
+}
+
+// The admin named by the request, None when the header is missing or blank
+pub fn admin(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(ADMIN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && name.len() <= 255)
+        .map(str::to_string)
+}
+
+impl Approvals {
+    pub fn new(required: bool, repo: ApprovalRepository, users: web::Data<CachedUserRepository>) -> Self {
+        Self { required, repo, users }
+    }
+
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    // Whether applying the update to the user changes their email
+    pub fn changes_email(user: &User, user_req: &UpdateUserRequest) -> bool {
+        user_req
+            .email
+            .as_deref()
+            .is_some_and(|email| !email.eq_ignore_ascii_case(&user.email))
+    }
+
+    pub async fn request_delete(&self, user_id: &Uuid, requested_by: &str) -> Result<Requested, Box<dyn StdError>> {
+        self.request(ApprovalAction::Delete, user_id, None, requested_by).await
+    }
+
+    pub async fn request_update(&self, user_id: &Uuid, user_req: &UpdateUserRequest, requested_by: &str) -> Result<Requested, Box<dyn StdError>> {
+        self.request(ApprovalAction::ChangeEmail, user_id, Some(user_req), requested_by).await
+    }
+
+    async fn request(
+        &self,
+        action: ApprovalAction,
+        user_id: &Uuid,
+        changes: Option<&UpdateUserRequest>,
+        requested_by: &str,
+    ) -> Result<Requested, Box<dyn StdError>> {
+        // The pending one can be decided between the insert and the lookup, then try again
+        loop {
+            if let Some(approval) = self.repo.create(action, user_id, changes, requested_by).await? {
+                log::info!(
+                    target: "audit",
+                    "Admin {} requested approval {} to {} user {}",
+                    requested_by, approval.id, action.as_str(), user_id
+                );
+                return Ok(Requested::Created(approval));
+            }
+            if let Some(approval) = self.repo.pending_for(user_id, action).await? {
+                return Ok(Requested::AlreadyPending(approval));
+            }
+        }
+    }
+
+    pub async fn list(&self, status: Option<ApprovalStatus>) -> Result<Vec<Approval>, Box<dyn StdError>> {
+        self.repo.list(status).await
+    }
+
+    // Approve a request and carry out the change. If that fails the request
+    // goes back to pending.
+    pub async fn approve(&self, id: &Uuid, approver: &str) -> Result<Decided, Box<dyn StdError>> {
+        let approval = match self.repo.decide(id, ApprovalStatus::Approved, approver).await? {
+            Some(approval) => approval,
+            None => return self.not_decided(id).await,
+        };
+
+        match self.carry_out(&approval).await {
+            Ok(true) => {
+                log::info!(
+                    target: "audit",
+                    "Admin {} approved {} of user {} requested by {} ({})",
+                    approver, approval.action.as_str(), approval.user_id, approval.requested_by, approval.id
+                );
+                Ok(Decided::Done(approval))
+            }
+            Ok(false) => {
+                self.repo.reopen(id).await?;
+                Ok(Decided::EmailTaken)
+            }
+            Err(e) => {
+                self.repo.reopen(id).await?;
+                Err(e)
+            }
+        }
+    }
+
+    pub async fn reject(&self, id: &Uuid, decided_by: &str) -> Result<Decided, Box<dyn StdError>> {
+        match self.repo.decide(id, ApprovalStatus::Rejected, decided_by).await? {
+            Some(approval) => {
+                log::info!(
+                    target: "audit",
+                    "Admin {} rejected {} of user {} requested by {} ({})",
+                    decided_by, approval.action.as_str(), approval.user_id, approval.requested_by, approval.id
+                );
+                Ok(Decided::Done(approval))
+            }
+            None => self.not_decided(id).await,
+        }
+    }
+
+    async fn not_decided(&self, id: &Uuid) -> Result<Decided, Box<dyn StdError>> {
+        Ok(match self.repo.get(id).await? {
+            Some(approval) => Decided::NotAllowed(approval),
+            None => Decided::NotFound,
+        })
+    }
+
+    // False when the new email belongs to someone else by now
+    async fn carry_out(&self, approval: &Approval) -> Result<bool, Box<dyn StdError>> {
+        match (approval.action, &approval.changes) {
+            (ApprovalAction::Delete, _) => {
+                self.users.delete(&approval.user_id).await?;
+            }
+            (ApprovalAction::ChangeEmail, Some(changes)) => {
+                if let Some(email) = &changes.email {
+                    let owner = self.users.get_by_email(email).await?;
+                    if owner.is_some_and(|owner| owner.id != approval.user_id) {
+                        return Ok(false);
+                    }
+                }
+                self.users.update(&approval.user_id, changes).await?;
+            }
+            (ApprovalAction::ChangeEmail, None) => {
+                return Err(format!("Approval {} has no changes to apply", approval.id).into());
+            }
+        }
+        Ok(true)
+    }
+}
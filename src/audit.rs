@@ -0,0 +1,36 @@
+use actix_web::{web, HttpMessage, HttpRequest};
+use std::future::Future;
+
+use crate::approvals;
+use crate::models::user::User;
+use crate::roles;
+use crate::signing::Signer;
+
+tokio::task_local! {
+    static ACTOR: Option<String>;
+}
+
+// Who is making the request currently being handled, for the audit log
+pub fn current_actor() -> Option<String> {
+    ACTOR.try_with(|actor| actor.clone()).ok().flatten()
+}
+
+// Run `f` with `actor` as the current actor
+pub async fn scope<F: Future>(actor: Option<String>, f: F) -> F::Output {
+    ACTOR.scope(actor, f).await
+}
+
+// The user of the request's session, or else the admin the proxy named. The
+// header comes from the client, so it only counts when nobody is logged in.
+// Cookie sessions are only known once loaded, so it has to be read inside SessionLoader.
+pub fn actor(req: &HttpRequest) -> Option<String> {
+    let session = req.extensions().get::<User>().map(|user| user.id).or_else(|| {
+        req.app_data::<web::Data<Signer>>()
+            .and_then(|signer| roles::session_user(req.headers(), signer))
+    });
+    match (session, approvals::admin(req)) {
+        (Some(user_id), _) => Some(format!("user:{}", user_id)),
+        (None, Some(admin)) => Some(format!("admin:{}", admin)),
+        (None, None) => None,
+    }
+}
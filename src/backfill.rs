@@ -0,0 +1,139 @@
+use actix_web::web;
+use serde::Serialize;
+use std::error::Error as StdError;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::models::validate::{MAX_USERNAME_LEN, MIN_USERNAME_LEN, RESERVED_USERNAMES};
+use crate::repositories::backfill_repo::{BackfillProgress, BackfillRepository};
+use crate::repositories::user_repo::CachedUserRepository;
+
+// How long a failed batch waits before it's tried again
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+// Data migrations over existing users, as opposed to the schema changes in
+// migrations.rs: each goes through the users in id order a batch at a time,
+// remembers how far it got and continues from there after a restart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackfillKind {
+    NormalizeEmails,
+    PopulateUsernames,
+}
+
+impl BackfillKind {
+    pub const ALL: [BackfillKind; 2] = [Self::NormalizeEmails, Self::PopulateUsernames];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::NormalizeEmails => "normalize_emails",
+            Self::PopulateUsernames => "populate_usernames",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BackfillConfig {
+    // Run in this order, one after the other
+    pub enabled: Vec<BackfillKind>,
+    pub batch_size: i64,
+    // Pause between batches, so a backfill doesn't crowd out requests
+    pub batch_delay: Duration,
+}
+
+pub struct Backfills {
+    config: BackfillConfig,
+    repo: BackfillRepository,
+    users: web::Data<CachedUserRepository>,
+}
+
+impl Backfills {
+    pub fn new(config: BackfillConfig, repo: BackfillRepository, users: web::Data<CachedUserRepository>) -> Self {
+        Self { config, repo, users }
+    }
+
+    // Progress of every backfill started so far, by any instance
+    pub async fn progress(&self) -> Result<Vec<BackfillProgress>, Box<dyn StdError>> {
+        self.repo.list().await
+    }
+
+    // Work through the enabled backfills in the background
+    pub fn spawn(backfills: web::Data<Backfills>) {
+        if backfills.config.enabled.is_empty() {
+            return;
+        }
+        actix_web::rt::spawn(async move {
+            for kind in backfills.config.enabled.clone() {
+                backfills.run(kind).await;
+            }
+        });
+    }
+
+    async fn run(&self, kind: BackfillKind) {
+        loop {
+            match self.repo.start(kind).await {
+                Ok(true) => break,
+                Ok(false) => return,
+                Err(e) => {
+                    log::error!("Failed to start backfill {}: {}", kind.name(), e);
+                    actix_web::rt::time::sleep(RETRY_DELAY).await;
+                }
+            }
+        }
+        log::info!("Running backfill {}", kind.name());
+
+        let mut changed = 0;
+        loop {
+            match self.repo.run_batch(kind, self.config.batch_size).await {
+                Ok(batch) => {
+                    // These writes bypass the repository, so the cache is told here
+                    self.users.evict(&batch.changed);
+                    changed += batch.changed.len();
+                    if batch.done {
+                        break;
+                    }
+                    actix_web::rt::time::sleep(self.config.batch_delay).await;
+                }
+                Err(e) => {
+                    log::error!("Backfill {} batch failed, retrying in {:?}: {}", kind.name(), RETRY_DELAY, e);
+                    if let Err(e) = self.repo.fail(kind, &e.to_string()).await {
+                        log::error!("Failed to record backfill {} failure: {}", kind.name(), e);
+                    }
+                    actix_web::rt::time::sleep(RETRY_DELAY).await;
+                }
+            }
+        }
+
+        log::info!(target: "audit", "Backfill {} finished, changed {} users in this run", kind.name(), changed);
+        if changed > 0 {
+            if let Err(e) = self.users.rebuild_suggestions().await {
+                log::error!("Failed to rebuild suggestions after backfill {}: {}", kind.name(), e);
+            }
+        }
+    }
+}
+
+// Usernames to try for a user without one: the local part of their email as a
+// valid username, then that with the start of their id appended
+pub fn usernames_for(email: &str, id: &Uuid) -> Vec<String> {
+    let local = email.split('@').next().unwrap_or_default();
+    let slug: String = local
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c.to_ascii_lowercase() } else { '-' })
+        .collect();
+    let trim = |s: &str| s.trim_matches(|c: char| !c.is_ascii_alphanumeric()).to_string();
+
+    let base = trim(&slug[..slug.len().min(MAX_USERNAME_LEN)]);
+    let suffix = &id.simple().to_string()[..8];
+    let suffixed = format!("{}-{}", trim(&base[..base.len().min(MAX_USERNAME_LEN - suffix.len() - 1)]), suffix);
+
+    [base, suffixed]
+        .into_iter()
+        .filter(|name| name.len() >= MIN_USERNAME_LEN && !name.starts_with('-'))
+        .filter(|name| !RESERVED_USERNAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(name)))
+        .collect()
+}
@@ -0,0 +1,110 @@
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::models::user::UpdateUserRequest;
+
+// Window the per-field limits apply to
+const WINDOW: Duration = Duration::from_secs(60 * 60);
+// Users and fields tracked before those without changes in the window are forgotten
+const PRUNE_AT: usize = 10_000;
+
+// Maximum number of changes per user and field within WINDOW, None means unlimited
+#[derive(Debug, Clone, Default)]
+pub struct ChangeLimits {
+    pub name: Option<u32>,
+    pub email: Option<u32>,
+    pub age: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct ChangeViolation {
+    pub field: &'static str,
+    pub limit: u32,
+    pub retry_after: Duration,
+}
+
+// Rejects suspicious mutation patterns, e.g. an account whose email is changed
+// more often than the configured limit per hour. Change history is kept in
+// memory per process.
+pub struct ChangeGuard {
+    limits: ChangeLimits,
+    history: Mutex<HashMap<(Uuid, &'static str), VecDeque<Instant>>>,
+}
+
+impl ChangeGuard {
+    pub fn new(limits: ChangeLimits) -> Self {
+        Self {
+            limits,
+            history: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Count applying `user_req` to the user, or refuse it when that would exceed
+    // a limit. Checked and counted under one lock, so concurrent updates can't
+    // both get through on the last change left. Returns when the change was
+    // counted, for `release` if it isn't applied after all.
+    pub fn claim(&self, id: &Uuid, user_req: &UpdateUserRequest) -> Result<Instant, ChangeViolation> {
+        let now = Instant::now();
+        let fields = self.limited_fields(user_req);
+        let mut history = self.history.lock();
+        if history.len() >= PRUNE_AT {
+            history.retain(|_, changes| changes.back().is_some_and(|at| now.duration_since(*at) < WINDOW));
+        }
+
+        for &(field, limit) in &fields {
+            if let Some(changes) = history.get_mut(&(*id, field)) {
+                while changes.front().is_some_and(|at| now.duration_since(*at) >= WINDOW) {
+                    changes.pop_front();
+                }
+
+                if changes.len() >= limit as usize {
+                    let oldest = changes.front().copied().unwrap_or(now);
+                    return Err(ChangeViolation {
+                        field,
+                        limit,
+                        retry_after: WINDOW.saturating_sub(now.duration_since(oldest)),
+                    });
+                }
+            }
+        }
+
+        for (field, _) in fields {
+            history.entry((*id, field)).or_default().push_back(now);
+        }
+        Ok(now)
+    }
+
+    // Take back a change counted by `claim` at `at` that wasn't applied
+    pub fn release(&self, id: &Uuid, user_req: &UpdateUserRequest, at: Instant) {
+        let mut history = self.history.lock();
+
+        for (field, _) in self.limited_fields(user_req) {
+            if let Some(changes) = history.get_mut(&(*id, field)) {
+                if let Some(position) = changes.iter().rposition(|changed| *changed == at) {
+                    changes.remove(position);
+                }
+                if changes.is_empty() {
+                    history.remove(&(*id, field));
+                }
+            }
+        }
+    }
+
+    fn limited_fields(&self, user_req: &UpdateUserRequest) -> Vec<(&'static str, u32)> {
+        let mut fields = Vec::new();
+
+        if let (Some(_), Some(limit)) = (&user_req.name, self.limits.name) {
+            fields.push(("name", limit));
+        }
+        if let (Some(_), Some(limit)) = (&user_req.email, self.limits.email) {
+            fields.push(("email", limit));
+        }
+        if let (Some(_), Some(limit)) = (&user_req.age, self.limits.age) {
+            fields.push(("age", limit));
+        }
+
+        fields
+    }
+}
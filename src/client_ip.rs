@@ -0,0 +1,49 @@
+use actix_web::HttpRequest;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+use crate::egress::Network;
+
+static TRUSTED_PROXIES: OnceLock<Vec<Network>> = OnceLock::new();
+
+// Called once at startup with TRUSTED_PROXIES
+pub fn init(trusted_proxies: Vec<Network>) {
+    if TRUSTED_PROXIES.set(trusted_proxies).is_err() {
+        log::warn!("Trusted proxies were already initialized");
+    }
+}
+
+fn trusted(ip: &IpAddr) -> bool {
+    TRUSTED_PROXIES.get().is_some_and(|networks| networks.iter().any(|network| network.contains(ip)))
+}
+
+// The address a request came from, for rate limits, deduplication and logs.
+// That's the connection's peer, unless the peer is a trusted proxy: then
+// X-Forwarded-For is read from the nearest hop back, and the first address
+// that isn't a trusted proxy is the client. Anything further left was written
+// by the client and can't be believed, so a client naming itself anew on every
+// request still counts as one.
+pub fn of(req: &HttpRequest) -> Option<IpAddr> {
+    let mut client = req.peer_addr()?.ip();
+    if !trusted(&client) {
+        return Some(client);
+    }
+
+    let forwarded: Vec<&str> = req
+        .headers()
+        .get_all("x-forwarded-for")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect();
+    for hop in forwarded.iter().rev() {
+        // A hop that isn't an address ends the chain, the one before it is the best we know
+        let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = ip;
+        if !trusted(&client) {
+            break;
+        }
+    }
+    Some(client)
+}
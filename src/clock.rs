@@ -0,0 +1,53 @@
+use chrono::{DateTime, Utc};
+#[cfg(test)]
+use chrono::Duration;
+#[cfg(test)]
+use parking_lot::Mutex;
+
+// Where everything that expires or comes due gets the time from: signed links,
+// tokens, cursors, sessions, exports, scheduled operations and retention
+// cutoffs. Shared as an Arc<dyn Clock> and as app data, so a test can swap in a
+// ManualClock and move time instead of sleeping. Short in-process windows
+// (rate and change limits, duplicate submissions, alert cooldowns) and
+// latencies measure elapsed time with Instant instead.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    // Seconds since the Unix epoch, what signed links and tokens carry
+    fn unix_now(&self) -> u64 {
+        self.now().timestamp().max(0) as u64
+    }
+}
+
+// The real time
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+// A clock that only moves when told to, for tests
+#[cfg(test)]
+pub struct ManualClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        Self { now: Mutex::new(start) }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock() += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock()
+    }
+}
@@ -1,13 +1,146 @@
-use deadpool_postgres::{Config as PgConfig, Pool, Runtime, SslMode};
+use deadpool_postgres::{Config as PgConfig, Hook, HookError, HookErrorCause, Pool, Runtime, SslMode};
 use dotenv::dotenv;
 use std::env;
+use std::time::Duration;
+
+use crate::alerts::{AlertConfig, WebhookKind};
+use crate::backfill::{BackfillConfig, BackfillKind};
+use crate::change_guard::ChangeLimits;
+use crate::crash::{CrashConfig, Dsn};
+use crate::egress::{EgressConfig, Network};
+use crate::exports::ExportConfig;
+use crate::models::password::{CharClass, PasswordPolicy};
+use crate::models::public_id::IdFormat;
+use crate::models::validate::MAX_REGION_LEN;
+use crate::oidc::OidcConfig;
+use crate::plugins::FailurePolicy;
+use crate::middleware::cors::{CorsConfig, CorsMode, DEFAULT_MAX_AGE_SECS};
+use crate::middleware::rate_limit::RateLimits;
+use crate::repositories::cursor::CursorConfig;
+use crate::repositories::user_cache::DEFAULT_COOLDOWN;
+#[cfg(feature = "admin-query")]
+use crate::repositories::query_repo::QueryLimits;
+use crate::request_id;
+use crate::sessions::RedisConfig;
+use crate::siem::{LogFormat, SiemConfig, SiemFormat, SiemTarget};
+use crate::telemetry::TelemetryConfig;
 use native_tls::TlsConnector;
 use postgres_native_tls::MakeTlsConnector;
+use sqlx::postgres::{PgConnectOptions, PgPool, PgPoolOptions, PgSslMode};
+
+// Externally visible base URL of the service, used when building absolute links
+pub struct PublicUrl(pub String);
+
+// Where users are stored. Only the user routes can run on memory, SQLite or
+// MySQL, everything else still needs Postgres.
+#[derive(Clone)]
+pub enum StorageBackend {
+    Postgres,
+    Memory,
+    // Path of the database file, created if missing
+    #[cfg(feature = "sqlite")]
+    Sqlite(String),
+    // Pool for a mysql:// or mariadb:// DATABASE_URL
+    #[cfg(feature = "mysql")]
+    Mysql(mysql_async::Pool),
+}
+
+// Where the instance runs, from APP_ENV. Staging and prod default to the safe
+// settings, so leaving a variable out doesn't quietly weaken a deployment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    Dev,
+    Staging,
+    Prod,
+}
+
+impl Profile {
+    fn from_env() -> Result<Self, String> {
+        match env::var("APP_ENV").as_deref() {
+            Ok("dev") | Ok("development") | Ok("") | Err(_) => Ok(Profile::Dev),
+            Ok("staging") => Ok(Profile::Staging),
+            Ok("prod") | Ok("production") => Ok(Profile::Prod),
+            Ok(other) => Err(format!("APP_ENV must be dev, staging or prod, got {}", other)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Profile::Dev => "dev",
+            Profile::Staging => "staging",
+            Profile::Prod => "prod",
+        }
+    }
+
+    // Anything that isn't a developer's machine
+    fn deployed(&self) -> bool {
+        *self != Profile::Dev
+    }
+}
 
 pub struct AppConfig {
     pub host: String,
     pub port: u16,
+    pub public_url: String,
+    pub log_format: LogFormat,
+    pub region: Option<String>,
+    pub storage_backend: StorageBackend,
     pub pg_pool: Pool,
+    // Same database and settings, for the user repository's sqlx queries
+    pub sqlx_pool: PgPool,
+    pub hedge_delay: Option<Duration>,
+    // How long the user cache is skipped after it fails
+    pub cache_cooldown: Duration,
+    pub change_limits: ChangeLimits,
+    pub signing_key: Vec<u8>,
+    pub cursors: CursorConfig,
+    pub qr_link_ttl_secs: u64,
+    pub exports: ExportConfig,
+    pub migrate_on_startup: bool,
+    // Let the startup preflight create missing Postgres extensions
+    pub preflight_create_extensions: bool,
+    pub seed_sample_data: bool,
+    // Reject query parameters an endpoint doesn't know instead of ignoring them
+    pub strict_validation: bool,
+    pub demo_mode: bool,
+    pub demo_reset_interval: Duration,
+    pub approvals_required: bool,
+    pub api_keys_required: bool,
+    pub roles_required: bool,
+    pub session_ttl_secs: u64,
+    pub refresh_ttl_secs: u64,
+    pub redis: Option<RedisConfig>,
+    pub oidc: Option<OidcConfig>,
+    pub password_policy: PasswordPolicy,
+    pub password_breach_api: String,
+    pub public_id_format: IdFormat,
+    pub dedup_window: Option<Duration>,
+    pub rate_limits: RateLimits,
+    // Proxies whose X-Forwarded-For is believed when telling clients apart
+    pub trusted_proxies: Vec<Network>,
+    pub cors: CorsConfig,
+    pub egress: EgressConfig,
+    pub alerts: Option<AlertConfig>,
+    pub siem: Option<SiemConfig>,
+    pub crash_reports: Option<CrashConfig>,
+    pub connector_spec: Option<String>,
+    pub retention_policies: Option<String>,
+    pub retention_interval: Duration,
+    pub backfills: BackfillConfig,
+    pub connector_interval: Duration,
+    pub scheduler_interval: Duration,
+    pub telemetry: Option<TelemetryConfig>,
+    // Failure policies replacing the plugins' own, by plugin name
+    pub plugin_policies: Vec<(String, FailurePolicy)>,
+    #[cfg(feature = "nats")]
+    pub nats_url: Option<String>,
+    #[cfg(feature = "nats")]
+    pub nats_subject_prefix: String,
+    #[cfg(feature = "admin-query")]
+    pub query_limits: QueryLimits,
+    // Logs in as the admin query role, ADMIN_QUERY_DATABASE_URL
+    #[cfg(feature = "admin-query")]
+    pub query_pool: Option<Pool>,
 }
 
 impl AppConfig {
@@ -15,15 +148,348 @@ impl AppConfig {
         // Load environment variables from .env file
         dotenv().ok();
 
+        let profile = Profile::from_env()?;
+        log::info!("Using the {} profile", profile.as_str());
+
         // Server config
         let host = env::var("SERVER_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
         let port = env::var("SERVER_PORT")
             .unwrap_or_else(|_| "8080".to_string())
             .parse::<u16>()?;
 
+        let public_url = env::var("PUBLIC_URL")
+            .map(|url| url.trim_end_matches('/').to_string())
+            .unwrap_or_else(|_| format!("http://{}:{}", host, port));
+
+        // Hedged reads are disabled unless a delay is configured
+        let hedge_delay = Self::optional_env::<u64>("HEDGE_DELAY_MS")?.map(Duration::from_millis);
+
+        let cache_cooldown = Self::optional_env::<u64>("CACHE_COOLDOWN_SECS")?.map_or(DEFAULT_COOLDOWN, Duration::from_secs);
+
+        // Per-field limits on how often a single user may be changed per hour
+        let change_limits = ChangeLimits {
+            name: Self::optional_env("CHANGE_LIMIT_NAME_PER_HOUR")?,
+            email: Self::optional_env("CHANGE_LIMIT_EMAIL_PER_HOUR")?,
+            age: Self::optional_env("CHANGE_LIMIT_AGE_PER_HOUR")?,
+        };
+
+        // Region this instance runs in, stamped on every user row it writes
+        let region = env::var("REGION").ok().filter(|region| !region.is_empty());
+        if let Some(region) = &region {
+            let valid = region.len() <= MAX_REGION_LEN
+                && region.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+            if !valid {
+                return Err(format!("REGION must be at most {} lowercase letters, digits, - or _, got {}", MAX_REGION_LEN, region).into());
+            }
+        }
+
+        // Key for signed links, sessions, JWTs and cursors. Without one they stop verifying when
+        // the process restarts and on every other instance, so deployed profiles refuse to start.
+        let signing_key = match env::var("SIGNING_KEY") {
+            Ok(key) if !key.is_empty() => key.into_bytes(),
+            _ if profile.deployed() => {
+                return Err(format!("SIGNING_KEY must be set with APP_ENV={}", profile.as_str()).into());
+            }
+            _ => {
+                log::warn!("SIGNING_KEY not set, using a random key for this process");
+                rand::random::<[u8; 32]>().to_vec()
+            }
+        };
+
+        // Keys for pagination cursors as `id:secret,...`, the first one signs. The signing key by default.
+        let cursor_keys = match env::var("CURSOR_KEYS") {
+            Ok(keys) if !keys.is_empty() => keys
+                .split(',')
+                .map(|key| match key.trim().split_once(':') {
+                    Some((id, secret)) if !id.is_empty() && !id.contains('.') && !secret.is_empty() => {
+                        Ok((id.to_string(), secret.as_bytes().to_vec()))
+                    }
+                    _ => Err("CURSOR_KEYS must be id:secret pairs, with ids without dots"),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            _ => vec![("0".to_string(), signing_key.clone())],
+        };
+        let cursors = CursorConfig {
+            keys: cursor_keys,
+            encrypt: env::var("CURSOR_ENCRYPT").is_ok_and(|v| v == "true"),
+            ttl_secs: Self::optional_env("CURSOR_TTL_SECS")?.unwrap_or(24 * 60 * 60),
+        };
+        let qr_link_ttl_secs = Self::optional_env("QR_LINK_TTL_SECS")?.unwrap_or(7 * 24 * 60 * 60);
+        let exports = ExportConfig {
+            dir: env::var("EXPORT_DIR").map(Into::into).unwrap_or_else(|_| env::temp_dir().join("hello_world-exports")),
+            link_ttl_secs: Self::optional_env("EXPORT_LINK_TTL_SECS")?.unwrap_or(60 * 60),
+            retention: Duration::from_secs(Self::optional_env("EXPORT_RETENTION_SECS")?.unwrap_or(24 * 60 * 60)),
+        };
+
+        // How user ids appear in API output, stored ids are UUIDs either way
+        let public_id_format = match env::var("PUBLIC_ID_FORMAT").as_deref() {
+            Ok("prefixed") => IdFormat::Prefixed,
+            Ok("uuid") | Err(_) => IdFormat::Uuid,
+            Ok(other) => return Err(format!("PUBLIC_ID_FORMAT must be uuid or prefixed, got {}", other).into()),
+        };
+
+        // Identical POST /users bodies from one client within this window get the first response
+        let dedup_window = Self::optional_env::<u64>("DEDUP_WINDOW_SECS")?.map(Duration::from_secs);
+
+        // Requests per minute per client IP, and per API key for requests that carry one
+        let rate_limits = RateLimits {
+            per_ip: Self::optional_env("RATE_LIMIT_PER_IP")?,
+            per_api_key: Self::optional_env("RATE_LIMIT_PER_API_KEY")?,
+        };
+        if rate_limits.per_ip == Some(0) || rate_limits.per_api_key == Some(0) {
+            return Err("Rate limits must be at least 1 request per minute".into());
+        }
+
+        // Load balancers and proxies in front, as addresses or CIDR ranges. Only their
+        // X-Forwarded-For is read, everyone else is known by the connection's address.
+        let trusted_proxies = env::var("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| Network::parse(item).map_err(|e| format!("TRUSTED_PROXIES: {}", e)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Cross-origin browser access: none unless origins are listed, anything in dev mode
+        let list = |name: &str| -> Option<Vec<String>> {
+            env::var(name)
+                .ok()
+                .map(|value| value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(str::to_string).collect())
+        };
+        let cors = CorsConfig {
+            mode: match env::var("CORS_MODE").as_deref() {
+                Ok("dev") => CorsMode::Dev,
+                Ok("strict") | Ok("") | Err(_) => CorsMode::Strict,
+                Ok(other) => return Err(format!("CORS_MODE must be strict or dev, got {}", other).into()),
+            },
+            allowed_origins: list("CORS_ALLOWED_ORIGINS").unwrap_or_default(),
+            allowed_methods: match list("CORS_ALLOWED_METHODS") {
+                Some(methods) => methods
+                    .iter()
+                    .map(|method| method.to_ascii_uppercase().parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| format!("Invalid method in CORS_ALLOWED_METHODS: {}", methods.join(",")))?,
+                None => CorsConfig::default_methods(),
+            },
+            allowed_headers: list("CORS_ALLOWED_HEADERS")
+                .map(|names| names.iter().map(|name| name.to_ascii_lowercase()).collect())
+                .unwrap_or_else(CorsConfig::default_headers),
+            allow_credentials: env::var("CORS_ALLOW_CREDENTIALS").is_ok_and(|v| v == "true"),
+            max_age_secs: Self::optional_env("CORS_MAX_AGE_SECS")?.unwrap_or(DEFAULT_MAX_AGE_SECS),
+        };
+        cors.check()?;
+        if cors.mode == CorsMode::Dev && profile == Profile::Prod {
+            return Err("CORS_MODE=dev allows every origin and can't be used with APP_ENV=prod".into());
+        }
+        if cors.mode == CorsMode::Dev {
+            log::warn!("CORS_MODE=dev allows every origin, don't use it in production");
+        }
+
+        // Operational alerts go to a Slack or Teams incoming webhook when one is configured
+        let alerts = match env::var("ALERT_WEBHOOK_URL") {
+            Ok(webhook_url) if !webhook_url.is_empty() => Some(AlertConfig {
+                webhook_url,
+                kind: match env::var("ALERT_WEBHOOK_KIND").as_deref() {
+                    Ok("teams") => WebhookKind::Teams,
+                    Ok("slack") | Err(_) => WebhookKind::Slack,
+                    Ok(other) => return Err(format!("ALERT_WEBHOOK_KIND must be slack or teams, got {}", other).into()),
+                },
+                cooldown: Duration::from_secs(Self::optional_env("ALERT_COOLDOWN_SECS")?.unwrap_or(15 * 60)),
+                error_rate_percent: Self::optional_env("ALERT_ERROR_RATE_PERCENT")?.unwrap_or(5.0),
+            }),
+            _ => None,
+        };
+
+        let egress = Self::egress()?;
+
+        // Audit events are forwarded to a SIEM when one is configured
+        let siem = match env::var("SIEM_URL") {
+            Ok(url) if !url.is_empty() => Some(SiemConfig {
+                target: if let Some(addr) = url.strip_prefix("syslog://") {
+                    SiemTarget::Syslog(addr.trim_end_matches('/').to_string())
+                } else if url.starts_with("http://") || url.starts_with("https://") {
+                    SiemTarget::Http(url)
+                } else {
+                    return Err(format!("SIEM_URL must be an http(s):// or syslog:// URL, got {}", url).into());
+                },
+                format: match env::var("SIEM_FORMAT").as_deref() {
+                    Ok("cef") => SiemFormat::Cef,
+                    Ok("json") | Err(_) => SiemFormat::Json,
+                    Ok(other) => return Err(format!("SIEM_FORMAT must be cef or json, got {}", other).into()),
+                },
+                spool_path: env::var("SIEM_SPOOL_PATH").unwrap_or_else(|_| "siem-spool.log".to_string()).into(),
+            }),
+            _ => None,
+        };
+
+        // Panics and server errors are reported to a Sentry-compatible DSN when one is configured
+        let crash_reports = match env::var("SENTRY_DSN") {
+            Ok(dsn) if !dsn.is_empty() => {
+                let sample_rate = Self::optional_env::<f64>("SENTRY_SAMPLE_RATE")?.unwrap_or(1.0);
+                if !(0.0..=1.0).contains(&sample_rate) {
+                    return Err("SENTRY_SAMPLE_RATE must be between 0 and 1".into());
+                }
+                Some(CrashConfig {
+                    dsn: Dsn::parse(&dsn)?,
+                    sample_rate,
+                    release: env::var("SENTRY_RELEASE").unwrap_or_else(|_| format!("hello_world@{}", env!("CARGO_PKG_VERSION"))),
+                    environment: env::var("SENTRY_ENVIRONMENT").ok().filter(|environment| !environment.is_empty()),
+                })
+            }
+            _ => None,
+        };
+
+        // Scheduled pull of users from an external HTTP source, see connector.rs
+        let connector_spec = env::var("CONNECTOR_SPEC").ok().filter(|path| !path.is_empty());
+        let connector_interval = Duration::from_secs(Self::optional_env("CONNECTOR_INTERVAL_SECS")?.unwrap_or(24 * 60 * 60));
+
+        // Data retention policies file, applied on a schedule
+        let retention_policies = env::var("RETENTION_POLICIES").ok().filter(|path| !path.is_empty());
+        let retention_interval = Duration::from_secs(Self::optional_env("RETENTION_INTERVAL_SECS")?.unwrap_or(24 * 60 * 60));
+
+        // Data backfills run at startup: all of them unless BACKFILLS lists some, none for `off`
+        let backfills = BackfillConfig {
+            enabled: match env::var("BACKFILLS").as_deref() {
+                Err(_) => BackfillKind::ALL.to_vec(),
+                Ok("off") | Ok("") => Vec::new(),
+                Ok(names) => names
+                    .split(',')
+                    .map(str::trim)
+                    .map(|name| BackfillKind::parse(name).ok_or_else(|| format!("Unknown backfill {} in BACKFILLS", name)))
+                    .collect::<Result<_, _>>()?,
+            },
+            batch_size: Self::optional_env("BACKFILL_BATCH_SIZE")?.unwrap_or(500),
+            batch_delay: Duration::from_millis(Self::optional_env("BACKFILL_BATCH_DELAY_MS")?.unwrap_or(100)),
+        };
+        if backfills.batch_size < 1 {
+            return Err("BACKFILL_BATCH_SIZE must be at least 1".into());
+        }
+
+        // `name=abort|warn,...`, overriding the policy a plugin comes with
+        let plugin_policies = match env::var("PLUGIN_POLICIES") {
+            Ok(policies) => policies
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| {
+                    item.split_once('=')
+                        .and_then(|(name, policy)| Some((name.trim().to_string(), FailurePolicy::parse(policy.trim())?)))
+                        .ok_or_else(|| format!("PLUGIN_POLICIES takes name=abort or name=warn, got {}", item))
+                })
+                .collect::<Result<_, _>>()?,
+            Err(_) => Vec::new(),
+        };
+
+        // How often scheduled user operations are checked for being due
+        let scheduler_interval = Duration::from_secs(Self::optional_env("SCHEDULER_INTERVAL_SECS")?.unwrap_or(30));
+
+        // Anonymous usage reports, sent only with TELEMETRY=on and an endpoint to send them to
+        let telemetry = match env::var("TELEMETRY").as_deref() {
+            Ok("on") => Some(TelemetryConfig {
+                url: env::var("TELEMETRY_URL").map_err(|_| "TELEMETRY_URL is required with TELEMETRY=on")?,
+                interval: Duration::from_secs(Self::optional_env("TELEMETRY_INTERVAL_SECS")?.unwrap_or(24 * 60 * 60)),
+            }),
+            Ok("off") | Ok("") | Err(_) => None,
+            Ok(other) => return Err(format!("TELEMETRY must be on or off, got {}", other).into()),
+        };
+
+        // A MySQL DATABASE_URL is for the users, Postgres then comes from the PG_* variables
+        let database_url = env::var("DATABASE_URL").ok();
+        let mysql_url = database_url.as_deref().filter(|url| url.starts_with("mysql://") || url.starts_with("mariadb://"));
+        let postgres_url = mysql_url.is_none();
+        let storage_backend = match env::var("STORAGE_BACKEND").as_deref() {
+            Ok("postgres") | Ok("") | Err(_) => match mysql_url {
+                #[cfg(feature = "mysql")]
+                Some(url) => StorageBackend::Mysql(Self::mysql_pool(url)?),
+                #[cfg(not(feature = "mysql"))]
+                Some(_) => return Err("A mysql:// DATABASE_URL needs a build with --features mysql".into()),
+                None => StorageBackend::Postgres,
+            },
+            Ok("memory") => StorageBackend::Memory,
+            #[cfg(feature = "sqlite")]
+            Ok("sqlite") => StorageBackend::Sqlite(env::var("SQLITE_PATH").unwrap_or_else(|_| "hello_world.db".to_string())),
+            #[cfg(not(feature = "sqlite"))]
+            Ok("sqlite") => return Err("STORAGE_BACKEND=sqlite needs a build with --features sqlite".into()),
+            Ok(other) => return Err(format!("STORAGE_BACKEND must be postgres, memory or sqlite, got {}", other).into()),
+        };
+
+        // Public demo instance: fixture data, periodic resets, no deletes
+        // Off when migrations are run as a separate deployment step
+        let migrate_on_startup = !env::var("MIGRATE_ON_STARTUP").is_ok_and(|v| v == "false");
+        let preflight_create_extensions = Self::flag("PREFLIGHT_CREATE_EXTENSIONS", true);
+        let demo_mode = env::var("DEMO_MODE").is_ok_and(|v| v == "true");
+        // John Doe, for trying the API out on an empty database
+        let seed_sample_data = Self::flag("SEED_SAMPLE_DATA", !profile.deployed());
+        let strict_validation = Self::flag("STRICT_VALIDATION", profile.deployed());
+        let demo_reset_interval = Duration::from_secs(Self::optional_env("DEMO_RESET_SECS")?.unwrap_or(60 * 60));
+
+        // Four-eyes mode: deletes and email changes wait for a second admin
+        let approvals_required = env::var("APPROVALS_REQUIRED").is_ok_and(|v| v == "true");
+
+        // Refuse requests without an X-Api-Key header, apart from health, status and admin routes
+        let api_keys_required = env::var("API_KEYS_REQUIRED").is_ok_and(|v| v == "true");
+
+        // Lifetime of the tokens handed out by OIDC and password logins
+        let session_ttl_secs = Self::optional_env("SESSION_TTL_SECS")?.unwrap_or(24 * 60 * 60);
+
+        // Lifetime of the refresh tokens that come with password logins
+        let refresh_ttl_secs = Self::optional_env("REFRESH_TOKEN_TTL_SECS")?.unwrap_or(30 * 24 * 60 * 60);
+
+        // Logins also set a session cookie, with the sessions kept in this Redis
+        let redis = match env::var("REDIS_URL") {
+            Ok(url) if !url.is_empty() => Some(RedisConfig::parse(&url)?),
+            _ => None,
+        };
+
+        // Require a session token on user routes, and the admin role to write
+        let roles_required = env::var("ROLES_REQUIRED").is_ok_and(|v| v == "true");
+
+        // Browser login through an OpenID Connect provider, off unless an issuer is set
+        let oidc = match env::var("OIDC_ISSUER") {
+            Ok(issuer) if !issuer.is_empty() => Some(OidcConfig {
+                issuer,
+                client_id: env::var("OIDC_CLIENT_ID").map_err(|_| "OIDC_CLIENT_ID is required with OIDC_ISSUER")?,
+                client_secret: env::var("OIDC_CLIENT_SECRET").map_err(|_| "OIDC_CLIENT_SECRET is required with OIDC_ISSUER")?,
+                redirect_url: env::var("OIDC_REDIRECT_URL").unwrap_or_else(|_| format!("{}/auth/callback", public_url)),
+                scopes: env::var("OIDC_SCOPES").unwrap_or_else(|_| "openid email profile".to_string()),
+                provider_name: env::var("OIDC_PROVIDER_NAME").unwrap_or_else(|_| "oidc".to_string()),
+            }),
+            _ => None,
+        };
+
+        // Rules for new passwords, also served to UIs at GET /auth/password-policy
+        let defaults = PasswordPolicy::default();
+        let password_policy = PasswordPolicy {
+            min_length: Self::optional_env("PASSWORD_MIN_LENGTH")?.unwrap_or(defaults.min_length),
+            max_length: defaults.max_length,
+            required_classes: match env::var("PASSWORD_REQUIRED_CLASSES") {
+                Ok(classes) => classes
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|class| !class.is_empty())
+                    .map(|class| {
+                        CharClass::parse(class).ok_or_else(|| {
+                            format!("PASSWORD_REQUIRED_CLASSES takes lowercase, uppercase, digit and symbol, got {}", class)
+                        })
+                    })
+                    .collect::<Result<_, _>>()?,
+                Err(_) => defaults.required_classes,
+            },
+            breach_check: env::var("PASSWORD_BREACH_CHECK").map_or(defaults.breach_check, |v| v != "false"),
+            disallow_email: env::var("PASSWORD_DISALLOW_EMAIL").map_or(defaults.disallow_email, |v| v != "false"),
+        };
+        if password_policy.min_length > password_policy.max_length {
+            return Err(format!("PASSWORD_MIN_LENGTH must be at most {}", password_policy.max_length).into());
+        }
+        if roles_required && oidc.is_none() {
+            log::warn!("ROLES_REQUIRED is set without OIDC_ISSUER, nobody can get a session token");
+        }
+        let password_breach_api =
+            env::var("PASSWORD_BREACH_API").unwrap_or_else(|_| "https://api.pwnedpasswords.com/range".to_string());
+
         // Create PostgreSQL configuration
-        let pg_config = match env::var("DATABASE_URL") {
-            Ok(url) => {
+        let mut pg_config = match database_url.filter(|_| postgres_url) {
+            Some(url) => {
                 // Parse connection string manually
                 log::info!("Using DATABASE_URL from environment");
                 let mut config = PgConfig::new();
@@ -33,7 +499,7 @@ impl AppConfig {
                     Self::default_db_config()
                 } else {
                     // Verify the config has required fields
-                    if config.dbname.is_none() || config.dbname.as_ref().map_or(true, |s| s.is_empty()) {
+                    if config.dbname.as_ref().is_none_or(|s| s.is_empty()) {
                         log::warn!("Database name is empty in DATABASE_URL, using default");
                         config.dbname = Some("postgres".to_string());
                     }
@@ -51,9 +517,9 @@ impl AppConfig {
                     config
                 }
             },
-            Err(_) => {
+            None => {
                 // Use individual parameters
-                log::info!("DATABASE_URL not found, using individual parameters");
+                log::info!("No PostgreSQL DATABASE_URL, using individual parameters");
                 let mut config = PgConfig::new();
                 
                 config.host = Some(env::var("PG_HOST").unwrap_or_else(|_| "localhost".to_string()));
@@ -78,6 +544,12 @@ impl AppConfig {
             }
         };
         
+        // Name connections so they can be told apart in pg_stat_activity, per request if enabled
+        pg_config.application_name = Some(env::var("PG_APPLICATION_NAME").unwrap_or_else(|_| "hello_world".to_string()));
+        let tag_request_id = env::var("PG_APPLICATION_NAME_REQUEST_ID").is_ok_and(|v| v == "true");
+        // Check the certificate and host name of sslmode=require connections
+        let verify_tls = Self::flag("PG_TLS_VERIFY", profile.deployed());
+        
         // Log configuration for debugging
         log::info!("PostgreSQL Configuration:");
         log::info!("  Host: {}", pg_config.host.as_deref().unwrap_or("not set"));
@@ -92,14 +564,18 @@ impl AppConfig {
         }));
         
         // Create the connection pool with TLS if required
-        let pg_pool = if pg_config.ssl_mode.as_ref().map_or(false, |m| *m == SslMode::Require) {
+        let pg_pool = if pg_config.ssl_mode.as_ref().is_some_and(|m| *m == SslMode::Require) {
             log::info!("Using TLS for PostgreSQL connection");
-            // Use TLS connector for secure connections
+            if !verify_tls {
+                log::warn!("PG_TLS_VERIFY=false, the PostgreSQL server certificate is not checked");
+            }
+            // Self-signed certificates are only accepted without verification
             let tls_connector = TlsConnector::builder()
-                .danger_accept_invalid_certs(true) // For self-signed certificates
+                .danger_accept_invalid_certs(!verify_tls)
+                .danger_accept_invalid_hostnames(!verify_tls)
                 .build()?;
             let connector = MakeTlsConnector::new(tls_connector);
-            pg_config.create_pool(Some(Runtime::Tokio1), connector)?
+            Self::build_pool(&pg_config, connector, tag_request_id, region.as_deref())?
         } else {
             log::info!("Using no TLS for PostgreSQL connection");
             // For local development without TLS
@@ -108,17 +584,294 @@ impl AppConfig {
                     .danger_accept_invalid_certs(true)
                     .build()?
             );
-            pg_config.create_pool(Some(Runtime::Tokio1), connector)?
+            Self::build_pool(&pg_config, connector, tag_request_id, region.as_deref())?
         };
         
+        let sqlx_pool = Self::build_sqlx_pool(&pg_config, pg_pool.status().max_size, verify_tls, tag_request_id, region.as_deref());
+        #[cfg(feature = "admin-query")]
+        let query_pool = Self::query_pool(&pg_config, verify_tls)?;
         log::info!("PostgreSQL connection pool created successfully");
 
         Ok(Self {
             host,
             port,
+            public_url,
+            log_format: Self::log_format()?,
+            region,
+            storage_backend,
             pg_pool,
+            sqlx_pool,
+            hedge_delay,
+            cache_cooldown,
+            change_limits,
+            signing_key,
+            cursors,
+            qr_link_ttl_secs,
+            exports,
+            migrate_on_startup,
+            preflight_create_extensions,
+            seed_sample_data,
+            strict_validation,
+            demo_mode,
+            demo_reset_interval,
+            approvals_required,
+            api_keys_required,
+            roles_required,
+            session_ttl_secs,
+            refresh_ttl_secs,
+            redis,
+            oidc,
+            password_policy,
+            password_breach_api,
+            public_id_format,
+            dedup_window,
+            rate_limits,
+            trusted_proxies,
+            cors,
+            egress,
+            alerts,
+            siem,
+            crash_reports,
+            connector_spec,
+            connector_interval,
+            scheduler_interval,
+            telemetry,
+            plugin_policies,
+            retention_policies,
+            retention_interval,
+            backfills,
+            #[cfg(feature = "nats")]
+            nats_url: env::var("NATS_URL").ok().filter(|url| !url.is_empty()),
+            #[cfg(feature = "nats")]
+            nats_subject_prefix: env::var("NATS_SUBJECT_PREFIX").unwrap_or_else(|_| "users".to_string()),
+            #[cfg(feature = "admin-query")]
+            query_limits: QueryLimits {
+                timeout: Duration::from_millis(Self::optional_env("ADMIN_QUERY_TIMEOUT_MS")?.unwrap_or(5000)),
+                max_rows: Self::optional_env("ADMIN_QUERY_MAX_ROWS")?.unwrap_or(1000),
+            },
+            #[cfg(feature = "admin-query")]
+            query_pool,
+        })
+    }
+
+    // Pool size, timeouts and TLS come from the URL's parameters, like
+    // mysql://app:secret@db:3306/users?pool_max=20&require_ssl=true
+    #[cfg(feature = "mysql")]
+    fn mysql_pool(url: &str) -> Result<mysql_async::Pool, Box<dyn std::error::Error>> {
+        let url = match url.strip_prefix("mariadb://") {
+            Some(rest) => format!("mysql://{}", rest),
+            None => url.to_string(),
+        };
+        let opts = mysql_async::Opts::from_url(&url).map_err(|e| format!("Failed to parse DATABASE_URL: {}", e))?;
+        log::info!("MySQL Configuration:");
+        log::info!("  Host: {}", opts.ip_or_hostname());
+        log::info!("  Port: {}", opts.tcp_port());
+        log::info!("  Database: {}", opts.db_name().unwrap_or("not set"));
+        log::info!("  User: {}", opts.user().unwrap_or("not set"));
+        Ok(mysql_async::Pool::new(opts))
+    }
+
+    // Build the pool. With `tag_request_id` every checked-out connection gets the
+    // current request id appended to its application_name, so slow queries and
+    // pg_stat_activity entries can be traced back to an HTTP request. A region
+    // is set as `app.region` on new connections, for the triggers that stamp rows.
+    fn build_pool(
+        pg_config: &PgConfig,
+        connector: MakeTlsConnector,
+        tag_request_id: bool,
+        region: Option<&str>,
+    ) -> Result<Pool, Box<dyn std::error::Error>> {
+        let mut builder = pg_config.builder(connector)?.runtime(Runtime::Tokio1);
+        
+        if let Some(region) = region {
+            log::info!("Stamping writes with region {}", region);
+            let region = region.to_string();
+            builder = builder.post_create(Hook::async_fn(move |client, _| {
+                let region = region.clone();
+                Box::pin(async move {
+                    client
+                        .execute("SELECT set_config('app.region', $1, false)", &[&region])
+                        .await
+                        .map_err(|e| HookError::Abort(HookErrorCause::Backend(e)))?;
+                    Ok(())
+                })
+            }));
+        }
+        
+        if tag_request_id {
+            log::info!("Tagging PostgreSQL application_name with request ids");
+            let base = pg_config.application_name.clone().unwrap_or_default();
+            builder = builder
+                .post_create(Self::application_name_hook(base.clone()))
+                .post_recycle(Self::application_name_hook(base));
+        }
+        
+        Ok(builder.build()?)
+    }
+    
+    // Pool for POST /admin/query, logged in with the credentials of
+    // ADMIN_QUERY_DATABASE_URL. Host and database default to the server's.
+    #[cfg(feature = "admin-query")]
+    fn query_pool(server: &PgConfig, verify_tls: bool) -> Result<Option<Pool>, Box<dyn std::error::Error>> {
+        let url = match env::var("ADMIN_QUERY_DATABASE_URL").ok().filter(|url| !url.is_empty()) {
+            Some(url) => url,
+            None => {
+                log::warn!("ADMIN_QUERY_DATABASE_URL is not set, POST /admin/query will fail");
+                return Ok(None);
+            }
+        };
+        let mut config = PgConfig::new();
+        Self::parse_db_url(&url, &mut config).map_err(|e| format!("Failed to parse ADMIN_QUERY_DATABASE_URL: {}", e))?;
+        config.host = config.host.or_else(|| server.host.clone());
+        config.port = config.port.or(server.port);
+        config.application_name = server.application_name.as_ref().map(|name| format!("{}-query", name));
+        log::info!("Admin queries log in as {}", config.user.as_deref().unwrap_or("not set"));
+
+        let verify = verify_tls && config.ssl_mode.as_ref().is_some_and(|m| *m == SslMode::Require);
+        let tls_connector = TlsConnector::builder()
+            .danger_accept_invalid_certs(!verify)
+            .danger_accept_invalid_hostnames(!verify)
+            .build()?;
+        Ok(Some(Self::build_pool(&config, MakeTlsConnector::new(tls_connector), false, None)?))
+    }
+
+    // The sqlx pool for the same database, with the same limit and connection
+    // settings as build_pool. Connections are opened on first use. Without
+    // sslmode=require TLS is used when the server offers it, like the connector above.
+    fn build_sqlx_pool(pg_config: &PgConfig, max_size: usize, verify_tls: bool, tag_request_id: bool, region: Option<&str>) -> PgPool {
+        let require_tls = pg_config.ssl_mode.as_ref().is_some_and(|m| *m == SslMode::Require);
+        let base = pg_config.application_name.clone().unwrap_or_default();
+        let mut options = PgConnectOptions::new_without_pgpass()
+            .host(pg_config.host.as_deref().unwrap_or("localhost"))
+            .port(pg_config.port.unwrap_or(5432))
+            .username(pg_config.user.as_deref().unwrap_or("postgres"))
+            .database(pg_config.dbname.as_deref().unwrap_or("postgres"))
+            .application_name(&base)
+            .ssl_mode(match (require_tls, verify_tls) {
+                (true, true) => PgSslMode::VerifyFull,
+                (true, false) => PgSslMode::Require,
+                (false, _) => PgSslMode::Prefer,
+            });
+        if let Some(password) = &pg_config.password {
+            options = options.password(password);
+        }
+
+        let region = region.map(str::to_string);
+        let mut pool = PgPoolOptions::new().max_connections(max_size as u32).after_connect(move |conn, _| {
+            let region = region.clone();
+            Box::pin(async move {
+                if let Some(region) = region {
+                    sqlx::query("SELECT set_config('app.region', $1, false)").bind(region).execute(conn).await?;
+                }
+                Ok(())
+            })
+        });
+        if tag_request_id {
+            pool = pool.before_acquire(move |conn, _| {
+                let name = match request_id::current() {
+                    Some(id) => format!("{}:{}", base, id),
+                    None => base.clone(),
+                };
+                Box::pin(async move {
+                    sqlx::query("SELECT set_config('application_name', $1, false)").bind(name).execute(conn).await?;
+                    Ok(true)
+                })
+            });
+        }
+        pool.connect_lazy_with(options)
+    }
+
+    // Hooks run in the task checking out the connection, so the request id is in scope
+    fn application_name_hook(base: String) -> Hook {
+        Hook::async_fn(move |client, _| {
+            let name = match request_id::current() {
+                Some(id) => format!("{}:{}", base, id),
+                None => base.clone(),
+            };
+            Box::pin(async move {
+                client
+                    .execute("SELECT set_config('application_name', $1, false)", &[&name])
+                    .await
+                    .map_err(|e| HookError::Abort(HookErrorCause::Backend(e)))?;
+                Ok(())
+            })
         })
     }
+
+    // Parse an optional environment variable, failing only if it is set but invalid
+    // Also read on its own, as the logger is set up before the rest of the configuration is loaded
+    pub fn log_format() -> Result<LogFormat, String> {
+        dotenv().ok();
+        match env::var("LOG_FORMAT").as_deref() {
+            Ok("json") => Ok(LogFormat::Json),
+            Ok("text") | Err(_) => Ok(LogFormat::Text),
+            Ok(other) => Err(format!("LOG_FORMAT must be text or json, got {}", other)),
+        }
+    }
+
+    // Outbound proxies from the usual variables, either case, plus overrides like
+    // EGRESS_PROXY_OVERRIDES=hooks.slack.com=http://proxy2:3128,idp.internal=direct
+    // Also read on its own by `smoke`, which has no database to configure
+    pub fn egress() -> Result<EgressConfig, Box<dyn std::error::Error>> {
+        let var = |name: &str| {
+            env::var(name)
+                .or_else(|_| env::var(name.to_lowercase()))
+                .ok()
+                .filter(|value| !value.trim().is_empty())
+        };
+        let proxy_url = |name: &str, value: &str| {
+            reqwest::Url::parse(value.trim()).map_err(|e| format!("{} must be a proxy URL, got {}: {}", name, value, e))
+        };
+        let list = |value: Option<String>| -> Vec<String> {
+            value
+                .map(|value| value.split(',').map(|item| item.trim().to_ascii_lowercase()).filter(|item| !item.is_empty()).collect())
+                .unwrap_or_default()
+        };
+
+        let mut overrides = Vec::new();
+        for entry in list(var("EGRESS_PROXY_OVERRIDES")) {
+            let (host, proxy) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("EGRESS_PROXY_OVERRIDES entries must be host=proxy_url or host=direct, got {}", entry))?;
+            let proxy = match proxy {
+                "direct" => None,
+                proxy => Some(proxy_url("EGRESS_PROXY_OVERRIDES", proxy)?),
+            };
+            overrides.push((host.to_string(), proxy));
+        }
+
+        Ok(EgressConfig {
+            http_proxy: var("HTTP_PROXY").map(|value| proxy_url("HTTP_PROXY", &value)).transpose()?,
+            https_proxy: var("HTTPS_PROXY").map(|value| proxy_url("HTTPS_PROXY", &value)).transpose()?,
+            no_proxy: list(var("NO_PROXY")),
+            overrides,
+            allowed_networks: list(var("WEBHOOK_ALLOWED_NETWORKS"))
+                .iter()
+                .map(|network| Network::parse(network))
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("WEBHOOK_ALLOWED_NETWORKS: {}", e))?,
+        })
+    }
+
+    // An on/off variable, `default` unless it is "true" or "false"
+    fn flag(name: &str, default: bool) -> bool {
+        match env::var(name).as_deref() {
+            Ok("true") => true,
+            Ok("false") => false,
+            _ => default,
+        }
+    }
+
+    fn optional_env<T>(name: &str) -> Result<Option<T>, Box<dyn std::error::Error>>
+    where
+        T: std::str::FromStr,
+        T::Err: std::error::Error + 'static,
+    {
+        match env::var(name) {
+            Ok(value) => Ok(Some(value.parse::<T>()?)),
+            Err(_) => Ok(None),
+        }
+    }
     
     fn parse_db_url(url: &str, config: &mut PgConfig) -> Result<(), String> {
         // Accept both postgres:// and postgresql:// protocol prefixes
@@ -0,0 +1,198 @@
+use actix_web::web;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::time::Duration;
+
+use crate::egress;
+use crate::models::user::CreateUserRequest;
+use crate::models::validate::Validate;
+use crate::repositories::user_repo::CachedUserRepository;
+
+// Records upserted per statement
+const BATCH_SIZE: usize = 1000;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Where to pull users from and how their fields map onto ours, read from a JSON
+// file. Secrets can be given as `$VAR` to read them from the environment.
+//
+// {
+//   "url": "https://legacy.example.com/api/users",
+//   "auth": { "bearer": "$LEGACY_TOKEN" },
+//   "records": "/data/users",
+//   "fields": { "name": "/full_name", "email": "/contact/email", "age": "/age" }
+// }
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConnectorSpec {
+    pub url: String,
+    #[serde(default)]
+    pub auth: Option<SourceAuth>,
+    // JSON pointer to the array of records, the whole response when omitted
+    #[serde(default)]
+    pub records: String,
+    pub fields: FieldMapping,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum SourceAuth {
+    Bearer(String),
+    Basic { username: String, password: String },
+    Header { name: String, value: String },
+}
+
+// JSON pointers into each record for our user fields
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldMapping {
+    pub name: String,
+    pub email: String,
+    #[serde(default)]
+    pub age: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ConnectorSummary {
+    pub fetched: u64,
+    pub created: u64,
+    pub updated: u64,
+    pub unchanged: u64,
+    pub errors: Vec<RecordError>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecordError {
+    // Position of the record in the source response, starting at 0
+    pub record: usize,
+    pub error: String,
+}
+
+impl ConnectorSpec {
+    pub fn load(path: &str) -> Result<Self, Box<dyn StdError>> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read connector spec {}: {}", path, e))?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+}
+
+// Fetch the source, map every record and upsert the valid ones by email
+pub async fn run(spec: &ConnectorSpec, users: &CachedUserRepository) -> Result<ConnectorSummary, Box<dyn StdError>> {
+    let records = fetch(spec).await?;
+    let mut summary = ConnectorSummary {
+        fetched: records.len() as u64,
+        ..Default::default()
+    };
+
+    // Keyed by email so a source listing someone twice doesn't make the upsert
+    // touch the same row twice; the later record wins
+    let mut by_email: HashMap<String, (usize, CreateUserRequest)> = HashMap::new();
+    for (index, record) in records.iter().enumerate() {
+        let user_req = map_record(record, &spec.fields).and_then(|user_req| {
+            user_req.validate().map(|()| user_req).map_err(|errors| {
+                errors
+                    .iter()
+                    .map(|e| format!("{} {}", e.field, e.message))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+        });
+        match user_req {
+            Ok(user_req) => {
+                by_email.insert(user_req.email.clone(), (index, user_req));
+            }
+            Err(error) => summary.errors.push(RecordError { record: index, error }),
+        }
+    }
+
+    let mut pending: Vec<(usize, CreateUserRequest)> = by_email.into_values().collect();
+    pending.sort_by_key(|(index, _)| *index);
+    let pending: Vec<CreateUserRequest> = pending.into_iter().map(|(_, user_req)| user_req).collect();
+
+    for batch in pending.chunks(BATCH_SIZE) {
+        let changed = users.upsert_many(batch).await?;
+        let created = changed.iter().filter(|(_, inserted)| *inserted).count() as u64;
+        summary.created += created;
+        summary.updated += changed.len() as u64 - created;
+        summary.unchanged += (batch.len() - changed.len()) as u64;
+    }
+
+    Ok(summary)
+}
+
+// Run the connector every `interval` for as long as the server runs. The first
+// run is one interval after startup, so deploys don't each trigger an import.
+pub fn spawn_schedule(spec: ConnectorSpec, users: web::Data<CachedUserRepository>, interval: Duration) {
+    actix_web::rt::spawn(async move {
+        let mut ticker = actix_web::rt::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            match run(&spec, &users).await {
+                Ok(summary) => log::info!(
+                    "Connector import from {}: {} fetched, {} created, {} updated, {} unchanged, {} errors",
+                    spec.url, summary.fetched, summary.created, summary.updated, summary.unchanged, summary.errors.len()
+                ),
+                Err(e) => log::error!("Connector import from {} failed: {}", spec.url, e),
+            }
+        }
+    });
+}
+
+async fn fetch(spec: &ConnectorSpec) -> Result<Vec<Value>, Box<dyn StdError>> {
+    let mut request = egress::client().get(&spec.url).timeout(REQUEST_TIMEOUT);
+    request = match &spec.auth {
+        Some(SourceAuth::Bearer(token)) => request.bearer_auth(resolve(token)?),
+        Some(SourceAuth::Basic { username, password }) => request.basic_auth(resolve(username)?, Some(resolve(password)?)),
+        Some(SourceAuth::Header { name, value }) => request.header(name.as_str(), resolve(value)?),
+        None => request,
+    };
+
+    let body: Value = request.send().await?.error_for_status()?.json().await?;
+    match body.pointer(&spec.records) {
+        Some(Value::Array(records)) => Ok(records.clone()),
+        Some(_) => Err(format!("{} in the response is not an array", spec.records).into()),
+        None => Err(format!("{} not found in the response", spec.records).into()),
+    }
+}
+
+fn map_record(record: &Value, fields: &FieldMapping) -> Result<CreateUserRequest, String> {
+    let text = |pointer: &str, field: &str| match record.pointer(pointer) {
+        Some(Value::String(value)) => Ok(value.trim().to_string()),
+        Some(other) => Err(format!("{} at {} is not a string: {}", field, pointer, other)),
+        None => Err(format!("{} missing at {}", field, pointer)),
+    };
+
+    let age = match fields.age.as_deref().map(|pointer| (pointer, record.pointer(pointer))) {
+        None | Some((_, None)) | Some((_, Some(Value::Null))) => None,
+        // Legacy systems are fond of numbers in strings
+        Some((pointer, Some(value))) => {
+            let age = match value {
+                Value::Number(n) => n.as_u64(),
+                Value::String(s) => s.trim().parse().ok(),
+                _ => None,
+            };
+            match age.and_then(|age| u8::try_from(age).ok()) {
+                Some(age) => Some(age),
+                None => return Err(format!("age at {} is not a valid age: {}", pointer, value)),
+            }
+        }
+    };
+
+    Ok(CreateUserRequest {
+        username: None,
+        name: text(&fields.name, "name")?,
+        email: text(&fields.email, "email")?.to_lowercase(),
+        age,
+        password: None,
+    })
+}
+
+// `$NAME` reads the environment variable NAME, anything else is used as is
+fn resolve(value: &str) -> Result<String, String> {
+    match value.strip_prefix('$') {
+        Some(name) => std::env::var(name).map_err(|_| format!("Environment variable {} is not set", name)),
+        None => Ok(value.to_string()),
+    }
+}
@@ -0,0 +1,167 @@
+use std::error::Error as StdError;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use uuid::Uuid;
+
+use crate::models::public_id;
+use crate::models::user::{Role, UpdateUserRequest, User};
+use crate::repositories::user_repo::CachedUserRepository;
+
+const HELP: &str = "Commands:
+  get <id>                          show a user
+  find email=<email>                look a user up by email
+  find username=<username>          look a user up by username
+  update <id> [username=..] [name=..] [email=..] [age=..]
+                                    change fields, quote values with spaces
+  role <id> admin|user|readonly     set a user's role, e.g. to make the first admin
+  help                              show this list
+  quit                              leave the console";
+
+// Interactive console over the repository layer, started with `hello_world console`.
+// Reads go through the same cache as the HTTP API and every change is written to
+// the audit log with the operating system user that made it.
+pub async fn run(users: &CachedUserRepository) -> Result<(), Box<dyn StdError>> {
+    let operator = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+
+    println!("hello_world console, type `help` for commands");
+    loop {
+        stdout.write_all(b"> ").await?;
+        stdout.flush().await?;
+
+        let line = match lines.next_line().await? {
+            Some(line) => line,
+            None => break,
+        };
+        let args = match split_args(&line) {
+            Ok(args) => args,
+            Err(e) => {
+                println!("error: {}", e);
+                continue;
+            }
+        };
+
+        let result = match args.first().map(String::as_str) {
+            None => continue,
+            Some("quit") | Some("exit") => break,
+            Some("help") => {
+                println!("{}", HELP);
+                continue;
+            }
+            Some("get") => get(users, &args[1..]).await,
+            Some("find") => find(users, &args[1..]).await,
+            Some("update") => update(users, &operator, &args[1..]).await,
+            Some("role") => role(users, &operator, &args[1..]).await,
+            Some(other) => Err(format!("unknown command `{}`, try `help`", other).into()),
+        };
+
+        if let Err(e) = result {
+            println!("error: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+async fn get(users: &CachedUserRepository, args: &[String]) -> Result<(), Box<dyn StdError>> {
+    let id = match args {
+        [id] => parse_id(id)?,
+        _ => return Err("usage: get <id>".into()),
+    };
+    print_user(users.get_by_id(&id).await?)
+}
+
+async fn find(users: &CachedUserRepository, args: &[String]) -> Result<(), Box<dyn StdError>> {
+    match args {
+        [arg] => match arg.split_once('=') {
+            Some(("email", email)) => print_user(users.get_by_email(email).await?),
+            Some(("username", username)) => print_user(users.get_by_username(username).await?),
+            _ => Err("only email=<email> and username=<username> are supported".into()),
+        },
+        _ => Err("usage: find email=<email> | username=<username>".into()),
+    }
+}
+
+async fn update(users: &CachedUserRepository, operator: &str, args: &[String]) -> Result<(), Box<dyn StdError>> {
+    let (id, fields) = match args {
+        [id, fields @ ..] if !fields.is_empty() => (parse_id(id)?, fields),
+        _ => return Err("usage: update <id> name=.. email=.. age=..".into()),
+    };
+
+    let mut user_req = UpdateUserRequest { username: None, name: None, email: None, age: None, version: None };
+    for field in fields {
+        match field.split_once('=') {
+            Some(("username", username)) => user_req.username = Some(username.to_string()),
+            Some(("name", name)) => user_req.name = Some(name.to_string()),
+            Some(("email", email)) => user_req.email = Some(email.to_string()),
+            Some(("age", age)) => user_req.age = Some(age.parse().map_err(|_| format!("invalid age `{}`", age))?),
+            _ => return Err(format!("unknown field `{}`, expected username=, name=, email= or age=", field).into()),
+        }
+    }
+
+    let updated = users.update(&id, &user_req).await?;
+    if updated.is_some() {
+        log::info!(target: "audit", "Console user {} updated user {}: {:?}", operator, id, user_req);
+    }
+    print_user(updated)
+}
+
+async fn role(users: &CachedUserRepository, operator: &str, args: &[String]) -> Result<(), Box<dyn StdError>> {
+    let (id, role) = match args {
+        [id, role] => (parse_id(id)?, Role::parse(role).ok_or_else(|| format!("unknown role `{}`", role))?),
+        _ => return Err("usage: role <id> admin|user|readonly".into()),
+    };
+
+    let updated = users.set_role(&id, role).await?;
+    if updated.is_some() {
+        log::info!(target: "audit", "Console user {} gave user {} the {} role", operator, id, role.as_str());
+    }
+    print_user(updated)
+}
+
+fn parse_id(id: &str) -> Result<Uuid, Box<dyn StdError>> {
+    public_id::decode(id).ok_or_else(|| format!("invalid user id `{}`", id).into())
+}
+
+fn print_user(user: Option<User>) -> Result<(), Box<dyn StdError>> {
+    match user {
+        Some(user) => println!("{}", serde_json::to_string_pretty(&user)?),
+        None => println!("not found"),
+    }
+    Ok(())
+}
+
+// Split a line on whitespace, keeping double-quoted parts (name="Jane Doe") together
+fn split_args(line: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut pending = false;
+
+    for c in line.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                pending = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if pending {
+                    args.push(std::mem::take(&mut current));
+                    pending = false;
+                }
+            }
+            c => {
+                current.push(c);
+                pending = true;
+            }
+        }
+    }
+
+    if in_quotes {
+        return Err("unterminated quote".to_string());
+    }
+    if pending {
+        args.push(current);
+    }
+    Ok(args)
+}
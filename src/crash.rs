@@ -0,0 +1,244 @@
+use actix_web::dev::ServiceResponse;
+use actix_web::http::Method;
+use chrono::{SecondsFormat, Utc};
+use serde_json::{json, Map, Value};
+use std::backtrace::Backtrace;
+use std::future::Future;
+use std::panic::PanicHookInfo;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::egress;
+use crate::request_id;
+
+// Reports waiting to be sent before new ones are dropped
+const QUEUE_CAPACITY: usize = 100;
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+// Values under these keys never leave the process
+const PII_KEYS: &[&str] = &["email", "name", "username", "password", "token", "authorization", "cookie", "sig"];
+const FILTERED: &str = "[Filtered]";
+
+static REPORTER: OnceLock<Reporter> = OnceLock::new();
+
+tokio::task_local! {
+    static ROUTE: Option<String>;
+}
+
+// Where a Sentry-compatible server (Sentry, GlitchTip, ...) accepts events,
+// from a DSN like `https://<public key>@sentry.example.com/<project id>`
+#[derive(Debug, Clone)]
+pub struct Dsn {
+    store_url: String,
+    public_key: String,
+}
+
+impl Dsn {
+    pub fn parse(dsn: &str) -> Result<Self, String> {
+        let url = reqwest::Url::parse(dsn).map_err(|e| format!("Invalid SENTRY_DSN: {}", e))?;
+        if url.username().is_empty() {
+            return Err("SENTRY_DSN has no public key".to_string());
+        }
+
+        let path = url.path().trim_end_matches('/');
+        let (prefix, project) = path.rsplit_once('/').unwrap_or(("", path));
+        if project.is_empty() {
+            return Err("SENTRY_DSN has no project id".to_string());
+        }
+
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        Ok(Self {
+            store_url: format!("{}://{}{}/api/{}/store/", url.scheme(), host, prefix, project),
+            public_key: url.username().to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CrashConfig {
+    pub dsn: Dsn,
+    // Fraction of events sent, between 0 and 1
+    pub sample_rate: f64,
+    pub release: String,
+    pub environment: Option<String>,
+}
+
+struct Reporter {
+    events: mpsc::Sender<Value>,
+    config: CrashConfig,
+}
+
+// Start capturing panics and server errors. Events are sent from a background
+// task, so a panic that takes the whole process down may not make it out.
+pub fn init(config: CrashConfig) {
+    let (events, mut receiver) = mpsc::channel::<Value>(QUEUE_CAPACITY);
+    let dsn = config.dsn.clone();
+    if REPORTER.set(Reporter { events, config }).is_err() {
+        return;
+    }
+
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        capture_panic(info);
+        default_hook(info);
+    }));
+
+    actix_web::rt::spawn(async move {
+        let client = egress::client();
+        let auth = format!(
+            "Sentry sentry_version=7, sentry_client=hello_world/{}, sentry_key={}",
+            env!("CARGO_PKG_VERSION"),
+            dsn.public_key
+        );
+
+        while let Some(event) = receiver.recv().await {
+            let sent = client
+                .post(&dsn.store_url)
+                .header("X-Sentry-Auth", &auth)
+                .timeout(SEND_TIMEOUT)
+                .json(&event)
+                .send()
+                .await;
+            if let Err(e) = sent.and_then(|response| response.error_for_status()) {
+                log::warn!("Failed to send crash report {}: {}", event["event_id"], e);
+            }
+        }
+    });
+}
+
+// Run a request with its route pattern as the current route, reporting it when
+// it ends in a server error
+pub async fn track<B, F>(route: Option<String>, method: Method, fut: F) -> F::Output
+where
+    F: Future<Output = Result<ServiceResponse<B>, actix_web::Error>>,
+{
+    ROUTE
+        .scope(route, async move {
+            let result = fut.await;
+            if REPORTER.get().is_some() {
+                report_server_error(&method, &result);
+            }
+            result
+        })
+        .await
+}
+
+fn report_server_error<B>(method: &Method, result: &Result<ServiceResponse<B>, actix_web::Error>) {
+    let (status, error) = match result {
+        Ok(res) => (res.status(), res.response().error().map(ToString::to_string)),
+        Err(e) => (e.as_response_error().status_code(), Some(e.to_string())),
+    };
+    if !status.is_server_error() {
+        return;
+    }
+
+    let message = error.unwrap_or_else(|| status.to_string());
+    capture(
+        "error",
+        json!({
+            "message": { "formatted": message },
+            "tags": { "method": method.as_str(), "status": status.as_str() },
+        }),
+    );
+}
+
+fn capture_panic(info: &PanicHookInfo) {
+    let payload = info.payload();
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Box<dyn Any>".to_string());
+    let location = info.location().map(|location| format!("{}:{}", location.file(), location.line()));
+
+    capture(
+        "fatal",
+        json!({
+            "exception": { "values": [{
+                "type": "panic",
+                "value": message,
+                "mechanism": { "type": "panic", "handled": false },
+            }] },
+            "extra": { "location": location, "backtrace": Backtrace::force_capture().to_string() },
+        }),
+    );
+}
+
+// Complete the event with what every report carries, scrub it and queue it
+fn capture(level: &str, mut event: Value) {
+    let Some(reporter) = REPORTER.get() else { return };
+    if rand::random::<f64>() >= reporter.config.sample_rate {
+        return;
+    }
+
+    let fields = event.as_object_mut().expect("events are JSON objects");
+    let tags = fields.entry("tags").or_insert_with(|| Value::Object(Map::new()));
+    if let Some(id) = request_id::current() {
+        tags["request_id"] = Value::String(id);
+    }
+    if let Some(route) = ROUTE.try_with(Clone::clone).ok().flatten() {
+        tags["route"] = Value::String(route);
+    }
+    fields.insert("event_id".to_string(), json!(Uuid::new_v4().simple().to_string()));
+    fields.insert("timestamp".to_string(), json!(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)));
+    fields.insert("level".to_string(), json!(level));
+    fields.insert("platform".to_string(), json!("rust"));
+    fields.insert("release".to_string(), json!(reporter.config.release));
+    if let Some(environment) = &reporter.config.environment {
+        fields.insert("environment".to_string(), json!(environment));
+    }
+
+    scrub(&mut event);
+    if reporter.events.try_send(event).is_err() {
+        log::warn!("Crash report queue full, dropped a report");
+    }
+}
+
+// Replace PII fields and any email address inside strings
+fn scrub(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields.iter_mut() {
+                if PII_KEYS.contains(&key.to_ascii_lowercase().as_str()) {
+                    *value = Value::String(FILTERED.to_string());
+                } else {
+                    scrub(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(scrub),
+        Value::String(text) if text.contains('@') => *text = mask_emails(text),
+        _ => {}
+    }
+}
+
+fn mask_emails(text: &str) -> String {
+    let is_local = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '%' | '+' | '-');
+    let is_domain = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '-');
+
+    let mut masked = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find('@') {
+        let start = rest[..at].rfind(|c| !is_local(c)).map_or(0, |i| i + 1);
+        let domain = &rest[at + 1..];
+        let end = at + 1 + domain.find(|c| !is_domain(c)).unwrap_or(domain.len());
+        let domain = rest[at + 1..end].trim_end_matches('.');
+
+        // A dot and a top-level domain of letters, so "name@1.2.3" versions are left alone
+        let tld = domain.rsplit_once('.').map_or("", |(_, tld)| tld);
+        if start < at && tld.len() >= 2 && tld.chars().all(|c| c.is_ascii_alphabetic()) {
+            masked.push_str(&rest[..start]);
+            masked.push_str("[email]");
+            rest = &rest[at + 1 + domain.len()..];
+        } else {
+            masked.push_str(&rest[..=at]);
+            rest = &rest[at + 1..];
+        }
+    }
+    masked.push_str(rest);
+    masked
+}
@@ -0,0 +1,173 @@
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderValue, CONTENT_TYPE};
+use actix_web::http::{Method, StatusCode};
+use actix_web::{web, HttpResponse};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use crate::client_ip;
+
+// Header added to responses replayed from an earlier identical request
+pub const REPLAY_HEADER: &str = "x-deduplicated";
+
+// Response of the first request, handed to identical requests in the window
+#[derive(Clone)]
+struct StoredResponse {
+    status: StatusCode,
+    content_type: Option<HeaderValue>,
+    body: web::Bytes,
+}
+
+type Key = (String, [u8; 32]);
+
+struct Entry {
+    started: Instant,
+    // None until the first request has finished
+    response: watch::Receiver<Option<StoredResponse>>,
+}
+
+// Short window in which byte-identical POST /users bodies from the same client
+// are answered with the first request's response instead of creating another
+// user. Catches double clicks and resubmits from the web UI; clients that need
+// real retry safety should use idempotency keys. Clones share their entries.
+#[derive(Clone)]
+pub struct DedupWindow {
+    window: Duration,
+    entries: Arc<Mutex<HashMap<Key, Entry>>>,
+}
+
+impl DedupWindow {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for DedupWindow
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Transform = DedupMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(DedupMiddleware {
+            service: Rc::new(service),
+            window: self.window,
+            entries: self.entries.clone(),
+        }))
+    }
+}
+
+pub struct DedupMiddleware<S> {
+    service: Rc<S>,
+    window: Duration,
+    entries: Arc<Mutex<HashMap<Key, Entry>>>,
+}
+
+impl<S, B> Service<ServiceRequest> for DedupMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+
+        if req.method() != Method::POST || req.path() != "/users" {
+            return Box::pin(async move { Ok(service.call(req).await?.map_into_boxed_body()) });
+        }
+
+        let window = self.window;
+        let entries = self.entries.clone();
+
+        Box::pin(async move {
+            // Buffer the body to hash it, then hand it back to the handler
+            let bytes = req.extract::<web::Bytes>().await?;
+            let principal = client_ip::of(req.request()).map_or_else(|| "unknown".to_string(), |ip| ip.to_string());
+            let key = (principal, Sha256::digest(&bytes).into());
+            req.set_payload(Payload::from(bytes));
+
+            // Either join an identical request in the window or become the first one
+            let sender = {
+                let mut entries = entries.lock();
+                entries.retain(|_, entry| entry.started.elapsed() < window);
+
+                match entries.get(&key) {
+                    Some(entry) => Err(entry.response.clone()),
+                    None => {
+                        let (sender, receiver) = watch::channel(None);
+                        entries.insert(key.clone(), Entry { started: Instant::now(), response: receiver });
+                        Ok(sender)
+                    }
+                }
+            };
+
+            let sender = match sender {
+                Ok(sender) => sender,
+                Err(mut receiver) => {
+                    // The first request may still be running. If it failed without
+                    // storing anything this one is handled normally.
+                    if let Ok(stored) = receiver.wait_for(Option::is_some).await {
+                        let stored = stored.clone().expect("waited for a stored response");
+                        log::info!("Replaying response to duplicate POST /users from {}", key.0);
+                        let mut response = HttpResponse::build(stored.status);
+                        if let Some(content_type) = stored.content_type {
+                            response.insert_header((CONTENT_TYPE, content_type));
+                        }
+                        response.insert_header((REPLAY_HEADER, "true"));
+                        return Ok(req.into_response(response.body(stored.body)));
+                    }
+                    return Ok(service.call(req).await?.map_into_boxed_body());
+                }
+            };
+
+            let res = match service.call(req).await {
+                Ok(res) => res,
+                Err(e) => {
+                    entries.lock().remove(&key);
+                    return Err(e);
+                }
+            };
+
+            // Server errors aren't replayed, a resubmit should get a fresh attempt
+            if res.status().is_server_error() {
+                entries.lock().remove(&key);
+                return Ok(res.map_into_boxed_body());
+            }
+
+            let status = res.status();
+            let content_type = res.headers().get(CONTENT_TYPE).cloned();
+            let (req, res) = res.into_parts();
+            let (res, body) = res.into_parts();
+            let body = match body::to_bytes(body).await {
+                Ok(body) => body,
+                Err(e) => {
+                    entries.lock().remove(&key);
+                    return Err(actix_web::error::ErrorInternalServerError(e.into().to_string()));
+                }
+            };
+
+            sender.send_replace(Some(StoredResponse { status, content_type, body: body.clone() }));
+            Ok(ServiceResponse::new(req, res.set_body(BoxBody::new(body))))
+        })
+    }
+}
@@ -0,0 +1,73 @@
+use actix_web::web;
+use std::error::Error as StdError;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::errors::AppError;
+use crate::models::user::CreateUserRequest;
+use crate::repositories::user_repo::CachedUserRepository;
+
+// Whether the instance is a public demo, checked by handlers that destroy data
+pub struct DemoMode(pub bool);
+
+const FIRST_NAMES: [&str; 10] = [
+    "Ada", "Ben", "Chloe", "Dev", "Elena", "Farid", "Grace", "Hiro", "Ines", "Jonas",
+];
+const LAST_NAMES: [&str; 5] = ["Ahmed", "Brown", "Costa", "Dubois", "Eriksen"];
+
+// One age per band (18-24, 25-34, ... 65+), cycled through the fixtures.
+// Every tenth user has no age so the optional field shows up in the demo too.
+const AGES: [u8; 6] = [21, 29, 38, 47, 59, 71];
+
+// The fixed demo dataset: 50 users with stable ids, usernames, names, emails and ages, so
+// links shared from the demo keep working across resets
+pub fn fixtures() -> Vec<(Uuid, CreateUserRequest)> {
+    let mut users = Vec::with_capacity(FIRST_NAMES.len() * LAST_NAMES.len());
+
+    for (i, last) in LAST_NAMES.iter().enumerate() {
+        for (j, first) in FIRST_NAMES.iter().enumerate() {
+            let n = i * FIRST_NAMES.len() + j;
+            let age = if n % 10 == 9 { None } else { Some(AGES[n % AGES.len()]) };
+
+            users.push((
+                Uuid::from_u128(0xdeadbeef_0000_4000_8000_000000000000 | (n as u128 + 1)),
+                CreateUserRequest {
+                    username: Some(format!("{}-{}", first.to_lowercase(), last.to_lowercase())),
+                    name: format!("{} {}", first, last),
+                    email: format!("{}.{}@demo.example", first.to_lowercase(), last.to_lowercase()),
+                    age,
+                    password: None,
+                },
+            ));
+        }
+    }
+
+    users
+}
+
+// Response for destructive requests against a demo instance
+pub fn forbidden() -> AppError {
+    AppError::forbidden("Deleting is disabled on the demo instance")
+}
+
+// Throw away whatever visitors changed and load the fixtures again
+pub async fn reset(users: &CachedUserRepository) -> Result<(), Box<dyn StdError>> {
+    let inserted = users.replace_all(&fixtures()).await?;
+    log::info!("Demo data reset, {} users loaded", inserted);
+    Ok(())
+}
+
+// Reset the demo data every `interval` for as long as the server runs
+pub fn spawn_reset_loop(users: web::Data<CachedUserRepository>, interval: Duration) {
+    actix_web::rt::spawn(async move {
+        let mut ticker = actix_web::rt::time::interval(interval);
+        // The first tick fires immediately, startup has just reset the data
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            if let Err(e) = reset(&users).await {
+                log::error!("Failed to reset demo data: {}", e);
+            }
+        }
+    });
+}
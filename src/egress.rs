@@ -0,0 +1,153 @@
+use reqwest::Url;
+use std::error::Error as StdError;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
+
+static CONFIG: OnceLock<EgressConfig> = OnceLock::new();
+
+// How outbound HTTP calls leave the network: through the proxies from
+// HTTP_PROXY/HTTPS_PROXY (minus NO_PROXY), with per-host overrides, and which
+// internal networks webhooks may still be sent to.
+#[derive(Debug, Clone, Default)]
+pub struct EgressConfig {
+    pub http_proxy: Option<Url>,
+    pub https_proxy: Option<Url>,
+    // Hosts and domain suffixes reached directly
+    pub no_proxy: Vec<String>,
+    // First match wins over the proxies above, None connects directly
+    pub overrides: Vec<(String, Option<Url>)>,
+    pub allowed_networks: Vec<Network>,
+}
+
+// An address range in CIDR notation, a bare address being a /32 or /128
+#[derive(Debug, Clone, Copy)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value, None),
+        };
+        let addr: IpAddr = addr.trim().parse().map_err(|_| format!("{} is not an IP address or CIDR range", value))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().ok().filter(|prefix| *prefix <= max).ok_or_else(|| format!("{} has an invalid prefix length", value))?,
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+// Called once at startup, before any client is built
+pub fn init(config: EgressConfig) {
+    if CONFIG.set(config).is_err() {
+        log::warn!("Egress settings were already initialized");
+    }
+}
+
+fn config() -> &'static EgressConfig {
+    CONFIG.get_or_init(EgressConfig::default)
+}
+
+// HTTP client for calls to other services, going through the configured proxies
+pub fn client() -> reqwest::Client {
+    match builder().build() {
+        Ok(client) => client,
+        Err(e) => {
+            log::error!("Failed to build HTTP client with proxy settings, connecting directly: {}", e);
+            reqwest::Client::new()
+        }
+    }
+}
+
+fn builder() -> reqwest::ClientBuilder {
+    let config = config();
+    // A proxy of our own turns off reqwest's reading of the environment, the config has those
+    let proxy = reqwest::Proxy::custom(move |url| proxy_for(config, url));
+    reqwest::Client::builder().proxy(proxy)
+}
+
+fn proxy_for(config: &EgressConfig, url: &Url) -> Option<Url> {
+    let host = url.host_str()?.to_ascii_lowercase();
+    if let Some((_, proxy)) = config.overrides.iter().find(|(pattern, _)| matches_host(pattern, &host)) {
+        return proxy.clone();
+    }
+    if config.no_proxy.iter().any(|pattern| pattern == "*" || matches_host(pattern, &host)) {
+        return None;
+    }
+    match url.scheme() {
+        "https" => config.https_proxy.clone().or_else(|| config.http_proxy.clone()),
+        _ => config.http_proxy.clone(),
+    }
+}
+
+// `example.com` and `.example.com` match the domain and everything under it
+fn matches_host(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.trim_start_matches('.');
+    host == pattern || host.strip_suffix(pattern).is_some_and(|rest| rest.ends_with('.'))
+}
+
+// Client for one delivery to a webhook URL. Refuses hosts that are or resolve
+// to a loopback, private, link-local or otherwise internal address outside
+// WEBHOOK_ALLOWED_NETWORKS, so a webhook setting can't be pointed at services
+// inside our network. The client connects to the addresses that were checked
+// rather than resolving the host again, and doesn't follow redirects, so
+// neither a DNS answer that changes in between nor a 3xx can get around it.
+pub async fn webhook_client(url: &str) -> Result<reqwest::Client, Box<dyn StdError>> {
+    let url = Url::parse(url)?;
+    let host = url.host_str().ok_or("Webhook URL has no host")?;
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    let addrs: Vec<SocketAddr> = match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => tokio::net::lookup_host((host, port)).await?.collect(),
+    };
+    if addrs.is_empty() {
+        return Err(format!("Webhook host {} did not resolve", host).into());
+    }
+    let allowed = &config().allowed_networks;
+    if let Some(addr) = addrs.iter().find(|addr| is_internal(&addr.ip()) && !allowed.iter().any(|network| network.contains(&addr.ip()))) {
+        return Err(format!("Webhook host {} resolves to internal address {}, add it to WEBHOOK_ALLOWED_NETWORKS to allow it", host, addr.ip()).into());
+    }
+
+    Ok(builder().resolve_to_addrs(host, &addrs).redirect(reqwest::redirect::Policy::none()).build()?)
+}
+
+fn is_internal(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // 0.0.0.0/8 and carrier-grade NAT 100.64.0.0/10
+                || octets[0] == 0
+                || (octets[0] == 100 && (octets[1] & 0xc0) == 64)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_internal(&IpAddr::V4(ip)),
+            // Unique local fc00::/7 and link-local fe80::/10
+            None => ip.is_loopback() || ip.is_unspecified() || (ip.segments()[0] & 0xfe00) == 0xfc00 || (ip.segments()[0] & 0xffc0) == 0xfe80,
+        },
+    }
+}
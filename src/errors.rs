@@ -0,0 +1,263 @@
+use actix_web::http::header::{self, HeaderMap, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use std::borrow::Cow;
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::extractors::problem;
+use crate::models::tenant::TenantSettings;
+use crate::models::validate::FieldError;
+use crate::repositories::user_store::VersionConflict;
+use crate::request_id;
+use crate::templates;
+
+// Error type of every handler. Client errors carry the message shown to the
+// caller; server-side failures keep their cause for the log and only show a
+// short description. Bodies are problem+json like the extractors produce, with
+// the message as `detail` and, for validation errors, the offending fields.
+// Requests that rank HTML first, browsers, get an error page instead, see negotiate.
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(Cow<'static, str>),
+    Unauthorized(Cow<'static, str>),
+    Forbidden(Cow<'static, str>),
+    NotFound(Cow<'static, str>),
+    Conflict(Cow<'static, str>),
+    // A write that has to name the version it was made against didn't
+    PreconditionRequired(Cow<'static, str>),
+    Validation {
+        detail: &'static str,
+        errors: Vec<FieldError>,
+    },
+    TooManyRequests {
+        message: String,
+        retry_after: Duration,
+    },
+    // A failed query or anything else that isn't the caller's fault
+    Database {
+        message: Cow<'static, str>,
+        source: Box<dyn StdError>,
+    },
+}
+
+impl AppError {
+    pub fn bad_request(message: impl Into<Cow<'static, str>>) -> Self {
+        Self::BadRequest(message.into())
+    }
+
+    pub fn unauthorized(message: impl Into<Cow<'static, str>>) -> Self {
+        Self::Unauthorized(message.into())
+    }
+
+    pub fn forbidden(message: impl Into<Cow<'static, str>>) -> Self {
+        Self::Forbidden(message.into())
+    }
+
+    pub fn not_found(message: impl Into<Cow<'static, str>>) -> Self {
+        Self::NotFound(message.into())
+    }
+
+    pub fn conflict(message: impl Into<Cow<'static, str>>) -> Self {
+        Self::Conflict(message.into())
+    }
+
+    pub fn precondition_required(message: impl Into<Cow<'static, str>>) -> Self {
+        Self::PreconditionRequired(message.into())
+    }
+
+    // A failed repository call, a 500 showing `message` unless the caller is
+    // at fault: an update against a stale version is a 409
+    fn repository(message: Cow<'static, str>, source: Box<dyn StdError>) -> Self {
+        match source.downcast::<VersionConflict>() {
+            Ok(conflict) => Self::Conflict(conflict.to_string().into()),
+            Err(source) => Self::Database { message, source },
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadRequest(message) | Self::Unauthorized(message) | Self::Forbidden(message) | Self::NotFound(message) | Self::Conflict(message)
+            | Self::PreconditionRequired(message) => {
+                f.write_str(message)
+            }
+            Self::Validation { detail, .. } => f.write_str(detail),
+            Self::TooManyRequests { message, .. } => f.write_str(message),
+            Self::Database { message, source } => write!(f, "{}: {}", message, source),
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::PreconditionRequired(_) => StatusCode::PRECONDITION_REQUIRED,
+            Self::Validation { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::Database { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let branding = ERROR_PAGES.try_with(Option::clone).ok().flatten();
+        self.respond(branding.as_ref(), request_id::current().as_deref())
+    }
+}
+
+impl AppError {
+    // A problem+json body, or a page branded with `branding` when there is one
+    fn respond(&self, branding: Option<&TenantSettings>, request_id: Option<&str>) -> HttpResponse {
+        let status = self.status_code();
+        let (message, errors) = match self {
+            Self::Validation { detail, errors } => (*detail, errors.as_slice()),
+            Self::TooManyRequests { message, .. } => (message.as_str(), [].as_slice()),
+            Self::Database { message, source } => {
+                log::error!("{}: {}", message, source);
+                (message.as_ref(), [].as_slice())
+            }
+            Self::BadRequest(message) | Self::Unauthorized(message) | Self::Forbidden(message) | Self::NotFound(message) | Self::Conflict(message)
+            | Self::PreconditionRequired(message) => {
+                (message.as_ref(), [].as_slice())
+            }
+        };
+
+        let mut response = match branding {
+            Some(branding) => HttpResponse::build(status)
+                .content_type("text/html; charset=utf-8")
+                .body(templates::error::error_page(status, message, errors, request_id, branding)),
+            None => {
+                let (title, extra) = match self {
+                    Self::Validation { errors, .. } => ("Validation failed", serde_json::json!({ "errors": errors })),
+                    _ => (status.canonical_reason().unwrap_or("Error"), serde_json::json!({})),
+                };
+                problem(status, title, message, extra)
+            }
+        };
+        if let Self::TooManyRequests { retry_after, .. } = self {
+            let retry_after = HeaderValue::from(retry_after.as_secs().max(1));
+            response.headers_mut().insert(header::RETRY_AFTER, retry_after);
+        }
+        response
+    }
+}
+
+tokio::task_local! {
+    // Branding for error pages while the request being handled wants HTML
+    static ERROR_PAGES: Option<TenantSettings>;
+}
+
+// Run `f` with its errors rendered as pages branded with `branding`, or as JSON
+// when it is None. Errors middleware returns instead of a response are only
+// rendered once they have left the scope, so they take the branding and the
+// request id along.
+pub async fn negotiate<T, F>(branding: Option<TenantSettings>, f: F) -> Result<T, actix_web::Error>
+where
+    F: Future<Output = Result<T, actix_web::Error>>,
+{
+    let request_id = request_id::current();
+    let result = ERROR_PAGES.scope(branding.clone(), f).await;
+    match branding {
+        Some(branding) => result.map_err(|error| ErrorPage { error, branding, request_id }.into()),
+        None => result,
+    }
+}
+
+// An error from an inner service that becomes a page if it is an AppError.
+// Others, like the extractors' problem+json responses, keep their body.
+struct ErrorPage {
+    error: actix_web::Error,
+    branding: TenantSettings,
+    request_id: Option<String>,
+}
+
+impl fmt::Debug for ErrorPage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.error, f)
+    }
+}
+
+impl fmt::Display for ErrorPage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl ResponseError for ErrorPage {
+    fn status_code(&self) -> StatusCode {
+        self.error.as_response_error().status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self.error.as_error::<AppError>() {
+            Some(error) => error.respond(Some(&self.branding), self.request_id.as_deref()),
+            None => self.error.error_response(),
+        }
+    }
+}
+
+// Repository errors become 500s with a generic message, see Context for a better one
+impl From<Box<dyn StdError>> for AppError {
+    fn from(source: Box<dyn StdError>) -> Self {
+        Self::repository(Cow::Borrowed("Internal server error"), source)
+    }
+}
+
+// Attach the message clients see when a repository call fails, as in
+// `repo.get_by_id(&id).await.context("Failed to retrieve user")?`
+pub trait Context<T> {
+    fn context(self, message: impl Into<Cow<'static, str>>) -> Result<T, AppError>;
+}
+
+impl<T> Context<T> for Result<T, Box<dyn StdError>> {
+    fn context(self, message: impl Into<Cow<'static, str>>) -> Result<T, AppError> {
+        self.map_err(|source| AppError::repository(message.into(), source))
+    }
+}
+
+// Error from an inner service plus headers for the response it becomes. For
+// middleware that must add headers to error responses: building the response
+// itself would need a clone of the request, and routing panics unless it is
+// the request's only owner.
+pub struct WithHeaders {
+    error: actix_web::Error,
+    headers: HeaderMap,
+}
+
+pub fn with_headers(error: actix_web::Error, headers: HeaderMap) -> actix_web::Error {
+    WithHeaders { error, headers }.into()
+}
+
+impl fmt::Debug for WithHeaders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.error, f)
+    }
+}
+
+impl fmt::Display for WithHeaders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl ResponseError for WithHeaders {
+    fn status_code(&self) -> StatusCode {
+        self.error.as_response_error().status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = self.error.error_response();
+        for (name, value) in &self.headers {
+            response.headers_mut().append(name.clone(), value.clone());
+        }
+        response
+    }
+}
@@ -0,0 +1,98 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
+
+use crate::models::user::User;
+use crate::request_id;
+
+// Events a subscriber may fall behind by before it starts missing them
+const CAPACITY: usize = 1024;
+
+// What happened to the data, with the rows as they were and as they are now.
+// Only users so far, events for other entities will go next to these. An update
+// carries two users, not worth a box for one event per write.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[allow(clippy::enum_variant_names, clippy::large_enum_variant)]
+pub enum DomainEvent {
+    UserCreated { user: User },
+    UserUpdated { before: User, after: User },
+    UserDeleted { user: User },
+    // A deleted user was brought back as they were
+    UserRestored { user: User },
+}
+
+impl DomainEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::UserCreated { .. } => "user_created",
+            Self::UserUpdated { .. } => "user_updated",
+            Self::UserDeleted { .. } => "user_deleted",
+            Self::UserRestored { .. } => "user_restored",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Envelope {
+    pub id: Uuid,
+    pub occurred_at: DateTime<Utc>,
+    // Of the request that caused the event, None for background jobs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(flatten)]
+    pub event: DomainEvent,
+}
+
+// In-process fan-out of domain events, every subscriber gets every event
+// published after it subscribed. Publishing never waits: a subscriber more than
+// CAPACITY events behind skips the oldest ones and is told how many it missed.
+// Clones publish to the same subscribers.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Arc<Envelope>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self { sender: broadcast::channel(CAPACITY).0 }
+    }
+}
+
+impl EventBus {
+    pub fn publish(&self, event: DomainEvent) {
+        let envelope = Envelope {
+            id: Uuid::new_v4(),
+            occurred_at: Utc::now(),
+            request_id: request_id::current(),
+            event,
+        };
+        // Only fails without subscribers, then nobody is interested
+        let _ = self.sender.send(Arc::new(envelope));
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Envelope>> {
+        self.sender.subscribe()
+    }
+
+    // Run `handle` for every event from now on, one at a time, in a task of its own
+    pub fn spawn_subscriber<F, Fut>(&self, name: &'static str, mut handle: F)
+    where
+        F: FnMut(Arc<Envelope>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        let mut receiver = self.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(envelope) => handle(envelope).await,
+                    Err(RecvError::Lagged(missed)) => log::warn!("Event subscriber {} fell behind and missed {} events", name, missed),
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+}
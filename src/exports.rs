@@ -0,0 +1,402 @@
+use actix_web::web::{self, Bytes};
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use futures_util::stream::{self, Stream};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use uuid::Uuid;
+
+use crate::clock::Clock;
+use crate::models::public_id;
+use crate::models::user::{User, UserFilter};
+use crate::rebuild::JobStatus;
+use crate::repositories::user_store::UserStore;
+use crate::signing::Signer;
+
+// Finished jobs kept for GET /users/exports/{id}, the oldest are dropped first
+const MAX_FINISHED_JOBS: usize = 100;
+
+// Users read per query while writing an export
+const PAGE_SIZE: i64 = 1000;
+
+// Read size when streaming an artifact back
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Spreadsheets read a field starting with one of these as a formula
+const FORMULA_PREFIXES: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+// Marks the file as UTF-8 for Excel, which otherwise reads it in the system's legacy code page
+const UTF8_BOM: &str = "\u{feff}";
+
+#[derive(Debug, Clone)]
+pub struct ExportConfig {
+    // Where finished exports are written, created when the first one runs
+    pub dir: PathBuf,
+    // How long a download link keeps working
+    pub link_ttl_secs: u64,
+    // How long a finished export stays downloadable before its file is removed
+    pub retention: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    // One JSON user per line, like POST /users/import reads
+    Ndjson,
+}
+
+impl ExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Column {
+    Id,
+    Username,
+    Name,
+    Email,
+    Age,
+    Role,
+    CreatedAt,
+    UpdatedAt,
+}
+
+impl Column {
+    const ALL: [Column; 8] = [
+        Self::Id,
+        Self::Username,
+        Self::Name,
+        Self::Email,
+        Self::Age,
+        Self::Role,
+        Self::CreatedAt,
+        Self::UpdatedAt,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Id => "id",
+            Self::Username => "username",
+            Self::Name => "name",
+            Self::Email => "email",
+            Self::Age => "age",
+            Self::Role => "role",
+            Self::CreatedAt => "created_at",
+            Self::UpdatedAt => "updated_at",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|column| column.name() == name)
+    }
+
+    fn value(&self, user: &User) -> String {
+        match self {
+            Self::Id => public_id::encode(&user.id),
+            Self::Username => user.username.clone().unwrap_or_default(),
+            Self::Name => user.name.clone(),
+            Self::Email => user.email.clone(),
+            Self::Age => user.age.map(|age| age.to_string()).unwrap_or_default(),
+            Self::Role => user.role.as_str().to_string(),
+            Self::CreatedAt => user.created_at.to_rfc3339(),
+            Self::UpdatedAt => user.updated_at.to_rfc3339(),
+        }
+    }
+}
+
+// How a CSV export is laid out. The defaults are RFC 4180 with every column;
+// Excel in locales with a decimal comma wants `;` and a BOM. Whatever the
+// options, a field that would open as a formula (a name like `=HYPERLINK(..)`)
+// is written as text: prefixed with `'` and quoted.
+#[derive(Debug, Clone, Serialize)]
+pub struct CsvOptions {
+    pub delimiter: char,
+    pub bom: bool,
+    // Quote every header field, not only those that need it
+    pub quote_headers: bool,
+    // In this order
+    pub columns: Vec<Column>,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self { delimiter: ',', bom: false, quote_headers: false, columns: Column::ALL.to_vec() }
+    }
+}
+
+impl CsvOptions {
+    // `columns` is a comma-separated list of column names, all of them when None
+    pub fn new(delimiter: Option<char>, bom: bool, quote_headers: bool, columns: Option<&str>) -> Result<Self, String> {
+        let delimiter = delimiter.unwrap_or(',');
+        if matches!(delimiter, '"' | '\r' | '\n') || !delimiter.is_ascii() {
+            return Err(format!("delimiter must be an ASCII character other than a quote or line break, got {:?}", delimiter));
+        }
+
+        let columns = match columns {
+            Some(names) => {
+                let mut columns = Vec::new();
+                for name in names.split(',').map(str::trim) {
+                    let column = Column::parse(name).ok_or_else(|| {
+                        let known: Vec<&str> = Column::ALL.iter().map(Column::name).collect();
+                        format!("Unknown column {:?}, expected some of {}", name, known.join(", "))
+                    })?;
+                    if !columns.contains(&column) {
+                        columns.push(column);
+                    }
+                }
+                columns
+            }
+            None => Column::ALL.to_vec(),
+        };
+        Ok(Self { delimiter, bom, quote_headers, columns })
+    }
+
+    fn header(&self) -> String {
+        let names = self.columns.iter().map(|column| column.name().to_string());
+        let mut header = if self.bom { UTF8_BOM.to_string() } else { String::new() };
+        header += &self.record(names, self.quote_headers);
+        header
+    }
+
+    fn row(&self, user: &User) -> String {
+        self.record(self.columns.iter().map(|column| column.value(user)), false)
+    }
+
+    // One CSV line, fields with the delimiter, a quote or a line break quoted (RFC 4180)
+    // and would-be formulas turned into text
+    fn record(&self, fields: impl Iterator<Item = String>, quote_all: bool) -> String {
+        let fields: Vec<String> = fields
+            .map(|field| {
+                if field.starts_with(FORMULA_PREFIXES) {
+                    format!("\"'{}\"", field.replace('"', "\"\""))
+                } else if quote_all || field.contains([self.delimiter, '"', '\n', '\r']) {
+                    format!("\"{}\"", field.replace('"', "\"\""))
+                } else {
+                    field
+                }
+            })
+            .collect();
+        fields.join(&self.delimiter.to_string()) + "\r\n"
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportJob {
+    pub id: Uuid,
+    pub format: ExportFormat,
+    // Set for CSV exports
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub csv: Option<CsvOptions>,
+    pub filter: UserFilter,
+    pub status: JobStatus,
+    // Users written so far
+    pub rows: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    // A fresh signed link on every status read once the export is done
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_url: Option<String>,
+}
+
+// User exports too large to answer within a request, started with POST
+// /users/exports and written to a file in the background. Jobs live in memory
+// on the instance that took the request, so status reads and downloads have to
+// reach that instance; a restart forgets them and leaves their files behind.
+pub struct Exports {
+    config: ExportConfig,
+    users: web::Data<dyn UserStore>,
+    signer: web::Data<Signer>,
+    public_url: String,
+    clock: Arc<dyn Clock>,
+    jobs: Mutex<HashMap<Uuid, ExportJob>>,
+}
+
+impl Exports {
+    pub fn new(
+        config: ExportConfig,
+        users: web::Data<dyn UserStore>,
+        signer: web::Data<Signer>,
+        public_url: String,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self { config, users, signer, public_url, clock, jobs: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<ExportJob> {
+        let mut job = self.jobs.lock().get(id).cloned()?;
+        if job.status == JobStatus::Done {
+            job.download_url = Some(self.signer.export_url(&self.public_url, id, self.config.link_ttl_secs));
+        }
+        Some(job)
+    }
+
+    // Queue an export of the users matching the filter and start it in the
+    // background. CSV exports without options get the defaults.
+    pub fn start(exports: &web::Data<Exports>, format: ExportFormat, csv: Option<CsvOptions>, filter: UserFilter) -> ExportJob {
+        let job = ExportJob {
+            id: Uuid::new_v4(),
+            format,
+            csv: (format == ExportFormat::Csv).then(|| csv.unwrap_or_default()),
+            filter,
+            status: JobStatus::Pending,
+            rows: 0,
+            error: None,
+            created_at: exports.clock.now(),
+            finished_at: None,
+            download_url: None,
+        };
+        {
+            let mut jobs = exports.jobs.lock();
+            jobs.insert(job.id, job.clone());
+            exports.prune(&mut jobs);
+        }
+
+        let exports = exports.clone();
+        let id = job.id;
+        actix_web::rt::spawn(async move {
+            exports.run(id).await;
+        });
+        job
+    }
+
+    // The file and format of a finished export, None unless the link is valid
+    // and the export is done
+    pub fn artifact(&self, id: &Uuid, expires: u64, signature: &str) -> Option<(PathBuf, ExportFormat)> {
+        if !self.signer.verify_export_link(id, expires, signature) {
+            return None;
+        }
+        let jobs = self.jobs.lock();
+        let job = jobs.get(id).filter(|job| job.status == JobStatus::Done)?;
+        Some((self.path(job), job.format))
+    }
+
+    async fn run(&self, id: Uuid) {
+        let Some(job) = self.jobs.lock().get_mut(&id).map(|job| {
+            job.status = JobStatus::Running;
+            job.clone()
+        }) else {
+            return;
+        };
+
+        let path = self.path(&job);
+        let partial = path.with_extension("part");
+        let result = match self.write(&job, &partial).await {
+            Ok(()) => tokio::fs::rename(&partial, &path).await.map_err(Into::into),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &result {
+            log::error!("Export {} failed: {}", id, e);
+            let _ = tokio::fs::remove_file(&partial).await;
+        }
+
+        self.update(id, |job| {
+            job.finished_at = Some(self.clock.now());
+            match result {
+                Ok(()) => job.status = JobStatus::Done,
+                Err(e) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(e.to_string());
+                }
+            }
+        });
+        log::info!("Export {} finished", id);
+    }
+
+    // Every matching user in id order, a page at a time so memory stays flat
+    async fn write(&self, job: &ExportJob, path: &PathBuf) -> Result<(), Box<dyn StdError>> {
+        tokio::fs::create_dir_all(&self.config.dir).await?;
+        let mut file = BufWriter::new(tokio::fs::File::create(path).await?);
+        if let Some(csv) = &job.csv {
+            file.write_all(csv.header().as_bytes()).await?;
+        }
+
+        let mut after = None;
+        loop {
+            let users = self.users.get_after(&job.filter, after.as_ref(), PAGE_SIZE).await?;
+            for user in &users {
+                let line = match &job.csv {
+                    Some(csv) => csv.row(user),
+                    None => serde_json::to_string(user)? + "\n",
+                };
+                file.write_all(line.as_bytes()).await?;
+            }
+            let written = users.len();
+            self.update(job.id, |job| job.rows += written);
+            match users.last() {
+                Some(last) if written as i64 == PAGE_SIZE => after = Some(last.id),
+                _ => break,
+            }
+        }
+        file.flush().await?;
+        Ok(())
+    }
+
+    fn path(&self, job: &ExportJob) -> PathBuf {
+        self.config.dir.join(format!("{}.{}", job.id, job.format.extension()))
+    }
+
+    fn update(&self, id: Uuid, f: impl FnOnce(&mut ExportJob)) {
+        if let Some(job) = self.jobs.lock().get_mut(&id) {
+            f(job);
+        }
+    }
+
+    // Drop exports past their retention and the oldest beyond MAX_FINISHED_JOBS,
+    // removing their files
+    fn prune(&self, jobs: &mut HashMap<Uuid, ExportJob>) {
+        let cutoff = self.clock.now() - self.config.retention;
+        let mut finished: Vec<(DateTime<Utc>, Uuid)> = jobs
+            .values()
+            .filter_map(|job| job.finished_at.map(|finished_at| (finished_at, job.id)))
+            .collect();
+        finished.sort();
+        let excess = finished.len().saturating_sub(MAX_FINISHED_JOBS);
+        for (i, (finished_at, id)) in finished.into_iter().enumerate() {
+            if i >= excess && finished_at >= cutoff {
+                continue;
+            }
+            if let Some(job) = jobs.remove(&id) {
+                let path = self.path(&job);
+                actix_web::rt::spawn(async move {
+                    let _ = tokio::fs::remove_file(path).await;
+                });
+            }
+        }
+    }
+}
+
+// The file in chunks, for a streaming response body
+pub async fn read_chunks(path: PathBuf) -> Result<impl Stream<Item = Result<Bytes, Box<dyn StdError>>>, Box<dyn StdError>> {
+    let file = tokio::fs::File::open(path).await?;
+    Ok(stream::try_unfold(file, |mut file| async move {
+        let mut buffer = vec![0; CHUNK_SIZE];
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        buffer.truncate(read);
+        Ok(Some((Bytes::from(buffer), file)))
+    }))
+}
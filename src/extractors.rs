@@ -0,0 +1,273 @@
+use actix_web::dev::Payload;
+use actix_web::error::{InternalError, JsonPayloadError, PathError, QueryPayloadError};
+use actix_web::http::StatusCode;
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse};
+use futures_util::future::LocalBoxFuture;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::ops::Deref;
+
+use crate::errors::AppError;
+use crate::models::public_id;
+use crate::models::sort::{SortSpec, SortableField};
+use crate::models::validate::Validate;
+
+// Shown to clients that sent an id in neither accepted form
+const ID_FORMAT: &str = "UUID (67e55044-10b1-426f-9247-bb680e5fe0c8) or usr_-prefixed id";
+
+// RFC 7807 problem details response. Members of `extra` (a JSON object) are
+// added next to the standard ones.
+pub fn problem(status: StatusCode, title: &str, detail: &str, extra: serde_json::Value) -> HttpResponse {
+    let mut body = serde_json::json!({
+        "type": "about:blank",
+        "title": title,
+        "status": status.as_u16(),
+        "detail": detail,
+    });
+    if let (Some(body), serde_json::Value::Object(extra)) = (body.as_object_mut(), extra) {
+        body.extend(extra);
+    }
+
+    HttpResponse::build(status)
+        .content_type("application/problem+json")
+        .body(body.to_string())
+}
+
+// JSON body that has been deserialized and passed its validation rules.
+// Malformed bodies and unknown fields are rejected with 400, bodies that
+// break a rule with 422 listing every offending field.
+pub struct ValidatedJson<T>(pub T);
+
+impl<T> Deref for ValidatedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + Validate + 'static> FromRequest for ValidatedJson<T> {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = web::Json::<T>::from_request(req, payload);
+
+        Box::pin(async move {
+            let value = match json.await {
+                Ok(json) => json.into_inner(),
+                Err(e) => {
+                    let detail = match e.as_error::<JsonPayloadError>() {
+                        Some(JsonPayloadError::Deserialize(e)) => e.to_string(),
+                        Some(other) => other.to_string(),
+                        None => e.to_string(),
+                    };
+                    let response = problem(StatusCode::BAD_REQUEST, "Invalid request body", &detail, serde_json::Value::Null);
+                    return Err(InternalError::from_response(detail, response).into());
+                }
+            };
+
+            if let Err(errors) = value.validate() {
+                return Err(AppError::Validation { detail: "One or more fields are invalid", errors }.into());
+            }
+
+            Ok(ValidatedJson(value))
+        })
+    }
+}
+
+// Query string that has been deserialized and passed its validation rules. Parse
+// errors go through query_config, rule violations get the same 422 as bodies.
+pub struct ValidatedQuery<T>(pub T);
+
+impl<T> Deref for ValidatedQuery<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + Validate + 'static> FromRequest for ValidatedQuery<T> {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let query = web::Query::<T>::from_request(req, payload);
+
+        Box::pin(async move {
+            let value = query.await?.into_inner();
+
+            if let Err(errors) = value.validate() {
+                return Err(AppError::Validation { detail: "One or more query parameters are invalid", errors }.into());
+            }
+
+            Ok(ValidatedQuery(value))
+        })
+    }
+}
+
+// STRICT_VALIDATION: list endpoints answer a misspelt parameter like
+// ?min-age=18 with a 400 instead of ignoring it and listing everything
+pub struct StrictValidation(pub bool);
+
+// Page size when only ?page= is given, and the largest page or keyset limit accepted
+const DEFAULT_PER_PAGE: u32 = 50;
+const MAX_PER_PAGE: u32 = 500;
+
+// Filter parameters of a list endpoint, read from the same query string as the
+// paging and sort parameters ListParams handles, along with the fields the
+// resource can be sorted by
+pub trait FilterSchema: DeserializeOwned + Validate + 'static {
+    type SortField: SortableField + 'static;
+    // Names of the filter parameters, for rejecting unknown ones
+    const PARAMS: &'static [&'static str];
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Paging {
+    // Neither kind of paging was asked for
+    Unpaged,
+    // ?page=&per_page=, page counts from 1
+    Offset { page: u32, per_page: u32 },
+    // ?after=&limit=, after is the opaque cursor of the previous page
+    Keyset { after: Option<String>, limit: u32 },
+}
+
+impl Paging {
+    pub fn first_page() -> Self {
+        Self::Offset { page: 1, per_page: DEFAULT_PER_PAGE }
+    }
+}
+
+const LIST_PARAMS: &[&str] = &["sort", "page", "per_page", "after", "limit"];
+
+#[derive(Deserialize)]
+struct ListQuery {
+    sort: Option<String>,
+    page: Option<u32>,
+    per_page: Option<u32>,
+    after: Option<String>,
+    limit: Option<u32>,
+}
+
+// Query string of a list endpoint: paging by page or by cursor, a sort over
+// the resource's fields and its validated filter. Keyset pages are in id
+// order, so they can't be combined with a sort or offset paging. Bad
+// combinations and ranges are a 400, filters that break a rule a 422.
+pub struct ListParams<T: FilterSchema> {
+    pub filter: T,
+    pub sort: SortSpec<T::SortField>,
+    // ?sort= as given, for links that keep it
+    pub raw_sort: Option<String>,
+    pub paging: Paging,
+}
+
+impl<T: FilterSchema> FromRequest for ListParams<T> {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let query = web::Query::<ListQuery>::from_request(req, payload);
+        let filter = ValidatedQuery::<T>::from_request(req, payload);
+        let strict = req.app_data::<web::Data<StrictValidation>>().is_some_and(|strict| strict.0);
+        let unknown = strict.then(|| unknown_param(req.query_string(), T::PARAMS)).flatten();
+
+        Box::pin(async move {
+            if let Some(name) = unknown {
+                let known: Vec<&str> = LIST_PARAMS.iter().chain(T::PARAMS).copied().collect();
+                let detail = format!("unknown query parameter `{}`, expected one of {}", name, known.join(", "));
+                let response = problem(StatusCode::BAD_REQUEST, "Invalid query parameter", &detail, serde_json::json!({ "parameter": name }));
+                return Err(InternalError::from_response(detail, response).into());
+            }
+            let query = query.await?.into_inner();
+            let filter = filter.await?.0;
+
+            let sort = match query.sort.as_deref().map(SortSpec::parse) {
+                Some(sort) => sort.map_err(AppError::bad_request)?,
+                None => SortSpec::default(),
+            };
+
+            let offset = query.page.is_some() || query.per_page.is_some();
+            let keyset = query.after.is_some() || query.limit.is_some();
+            let paging = if keyset {
+                if offset || query.sort.is_some() {
+                    return Err(AppError::bad_request("after and limit page by id and can't be combined with sort, page or per_page").into());
+                }
+                let limit = query.limit.unwrap_or(DEFAULT_PER_PAGE);
+                if limit == 0 || limit > MAX_PER_PAGE {
+                    return Err(AppError::bad_request(format!("limit must be between 1 and {}", MAX_PER_PAGE)).into());
+                }
+                Paging::Keyset { after: query.after, limit }
+            } else if offset {
+                let (page, per_page) = (query.page.unwrap_or(1), query.per_page.unwrap_or(DEFAULT_PER_PAGE));
+                if page == 0 || per_page == 0 || per_page > MAX_PER_PAGE {
+                    return Err(AppError::bad_request(format!(
+                        "page must be at least 1 and per_page between 1 and {}", MAX_PER_PAGE
+                    ))
+                    .into());
+                }
+                Paging::Offset { page, per_page }
+            } else {
+                Paging::Unpaged
+            };
+
+            Ok(ListParams { filter, sort, raw_sort: query.sort, paging })
+        })
+    }
+}
+
+// Registered app-wide so every web::Path extractor reports bad segments in the
+// same format. Typed path segments in this service are all user ids, so the
+// first `id`-like segment that doesn't parse is the one named in the response.
+pub fn path_config() -> web::PathConfig {
+    web::PathConfig::default().error_handler(|err: PathError, req: &HttpRequest| {
+        let detail = err.to_string();
+        let offending = req
+            .match_info()
+            .iter()
+            .find(|(name, value)| name.ends_with("id") && public_id::decode(value).is_none());
+
+        let extra = match offending {
+            Some((name, value)) => serde_json::json!({
+                "parameter": name,
+                "value": value,
+                "expected": ID_FORMAT,
+            }),
+            None => serde_json::Value::Null,
+        };
+        let response = problem(StatusCode::BAD_REQUEST, "Invalid path parameter", &detail, extra);
+        InternalError::from_response(err, response).into()
+    })
+}
+
+// Registered app-wide for web::Query extractors. serde names the field for
+// missing or unknown values (`missing field `exp``), which becomes `parameter`.
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|err: QueryPayloadError, _req: &HttpRequest| {
+        let detail = match &err {
+            QueryPayloadError::Deserialize(e) => e.to_string(),
+            other => other.to_string(),
+        };
+
+        let extra = match quoted_name(&detail) {
+            Some(name) if detail.starts_with("missing field") => serde_json::json!({ "parameter": name }),
+            _ => serde_json::Value::Null,
+        };
+        let response = problem(StatusCode::BAD_REQUEST, "Invalid query parameter", &detail, extra);
+        InternalError::from_response(err, response).into()
+    })
+}
+
+// First parameter of the query string that is neither a list parameter nor one of `filters`
+fn unknown_param(query: &str, filters: &[&str]) -> Option<String> {
+    let params = web::Query::<Vec<(String, String)>>::from_query(query).ok()?.into_inner();
+    params.into_iter().map(|(name, _)| name).find(|name| !LIST_PARAMS.contains(&name.as_str()) && !filters.contains(&name.as_str()))
+}
+
+// First `backtick-quoted` word of a serde error message
+fn quoted_name(message: &str) -> Option<&str> {
+    let start = message.find('`')? + 1;
+    let len = message[start..].find('`')?;
+    Some(&message[start..start + len])
+}
@@ -0,0 +1,122 @@
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::repositories::user_repo::CachedUserRepository;
+
+// Error rate is reported over BUCKETS * BUCKET_SECS seconds
+const BUCKET_SECS: u64 = 10;
+const BUCKETS: usize = 30;
+
+#[derive(Debug, Serialize)]
+pub struct ComponentStatus {
+    pub name: &'static str,
+    pub healthy: bool,
+    // Working around a failure, the service still answers. Doesn't fail readiness.
+    pub degraded: bool,
+    pub detail: String,
+}
+
+// Run the readiness checks for every component the service depends on
+pub async fn check_components(users: &CachedUserRepository) -> Vec<ComponentStatus> {
+    let database = match users.ping().await {
+        Ok(()) => ComponentStatus {
+            name: "database",
+            healthy: true,
+            degraded: false,
+            detail: users.pool_status(),
+        },
+        Err(e) => ComponentStatus {
+            name: "database",
+            healthy: false,
+            degraded: false,
+            detail: e.to_string(),
+        },
+    };
+
+    // Counting goes through the cache, so a failure not noticed yet trips it first
+    let entries = users.cache_len();
+    let cache = match users.cache_degraded() {
+        None => ComponentStatus {
+            name: "cache",
+            healthy: true,
+            degraded: false,
+            detail: format!("in-process, {} entries", entries),
+        },
+        Some(degraded) => ComponentStatus {
+            name: "cache",
+            healthy: true,
+            degraded: true,
+            detail: format!(
+                "bypassed, reads go to the database, retrying in {}s: {}",
+                degraded.retry_in.as_secs(),
+                degraded.error
+            ),
+        },
+    };
+
+    let queue = ComponentStatus {
+        name: "queue",
+        healthy: true,
+        degraded: false,
+        detail: "not configured".to_string(),
+    };
+
+    vec![database, cache, queue]
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct RequestCounts {
+    pub requests: u64,
+    pub errors: u64,
+}
+
+// Rolling count of requests and server errors, kept in fixed time buckets
+pub struct RequestStats {
+    buckets: Mutex<[(u64, RequestCounts); BUCKETS]>,
+}
+
+impl RequestStats {
+    pub fn new() -> Self {
+        Self {
+            buckets: Mutex::new([(0, RequestCounts::default()); BUCKETS]),
+        }
+    }
+
+    pub fn record(&self, server_error: bool) {
+        let slot = current_slot();
+        let mut buckets = self.buckets.lock();
+        let bucket = &mut buckets[(slot % BUCKETS as u64) as usize];
+
+        if bucket.0 != slot {
+            *bucket = (slot, RequestCounts::default());
+        }
+        bucket.1.requests += 1;
+        if server_error {
+            bucket.1.errors += 1;
+        }
+    }
+
+    // Totals over the reporting window
+    pub fn recent(&self) -> RequestCounts {
+        let slot = current_slot();
+        let buckets = self.buckets.lock();
+
+        buckets
+            .iter()
+            .filter(|(bucket_slot, _)| slot.saturating_sub(*bucket_slot) < BUCKETS as u64)
+            .fold(RequestCounts::default(), |total, (_, counts)| RequestCounts {
+                requests: total.requests + counts.requests,
+                errors: total.errors + counts.errors,
+            })
+    }
+
+    pub fn window_secs(&self) -> u64 {
+        BUCKET_SECS * BUCKETS as u64
+    }
+}
+
+fn current_slot() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    now.as_secs() / BUCKET_SECS
+}
@@ -0,0 +1,166 @@
+use actix_web::web;
+use futures_util::StreamExt;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::error::Error as StdError;
+
+use crate::models::user::CreateUserRequest;
+use crate::models::validate::Validate;
+use crate::repositories::user_store::UserStore;
+
+// Number of parsed rows sent to the database in a single INSERT
+const BATCH_SIZE: usize = 1000;
+
+// Longest line read. A longer one is reported and skipped without being buffered.
+const MAX_LINE_BYTES: usize = 64 * 1024;
+
+// Errors reported before the import gives up on the rest of the body
+const MAX_ERRORS: usize = 1000;
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    pub imported: u64,
+    pub skipped: u64,
+    pub errors: Vec<ImportError>,
+    // Stopped after MAX_ERRORS errors, the lines after the last error weren't read.
+    // Users from the lines before are imported.
+    pub stopped: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportError {
+    pub line: u64,
+    pub error: String,
+}
+
+// Parse a single JSON document in place. simd-json needs a mutable buffer,
+// which is why both variants take `&mut [u8]`.
+#[cfg(feature = "simd")]
+pub fn parse_json<T: DeserializeOwned>(buf: &mut [u8]) -> Result<T, String> {
+    simd_json::serde::from_slice(buf).map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "simd"))]
+pub fn parse_json<T: DeserializeOwned>(buf: &mut [u8]) -> Result<T, String> {
+    serde_json::from_slice(buf).map_err(|e| e.to_string())
+}
+
+// Import users from an NDJSON body, one CreateUserRequest per line. The payload
+// is consumed chunk by chunk so arbitrarily large imports never need to be
+// buffered as a whole, and no line longer than MAX_LINE_BYTES is kept.
+// Unparseable and invalid lines are reported but don't abort the import, until
+// there are MAX_ERRORS of them.
+pub async fn import_ndjson(
+    mut payload: web::Payload,
+    repo: &dyn UserStore,
+) -> Result<ImportSummary, Box<dyn StdError>> {
+    let mut summary = ImportSummary::default();
+    let mut pending = Vec::new();
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut line_no = 0;
+    // In a line already reported as too long, until its end arrives
+    let mut skipping = false;
+
+    while let Some(chunk) = payload.next().await {
+        pending.extend_from_slice(&chunk?);
+
+        let mut start = 0;
+        while let Some(len) = pending[start..].iter().position(|b| *b == b'\n') {
+            let line = start..start + len;
+            start += len + 1;
+            if std::mem::take(&mut skipping) {
+                continue;
+            }
+
+            line_no += 1;
+            parse_line(&mut pending[line], line_no, &mut batch, &mut summary);
+            if batch.len() >= BATCH_SIZE {
+                flush(repo, &mut batch, &mut summary).await?;
+            }
+            if summary.errors.len() >= MAX_ERRORS {
+                return stop(repo, batch, summary).await;
+            }
+        }
+        pending.drain(..start);
+
+        if !skipping && pending.len() > MAX_LINE_BYTES {
+            line_no += 1;
+            summary.errors.push(too_long(line_no));
+            skipping = true;
+            if summary.errors.len() >= MAX_ERRORS {
+                return stop(repo, batch, summary).await;
+            }
+        }
+        if skipping {
+            pending.clear();
+        }
+    }
+
+    // Last line without a trailing newline
+    if !pending.is_empty() && !skipping {
+        line_no += 1;
+        parse_line(&mut pending, line_no, &mut batch, &mut summary);
+    }
+    flush(repo, &mut batch, &mut summary).await?;
+
+    Ok(summary)
+}
+
+// Give up on the rest of the body, keeping the users parsed so far
+async fn stop(
+    repo: &dyn UserStore,
+    mut batch: Vec<CreateUserRequest>,
+    mut summary: ImportSummary,
+) -> Result<ImportSummary, Box<dyn StdError>> {
+    flush(repo, &mut batch, &mut summary).await?;
+    summary.stopped = true;
+    Ok(summary)
+}
+
+fn too_long(line_no: u64) -> ImportError {
+    ImportError { line: line_no, error: format!("Line is longer than {} bytes", MAX_LINE_BYTES) }
+}
+
+fn parse_line(line: &mut [u8], line_no: u64, batch: &mut Vec<CreateUserRequest>, summary: &mut ImportSummary) {
+    if line.iter().all(u8::is_ascii_whitespace) {
+        return;
+    }
+    if line.len() > MAX_LINE_BYTES {
+        return summary.errors.push(too_long(line_no));
+    }
+
+    let user_req = match parse_json::<CreateUserRequest>(line) {
+        Ok(user_req) => user_req,
+        Err(error) => return summary.errors.push(ImportError { line: line_no, error }),
+    };
+
+    // Same rules as POST /users
+    match user_req.validate() {
+        Ok(()) => batch.push(user_req),
+        Err(errors) => {
+            let error = errors
+                .iter()
+                .map(|e| format!("{} {}", e.field, e.message))
+                .collect::<Vec<_>>()
+                .join(", ");
+            summary.errors.push(ImportError { line: line_no, error });
+        }
+    }
+}
+
+async fn flush(
+    repo: &dyn UserStore,
+    batch: &mut Vec<CreateUserRequest>,
+    summary: &mut ImportSummary,
+) -> Result<(), Box<dyn StdError>> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+
+    let created = repo.create_many(batch).await?;
+    summary.imported += created.len() as u64;
+    summary.skipped += (batch.len() - created.len()) as u64;
+    batch.clear();
+
+    Ok(())
+}
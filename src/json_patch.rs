@@ -0,0 +1,182 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::fmt;
+
+pub const JSON_PATCH: &str = "application/json-patch+json";
+pub const MERGE_PATCH: &str = "application/merge-patch+json";
+
+// One RFC 6902 operation. Members other than the ones an operation uses are ignored.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+#[derive(Debug)]
+pub enum PatchError {
+    // The patch itself is malformed, like a pointer without a leading slash
+    Invalid(String),
+    // A path doesn't exist in the document
+    Conflict(String),
+    TestFailed(String),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(reason) | Self::Conflict(reason) => f.write_str(reason),
+            Self::TestFailed(path) => write!(f, "Test failed at {}", path),
+        }
+    }
+}
+
+// Apply the operations in order, to a copy so a failing one leaves the document as it was
+pub fn apply(doc: &Value, operations: &[Operation]) -> Result<Value, PatchError> {
+    let mut patched = doc.clone();
+    for operation in operations {
+        match operation {
+            Operation::Add { path, value } => add(&mut patched, &pointer(path)?, value.clone())?,
+            Operation::Remove { path } => {
+                remove(&mut patched, &pointer(path)?)?;
+            }
+            Operation::Replace { path, value } => {
+                let target = lookup_mut(&mut patched, &pointer(path)?).ok_or_else(|| missing(path))?;
+                *target = value.clone();
+            }
+            Operation::Move { from, path } => {
+                if path.starts_with(&format!("{}/", from)) {
+                    return Err(PatchError::Invalid(format!("Can't move {} into itself", from)));
+                }
+                let value = remove(&mut patched, &pointer(from)?)?;
+                add(&mut patched, &pointer(path)?, value)?;
+            }
+            Operation::Copy { from, path } => {
+                let value = lookup(&patched, &pointer(from)?).ok_or_else(|| missing(from))?.clone();
+                add(&mut patched, &pointer(path)?, value)?;
+            }
+            Operation::Test { path, value } => {
+                let actual = lookup(&patched, &pointer(path)?).ok_or_else(|| missing(path))?;
+                if !equal(actual, value) {
+                    return Err(PatchError::TestFailed(path.clone()));
+                }
+            }
+        }
+    }
+    Ok(patched)
+}
+
+// RFC 7396: objects are merged member by member, null removes a member and
+// anything else replaces the target
+pub fn merge(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                merge(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+// RFC 6901 pointer as its unescaped reference tokens, "" being the whole document
+fn pointer(path: &str) -> Result<Vec<String>, PatchError> {
+    if path.is_empty() {
+        return Ok(Vec::new());
+    }
+    let Some(rest) = path.strip_prefix('/') else {
+        return Err(PatchError::Invalid(format!("{} is not a JSON pointer, it must start with /", path)));
+    };
+    Ok(rest.split('/').map(|token| token.replace("~1", "/").replace("~0", "~")).collect())
+}
+
+fn missing(path: &str) -> PatchError {
+    PatchError::Conflict(format!("{} does not exist", path))
+}
+
+// Array index token: digits without leading zeros
+fn index(token: &str) -> Option<usize> {
+    if token.is_empty() || (token.len() > 1 && token.starts_with('0')) || !token.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    token.parse().ok()
+}
+
+fn lookup<'a>(doc: &'a Value, tokens: &[String]) -> Option<&'a Value> {
+    tokens.iter().try_fold(doc, |value, token| match value {
+        Value::Object(map) => map.get(token),
+        Value::Array(items) => items.get(index(token)?),
+        _ => None,
+    })
+}
+
+fn lookup_mut<'a>(doc: &'a mut Value, tokens: &[String]) -> Option<&'a mut Value> {
+    tokens.iter().try_fold(doc, |value, token| match value {
+        Value::Object(map) => map.get_mut(token),
+        Value::Array(items) => items.get_mut(index(token)?),
+        _ => None,
+    })
+}
+
+fn add(doc: &mut Value, tokens: &[String], value: Value) -> Result<(), PatchError> {
+    let Some((last, parent)) = tokens.split_last() else {
+        *doc = value;
+        return Ok(());
+    };
+    let path = describe(tokens);
+    match lookup_mut(doc, parent) {
+        Some(Value::Object(map)) => {
+            map.insert(last.clone(), value);
+        }
+        Some(Value::Array(items)) if last == "-" => items.push(value),
+        Some(Value::Array(items)) => match index(last) {
+            Some(i) if i <= items.len() => items.insert(i, value),
+            _ => return Err(missing(&path)),
+        },
+        _ => return Err(missing(&path)),
+    }
+    Ok(())
+}
+
+fn remove(doc: &mut Value, tokens: &[String]) -> Result<Value, PatchError> {
+    let Some((last, parent)) = tokens.split_last() else {
+        return Err(PatchError::Invalid("Can't remove the whole document".to_string()));
+    };
+    let path = describe(tokens);
+    match lookup_mut(doc, parent) {
+        Some(Value::Object(map)) => map.remove(last).ok_or_else(|| missing(&path)),
+        Some(Value::Array(items)) => match index(last) {
+            Some(i) if i < items.len() => Ok(items.remove(i)),
+            _ => Err(missing(&path)),
+        },
+        _ => Err(missing(&path)),
+    }
+}
+
+fn describe(tokens: &[String]) -> String {
+    tokens.iter().map(|token| format!("/{}", token.replace('~', "~0").replace('/', "~1"))).collect()
+}
+
+// Numbers compare by value, so 1 and 1.0 are the same
+fn equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a == b || a.as_f64() == b.as_f64(),
+        (Value::Array(a), Value::Array(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| equal(a, b)),
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len() && a.iter().all(|(key, a)| b.get(key).is_some_and(|b| equal(a, b)))
+        }
+        _ => a == b,
+    }
+}
@@ -0,0 +1,178 @@
+use actix_web::http::{Method, StatusCode};
+use actix_web::HttpRequest;
+use chrono::{DateTime, SubsecRound, Utc};
+use serde::{Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::error::Error as StdError;
+
+use crate::audit;
+use crate::repositories::ledger_repo::LedgerRepository;
+use crate::request_id;
+
+// What the first row's prev_hash is
+pub const GENESIS: [u8; 32] = [0; 32];
+
+// Rows read at a time while verifying
+const VERIFY_BATCH: i64 = 1000;
+
+// An admin API call, as it is recorded
+#[derive(Debug, Clone)]
+pub struct Call {
+    pub actor: String,
+    pub method: String,
+    pub path: String,
+    pub request_id: Option<String>,
+}
+
+impl Call {
+    // The call if it goes to an admin route. It was made by the session's user,
+    // or else the admin the proxy named when nobody is logged in.
+    pub fn admin(req: &HttpRequest) -> Option<Self> {
+        if !req.path().starts_with("/admin/") || req.method() == Method::OPTIONS {
+            return None;
+        }
+        Some(Self {
+            actor: audit::actor(req).unwrap_or_else(|| "anonymous".to_string()),
+            method: req.method().to_string(),
+            path: req.uri().path_and_query().map_or(req.path(), |path| path.as_str()).to_string(),
+            request_id: request_id::current(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LedgerEntry {
+    pub seq: i64,
+    pub recorded_at: DateTime<Utc>,
+    pub actor: String,
+    pub method: String,
+    pub path: String,
+    pub status: i16,
+    pub request_id: Option<String>,
+    #[serde(serialize_with = "as_hex")]
+    pub prev_hash: Vec<u8>,
+    #[serde(serialize_with = "as_hex")]
+    pub hash: Vec<u8>,
+}
+
+impl LedgerEntry {
+    // The entry following one whose hash is `prev_hash`. The time is cut to
+    // microseconds, what Postgres keeps, so the hash can be recomputed from the row.
+    pub fn chain(seq: i64, prev_hash: Vec<u8>, call: Call, status: i16) -> Self {
+        let mut entry = Self {
+            seq,
+            recorded_at: Utc::now().trunc_subsecs(6),
+            actor: call.actor,
+            method: call.method,
+            path: call.path,
+            status,
+            request_id: call.request_id,
+            prev_hash,
+            hash: Vec::new(),
+        };
+        entry.hash = entry.compute_hash();
+        entry
+    }
+
+    // SHA-256 over the previous hash and every field, strings length-prefixed so
+    // moving bytes from one field to the next changes the hash
+    fn compute_hash(&self) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.prev_hash);
+        hasher.update(self.seq.to_be_bytes());
+        hasher.update(self.recorded_at.timestamp_micros().to_be_bytes());
+        for field in [&self.actor, &self.method, &self.path] {
+            hasher.update((field.len() as u64).to_be_bytes());
+            hasher.update(field.as_bytes());
+        }
+        hasher.update(self.status.to_be_bytes());
+        match &self.request_id {
+            Some(id) => {
+                hasher.update([1]);
+                hasher.update((id.len() as u64).to_be_bytes());
+                hasher.update(id.as_bytes());
+            }
+            None => hasher.update([0]),
+        }
+        hasher.finalize().to_vec()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn as_hex<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex(bytes))
+}
+
+#[derive(Debug, Serialize)]
+pub struct Verification {
+    pub valid: bool,
+    // Rows checked and found intact
+    pub rows: i64,
+    // Hash of the last intact row. Rows cut off the end leave a shorter chain
+    // that still verifies, so keep this somewhere else to compare against.
+    pub head: Option<String>,
+    pub broken_at: Option<i64>,
+    pub problem: Option<String>,
+}
+
+// Tamper-evident record of admin calls, on top of the audit log: each row is
+// chained to the previous one by its hash, so edited, removed or reordered rows
+// are found by walking the chain.
+pub struct Ledger {
+    repo: LedgerRepository,
+}
+
+impl Ledger {
+    pub fn new(repo: LedgerRepository) -> Self {
+        Self { repo }
+    }
+
+    // Called before the response is sent, so a call is never acknowledged
+    // without being on record. Failures go to the audit log.
+    pub async fn record(&self, call: Call, status: StatusCode) {
+        if let Err(e) = self.repo.append(&call, status.as_u16() as i16).await {
+            log::error!(target: "audit", "Failed to record {} {} by {} in the admin ledger: {}", call.method, call.path, call.actor, e);
+        }
+    }
+
+    // Walk the chain from the first row, stopping at the first one that doesn't fit
+    pub async fn verify(&self) -> Result<Verification, Box<dyn StdError>> {
+        // seq and hash of the last intact row, rows are numbered from 1
+        let mut rows = 0;
+        let mut head = GENESIS.to_vec();
+        loop {
+            let batch = self.repo.after(rows, VERIFY_BATCH).await?;
+            if batch.is_empty() {
+                break;
+            }
+            for entry in batch {
+                let problem = if entry.seq != rows + 1 {
+                    Some(format!("row {} follows row {}, rows in between are missing", entry.seq, rows))
+                } else if entry.prev_hash != head {
+                    Some("previous hash doesn't match the row before".to_string())
+                } else if entry.hash != entry.compute_hash() {
+                    Some("hash doesn't match the row's contents".to_string())
+                } else {
+                    None
+                };
+                if let Some(problem) = problem {
+                    log::warn!(target: "audit", "Admin ledger is broken at row {}: {}", entry.seq, problem);
+                    return Ok(Verification {
+                        valid: false,
+                        rows,
+                        head: (rows > 0).then(|| hex(&head)),
+                        broken_at: Some(entry.seq),
+                        problem: Some(problem),
+                    });
+                }
+                rows = entry.seq;
+                head = entry.hash;
+            }
+        }
+
+        Ok(Verification { valid: true, rows, head: (rows > 0).then(|| hex(&head)), broken_at: None, problem: None })
+    }
+}
@@ -1,17 +1,143 @@
+mod activitypub;
+mod api_keys;
+mod alerts;
+mod approvals;
+mod audit;
+mod backfill;
+mod change_guard;
+mod client_ip;
+mod clock;
 mod config;
-mod models;
+mod connector;
+mod console;
+mod crash;
+mod dedup;
+mod demo;
+mod egress;
+mod errors;
+mod events;
+mod exports;
+mod extractors;
+mod health;
+mod import;
+mod json_patch;
+mod ledger;
+mod metrics;
+mod middleware;
+mod migrations;
+mod oidc;
+mod password_hash;
+mod password_policy;
+mod plugins;
+mod preflight;
+mod qr;
+mod rebuild;
+#[cfg(feature = "nats")]
+mod queue;
 mod repositories;
+mod request_id;
+mod resource;
+mod retention;
+mod roles;
 mod routes;
+mod scheduler;
+mod sessions;
+mod siem;
+mod startup;
+mod signing;
+mod smoke;
+mod suggest;
+mod sync;
+mod telemetry;
+mod templates;
+mod vcard;
 
+use hello_world_models as models;
 use std::process;
-use actix_web::{web, App, HttpServer, middleware::Logger};
-use config::AppConfig;
+use std::sync::Arc;
+use actix_web::{dev::Service, web, App, HttpServer, middleware::{Condition, Logger}};
+use alerts::Alerter;
+use api_keys::ApiKeyAuth;
+use approvals::Approvals;
+use backfill::Backfills;
+use change_guard::ChangeGuard;
+use clock::{Clock, SystemClock};
+use config::{AppConfig, PublicUrl, StorageBackend};
+use dedup::DedupWindow;
+use demo::DemoMode;
+use exports::Exports;
+use health::RequestStats;
+use ledger::Ledger;
+use metrics::Metrics;
+use middleware::access_log::AccessLog;
+use middleware::cors::Cors;
+use middleware::rate_limit::RateLimiter;
+use migrations::Migrator;
+use oidc::Oidc;
+use password_policy::PasswordChecker;
+use plugins::{PluggedUserStore, Plugins};
+use preflight::Preflight;
+use rebuild::Rebuilds;
+use request_id::RequestId;
+use retention::Retention;
+use roles::{Admins, RoleGuard};
+use scheduler::Scheduler;
+use sessions::{RedisSessionStore, SessionLoader, SessionStore, Sessions};
+use siem::LogFormat;
+use startup::Startup;
+use signing::Signer;
+use repositories::api_key_repo::ApiKeyRepository;
+use repositories::approval_repo::ApprovalRepository;
+use repositories::backfill_repo::BackfillRepository;
+use repositories::audit_repo::AuditRepository;
+use repositories::consent_repo::ConsentRepository;
+use repositories::cursor::CursorCodec;
+use repositories::identity_repo::IdentityRepository;
+use repositories::ledger_repo::LedgerRepository;
+use repositories::memory_user_repo::InMemoryUserRepository;
+#[cfg(feature = "mysql")]
+use repositories::mysql_user_repo::MysqlUserRepository;
+#[cfg(feature = "sqlite")]
+use repositories::sqlite_user_repo::SqliteUserRepository;
+use repositories::refresh_token_repo::RefreshTokenRepository;
+use repositories::retention_repo::RetentionRepository;
+use repositories::schedule_repo::ScheduleRepository;
+use repositories::sync_repo::SyncRepository;
+use repositories::tenant_repo::TenantSettingsRepository;
 use repositories::user_repo::CachedUserRepository;
+use repositories::user_store::{self, UserStore};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    // Initialize logger
-    env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
+    // Initialize logger, it also captures audit events for the SIEM forwarder.
+    // Its format is read ahead of the rest of the configuration, which logs while loading.
+    let log_format = match AppConfig::log_format() {
+        Ok(log_format) => log_format,
+        Err(e) => {
+            eprintln!("Failed to load configuration: {}", e);
+            process::exit(1);
+        }
+    };
+    siem::init_logger(env_logger::Env::default().default_filter_or("info"), log_format);
+    
+    // `hello_world smoke --base-url <url>` checks a running instance and exits,
+    // failing if any step did. It only needs the proxy settings.
+    if std::env::args().nth(1).as_deref() == Some("smoke") {
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        let options = match AppConfig::egress().and_then(|egress| {
+            egress::init(egress);
+            smoke::Options::parse(&args)
+        }) {
+            Ok(options) => options,
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(2);
+            }
+        };
+        let report = smoke::run(options).await;
+        report.print();
+        process::exit(if report.passed() { 0 } else { 1 });
+    }
     
     // Load configuration from environment
     let config = match AppConfig::from_env() {
@@ -23,44 +149,579 @@ async fn main() -> std::io::Result<()> {
         }
     };
     
+    // Before anything builds an HTTP client
+    egress::init(config.egress.clone());
+    client_ip::init(config.trusted_proxies.clone());
+    
+    if let Some(crash_reports) = config.crash_reports.clone() {
+        crash::init(crash_reports);
+    }
+    
+    if let Some(siem) = config.siem.clone() {
+        siem::spawn_forwarder(siem);
+    }
+    
+    // Alerting is set up first so schema failures can be reported
+    let alerter = web::Data::new(Alerter::new(config.alerts.clone()));
+    
+    // Must happen before anything serializes a user
+    models::public_id::set_format(config.public_id_format);
+    
     // Create user repository
-    let user_repository = CachedUserRepository::new(config.pg_pool.clone());
+    let user_repository = CachedUserRepository::new(config.pg_pool.clone(), config.sqlx_pool.clone())
+        .with_hedge_delay(config.hedge_delay)
+        .with_cache_cooldown(config.cache_cooldown);
     
-    // Initialize database schema
-    match user_repository.init_db().await {
-        Ok(_) => log::info!("Database schema initialized successfully"),
-        Err(e) => {
-            eprintln!("Failed to initialize database schema: {}", e);
-            log::error!("Failed to initialize database schema: {}", e);
+    // `hello_world migrate` applies pending migrations and exits, `migrate status` lists them
+    let migrator = Migrator::new(config.pg_pool.clone());
+    if std::env::args().nth(1).as_deref() == Some("migrate") {
+        let result = match std::env::args().nth(2).as_deref() {
+            None => migrator.run().await.map(|applied| println!("Applied {} migrations", applied.len())),
+            Some("status") => migrator.status().await.map(|status| {
+                for migration in status {
+                    let applied = migration.applied_at.map_or("pending".to_string(), |at| at.to_rfc3339());
+                    println!("{:>4}  {:<32} {}", migration.version, migration.name, applied);
+                }
+            }),
+            Some(_) => Err("Usage: hello_world migrate [status]".into()),
+        };
+        if let Err(e) = result {
+            eprintln!("Migration failed: {}", e);
             process::exit(1);
         }
+        return Ok(());
     }
     
-    // Seed sample data
-    match user_repository.seed_sample_data().await {
-        Ok(_) => log::info!("Sample data seeded successfully"),
-        Err(e) => {
-            log::warn!("Failed to seed sample data: {}", e);
-            // Don't exit on seeding failure, it's not critical
+    // `hello_world preflight` runs the database checks done before migrating, exiting with 1 when one fails
+    let preflight = Preflight::new(config.pg_pool.clone(), config.preflight_create_extensions, config.migrate_on_startup);
+    if std::env::args().nth(1).as_deref() == Some("preflight") {
+        match preflight.run().await {
+            Ok(checks) => {
+                println!("{}", preflight::report(&checks));
+                if checks.iter().any(|check| !check.ok) {
+                    process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("Preflight failed: {}", e);
+                process::exit(1);
+            }
         }
+        return Ok(());
     }
     
+    // `hello_world ledger verify` checks the admin ledger's hash chain, exiting with 1 when it is broken
+    let ledger = Ledger::new(LedgerRepository::new(config.pg_pool.clone()));
+    if std::env::args().nth(1).as_deref() == Some("ledger") {
+        let result = match std::env::args().nth(2).as_deref() {
+            Some("verify") => ledger.verify().await,
+            _ => Err("Usage: hello_world ledger verify".into()),
+        };
+        match result {
+            Ok(verification) => {
+                println!("{}", serde_json::to_string_pretty(&verification).unwrap_or_default());
+                if !verification.valid {
+                    process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("Ledger verification failed: {}", e);
+                process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    
+    // Everything that expires or comes due reads the time from here
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+    let sync_repository = SyncRepository::new(config.pg_pool.clone());
+    let identity_repository = IdentityRepository::new(config.pg_pool.clone());
+    let refresh_token_repository = RefreshTokenRepository::new(config.pg_pool.clone(), clock.clone());
+    let approval_repository = ApprovalRepository::new(config.pg_pool.clone());
+    let schedule_repository = ScheduleRepository::new(config.pg_pool.clone());
+    let consent_repository = ConsentRepository::new(config.pg_pool.clone());
+    let audit_repository = AuditRepository::new(config.pg_pool.clone());
+    let api_key_repository = ApiKeyRepository::new(config.pg_pool.clone());
+    let backfill_repository = BackfillRepository::new(config.pg_pool.clone());
+    // Branding for rendered pages, loaded into memory at startup
+    let tenant_repo_data = web::Data::new(TenantSettingsRepository::new(config.pg_pool.clone()));
+    
     let user_repo_data = web::Data::new(user_repository);
+    // What the user routes see: the same repository and cache, or users kept in memory, SQLite or MySQL
+    #[cfg(feature = "mysql")]
+    let mysql_store = match &config.storage_backend {
+        StorageBackend::Mysql(pool) => Some(Arc::new(MysqlUserRepository::new(pool.clone(), config.region.clone()))),
+        _ => None,
+    };
+    let local_store: Option<Arc<dyn UserStore>> = match &config.storage_backend {
+        StorageBackend::Postgres => None,
+        StorageBackend::Memory => Some(Arc::new(InMemoryUserRepository::new(config.region.clone()))),
+        #[cfg(feature = "sqlite")]
+        StorageBackend::Sqlite(path) => match SqliteUserRepository::open(path, config.region.clone()) {
+            Ok(store) => Some(Arc::new(store)),
+            Err(e) => {
+                eprintln!("Failed to open SQLite database {}: {}", path, e);
+                log::error!("Failed to open SQLite database {}: {}", path, e);
+                process::exit(1);
+            }
+        },
+        #[cfg(feature = "mysql")]
+        StorageBackend::Mysql(_) => mysql_store.clone().map(|store| store as Arc<dyn UserStore>),
+    };
+    let user_store: Arc<dyn UserStore> = match &local_store {
+        Some(store) => store.clone(),
+        None => user_repo_data.clone().into_inner(),
+    };
+    // Only the user routes' writes run the hooks, like only they see the other stores
+    let plugins = Plugins::new(plugins::registered(), &config.plugin_policies);
+    let user_store: web::Data<dyn UserStore> = if plugins.is_empty() {
+        web::Data::from(user_store)
+    } else {
+        web::Data::from(Arc::new(PluggedUserStore::new(user_store, plugins)) as Arc<dyn UserStore>)
+    };
+    if log::log_enabled!(target: "events", log::Level::Debug) {
+        user_repo_data.events().spawn_subscriber("debug log", |envelope| async move {
+            log::debug!(target: "events", "{}: {}", envelope.event.name(), serde_json::to_string(&*envelope).unwrap_or_default());
+        });
+    }
+    // Unlike the connector, a broken policy file is fatal: retention is a legal requirement
+    let retention_policies = match &config.retention_policies {
+        Some(path) => match Retention::load(path) {
+            Ok(policies) => policies,
+            Err(e) => {
+                eprintln!("Failed to load retention policies: {}", e);
+                log::error!("Failed to load retention policies: {}", e);
+                process::exit(1);
+            }
+        },
+        None => Vec::new(),
+    };
+    let has_retention_policies = !retention_policies.is_empty();
+    let retention = web::Data::new(Retention::new(
+        retention_policies,
+        RetentionRepository::new(config.pg_pool.clone()),
+        user_repo_data.clone(),
+        clock.clone(),
+    ));
+    let backfills = web::Data::new(Backfills::new(config.backfills.clone(), backfill_repository, user_repo_data.clone()));
+    let rebuilds = web::Data::new(Rebuilds::new(user_repo_data.clone()));
+    let approvals = web::Data::new(Approvals::new(config.approvals_required, approval_repository, user_repo_data.clone()));
+    let scheduler = web::Data::new(Scheduler::new(schedule_repository, user_repo_data.clone(), clock.clone()));
+    
+    // Everything that has to happen before serving. The console and the import
+    // only need the schema, and the demo or sample data would get in their way.
+    let command = std::env::args().nth(1);
+    let serving = !matches!(command.as_deref(), Some("console") | Some("import-http"));
+    let mut startup = Startup::default();
+    
+    // Nothing touches Postgres on the way up, the other routes find out when they're called
+    if let Some(store) = local_store.clone() {
+        if !serving {
+            eprintln!("The console and import-http need STORAGE_BACKEND=postgres");
+            process::exit(1);
+        }
+        match &config.storage_backend {
+            StorageBackend::Memory => log::warn!("Storing users in memory, they are lost on restart"),
+            #[cfg(feature = "sqlite")]
+            StorageBackend::Sqlite(path) => log::info!("Storing users in SQLite database {}", path),
+            #[cfg(feature = "mysql")]
+            StorageBackend::Mysql(_) => log::info!("Storing users in MySQL"),
+            StorageBackend::Postgres => {}
+        }
+        #[allow(unused_mut)]
+        let mut seed_after: &'static [&'static str] = &[];
+        // The MySQL tables are created here, there are no migrations for them
+        #[cfg(feature = "mysql")]
+        if let Some(store) = mysql_store {
+            startup.task("mysql schema", &[], async move { store.create_schema().await });
+            seed_after = &["mysql schema"];
+        }
+        if config.seed_sample_data {
+            startup.task("seed", seed_after, async move { user_store::seed_sample_data(store.as_ref()).await });
+        }
+    }
+    let postgres = local_store.is_none();
+    
+    if postgres {
+        // Bring the schema up to date, or with MIGRATE_ON_STARTUP=false refuse to run on an outdated one
+        let migrate_on_startup = config.migrate_on_startup;
+        let migration_alerter = alerter.clone();
+        // Missing extensions and grants are reported with how to fix them, before a migration trips over them
+        startup.task("preflight", &[], async move {
+            let checks = preflight.run().await?;
+            for check in &checks {
+                match &check.remediation {
+                    None => log::info!("Preflight {}: {}", check.name, check.detail),
+                    Some(remediation) => log::error!("Preflight {} failed: {}. {}", check.name, check.detail, remediation),
+                }
+            }
+            preflight::failure(&checks).map_or(Ok(()), |failure| Err(failure.into()))
+        });
+        startup.task("migrations", &["preflight"], async move {
+            let schema = if migrate_on_startup {
+                migrator.run().await.map(|applied| {
+                    if !applied.is_empty() {
+                        log::info!("Applied {} migrations", applied.len());
+                    }
+                })
+            } else {
+                migrator.status().await.and_then(|status| match status.iter().filter(|migration| migration.applied_at.is_none()).count() {
+                    0 => Ok(()),
+                    pending => Err(format!("{} migrations are pending, run `hello_world migrate` first", pending).into()),
+                })
+            };
+            if let Err(e) = &schema {
+                migration_alerter.alert("migration", &format!("Failed to migrate database schema: {}", e)).await;
+            }
+            schema
+        });
+        startup.task("pool", &[], startup::warm_pool(config.pg_pool.clone()));
+    }
+    
+    if serving && postgres {
+        let tenants = tenant_repo_data.clone();
+        startup.task("tenant_settings", &["migrations"], async move { tenants.load().await });
+    
+        let users = user_repo_data.clone();
+        if config.demo_mode {
+            // A demo that starts without its fixtures is useless, so this one is fatal
+            log::info!("Demo mode enabled, resetting data every {}s", config.demo_reset_interval.as_secs());
+            startup.task("seed", &["migrations"], async move { demo::reset(&users).await });
+        } else {
+            // Kept when seeding is off, later tasks run after it
+            let seed = config.seed_sample_data;
+            startup.task("seed", &["migrations"], async move {
+                if !seed {
+                    log::info!("Sample data seeding is off");
+                    return Ok(());
+                }
+                match users.seed_sample_data().await {
+                    Ok(_) => log::info!("Sample data seeded successfully"),
+                    // Don't exit on seeding failure, it's not critical
+                    Err(e) => log::warn!("Failed to seed sample data: {}", e),
+                }
+                Ok(())
+            });
+            // Demo resets rebuild it themselves
+            let users = user_repo_data.clone();
+            startup.task("suggestions", &["seed"], async move {
+                if let Err(e) = users.rebuild_suggestions().await {
+                    log::warn!("Failed to build the suggestion index: {}", e);
+                }
+                Ok(())
+            });
+        }
+    
+        #[cfg(feature = "nats")]
+        if let Some(url) = config.nats_url.clone() {
+            let prefix = config.nats_subject_prefix.clone();
+            let users = user_repo_data.clone();
+            let (demo_mode, approvals_required) = (config.demo_mode, config.approvals_required);
+            startup.task("nats", &["migrations"], async move {
+                // Queue-first clients are optional, HTTP keeps working without a broker
+                if let Err(e) = queue::spawn_consumer(&url, &prefix, users, demo_mode, approvals_required).await {
+                    log::error!("Failed to start NATS consumer: {}", e);
+                }
+                Ok(())
+            });
+        }
+    
+        // Background jobs start from the seeded data
+        let users = user_repo_data.clone();
+        let connector_spec = config.connector_spec.clone();
+        let (connector_interval, retention_interval) = (config.connector_interval, config.retention_interval);
+        let (demo_mode, demo_reset_interval) = (config.demo_mode, config.demo_reset_interval);
+        let scheduler_interval = config.scheduler_interval;
+        let telemetry = config.telemetry.clone().map(|telemetry| (telemetry, telemetry::features(&config)));
+        let (retention, backfills, scheduler) = (retention.clone(), backfills.clone(), scheduler.clone());
+        startup.task("background_jobs", &["seed"], async move {
+            if let Some(path) = &connector_spec {
+                // A broken spec shouldn't keep the API down, the import just doesn't run
+                match connector::ConnectorSpec::load(path) {
+                    Ok(spec) => connector::spawn_schedule(spec, users.clone(), connector_interval),
+                    Err(e) => log::error!("Not scheduling connector import: {}", e),
+                }
+            }
+            if has_retention_policies {
+                Retention::spawn_schedule(retention, retention_interval);
+            }
+            Backfills::spawn(backfills);
+            if demo_mode {
+                demo::spawn_reset_loop(users.clone(), demo_reset_interval);
+            }
+            Scheduler::spawn(scheduler, scheduler_interval);
+            if let Some((telemetry, features)) = telemetry {
+                telemetry::spawn_reporter(telemetry, features, users);
+            }
+            Ok(())
+        });
+    }
+    
+    if let Err(e) = startup.run().await {
+        eprintln!("{}", e);
+        log::error!("{}", e);
+        process::exit(1);
+    }
+    
+    // `hello_world console` opens the interactive console instead of serving HTTP
+    if command.as_deref() == Some("console") {
+        if let Err(e) = console::run(&user_repo_data).await {
+            eprintln!("Console failed: {}", e);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    
+    // `hello_world import-http <spec.json>` runs a connector import once and prints the summary
+    if command.as_deref() == Some("import-http") {
+        let result = match std::env::args().nth(2) {
+            Some(path) => match connector::ConnectorSpec::load(&path) {
+                Ok(spec) => connector::run(&spec, &user_repo_data).await,
+                Err(e) => Err(e),
+            },
+            None => Err("Usage: hello_world import-http <spec.json>".into()),
+        };
+        match result {
+            Ok(summary) => println!("{}", serde_json::to_string_pretty(&summary).unwrap_or_default()),
+            Err(e) => {
+                eprintln!("Import failed: {}", e);
+                process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    
+    if config.approvals_required {
+        log::info!("Approvals required: deletes and email changes wait for a second admin");
+    }
+    if config.api_keys_required {
+        log::info!("API keys required: requests without an X-Api-Key header are refused");
+    }
+    let api_key_repo_data = web::Data::new(api_key_repository);
+    let oidc = web::Data::new(Oidc::new(config.oidc.clone(), clock.clone()));
+    let passwords = web::Data::new(PasswordChecker::new(config.password_policy.clone(), config.password_breach_api.clone()));
+    let sync_repo_data = web::Data::new(sync_repository);
+    let identity_repo_data = web::Data::new(identity_repository);
+    let refresh_token_repo_data = web::Data::new(refresh_token_repository);
+    let consent_repo_data = web::Data::new(consent_repository);
+    let audit_repo_data = web::Data::new(audit_repository);
+    let signer = web::Data::new(Signer::new(config.signing_key.clone(), clock.clone()));
+    let cursors = web::Data::new(CursorCodec::new(&config.cursors, clock.clone()));
+    let qr_settings = web::Data::new(routes::user::QrSettings {
+        link_ttl_secs: config.qr_link_ttl_secs,
+    });
+    let session_settings = web::Data::new(routes::auth::SessionSettings {
+        ttl_secs: config.session_ttl_secs,
+        refresh_ttl_secs: config.refresh_ttl_secs,
+    });
+    let sessions = web::Data::new(Sessions::new(
+        config.redis.clone().map(|redis| Box::new(RedisSessionStore::new(redis)) as Box<dyn SessionStore>),
+        config.public_url.starts_with("https://"),
+        clock.clone(),
+    ));
+    let admins = web::Data::new(Admins::new(
+        config.roles_required || config.oidc.is_some() || config.redis.is_some(),
+        signer.clone(),
+        user_repo_data.clone(),
+    ));
+    let request_stats = web::Data::new(RequestStats::new());
+    let metrics = web::Data::new(Metrics::default());
+    Alerter::spawn_monitor(alerter.clone(), user_repo_data.clone(), request_stats.clone());
+    let public_url = web::Data::new(PublicUrl(config.public_url.clone()));
+    let clock_data: web::Data<dyn Clock> = web::Data::from(clock.clone());
+    let exports = web::Data::new(Exports::new(config.exports.clone(), user_store.clone(), signer.clone(), config.public_url.clone(), clock.clone()));
+    let change_guard = web::Data::new(ChangeGuard::new(config.change_limits.clone()));
+    let demo_mode = web::Data::new(DemoMode(config.demo_mode));
+    let ledger = web::Data::new(ledger);
+    let strict_validation = web::Data::new(extractors::StrictValidation(config.strict_validation));
+    #[cfg(feature = "admin-query")]
+    let query_repo_data = web::Data::new(repositories::query_repo::QueryRepository::new(config.query_pool.clone(), config.query_limits));
     
     log::info!("Starting server at http://{}:{}", config.host, config.port);
     
     // Start HTTP server
+    // Built once so every worker shares the same window, a resubmit may arrive on another connection
+    let dedup_enabled = config.dedup_window.is_some();
+    let api_keys_required = config.api_keys_required;
+    let roles_required = config.roles_required;
+    let dedup_window = DedupWindow::new(config.dedup_window.unwrap_or_default());
+    let rate_limits_enabled = config.rate_limits.enabled();
+    let rate_limiter = RateLimiter::new(config.rate_limits);
+    let cors_enabled = config.cors.enabled();
+    let cors = Cors::new(config.cors.clone());
+    let json_logs = config.log_format == LogFormat::Json;
+    
     HttpServer::new(move || {
         let user_repo = user_repo_data.clone();
-        App::new()
-            .wrap(Logger::default())
+        let stats = request_stats.clone();
+        let route_metrics = metrics.clone();
+        let admin_ledger = ledger.clone();
+        let error_tenants = tenant_repo_data.clone();
+        let app = App::new()
+            // Inside the guards, which refuse calls in the ledger and put the session's user
+            // in the request extensions. The actor is also what user writes are audited under.
+            .wrap_fn(move |req, srv| {
+                let ledger = admin_ledger.clone();
+                let call = ledger::Call::admin(req.request());
+                let fut = audit::scope(audit::actor(req.request()), srv.call(req));
+                async move {
+                    let result = fut.await;
+                    if let Some(call) = call {
+                        let status = match &result {
+                            Ok(res) => res.status(),
+                            Err(e) => e.as_response_error().status_code(),
+                        };
+                        ledger.record(call, status).await;
+                    }
+                    result
+                }
+            })
+            .wrap(Condition::new(dedup_enabled, dedup_window.clone()))
+            .wrap(RoleGuard::new(roles_required, signer.clone(), user_repo_data.clone()))
+            .wrap(SessionLoader::new(sessions.clone(), user_repo_data.clone()))
+            // Inside ApiKeyAuth, which tells it the key a request is counted against and
+            // counts failed keys per address before looking them up
+            .wrap(Condition::new(rate_limits_enabled, rate_limiter.clone()))
+            // Outside the dedup window, so replays are only handed to authenticated clients
+            .wrap(ApiKeyAuth::new(api_keys_required, api_key_repo_data.clone(), rate_limits_enabled.then(|| rate_limiter.clone())))
+            // Preflights carry no credentials, so they are answered before any check
+            .wrap(Condition::new(cors_enabled, cors.clone()))
+            // Inside the request id scope, so reports carry it
+            .wrap_fn(|req, srv| {
+                let route = req.match_pattern();
+                let method = req.method().clone();
+                crash::track(route, method, srv.call(req))
+            })
+            // Errors are pages for browsers and JSON for API clients. Inside the request id
+            // scope, so pages show it, and outside the guards, so their refusals are pages too.
+            .wrap_fn(move |req, srv| {
+                let branding = templates::wants_html(req.request()).then(|| error_tenants.get());
+                errors::negotiate(branding, srv.call(req))
+            })
+            // JSON access log records, inside the request id scope
+            .wrap(Condition::new(json_logs, AccessLog))
+            // Makes the request id available to logs and the database layer.
+            // Inside the Logger, so the access log line can show the echoed header.
+            .wrap(RequestId)
+            .wrap(Condition::new(
+                !json_logs,
+                Logger::new(r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T request_id=%{x-request-id}o"#),
+            ))
+            // Count responses for the status page error rate
+            .wrap_fn(move |req, srv| {
+                let stats = stats.clone();
+                let fut = srv.call(req);
+                async move {
+                    let res = fut.await?;
+                    stats.record(res.status().is_server_error());
+                    Ok(res)
+                }
+            })
+            // Per-route counts and latencies for /metrics
+            .wrap_fn(move |req, srv| {
+                let metrics = route_metrics.clone();
+                let route = req.match_pattern();
+                let method = req.method().clone();
+                let fut = srv.call(req);
+                async move { metrics::track(&metrics, route, method, fut).await }
+            })
+            // Bad path segments and query strings get problem+json bodies
+            .app_data(extractors::path_config())
+            .app_data(extractors::query_config())
             .app_data(user_repo)
+            .app_data(user_store.clone())
+            .app_data(sync_repo_data.clone())
+            .app_data(identity_repo_data.clone())
+            .app_data(refresh_token_repo_data.clone())
+            .app_data(sessions.clone())
+            .app_data(cursors.clone())
+            .app_data(consent_repo_data.clone())
+            .app_data(audit_repo_data.clone())
+            .app_data(tenant_repo_data.clone())
+            .app_data(change_guard.clone())
+            .app_data(public_url.clone())
+            .app_data(request_stats.clone())
+            .app_data(metrics.clone())
+            .app_data(signer.clone())
+            .app_data(clock_data.clone())
+            .app_data(qr_settings.clone())
+            .app_data(session_settings.clone())
+            .app_data(demo_mode.clone())
+            .app_data(strict_validation.clone())
+            .app_data(ledger.clone())
+            .app_data(retention.clone())
+            .app_data(backfills.clone())
+            .app_data(rebuilds.clone())
+            .app_data(exports.clone())
+            .app_data(approvals.clone())
+            .app_data(admins.clone())
+            .app_data(scheduler.clone())
+            .app_data(api_key_repo_data.clone())
+            .app_data(oidc.clone())
+            .app_data(passwords.clone())
             .service(routes::user::health_check)
+            .service(routes::status::readiness)
+            .service(routes::status::status_page)
+            .service(routes::status::metrics)
             .service(routes::user::get_users)
+            .service(routes::user::import_users)
+            .service(routes::user::batch_get_users)
+            .service(routes::export::start_export)
+            .service(routes::export::get_export)
+            .service(routes::export::download_export)
+            .service(routes::user::get_user_by_username)
+            .service(routes::user::suggest_users)
+            .service(routes::user::search_users)
+            .service(routes::user::get_user_vcard)
             .service(routes::user::get_user)
+            .service(routes::user::get_user_actor)
+            .service(routes::user::get_user_qr)
+            .service(routes::user::verify_user)
+            .service(routes::identity::list_identities)
+            .service(routes::identity::link_identity)
+            .service(routes::identity::unlink_identity)
+            .service(routes::consent::list_consents)
+            .service(routes::consent::check_consent)
+            .service(routes::consent::record_consent)
+            .service(routes::schedule::schedule_operation)
+            .service(routes::schedule::list_operations)
+            .service(routes::schedule::cancel_operation)
             .service(routes::user::create_user)
             .service(routes::user::update_user)
+            .service(routes::user::patch_user)
             .service(routes::user::delete_user)
+            .service(routes::user::delete_users)
+            .service(routes::user::restore_user)
+            .service(routes::audit::user_audit_log)
+            .service(routes::audit::audit_log)
+            .service(routes::sync::pull_users)
+            .service(routes::sync::push_users)
+            .service(routes::admin::get_tenant_settings)
+            .service(routes::admin::put_tenant_settings)
+            .service(routes::admin::retention_report)
+            .service(routes::admin::list_backfills)
+            .service(routes::admin::start_rebuild)
+            .service(routes::admin::get_rebuild)
+            .service(routes::admin::invalidate_cache_batch)
+            .service(routes::admin::list_approvals)
+            .service(routes::admin::approve)
+            .service(routes::admin::reject)
+            .service(routes::admin::verify_ledger)
+            .service(routes::auth::login)
+            .service(routes::auth::callback)
+            .service(routes::auth::password_login)
+            .service(routes::auth::refresh)
+            .service(routes::auth::logout)
+            .service(routes::auth::session)
+            .service(routes::auth::password_policy)
+            .service(routes::auth::check_password)
+            .service(routes::admin::list_api_keys)
+            .service(routes::admin::issue_api_key)
+            .service(routes::admin::revoke_api_key)
+            .service(routes::admin::set_user_role);
+        #[cfg(feature = "admin-query")]
+        let app = app.app_data(query_repo_data.clone()).service(routes::query::run_query);
+        app
     })
     .bind((config.host.as_str(), config.port))?
     .run()
@@ -0,0 +1,127 @@
+use actix_web::dev::ServiceResponse;
+use actix_web::http::Method;
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::repositories::transaction;
+use crate::repositories::user_repo::CachedUserRepository;
+
+// Upper bounds of the latency histogram buckets in seconds, Prometheus' defaults
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+// Requests that matched no route share one label, so scanners can't add series
+const UNMATCHED: &str = "unmatched";
+
+#[derive(Default)]
+struct RouteStats {
+    statuses: BTreeMap<u16, u64>,
+    // Not cumulative, each count is for its own bucket and the last one past all bounds
+    buckets: [u64; BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+// Request counts and latencies per route pattern since startup, for GET /metrics.
+// Everything else there is read from its owner when scraped.
+#[derive(Default)]
+pub struct Metrics {
+    routes: Mutex<BTreeMap<(String, String), RouteStats>>,
+}
+
+impl Metrics {
+    fn record(&self, method: &Method, route: Option<String>, status: u16, elapsed: Duration) {
+        let route = route.unwrap_or_else(|| UNMATCHED.to_string());
+        let seconds = elapsed.as_secs_f64();
+        let bucket = BUCKETS.iter().position(|bound| seconds <= *bound).unwrap_or(BUCKETS.len());
+
+        let mut routes = self.routes.lock();
+        let stats = routes.entry((method.to_string(), route)).or_default();
+        *stats.statuses.entry(status).or_default() += 1;
+        stats.buckets[bucket] += 1;
+        stats.sum += seconds;
+        stats.count += 1;
+    }
+
+    // Prometheus text exposition format
+    pub fn render(&self, users: &CachedUserRepository) -> String {
+        let mut out = String::new();
+
+        let routes = self.routes.lock();
+        header(&mut out, "http_requests_total", "counter", "Requests handled, by route pattern and status");
+        for ((method, route), stats) in routes.iter() {
+            for (status, count) in &stats.statuses {
+                let _ = writeln!(out, "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}", method, escape(route), status, count);
+            }
+        }
+        header(&mut out, "http_request_duration_seconds", "histogram", "Time until the response head, by route pattern");
+        for ((method, route), stats) in routes.iter() {
+            let labels = format!("method=\"{}\",route=\"{}\"", method, escape(route));
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(&stats.buckets) {
+                cumulative += count;
+                let _ = writeln!(out, "http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels, bound, cumulative);
+            }
+            let _ = writeln!(out, "http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, stats.count);
+            let _ = writeln!(out, "http_request_duration_seconds_sum{{{}}} {}", labels, stats.sum);
+            let _ = writeln!(out, "http_request_duration_seconds_count{{{}}} {}", labels, stats.count);
+        }
+        drop(routes);
+
+        let pool = users.pool_usage();
+        let idle = pool.available.max(0) as usize;
+        header(&mut out, "db_pool_connections", "gauge", "Open database connections by state");
+        let _ = writeln!(out, "db_pool_connections{{state=\"in_use\"}} {}", pool.size.saturating_sub(idle));
+        let _ = writeln!(out, "db_pool_connections{{state=\"idle\"}} {}", idle);
+        gauge(&mut out, "db_pool_max_connections", "Most connections the pool opens", pool.max_size as u64);
+        gauge(&mut out, "db_pool_waiting", "Requests waiting for a connection", (-pool.available).max(0) as u64);
+        counter(&mut out, "db_transaction_retries_total", "Transactions retried after a serialization failure or deadlock", transaction::retries());
+
+        let (hits, misses) = users.cache_stats();
+        counter(&mut out, "user_cache_hits_total", "User lookups answered from the cache", hits);
+        counter(&mut out, "user_cache_misses_total", "User lookups that went to the database", misses);
+        gauge(&mut out, "user_cache_entries", "Users in the cache", users.cache_len() as u64);
+        let (trips, bypassed) = users.cache_breaker_stats();
+        gauge(&mut out, "user_cache_degraded", "1 while the cache has failed and reads go to the database", users.cache_degraded().is_some() as u64);
+        counter(&mut out, "user_cache_trips_total", "Times the cache failed and was taken out of use", trips);
+        counter(&mut out, "user_cache_bypassed_total", "Cache reads and writes skipped while it was out of use", bypassed);
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, "counter", help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, "gauge", help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// Run a request and count it under its route pattern
+pub async fn track<B, F>(metrics: &Metrics, route: Option<String>, method: Method, fut: F) -> F::Output
+where
+    F: Future<Output = Result<ServiceResponse<B>, actix_web::Error>>,
+{
+    let started = Instant::now();
+    let result = fut.await;
+    let status = match &result {
+        Ok(res) => res.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    metrics.record(&method, route, status.as_u16(), started.elapsed());
+    result
+}
@@ -0,0 +1,74 @@
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use std::rc::Rc;
+use std::time::Instant;
+
+use crate::client_ip;
+
+// The access log for JSON logging, one record per request with the route and
+// latency as fields, where the Logger middleware only has a formatted line.
+// Runs inside the request id scope, so the record carries the id too.
+#[derive(Clone, Default)]
+pub struct AccessLog;
+
+impl<S, B> Transform<S, ServiceRequest> for AccessLog
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = AccessLogMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AccessLogMiddleware { service: Rc::new(service) }))
+    }
+}
+
+pub struct AccessLogMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for AccessLogMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let method = req.method().to_string();
+        let path = req.path().to_string();
+        let route = req.match_pattern();
+        let peer = client_ip::of(req.request()).map(|ip| ip.to_string());
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await;
+            let status = match &res {
+                Ok(res) => res.status(),
+                Err(e) => e.as_response_error().status_code(),
+            };
+            // Until the handler returned, streamed bodies may still be going out
+            let latency_ms = started.elapsed().as_micros() as f64 / 1000.0;
+            log::info!(
+                target: "access",
+                method = method.as_str(),
+                path = path.as_str(),
+                route = route.as_deref().unwrap_or(""),
+                status = status.as_u16(),
+                latency_ms = latency_ms,
+                peer = peer.as_deref().unwrap_or("");
+                "{} {} {} in {}ms", method, path, status.as_u16(), latency_ms
+            );
+            res
+        })
+    }
+}
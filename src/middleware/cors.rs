@@ -0,0 +1,252 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderMap, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::HttpResponse;
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::api_keys::API_KEY_HEADER;
+use crate::dedup::REPLAY_HEADER;
+use crate::errors::with_headers;
+use crate::extractors::problem;
+use crate::request_id;
+use crate::resource::VERSION_HEADER;
+
+const DEFAULT_METHODS: &str = "GET, HEAD, POST, PUT, PATCH, DELETE";
+pub const DEFAULT_MAX_AGE_SECS: u64 = 600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorsMode {
+    // Only the configured origins, methods and headers
+    Strict,
+    // Any origin with any method and headers, for local frontends on other ports
+    Dev,
+}
+
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    pub mode: CorsMode,
+    // Exact origins like `https://app.example.com`, or `*` for all
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<Method>,
+    // Lowercase names
+    pub allowed_headers: Vec<String>,
+    pub allow_credentials: bool,
+    pub max_age_secs: u64,
+}
+
+impl CorsConfig {
+    pub fn default_methods() -> Vec<Method> {
+        DEFAULT_METHODS.split(", ").map(|method| method.parse().expect("valid method")).collect()
+    }
+
+    // X-Admin-User is left out, only the proxy in front may set it
+    pub fn default_headers() -> Vec<String> {
+        ["content-type", "authorization", "if-match", "if-none-match", request_id::HEADER, API_KEY_HEADER]
+            .iter()
+            .map(|name| name.to_ascii_lowercase())
+            .collect()
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.mode == CorsMode::Dev || !self.allowed_origins.is_empty()
+    }
+
+    // Credentials with every origin would let any site read the API as the
+    // logged-in user, so `*` has to go when credentials are allowed
+    pub fn check(&self) -> Result<(), String> {
+        if self.mode == CorsMode::Strict && self.allow_credentials && self.allowed_origins.iter().any(|allowed| allowed == "*") {
+            return Err("CORS_ALLOWED_ORIGINS=* can't be combined with CORS_ALLOW_CREDENTIALS=true, list the origins".to_string());
+        }
+        Ok(())
+    }
+
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.mode == CorsMode::Dev || self.allowed_origins.iter().any(|allowed| allowed == "*" || allowed == origin)
+    }
+}
+
+// Answers preflight requests and adds the CORS headers to responses for allowed
+// origins. Runs outside the API key and role checks: preflights never carry
+// credentials, and error responses need the headers for browsers to read them.
+#[derive(Clone)]
+pub struct Cors {
+    config: Arc<CorsConfig>,
+}
+
+impl Cors {
+    pub fn new(config: CorsConfig) -> Self {
+        Self { config: Arc::new(config) }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Cors
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Transform = CorsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CorsMiddleware {
+            service: Rc::new(service),
+            config: self.config.clone(),
+        }))
+    }
+}
+
+pub struct CorsMiddleware<S> {
+    service: Rc<S>,
+    config: Arc<CorsConfig>,
+}
+
+impl<S, B> Service<ServiceRequest> for CorsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let origin = req
+            .headers()
+            .get(header::ORIGIN)
+            .and_then(|value| value.to_str().ok())
+            .filter(|origin| self.config.allows_origin(origin))
+            .map(str::to_string);
+        let Some(origin) = origin else {
+            return Box::pin(async move { Ok(service.call(req).await?.map_into_boxed_body()) });
+        };
+
+        let config = self.config.clone();
+        if req.method() == Method::OPTIONS && req.headers().contains_key(header::ACCESS_CONTROL_REQUEST_METHOD) {
+            let response = preflight(&config, &origin, req.headers());
+            return Box::pin(ready(Ok(req.into_response(response))));
+        }
+
+        Box::pin(async move {
+            let mut headers = HeaderMap::new();
+            allow_origin(&config, &origin, &mut headers);
+            let exposed = [
+                header::LOCATION.as_str(),
+                header::RETRY_AFTER.as_str(),
+                header::ETAG.as_str(),
+                VERSION_HEADER,
+                request_id::HEADER,
+                REPLAY_HEADER,
+            ];
+            headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, HeaderValue::from_str(&exposed.join(", ")).expect("valid header names"));
+
+            // Errors from inner middleware get the headers too, or browsers can't read them
+            match service.call(req).await {
+                Ok(mut res) => {
+                    for (name, value) in headers {
+                        res.headers_mut().append(name, value);
+                    }
+                    Ok(res.map_into_boxed_body())
+                }
+                Err(e) => Err(with_headers(e, headers)),
+            }
+        })
+    }
+}
+
+fn allow_origin(config: &CorsConfig, origin: &str, headers: &mut HeaderMap) {
+    // A wildcard only without credentials, see CorsConfig::check; dev mode echoes the origin
+    let any = config.mode == CorsMode::Strict && config.allowed_origins.iter().any(|allowed| allowed == "*");
+    if any && !config.allow_credentials {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+    } else if let Ok(value) = HeaderValue::from_str(origin) {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, value);
+        headers.append(header::VARY, HeaderValue::from_static("Origin"));
+    }
+    if config.allow_credentials {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+    }
+}
+
+// 204 with what may be sent, or 403 when the method or a header isn't allowed
+fn preflight(config: &CorsConfig, origin: &str, request: &HeaderMap) -> HttpResponse {
+    let method = request
+        .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|method| method.parse::<Method>().ok());
+    let requested_headers: Vec<String> = request
+        .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+        .and_then(|value| value.to_str().ok())
+        .map(|names| names.split(',').map(|name| name.trim().to_ascii_lowercase()).filter(|name| !name.is_empty()).collect())
+        .unwrap_or_default();
+
+    let allowed = match (config.mode, &method) {
+        (CorsMode::Dev, Some(_)) => true,
+        (CorsMode::Strict, Some(method)) => {
+            config.allowed_methods.contains(method) && requested_headers.iter().all(|name| config.allowed_headers.contains(name))
+        }
+        (_, None) => false,
+    };
+    if !allowed {
+        log::debug!("Refused CORS preflight from {} for {:?} with headers {:?}", origin, method, requested_headers);
+        return problem(StatusCode::FORBIDDEN, "Forbidden", "CORS request not allowed", serde_json::json!({}));
+    }
+
+    let mut response = HttpResponse::NoContent().finish();
+    let headers = response.headers_mut();
+    allow_origin(config, origin, headers);
+    let (methods, allowed_headers) = match config.mode {
+        CorsMode::Dev => (method.map(|method| method.to_string()).unwrap_or_default(), requested_headers.join(", ")),
+        CorsMode::Strict => (
+            config.allowed_methods.iter().map(Method::as_str).collect::<Vec<_>>().join(", "),
+            config.allowed_headers.join(", "),
+        ),
+    };
+    for (name, value) in [
+        (header::ACCESS_CONTROL_ALLOW_METHODS, methods),
+        (header::ACCESS_CONTROL_ALLOW_HEADERS, allowed_headers),
+        (header::ACCESS_CONTROL_MAX_AGE, config.max_age_secs.to_string()),
+    ] {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(name, value);
+        }
+    }
+    headers.append(header::VARY, HeaderValue::from_static("Access-Control-Request-Method, Access-Control-Request-Headers"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(origins: &[&str], allow_credentials: bool) -> CorsConfig {
+        CorsConfig {
+            mode: CorsMode::Strict,
+            allowed_origins: origins.iter().map(|origin| origin.to_string()).collect(),
+            allowed_methods: CorsConfig::default_methods(),
+            allowed_headers: CorsConfig::default_headers(),
+            allow_credentials,
+            max_age_secs: DEFAULT_MAX_AGE_SECS,
+        }
+    }
+
+    #[test]
+    fn any_origin_with_credentials_is_refused() {
+        assert!(config(&["*"], true).check().is_err());
+        assert!(config(&["https://app.example.com", "*"], true).check().is_err());
+    }
+
+    #[test]
+    fn listed_origins_with_credentials_and_any_origin_without_are_allowed() {
+        assert!(config(&["https://app.example.com"], true).check().is_ok());
+        assert!(config(&["*"], false).check().is_ok());
+    }
+}
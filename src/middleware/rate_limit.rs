@@ -0,0 +1,181 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{HttpMessage, HttpRequest};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::client_ip;
+use crate::errors::AppError;
+use crate::models::api_key::ApiKey;
+
+const WINDOW: Duration = Duration::from_secs(60);
+// Clients tracked before idle ones are forgotten
+const PRUNE_AT: usize = 10_000;
+
+// Requests per minute, None for no limit
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateLimits {
+    pub per_ip: Option<u32>,
+    pub per_api_key: Option<u32>,
+}
+
+impl RateLimits {
+    pub fn enabled(&self) -> bool {
+        self.per_ip.is_some() || self.per_api_key.is_some()
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Client {
+    Ip(String),
+    ApiKey(Uuid),
+    // Unknown or revoked API keys sent from an address
+    FailedKeys(String),
+}
+
+// Requests with an API key count against that key, others against their IP, so
+// machine clients sharing an address don't use up each other's budget. Limits
+// are a GCRA per client: `limit` requests fit in any minute, spread or in a
+// burst, and a refused request learns exactly how long to wait. Clones share
+// their state, so the limiter is built once for all workers.
+#[derive(Clone)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    // When each client's budget is back to full
+    clients: Arc<Mutex<HashMap<Client, Instant>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits,
+            clients: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Take one request from the client's budget, or how long until there is one
+    fn check(&self, client: Client, limit: u32) -> Result<(), Duration> {
+        self.take(client, limit, true)
+    }
+
+    // Like check, but only looks: Ok if the client has a request left
+    fn peek(&self, client: Client, limit: u32) -> Result<(), Duration> {
+        self.take(client, limit, false)
+    }
+
+    fn take(&self, client: Client, limit: u32, take: bool) -> Result<(), Duration> {
+        let interval = WINDOW / limit.max(1);
+        let now = Instant::now();
+        let mut clients = self.clients.lock();
+        if clients.len() >= PRUNE_AT {
+            clients.retain(|_, full_at| *full_at > now);
+        }
+
+        let full_at = clients.get(&client).copied().filter(|full_at| *full_at > now).unwrap_or(now);
+        let used = full_at - now;
+        if used + interval > WINDOW {
+            return Err(used + interval - WINDOW);
+        }
+        if take {
+            clients.insert(client, full_at + interval);
+        }
+        Ok(())
+    }
+
+    // Called by ApiKeyAuth before it looks a key up. Keyed requests are only
+    // counted once their key is known, so failed lookups are counted here
+    // against the address, and an address that has used up RATE_LIMIT_PER_IP
+    // on them gets no more lookups until it has budget again.
+    pub fn check_key_attempt(&self, req: &HttpRequest) -> Result<(), AppError> {
+        let Some(limit) = self.limits.per_ip else { return Ok(()) };
+        self.peek(Client::FailedKeys(ip(req)), limit).map_err(|retry_after| {
+            log::warn!("Rate limited {} {} from IP {} after failed API keys", req.method(), req.path(), ip(req));
+            too_many(limit, retry_after)
+        })
+    }
+
+    pub fn key_failed(&self, req: &HttpRequest) {
+        if let Some(limit) = self.limits.per_ip {
+            let _ = self.check(Client::FailedKeys(ip(req)), limit);
+        }
+    }
+}
+
+fn ip(req: &HttpRequest) -> String {
+    client_ip::of(req).map_or_else(|| "unknown".to_string(), |ip| ip.to_string())
+}
+
+fn too_many(limit: u32, retry_after: Duration) -> AppError {
+    AppError::TooManyRequests {
+        message: format!("Rate limit of {} requests per minute exceeded", limit),
+        // Rounded up, waiting the truncated seconds would be refused again
+        retry_after: Duration::from_secs(retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0)),
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            limiter: self.clone(),
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    limiter: RateLimiter,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let limits = self.limiter.limits;
+        // ApiKeyAuth runs first and leaves the matching key in the extensions
+        let api_key = req.extensions().get::<ApiKey>().map(|api_key| api_key.id);
+        let limited = match api_key {
+            Some(id) => limits.per_api_key.map(|limit| (Client::ApiKey(id), limit)),
+            None => limits.per_ip.map(|limit| (Client::Ip(ip(req.request())), limit)),
+        };
+        // Load balancer health checks are never limited
+        let exempt = req.path() == "/health" || req.path().starts_with("/health/");
+
+        if let Some((client, limit)) = limited.filter(|_| !exempt) {
+            if let Err(retry_after) = self.limiter.check(client.clone(), limit) {
+                let client = match client {
+                    Client::Ip(ip) | Client::FailedKeys(ip) => format!("IP {}", ip),
+                    Client::ApiKey(id) => format!("API key {}", id),
+                };
+                log::warn!("Rate limited {} {} from {}", req.method(), req.path(), client);
+                return Box::pin(ready(Err(too_many(limit, retry_after).into())));
+            }
+        }
+
+        let service = self.service.clone();
+        Box::pin(async move { Ok(service.call(req).await?.map_into_boxed_body()) })
+    }
+}
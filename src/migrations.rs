@@ -0,0 +1,193 @@
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{Object, Pool};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::time::Instant;
+
+// Held while migrating, so instances starting together don't apply the same migration twice
+const LOCK_KEY: i64 = 0x6865_6c6c_6f5f_6d67;
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+// Every schema change, in the order they're applied. Applied migrations must
+// not be edited, change the schema with a new file instead.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        sql: include_str!("../migrations/0001_initial_schema.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "admin_ledger",
+        sql: include_str!("../migrations/0002_admin_ledger.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "soft_delete",
+        sql: include_str!("../migrations/0003_soft_delete.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "audit_log",
+        sql: include_str!("../migrations/0004_audit_log.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "user_version",
+        sql: include_str!("../migrations/0005_user_version.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "admin_query_role",
+        sql: include_str!("../migrations/0006_admin_query_role.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "audit_skip_noop_updates",
+        sql: include_str!("../migrations/0007_audit_skip_noop_updates.sql"),
+    },
+];
+
+#[derive(Debug, Serialize)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: String,
+    pub applied_at: Option<DateTime<Utc>>,
+}
+
+// Applies the embedded migrations the database hasn't seen yet, each in its own
+// transaction, and records them in schema_migrations along with a checksum of
+// their SQL so an edited migration is noticed instead of silently skipped.
+pub struct Migrator {
+    pool: Pool,
+}
+
+impl Migrator {
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    // Returns the migrations applied by this call
+    pub async fn run(&self) -> Result<Vec<&'static Migration>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+        // Closed afterwards, which also releases the lock if a migration fails
+        let mut client = Object::take(client);
+
+        client.execute("SELECT pg_advisory_lock($1)", &[&LOCK_KEY]).await?;
+        create_table(&client).await?;
+        // The initial migration skips whatever exists already, with a notice for each
+        client.batch_execute("SET client_min_messages = warning").await?;
+        let applied = applied(&client).await?;
+        verify(&applied)?;
+
+        let mut ran = Vec::new();
+        for migration in MIGRATIONS.iter().filter(|migration| !applied.contains_key(&migration.version)) {
+            let started = Instant::now();
+            let tx = client.transaction().await?;
+            tx.batch_execute(migration.sql)
+                .await
+                .map_err(|e| format!("Migration {} ({}) failed: {}", migration.version, migration.name, e))?;
+            tx.execute(
+                "INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3)",
+                &[&migration.version, &migration.name, &checksum(migration.sql)],
+            )
+            .await?;
+            tx.commit().await?;
+            log::info!("Applied migration {} ({}) in {:?}", migration.version, migration.name, started.elapsed());
+            ran.push(migration);
+        }
+
+        Ok(ran)
+    }
+
+    // Every known migration and when it was applied, None for pending ones
+    pub async fn status(&self) -> Result<Vec<MigrationStatus>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        create_table(&client).await?;
+        let applied = applied(&client).await?;
+        Ok(MIGRATIONS
+            .iter()
+            .map(|migration| MigrationStatus {
+                version: migration.version,
+                name: migration.name.to_string(),
+                applied_at: applied.get(&migration.version).map(|applied| applied.applied_at),
+            })
+            .collect())
+    }
+}
+
+struct Applied {
+    checksum: String,
+    applied_at: DateTime<Utc>,
+}
+
+async fn create_table(client: &tokio_postgres::Client) -> Result<(), Box<dyn StdError>> {
+    client
+        .batch_execute(
+            "SET client_min_messages = warning;
+            CREATE TABLE IF NOT EXISTS schema_migrations (
+                version BIGINT PRIMARY KEY,
+                name VARCHAR(255) NOT NULL,
+                checksum VARCHAR(64) NOT NULL,
+                applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            );
+            RESET client_min_messages;",
+        )
+        .await?;
+    Ok(())
+}
+
+async fn applied(client: &tokio_postgres::Client) -> Result<HashMap<i64, Applied>, Box<dyn StdError>> {
+    let rows = client.query("SELECT version, checksum, applied_at FROM schema_migrations", &[]).await?;
+    Ok(rows
+        .iter()
+        .map(|row| (row.get("version"), Applied { checksum: row.get("checksum"), applied_at: row.get("applied_at") }))
+        .collect())
+}
+
+// Applied migrations must match the embedded ones. Versions this build doesn't
+// know come from a newer one, which is fine while rolling back a deployment.
+fn verify(applied: &HashMap<i64, Applied>) -> Result<(), Box<dyn StdError>> {
+    for migration in MIGRATIONS {
+        if let Some(applied) = applied.get(&migration.version) {
+            if applied.checksum != checksum(migration.sql) {
+                return Err(format!(
+                    "Migration {} ({}) was changed after it was applied, add a new migration instead",
+                    migration.version, migration.name
+                )
+                .into());
+            }
+        }
+    }
+    let latest = MIGRATIONS.last().map_or(0, |migration| migration.version);
+    let mut unknown: Vec<_> = applied.keys().filter(|version| **version > latest).collect();
+    if !unknown.is_empty() {
+        unknown.sort();
+        log::warn!("Database has migrations {:?} that this build doesn't know, it is older than the schema", unknown);
+    }
+    Ok(())
+}
+
+fn checksum(sql: &str) -> String {
+    Sha256::digest(sql.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
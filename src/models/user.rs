@@ -1,27 +0,0 @@
-use serde::{Deserialize, Serialize};
-use uuid::Uuid;
-
-// User model
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct User {
-    pub id: Uuid,
-    pub name: String,
-    pub email: String,
-    pub age: Option<u8>,
-}
-
-// Creation DTO
-#[derive(Debug, Deserialize)]
-pub struct CreateUserRequest {
-    pub name: String,
-    pub email: String,
-    pub age: Option<u8>,
-}
-
-// Update DTO
-#[derive(Debug, Deserialize)]
-pub struct UpdateUserRequest {
-    pub name: Option<String>,
-    pub email: Option<String>,
-    pub age: Option<u8>,
-}
\ No newline at end of file
@@ -0,0 +1,170 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::Deserialize;
+use std::error::Error as StdError;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+use crate::egress;
+use crate::clock::Clock;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    // Where the provider sends the browser back to, our /auth/callback
+    pub redirect_url: String,
+    pub scopes: String,
+    // Stored as the provider of linked identities
+    pub provider_name: String,
+}
+
+// Endpoints from the provider's /.well-known/openid-configuration
+#[derive(Debug, Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+// Claims of an ID token that logging in relies on
+#[derive(Debug, Deserialize)]
+pub struct Claims {
+    iss: String,
+    aud: Audience,
+    exp: u64,
+    nonce: Option<String>,
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub name: Option<String>,
+}
+
+// Authorization-code login against one OpenID Connect provider. The
+// provider's endpoints are discovered on first use, so the server starts even
+// while the provider is unreachable. Without a config login is disabled.
+pub struct Oidc {
+    config: Option<OidcConfig>,
+    client: reqwest::Client,
+    discovery: OnceCell<Discovery>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Oidc {
+    pub fn new(config: Option<OidcConfig>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            config,
+            client: egress::client(),
+            discovery: OnceCell::new(),
+            clock,
+        }
+    }
+
+    pub fn config(&self) -> Option<&OidcConfig> {
+        self.config.as_ref()
+    }
+
+    fn required_config(&self) -> Result<&OidcConfig, Box<dyn StdError>> {
+        self.config.as_ref().ok_or_else(|| "OIDC login is not configured".into())
+    }
+
+    async fn discovery(&self) -> Result<&Discovery, Box<dyn StdError>> {
+        let config = self.required_config()?;
+        self.discovery
+            .get_or_try_init(|| async {
+                let url = format!("{}/.well-known/openid-configuration", config.issuer.trim_end_matches('/'));
+                let discovery: Discovery = self
+                    .client
+                    .get(&url)
+                    .timeout(REQUEST_TIMEOUT)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                Ok::<_, Box<dyn StdError>>(discovery)
+            })
+            .await
+    }
+
+    // Provider URL to send the browser to
+    pub async fn authorize_url(&self, state: &str, nonce: &str) -> Result<String, Box<dyn StdError>> {
+        let config = self.required_config()?;
+        let discovery = self.discovery().await?;
+        let url = reqwest::Url::parse_with_params(
+            &discovery.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", config.client_id.as_str()),
+                ("redirect_uri", config.redirect_url.as_str()),
+                ("scope", config.scopes.as_str()),
+                ("state", state),
+                ("nonce", nonce),
+            ],
+        )?;
+        Ok(url.into())
+    }
+
+    // Trade the code from the callback for the user's ID token claims. The
+    // token comes straight from the provider's token endpoint over TLS, which
+    // OIDC accepts in place of checking its signature; issuer, audience,
+    // expiry and nonce are still checked.
+    pub async fn exchange(&self, code: &str, nonce: &str) -> Result<Claims, Box<dyn StdError>> {
+        let config = self.required_config()?;
+        let discovery = self.discovery().await?;
+        let tokens: TokenResponse = self
+            .client
+            .post(&discovery.token_endpoint)
+            .timeout(REQUEST_TIMEOUT)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", config.redirect_url.as_str()),
+                ("client_id", config.client_id.as_str()),
+                ("client_secret", config.client_secret.as_str()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let payload = tokens.id_token.split('.').nth(1).ok_or("ID token is not a JWT")?;
+        let claims: Claims = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload.trim_end_matches('='))?)?;
+
+        let audience_ok = match &claims.aud {
+            Audience::One(aud) => *aud == config.client_id,
+            Audience::Many(auds) => auds.contains(&config.client_id),
+        };
+        if claims.iss != discovery.issuer {
+            return Err(format!("ID token issued by {}, expected {}", claims.iss, discovery.issuer).into());
+        }
+        if !audience_ok {
+            return Err("ID token is for another client".into());
+        }
+        if claims.exp < self.clock.unix_now() {
+            return Err("ID token has expired".into());
+        }
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err("ID token nonce doesn't match the login".into());
+        }
+        Ok(claims)
+    }
+}
@@ -0,0 +1,459 @@
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use std::error::Error as StdError;
+
+// Argon2id (RFC 9106) with Blake2b, producing and checking PHC strings such as
+// `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`. That is the format the
+// argon2 crate and libargon2 use, so stored hashes work with either.
+
+// OWASP's recommended minimum: 19 MiB, two passes, one lane
+const MEMORY_KIB: u32 = 19 * 1024;
+const PASSES: u32 = 2;
+const LANES: u32 = 1;
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+const VERSION: u32 = 0x13;
+// Largest parameters accepted from a stored hash, so a bad row can't exhaust the server
+const MAX_MEMORY_KIB: u32 = 1024 * 1024;
+const MAX_PASSES: u32 = 16;
+const MAX_LANES: u32 = 16;
+
+const BLOCK_WORDS: usize = 128;
+const SYNC_POINTS: u32 = 4;
+const ARGON2ID: u64 = 2;
+
+type Block = [u64; BLOCK_WORDS];
+
+// Hash a new password. Takes tens of milliseconds of CPU, so it runs off the async workers.
+pub async fn hash(password: &str) -> Result<String, Box<dyn StdError>> {
+    let password = password.as_bytes().to_vec();
+    let salt: [u8; SALT_LEN] = rand::random();
+    let phc = actix_web::rt::task::spawn_blocking(move || {
+        let tag = argon2id(&password, &salt, &[], &[], MEMORY_KIB, PASSES, LANES, HASH_LEN);
+        format!(
+            "$argon2id$v={}$m={},t={},p={}${}${}",
+            VERSION, MEMORY_KIB, PASSES, LANES, STANDARD_NO_PAD.encode(salt), STANDARD_NO_PAD.encode(tag)
+        )
+    })
+    .await?;
+    Ok(phc)
+}
+
+// Whether `password` matches a PHC string made by `hash`. Malformed hashes never match.
+pub async fn verify(password: &str, phc: &str) -> Result<bool, Box<dyn StdError>> {
+    let password = password.as_bytes().to_vec();
+    let Some(params) = Params::parse(phc) else {
+        log::warn!("Stored password hash is not an argon2id PHC string");
+        return Ok(false);
+    };
+    let matches = actix_web::rt::task::spawn_blocking(move || {
+        let tag = argon2id(&password, &params.salt, &[], &[], params.memory, params.passes, params.lanes, params.hash.len());
+        tag.iter().zip(&params.hash).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+    })
+    .await?;
+    Ok(matches)
+}
+
+// Spend about as long as a real verification, for logins of unknown users
+pub async fn verify_nothing(password: &str) {
+    let password = password.as_bytes().to_vec();
+    let _ = actix_web::rt::task::spawn_blocking(move || {
+        argon2id(&password, &[0; SALT_LEN], &[], &[], MEMORY_KIB, PASSES, LANES, HASH_LEN)
+    })
+    .await;
+}
+
+struct Params {
+    memory: u32,
+    passes: u32,
+    lanes: u32,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+impl Params {
+    fn parse(phc: &str) -> Option<Self> {
+        let mut fields = phc.strip_prefix("$argon2id$")?.split('$');
+        if fields.next()? != format!("v={}", VERSION) {
+            return None;
+        }
+
+        let (mut memory, mut passes, mut lanes) = (None, None, None);
+        for param in fields.next()?.split(',') {
+            let (name, value) = param.split_once('=')?;
+            let value: u32 = value.parse().ok()?;
+            match name {
+                "m" => memory = Some(value),
+                "t" => passes = Some(value),
+                "p" => lanes = Some(value),
+                _ => return None,
+            }
+        }
+        let params = Self {
+            memory: memory?,
+            passes: passes?,
+            lanes: lanes?,
+            salt: STANDARD_NO_PAD.decode(fields.next()?).ok()?,
+            hash: STANDARD_NO_PAD.decode(fields.next()?).ok()?,
+        };
+
+        let valid = (1..=MAX_LANES).contains(&params.lanes)
+            && (8 * params.lanes..=MAX_MEMORY_KIB).contains(&params.memory)
+            && (1..=MAX_PASSES).contains(&params.passes)
+            && params.salt.len() >= 8
+            && params.hash.len() >= 4
+            && fields.next().is_none();
+        valid.then_some(params)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn argon2id(password: &[u8], salt: &[u8], secret: &[u8], data: &[u8], memory: u32, passes: u32, lanes: u32, tag_len: usize) -> Vec<u8> {
+    let mut h0 = Blake2b::new(64);
+    for value in [lanes, tag_len as u32, memory, passes, VERSION, ARGON2ID as u32] {
+        h0.update(&value.to_le_bytes());
+    }
+    for input in [password, salt, secret, data] {
+        h0.update(&(input.len() as u32).to_le_bytes());
+        h0.update(input);
+    }
+    let h0 = h0.finalize();
+
+    let segment_len = memory / (SYNC_POINTS * lanes);
+    let lane_len = segment_len * SYNC_POINTS;
+    let mut blocks: Vec<Block> = vec![[0; BLOCK_WORDS]; (lane_len * lanes) as usize];
+
+    for lane in 0..lanes {
+        for column in 0..2u32 {
+            let mut input = h0.clone();
+            input.extend_from_slice(&column.to_le_bytes());
+            input.extend_from_slice(&lane.to_le_bytes());
+            let bytes = blake2b_long(&input, 1024);
+            let block = &mut blocks[(lane * lane_len + column) as usize];
+            for (word, chunk) in block.iter_mut().zip(bytes.chunks_exact(8)) {
+                *word = u64::from_le_bytes(chunk.try_into().unwrap());
+            }
+        }
+    }
+
+    let memory_blocks = lane_len * lanes;
+    for pass in 0..passes {
+        for slice in 0..SYNC_POINTS {
+            for lane in 0..lanes {
+                fill_segment(&mut blocks, pass, slice, lane, lanes, lane_len, segment_len, memory_blocks, passes);
+            }
+        }
+    }
+
+    let mut last = blocks[(lane_len - 1) as usize];
+    for lane in 1..lanes {
+        let block = &blocks[(lane * lane_len + lane_len - 1) as usize];
+        last.iter_mut().zip(block).for_each(|(a, b)| *a ^= b);
+    }
+    let bytes: Vec<u8> = last.iter().flat_map(|word| word.to_le_bytes()).collect();
+    blake2b_long(&bytes, tag_len)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn fill_segment(
+    blocks: &mut [Block],
+    pass: u32,
+    slice: u32,
+    lane: u32,
+    lanes: u32,
+    lane_len: u32,
+    segment_len: u32,
+    memory_blocks: u32,
+    passes: u32,
+) {
+    // Argon2id addresses independently of the password in the first half of the first pass
+    let independent = pass == 0 && slice < SYNC_POINTS / 2;
+    let zero: Block = [0; BLOCK_WORDS];
+    let mut input: Block = [0; BLOCK_WORDS];
+    let mut addresses: Block = [0; BLOCK_WORDS];
+    if independent {
+        input[..6].copy_from_slice(&[pass as u64, lane as u64, slice as u64, memory_blocks as u64, passes as u64, ARGON2ID]);
+    }
+    let next_addresses = |input: &mut Block, addresses: &mut Block| {
+        input[6] += 1;
+        *addresses = compress(&zero, input);
+        *addresses = compress(&zero, addresses);
+    };
+
+    let start = if pass == 0 && slice == 0 { 2 } else { 0 };
+    if independent && start == 2 {
+        next_addresses(&mut input, &mut addresses);
+    }
+
+    for index in start..segment_len {
+        let current = lane * lane_len + slice * segment_len + index;
+        let previous = if current.is_multiple_of(lane_len) { current + lane_len - 1 } else { current - 1 };
+
+        let pseudo_random = if independent {
+            if (index as usize).is_multiple_of(BLOCK_WORDS) {
+                next_addresses(&mut input, &mut addresses);
+            }
+            addresses[index as usize % BLOCK_WORDS]
+        } else {
+            blocks[previous as usize][0]
+        };
+
+        let ref_lane = if pass == 0 && slice == 0 { lane } else { ((pseudo_random >> 32) % lanes as u64) as u32 };
+        let same_lane = ref_lane == lane;
+        let area = if pass == 0 {
+            if slice == 0 {
+                index - 1
+            } else if same_lane {
+                slice * segment_len + index - 1
+            } else {
+                slice * segment_len - (index == 0) as u32
+            }
+        } else if same_lane {
+            lane_len - segment_len + index - 1
+        } else {
+            lane_len - segment_len - (index == 0) as u32
+        } as u64;
+
+        let low = pseudo_random & 0xFFFF_FFFF;
+        let relative = area - 1 - ((area * ((low * low) >> 32)) >> 32);
+        let start_position = if pass == 0 || slice == SYNC_POINTS - 1 { 0 } else { (slice + 1) * segment_len } as u64;
+        let ref_index = ((start_position + relative) % lane_len as u64) as u32;
+
+        let next = compress(&blocks[previous as usize], &blocks[(ref_lane * lane_len + ref_index) as usize]);
+        let block = &mut blocks[current as usize];
+        if pass == 0 {
+            *block = next;
+        } else {
+            block.iter_mut().zip(&next).for_each(|(a, b)| *a ^= b);
+        }
+    }
+}
+
+// The compression function G
+fn compress(x: &Block, y: &Block) -> Block {
+    let mut r = [0u64; BLOCK_WORDS];
+    for i in 0..BLOCK_WORDS {
+        r[i] = x[i] ^ y[i];
+    }
+    let mut z = r;
+
+    for row in 0..8 {
+        let indices: [usize; 16] = std::array::from_fn(|i| row * 16 + i);
+        permute(&mut z, indices);
+    }
+    for column in 0..8 {
+        let indices: [usize; 16] = std::array::from_fn(|i| 2 * column + (i / 2) * 16 + i % 2);
+        permute(&mut z, indices);
+    }
+
+    for i in 0..BLOCK_WORDS {
+        z[i] ^= r[i];
+    }
+    z
+}
+
+fn permute(v: &mut Block, i: [usize; 16]) {
+    mix(v, i[0], i[4], i[8], i[12]);
+    mix(v, i[1], i[5], i[9], i[13]);
+    mix(v, i[2], i[6], i[10], i[14]);
+    mix(v, i[3], i[7], i[11], i[15]);
+    mix(v, i[0], i[5], i[10], i[15]);
+    mix(v, i[1], i[6], i[11], i[12]);
+    mix(v, i[2], i[7], i[8], i[13]);
+    mix(v, i[3], i[4], i[9], i[14]);
+}
+
+fn mix(v: &mut Block, a: usize, b: usize, c: usize, d: usize) {
+    let blamka = |x: u64, y: u64| x.wrapping_add(y).wrapping_add(2u64.wrapping_mul(x & 0xFFFF_FFFF).wrapping_mul(y & 0xFFFF_FFFF));
+    v[a] = blamka(v[a], v[b]);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = blamka(v[c], v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = blamka(v[a], v[b]);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = blamka(v[c], v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+// H', Blake2b stretched to any output length
+fn blake2b_long(input: &[u8], len: usize) -> Vec<u8> {
+    let hash = |out_len: usize, parts: &[&[u8]]| {
+        let mut hasher = Blake2b::new(out_len);
+        parts.iter().for_each(|part| hasher.update(part));
+        hasher.finalize()
+    };
+    let len_bytes = (len as u32).to_le_bytes();
+    if len <= 64 {
+        return hash(len, &[&len_bytes, input]);
+    }
+
+    let mut out = Vec::with_capacity(len);
+    let mut v = hash(64, &[&len_bytes, input]);
+    out.extend_from_slice(&v[..32]);
+    while len - out.len() > 64 {
+        v = hash(64, &[&v]);
+        out.extend_from_slice(&v[..32]);
+    }
+    out.extend_from_slice(&hash(len - out.len(), &[&v]));
+    out
+}
+
+const IV: [u64; 8] = [
+    0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+    0x510e527fade682d1, 0x9b05688c2b3e6c1f, 0x1f83d9abfb41bd6b, 0x5be0cd19137e2179,
+];
+
+const SIGMA: [[usize; 16]; 12] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+];
+
+// Unkeyed Blake2b (RFC 7693) with 1 to 64 bytes of output
+struct Blake2b {
+    h: [u64; 8],
+    buffer: [u8; 128],
+    buffered: usize,
+    length: u128,
+    out_len: usize,
+}
+
+impl Blake2b {
+    fn new(out_len: usize) -> Self {
+        let mut h = IV;
+        h[0] ^= 0x0101_0000 ^ out_len as u64;
+        Self { h, buffer: [0; 128], buffered: 0, length: 0, out_len }
+    }
+
+    fn update(&mut self, mut input: &[u8]) {
+        while !input.is_empty() {
+            // The last block is compressed in finalize, with the final flag
+            if self.buffered == 128 {
+                self.length += 128;
+                self.compress(false);
+                self.buffered = 0;
+            }
+            let take = (128 - self.buffered).min(input.len());
+            self.buffer[self.buffered..self.buffered + take].copy_from_slice(&input[..take]);
+            self.buffered += take;
+            input = &input[take..];
+        }
+    }
+
+    fn finalize(mut self) -> Vec<u8> {
+        self.length += self.buffered as u128;
+        self.buffer[self.buffered..].fill(0);
+        self.compress(true);
+        self.h.iter().flat_map(|word| word.to_le_bytes()).take(self.out_len).collect()
+    }
+
+    fn compress(&mut self, last: bool) {
+        let m: [u64; 16] = std::array::from_fn(|i| u64::from_le_bytes(self.buffer[i * 8..i * 8 + 8].try_into().unwrap()));
+        let mut v = [0u64; 16];
+        v[..8].copy_from_slice(&self.h);
+        v[8..].copy_from_slice(&IV);
+        v[12] ^= self.length as u64;
+        v[13] ^= (self.length >> 64) as u64;
+        if last {
+            v[14] = !v[14];
+        }
+
+        let g = |v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize, x: u64, y: u64| {
+            v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+            v[d] = (v[d] ^ v[a]).rotate_right(32);
+            v[c] = v[c].wrapping_add(v[d]);
+            v[b] = (v[b] ^ v[c]).rotate_right(24);
+            v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+            v[d] = (v[d] ^ v[a]).rotate_right(16);
+            v[c] = v[c].wrapping_add(v[d]);
+            v[b] = (v[b] ^ v[c]).rotate_right(63);
+        };
+        for s in &SIGMA {
+            g(&mut v, 0, 4, 8, 12, m[s[0]], m[s[1]]);
+            g(&mut v, 1, 5, 9, 13, m[s[2]], m[s[3]]);
+            g(&mut v, 2, 6, 10, 14, m[s[4]], m[s[5]]);
+            g(&mut v, 3, 7, 11, 15, m[s[6]], m[s[7]]);
+            g(&mut v, 0, 5, 10, 15, m[s[8]], m[s[9]]);
+            g(&mut v, 1, 6, 11, 12, m[s[10]], m[s[11]]);
+            g(&mut v, 2, 7, 8, 13, m[s[12]], m[s[13]]);
+            g(&mut v, 3, 4, 9, 14, m[s[14]], m[s[15]]);
+        }
+
+        for i in 0..8 {
+            self.h[i] ^= v[i] ^ v[i + 8];
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn blake2b(input: &[u8], out_len: usize) -> Vec<u8> {
+        let mut hasher = Blake2b::new(out_len);
+        hasher.update(input);
+        hasher.finalize()
+    }
+
+    // RFC 7693 appendix A
+    #[test]
+    fn blake2b_matches_rfc_7693() {
+        assert_eq!(
+            hex(&blake2b(b"abc", 64)),
+            "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+             7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923"
+        );
+    }
+
+    // Inputs of exactly one block and of more than one, where the last block is handled differently
+    #[test]
+    fn blake2b_handles_block_boundaries() {
+        let input: Vec<u8> = (0..=255).collect();
+        assert_eq!(
+            hex(&blake2b(&input[..128], 64)),
+            "2319e3789c47e2daa5fe807f61bec2a1a6537fa03f19ff32e87eecbfd64b7e0e\
+             8ccff439ac333b040f19b0c4ddd11a61e24ac1fe0f10a039806c5dcc0da3d115"
+        );
+        assert_eq!(
+            hex(&blake2b(&input[..200], 64)),
+            "fb3c1f0f56a56f8e316fdf5d853c8c872c39635d083634c3904fc3ac07d1b578\
+             e85ff0e480e92d44ade33b62e893ee32343e79ddf6ef292e89b582d312502314"
+        );
+        assert_eq!(hex(&blake2b(b"abc", 32)), "bddd813c634239723171ef3fee98579b94964e3bb1cb3e427262c8c068d52319");
+    }
+
+    // RFC 9106 section 5.3
+    #[test]
+    fn argon2id_matches_rfc_9106() {
+        let tag = argon2id(&[1; 32], &[2; 16], &[3; 8], &[4; 12], 32, 3, 4, 32);
+        assert_eq!(hex(&tag), "0d640df58d78766c08c037a34a8b53c9d01ef0452d75b65eb52520e96b01e659");
+    }
+
+    #[tokio::test]
+    async fn hash_verifies_its_password_only() {
+        let phc = hash("correct horse battery staple").await.unwrap();
+        assert!(phc.starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+        assert!(verify("correct horse battery staple", &phc).await.unwrap());
+        assert!(!verify("correct horse battery stapler", &phc).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn malformed_hashes_never_match() {
+        assert!(!verify("", "").await.unwrap());
+        assert!(!verify("password", "$argon2i$v=19$m=32,t=1,p=1$c2FsdHNhbHQ$aGFzaGhhc2hoYXNoaGFzaA").await.unwrap());
+    }
+}
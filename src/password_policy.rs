@@ -0,0 +1,70 @@
+use sha1::{Digest, Sha1};
+use std::error::Error as StdError;
+use std::time::Duration;
+
+use crate::egress;
+use crate::models::password::PasswordPolicy;
+
+const BREACH_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Checks new passwords against the policy. The breach check uses the k-anonymity
+// range API of Have I Been Pwned (or a compatible mirror): only the first five
+// hex digits of the password's SHA-1 are sent, and the match is done locally.
+pub struct PasswordChecker {
+    policy: PasswordPolicy,
+    breach_api: String,
+    client: reqwest::Client,
+}
+
+impl PasswordChecker {
+    pub fn new(policy: PasswordPolicy, breach_api: String) -> Self {
+        Self {
+            policy,
+            breach_api: breach_api.trim_end_matches('/').to_string(),
+            client: egress::client(),
+        }
+    }
+
+    pub fn policy(&self) -> &PasswordPolicy {
+        &self.policy
+    }
+
+    // Every rule `password` breaks, empty when it's acceptable. An unreachable
+    // breach API is logged and skipped so signups don't depend on it.
+    pub async fn check(&self, password: &str, email: Option<&str>) -> Vec<String> {
+        let mut problems = self.policy.problems(password, email);
+        if self.policy.breach_check && problems.is_empty() {
+            match self.breach_count(password).await {
+                Ok(0) => {}
+                Ok(_) => problems.push("appears in a known data breach, choose another".into()),
+                Err(e) => log::warn!("Skipped password breach check: {}", e),
+            }
+        }
+        problems
+    }
+
+    // How often the password was seen in breaches
+    async fn breach_count(&self, password: &str) -> Result<u64, Box<dyn StdError>> {
+        let hash: String = Sha1::digest(password.as_bytes()).iter().map(|b| format!("{:02X}", b)).collect();
+        let (prefix, suffix) = hash.split_at(5);
+
+        let body = self
+            .client
+            .get(format!("{}/{}", self.breach_api, prefix))
+            .header("Add-Padding", "true")
+            .timeout(BREACH_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        // One `SUFFIX:COUNT` per line, padding entries have a count of 0
+        Ok(body
+            .lines()
+            .filter_map(|line| line.trim().split_once(':'))
+            .find(|(candidate, _)| candidate.eq_ignore_ascii_case(suffix))
+            .and_then(|(_, count)| count.parse().ok())
+            .unwrap_or(0))
+    }
+}
@@ -0,0 +1,289 @@
+use async_trait::async_trait;
+use std::error::Error as StdError;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::models::sort::SortSpec;
+use crate::models::user::{CreateUserRequest, Suggestion, UpdateUserRequest, User, UserFilter};
+use crate::repositories::user_store::{UserStore, UserStream};
+
+// Plugins compiled into this build. Forks add theirs here instead of patching
+// the routes or the repositories.
+pub fn registered() -> Vec<Box<dyn Plugin>> {
+    vec![]
+}
+
+// What happens when a hook fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailurePolicy {
+    // The write fails with the hook's error, a 500 for the caller. After hooks
+    // run once the write is done, so it stays done and only the response fails.
+    Abort,
+    // The failure is logged and the write goes on as if the hook had passed
+    Warn,
+}
+
+impl FailurePolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "abort" => Some(Self::Abort),
+            "warn" => Some(Self::Warn),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Operation {
+    Create,
+    Update,
+    Delete,
+}
+
+impl Operation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Create => "create",
+            Self::Update => "update",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+// Hooks around the writes of the user routes. Every hook does nothing unless
+// the plugin overrides it. Hooks of several plugins run in registration order.
+#[async_trait(?Send)]
+pub trait Plugin: Send + Sync {
+    // Used in logs and to set the policy with PLUGIN_POLICIES
+    fn name(&self) -> &str;
+
+    fn policy(&self) -> FailurePolicy {
+        FailurePolicy::Abort
+    }
+
+    // May change the request, later plugins and the store see the changed one
+    async fn before_create(&self, _user_req: &mut CreateUserRequest) -> Result<(), Box<dyn StdError>> {
+        Ok(())
+    }
+
+    async fn after_update(&self, _user: &User) -> Result<(), Box<dyn StdError>> {
+        Ok(())
+    }
+
+    async fn before_delete(&self, _id: &Uuid) -> Result<(), Box<dyn StdError>> {
+        Ok(())
+    }
+
+    // The store failed the write, or another plugin's hook aborted it
+    async fn on_error(&self, _operation: Operation, _error: &dyn StdError) {}
+}
+
+// The registered plugins, each with the policy it runs under
+pub struct Plugins {
+    plugins: Vec<(Box<dyn Plugin>, FailurePolicy)>,
+}
+
+impl Plugins {
+    // `overrides` from PLUGIN_POLICIES replace the plugins' own policies
+    pub fn new(plugins: Vec<Box<dyn Plugin>>, overrides: &[(String, FailurePolicy)]) -> Self {
+        for (name, _) in overrides {
+            if !plugins.iter().any(|plugin| plugin.name() == name) {
+                log::warn!("PLUGIN_POLICIES names {}, which isn't a registered plugin", name);
+            }
+        }
+        let plugins = plugins
+            .into_iter()
+            .map(|plugin| {
+                let policy = overrides
+                    .iter()
+                    .find(|(name, _)| name == plugin.name())
+                    .map_or_else(|| plugin.policy(), |(_, policy)| *policy);
+                log::info!("Loaded plugin {} ({:?} on failure)", plugin.name(), policy);
+                (plugin, policy)
+            })
+            .collect();
+        Self { plugins }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&dyn Plugin, FailurePolicy)> {
+        self.plugins.iter().map(|(plugin, policy)| (plugin.as_ref(), *policy))
+    }
+
+    async fn failed(&self, operation: Operation, error: &dyn StdError) {
+        for (plugin, _) in self.iter() {
+            plugin.on_error(operation, error).await;
+        }
+    }
+}
+
+// A failed hook stops the write only under Abort
+fn settle(plugin: &dyn Plugin, policy: FailurePolicy, operation: Operation, result: Result<(), Box<dyn StdError>>) -> Result<(), Box<dyn StdError>> {
+    let Err(e) = result else { return Ok(()) };
+    match policy {
+        FailurePolicy::Abort => {
+            log::warn!("Plugin {} aborted {}: {}", plugin.name(), operation.as_str(), e);
+            Err(e)
+        }
+        FailurePolicy::Warn => {
+            log::warn!("Plugin {} failed on {}, carrying on: {}", plugin.name(), operation.as_str(), e);
+            Ok(())
+        }
+    }
+}
+
+// A user store with the plugins' hooks around its writes, reads go straight through
+pub struct PluggedUserStore {
+    inner: Arc<dyn UserStore>,
+    plugins: Plugins,
+}
+
+impl PluggedUserStore {
+    pub fn new(inner: Arc<dyn UserStore>, plugins: Plugins) -> Self {
+        Self { inner, plugins }
+    }
+
+    async fn create(&self, user_req: &CreateUserRequest) -> Result<User, Box<dyn StdError>> {
+        let mut user_req = user_req.clone();
+        for (plugin, policy) in self.plugins.iter() {
+            settle(plugin, policy, Operation::Create, plugin.before_create(&mut user_req).await)?;
+        }
+        self.inner.create(&user_req).await
+    }
+
+    async fn update(&self, id: &Uuid, user_req: &UpdateUserRequest) -> Result<Option<User>, Box<dyn StdError>> {
+        let Some(user) = self.inner.update(id, user_req).await? else { return Ok(None) };
+        for (plugin, policy) in self.plugins.iter() {
+            settle(plugin, policy, Operation::Update, plugin.after_update(&user).await)?;
+        }
+        Ok(Some(user))
+    }
+
+    async fn delete(&self, id: &Uuid) -> Result<bool, Box<dyn StdError>> {
+        for (plugin, policy) in self.plugins.iter() {
+            settle(plugin, policy, Operation::Delete, plugin.before_delete(id).await)?;
+        }
+        self.inner.delete(id).await
+    }
+
+    // before_delete for every id first, one aborting refuses the whole batch
+    async fn delete_many(&self, ids: &[Uuid]) -> Result<Vec<Uuid>, Box<dyn StdError>> {
+        for id in ids {
+            for (plugin, policy) in self.plugins.iter() {
+                settle(plugin, policy, Operation::Delete, plugin.before_delete(id).await)?;
+            }
+        }
+        self.inner.delete_many(ids).await
+    }
+
+    // before_create for every user first, one aborting refuses the whole batch
+    async fn create_many(&self, user_reqs: &[CreateUserRequest]) -> Result<Vec<User>, Box<dyn StdError>> {
+        let mut user_reqs = user_reqs.to_vec();
+        for user_req in &mut user_reqs {
+            for (plugin, policy) in self.plugins.iter() {
+                settle(plugin, policy, Operation::Create, plugin.before_create(user_req).await)?;
+            }
+        }
+        self.inner.create_many(&user_reqs).await
+    }
+
+    // on_error for every plugin when the write failed
+    async fn reported<T>(&self, operation: Operation, result: Result<T, Box<dyn StdError>>) -> Result<T, Box<dyn StdError>> {
+        if let Err(e) = &result {
+            self.plugins.failed(operation, e.as_ref()).await;
+        }
+        result
+    }
+}
+
+#[async_trait(?Send)]
+impl UserStore for PluggedUserStore {
+    async fn stream_all(&self, sort: &SortSpec) -> Result<UserStream, Box<dyn StdError>> {
+        self.inner.stream_all(sort).await
+    }
+
+    async fn find(&self, filter: &UserFilter, sort: &SortSpec) -> Result<Vec<User>, Box<dyn StdError>> {
+        self.inner.find(filter, sort).await
+    }
+
+    async fn get_paginated(&self, filter: &UserFilter, sort: &SortSpec, offset: i64, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        self.inner.get_paginated(filter, sort, offset, limit).await
+    }
+
+    async fn get_after(&self, filter: &UserFilter, after: Option<&Uuid>, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        self.inner.get_after(filter, after, limit).await
+    }
+
+    async fn count(&self, filter: &UserFilter) -> Result<i64, Box<dyn StdError>> {
+        self.inner.count(filter).await
+    }
+
+    async fn get_by_id(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
+        self.inner.get_by_id(id).await
+    }
+
+    async fn get_by_ids(&self, ids: &[Uuid]) -> Result<Vec<Option<User>>, Box<dyn StdError>> {
+        self.inner.get_by_ids(ids).await
+    }
+
+    async fn get_by_username(&self, username: &str) -> Result<Option<User>, Box<dyn StdError>> {
+        self.inner.get_by_username(username).await
+    }
+
+    async fn renamed_to(&self, old_username: &str) -> Result<Option<String>, Box<dyn StdError>> {
+        self.inner.renamed_to(old_username).await
+    }
+
+    async fn exists(&self, id: &Uuid) -> Result<bool, Box<dyn StdError>> {
+        self.inner.exists(id).await
+    }
+
+    async fn exists_by_email(&self, email: &str) -> Result<bool, Box<dyn StdError>> {
+        self.inner.exists_by_email(email).await
+    }
+
+    async fn username_taken(&self, username: &str, except: Option<&Uuid>) -> Result<bool, Box<dyn StdError>> {
+        self.inner.username_taken(username, except).await
+    }
+
+    async fn create(&self, user_req: &CreateUserRequest) -> Result<User, Box<dyn StdError>> {
+        let result = PluggedUserStore::create(self, user_req).await;
+        self.reported(Operation::Create, result).await
+    }
+
+    async fn update(&self, id: &Uuid, user_req: &UpdateUserRequest) -> Result<Option<User>, Box<dyn StdError>> {
+        let result = PluggedUserStore::update(self, id, user_req).await;
+        self.reported(Operation::Update, result).await
+    }
+
+    async fn delete(&self, id: &Uuid) -> Result<bool, Box<dyn StdError>> {
+        let result = PluggedUserStore::delete(self, id).await;
+        self.reported(Operation::Delete, result).await
+    }
+
+    async fn delete_many(&self, ids: &[Uuid]) -> Result<Vec<Uuid>, Box<dyn StdError>> {
+        let result = PluggedUserStore::delete_many(self, ids).await;
+        self.reported(Operation::Delete, result).await
+    }
+
+    // Not a write the plugins hook into
+    async fn restore(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
+        self.inner.restore(id).await
+    }
+
+    async fn create_many(&self, user_reqs: &[CreateUserRequest]) -> Result<Vec<User>, Box<dyn StdError>> {
+        let result = PluggedUserStore::create_many(self, user_reqs).await;
+        self.reported(Operation::Create, result).await
+    }
+
+    async fn suggest(&self, query: &str, limit: usize) -> Result<Vec<Suggestion>, Box<dyn StdError>> {
+        self.inner.suggest(query, limit).await
+    }
+
+    async fn search(&self, query: &str, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        self.inner.search(query, limit).await
+    }
+}
@@ -0,0 +1,188 @@
+use deadpool_postgres::Pool;
+use std::error::Error as StdError;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::Client;
+
+// Postgres extensions the schema and queries rely on
+pub struct Extension {
+    pub name: &'static str,
+    pub purpose: &'static str,
+}
+
+pub const EXTENSIONS: &[Extension] = &[
+    Extension {
+        name: "pg_trgm",
+        purpose: "trigram indexes for fuzzy name and email search",
+    },
+    Extension {
+        name: "citext",
+        purpose: "case-insensitive text columns",
+    },
+    Extension {
+        name: "uuid-ossp",
+        purpose: "generating UUIDs in SQL",
+    },
+];
+
+// One thing the database must have or allow, and what to do about it when it doesn't
+#[derive(Debug)]
+pub struct Check {
+    pub name: String,
+    pub ok: bool,
+    pub detail: String,
+    pub remediation: Option<String>,
+}
+
+impl Check {
+    fn ok(name: String, detail: String) -> Self {
+        Self { name, ok: true, detail, remediation: None }
+    }
+
+    fn failed(name: String, detail: String, remediation: String) -> Self {
+        Self { name, ok: false, detail, remediation: Some(remediation) }
+    }
+}
+
+// Checks before migrating that the database has the extensions and grants the
+// server needs, so a missing one fails startup with instructions instead of
+// failing queries once traffic reaches them. Extensions that are available but
+// not installed are created when `create_extensions` is set and the database
+// user may.
+pub struct Preflight {
+    pool: Pool,
+    create_extensions: bool,
+    // Migrations create tables, so the schema has to allow it
+    migrations: bool,
+}
+
+impl Preflight {
+    pub fn new(pool: Pool, create_extensions: bool, migrations: bool) -> Self {
+        Self { pool, create_extensions, migrations }
+    }
+
+    // Every check, failed ones included. Errors only when the database can't be asked.
+    pub async fn run(&self) -> Result<Vec<Check>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let row = client.query_one("SELECT current_user::text, current_database()::text, current_schema()::text", &[]).await?;
+        let (user, database, schema): (String, String, Option<String>) = (row.get(0), row.get(1), row.get(2));
+
+        let mut checks = Vec::new();
+        if self.migrations {
+            checks.push(schema_privilege(&client, &user, schema.as_deref()).await?);
+        }
+        for extension in EXTENSIONS {
+            checks.push(self.extension(&client, extension, &user, &database).await?);
+        }
+        Ok(checks)
+    }
+
+    async fn extension(&self, client: &Client, extension: &Extension, user: &str, database: &str) -> Result<Check, Box<dyn StdError>> {
+        let name = format!("extension {}", extension.name);
+        let create = format!("CREATE EXTENSION IF NOT EXISTS \"{}\";", extension.name);
+
+        if let Some(version) = installed_version(client, extension.name).await? {
+            return Ok(Check::ok(name, format!("installed, version {}", version)));
+        }
+
+        let available = client
+            .query_opt("SELECT default_version FROM pg_available_extensions WHERE name = $1", &[&extension.name])
+            .await?;
+        if available.is_none() {
+            return Ok(Check::failed(
+                name,
+                format!("not available on the database server, needed for {}", extension.purpose),
+                format!(
+                    "Install the PostgreSQL contrib modules on the database server (the postgresql-contrib package on most \
+                     distributions), then restart to have it created or run `{}` in database {}",
+                    create, database
+                ),
+            ));
+        }
+
+        let as_superuser = format!(
+            "Connect to database {} as a superuser and run `{}`, or for extensions marked trusted on PostgreSQL 13+ \
+             run `GRANT CREATE ON DATABASE \"{}\" TO \"{}\";` and restart",
+            database, create, database, user
+        );
+        if !self.create_extensions {
+            return Ok(Check::failed(
+                name,
+                format!("available but not installed, needed for {}; PREFLIGHT_CREATE_EXTENSIONS=false", extension.purpose),
+                as_superuser,
+            ));
+        }
+
+        match client.batch_execute(&create).await {
+            Ok(()) => {
+                let version = installed_version(client, extension.name).await?.unwrap_or_default();
+                log::info!("Created Postgres extension {} version {}", extension.name, version);
+                Ok(Check::ok(name, format!("created, version {}", version)))
+            }
+            Err(e) if e.code() == Some(&SqlState::INSUFFICIENT_PRIVILEGE) => Ok(Check::failed(
+                name,
+                format!("not installed and user {} may not create it, needed for {}", user, extension.purpose),
+                as_superuser,
+            )),
+            Err(e) => Ok(Check::failed(name, format!("failed to create: {}", e), as_superuser)),
+        }
+    }
+}
+
+async fn installed_version(client: &Client, extension: &str) -> Result<Option<String>, Box<dyn StdError>> {
+    let row = client.query_opt("SELECT extversion::text FROM pg_extension WHERE extname = $1", &[&extension]).await?;
+    Ok(row.map(|row| row.get(0)))
+}
+
+async fn schema_privilege(client: &Client, user: &str, schema: Option<&str>) -> Result<Check, Box<dyn StdError>> {
+    let Some(schema) = schema else {
+        return Ok(Check::failed(
+            "schema".to_string(),
+            "no schema on the search_path exists".to_string(),
+            format!("Create one, e.g. `CREATE SCHEMA \"{}\" AUTHORIZATION \"{}\";`, or fix the search_path", user, user),
+        ));
+    };
+
+    let name = format!("create on schema {}", schema);
+    let row = client.query_one("SELECT has_schema_privilege($1, 'CREATE')", &[&schema]).await?;
+    if row.get(0) {
+        return Ok(Check::ok(name, format!("user {} may create tables", user)));
+    }
+    Ok(Check::failed(
+        name,
+        format!("user {} may not create tables, which migrations do", user),
+        format!(
+            "Run `GRANT CREATE ON SCHEMA \"{}\" TO \"{}\";` as the schema's owner, or run migrations as a user that may \
+             and start with MIGRATE_ON_STARTUP=false",
+            schema, user
+        ),
+    ))
+}
+
+// One line per check, remediation under the failed ones
+pub fn report(checks: &[Check]) -> String {
+    checks
+        .iter()
+        .map(|check| match &check.remediation {
+            None => format!("ok      {}: {}", check.name, check.detail),
+            Some(remediation) => format!("FAILED  {}: {}\n        {}", check.name, check.detail, remediation),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Startup error naming the failed checks and how to fix them, None when all passed
+pub fn failure(checks: &[Check]) -> Option<String> {
+    let failed: Vec<String> = checks
+        .iter()
+        .filter(|check| !check.ok)
+        .map(|check| format!("{} ({}). {}", check.name, check.detail, check.remediation.as_deref().unwrap_or_default()))
+        .collect();
+    (!failed.is_empty()).then(|| format!("Database preflight failed: {}", failed.join("; ")))
+}
@@ -0,0 +1,40 @@
+use image::codecs::png::PngEncoder;
+use image::{ExtendedColorType, ImageEncoder, Luma};
+use qrcode::render::svg;
+use qrcode::QrCode;
+use std::error::Error as StdError;
+
+use crate::models::user::QrFormat;
+
+// Smallest edge length of the rendered code in pixels, large enough to scan from a phone
+const MIN_DIMENSION: u32 = 256;
+
+// Render `data` as a QR code image, returning the bytes and their content type
+pub fn render(data: &str, format: QrFormat) -> Result<(Vec<u8>, &'static str), Box<dyn StdError>> {
+    let code = QrCode::new(data.as_bytes())?;
+
+    match format {
+        QrFormat::Svg => {
+            let image = code
+                .render::<svg::Color>()
+                .min_dimensions(MIN_DIMENSION, MIN_DIMENSION)
+                .build();
+            Ok((image.into_bytes(), "image/svg+xml"))
+        }
+        QrFormat::Png => {
+            let image = code
+                .render::<Luma<u8>>()
+                .min_dimensions(MIN_DIMENSION, MIN_DIMENSION)
+                .build();
+
+            let mut png = Vec::new();
+            PngEncoder::new(&mut png).write_image(
+                image.as_raw(),
+                image.width(),
+                image.height(),
+                ExtendedColorType::L8,
+            )?;
+            Ok((png, "image/png"))
+        }
+    }
+}
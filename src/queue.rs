@@ -0,0 +1,173 @@
+use actix_web::web;
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::error::Error as StdError;
+
+use crate::approvals::Approvals;
+use crate::models::public_id::PublicId;
+use crate::models::user::{CreateUserRequest, UpdateUserRequest};
+use crate::models::validate::Validate;
+use crate::repositories::user_repo::CachedUserRepository;
+
+// Instances share the subscription, so each command is handled once
+const QUEUE_GROUP: &str = "hello_world";
+
+#[derive(Deserialize)]
+struct IdCommand {
+    id: PublicId,
+}
+
+#[derive(Deserialize)]
+struct UpdateCommand {
+    id: PublicId,
+    changes: UpdateUserRequest,
+}
+
+// Serve user commands sent as NATS requests on `<prefix>.get`, `.create`,
+// `.update` and `.delete`. Replies are `{"status": <http status>, "body": ..}`
+// so queue clients see the same outcomes as HTTP ones. Updates skip the change
+// guard, the callers are internal systems rather than end users. They can't
+// name an admin either, so changes that need approval are refused.
+pub async fn spawn_consumer(
+    url: &str,
+    prefix: &str,
+    users: web::Data<CachedUserRepository>,
+    demo_mode: bool,
+    approvals_required: bool,
+) -> Result<(), Box<dyn StdError>> {
+    let client = async_nats::connect(url).await?;
+    let mut subscriber = client
+        .queue_subscribe(format!("{}.*", prefix), QUEUE_GROUP.to_string())
+        .await?;
+    log::info!("Consuming user commands from NATS on {}.*", prefix);
+
+    let prefix = format!("{}.", prefix);
+    actix_web::rt::spawn(async move {
+        while let Some(message) = subscriber.next().await {
+            let reply = match message.reply {
+                Some(reply) => reply,
+                None => {
+                    log::warn!("Ignoring NATS message on {} without a reply subject", message.subject);
+                    continue;
+                }
+            };
+            let command = message.subject.trim_start_matches(prefix.as_str()).to_string();
+            let client = client.clone();
+            let users = users.clone();
+
+            actix_web::rt::spawn(async move {
+                let (status, body) = handle(&users, &command, &message.payload, demo_mode, approvals_required).await;
+                let response = json!({ "status": status, "body": body }).to_string();
+                if let Err(e) = client.publish(reply, response.into()).await {
+                    log::error!("Failed to publish NATS reply for {}: {}", command, e);
+                }
+            });
+        }
+        log::warn!("NATS subscription closed, no longer consuming user commands");
+    });
+
+    Ok(())
+}
+
+async fn handle(users: &CachedUserRepository, command: &str, payload: &[u8], demo_mode: bool, approvals_required: bool) -> (u16, Value) {
+    let result = match command {
+        "get" => get(users, payload).await,
+        "create" => create(users, payload).await,
+        "update" => update(users, payload, approvals_required).await,
+        "delete" if demo_mode => {
+            log::warn!(target: "audit", "Refused NATS delete command on the demo instance");
+            Ok((403, json!({ "error": "Deleting is disabled on the demo instance" })))
+        }
+        "delete" if approvals_required => {
+            log::warn!(target: "audit", "Refused NATS delete command while approvals are required");
+            Ok((403, json!({ "error": "Deletes need approval, send them through the HTTP API" })))
+        }
+        "delete" => delete(users, payload).await,
+        other => Ok((404, json!({ "error": format!("Unknown command {}", other) }))),
+    };
+
+    result.unwrap_or_else(|e| {
+        log::error!("Failed to handle NATS {} command: {}", command, e);
+        (500, json!({ "error": format!("Failed to {} user", command) }))
+    })
+}
+
+// Parse a command body, or the 400 reply explaining why it couldn't be
+fn parse<T: for<'de> Deserialize<'de>>(payload: &[u8]) -> Result<T, (u16, Value)> {
+    serde_json::from_slice(payload).map_err(|e| (400, json!({ "error": e.to_string() })))
+}
+
+async fn get(users: &CachedUserRepository, payload: &[u8]) -> Result<(u16, Value), Box<dyn StdError>> {
+    let cmd: IdCommand = match parse(payload) {
+        Ok(cmd) => cmd,
+        Err(reply) => return Ok(reply),
+    };
+
+    Ok(match users.get_by_id(&cmd.id.0).await? {
+        Some(user) => (200, json!(user)),
+        None => (404, json!({ "error": "User not found" })),
+    })
+}
+
+async fn create(users: &CachedUserRepository, payload: &[u8]) -> Result<(u16, Value), Box<dyn StdError>> {
+    let user_req: CreateUserRequest = match parse(payload) {
+        Ok(user_req) => user_req,
+        Err(reply) => return Ok(reply),
+    };
+    if let Err(errors) = user_req.validate() {
+        return Ok((422, json!({ "error": "Validation failed", "errors": errors })));
+    }
+    if users.exists_by_email(&user_req.email).await? {
+        return Ok((409, json!({ "error": "A user with this email already exists" })));
+    }
+    if let Some(username) = &user_req.username {
+        if users.username_taken(username, None).await? {
+            return Ok((409, json!({ "error": "This username is already taken" })));
+        }
+    }
+
+    let user = users.create(&user_req).await?;
+    Ok((201, json!(user)))
+}
+
+async fn update(users: &CachedUserRepository, payload: &[u8], approvals_required: bool) -> Result<(u16, Value), Box<dyn StdError>> {
+    let cmd: UpdateCommand = match parse(payload) {
+        Ok(cmd) => cmd,
+        Err(reply) => return Ok(reply),
+    };
+    if let Err(errors) = cmd.changes.validate() {
+        return Ok((422, json!({ "error": "Validation failed", "errors": errors })));
+    }
+    if let Some(username) = &cmd.changes.username {
+        if users.username_taken(username, Some(&cmd.id.0)).await? {
+            return Ok((409, json!({ "error": "This username is already taken" })));
+        }
+    }
+    if approvals_required {
+        let current = users.get_by_id(&cmd.id.0).await?;
+        if current.is_some_and(|user| Approvals::changes_email(&user, &cmd.changes)) {
+            log::warn!(target: "audit", "Refused NATS email change of user {} while approvals are required", cmd.id.0);
+            return Ok((403, json!({ "error": "Email changes need approval, send them through the HTTP API" })));
+        }
+    }
+
+    Ok(match users.update(&cmd.id.0, &cmd.changes).await? {
+        Some(user) => (200, json!(user)),
+        None => (404, json!({ "error": "User not found" })),
+    })
+}
+
+async fn delete(users: &CachedUserRepository, payload: &[u8]) -> Result<(u16, Value), Box<dyn StdError>> {
+    let cmd: IdCommand = match parse(payload) {
+        Ok(cmd) => cmd,
+        Err(reply) => return Ok(reply),
+    };
+
+    Ok(if users.delete(&cmd.id.0).await? {
+        log::info!(target: "audit", "Deleted user {} via NATS", cmd.id.0);
+        (204, Value::Null)
+    } else {
+        (404, json!({ "error": "User not found" }))
+    })
+}
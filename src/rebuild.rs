@@ -0,0 +1,181 @@
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use uuid::Uuid;
+
+use crate::repositories::user_repo::CachedUserRepository;
+
+// Finished jobs kept for GET /admin/rebuild/{id}, the oldest are dropped first
+const MAX_FINISHED_JOBS: usize = 100;
+
+// Derived data that can drift from the users table and be rebuilt from it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RebuildTarget {
+    // The in-memory user cache of this instance
+    Cache,
+    // The typeahead index behind GET /users/suggest, also per instance
+    Suggestions,
+    // The full-text index behind GET /users/search, shared by all instances
+    SearchIndex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TargetProgress {
+    pub target: RebuildTarget,
+    pub status: JobStatus,
+    // Users loaded, when the target holds any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub users: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RebuildJob {
+    pub id: Uuid,
+    pub requested_by: String,
+    pub status: JobStatus,
+    // Rebuilt one after the other, in this order
+    pub targets: Vec<TargetProgress>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+// Background rebuilds requested by operators with POST /admin/rebuild. Jobs
+// live in memory on the instance that took the request, like the caches they
+// rebuild.
+pub struct Rebuilds {
+    users: web::Data<CachedUserRepository>,
+    jobs: Mutex<HashMap<Uuid, RebuildJob>>,
+}
+
+impl Rebuilds {
+    pub fn new(users: web::Data<CachedUserRepository>) -> Self {
+        Self { users, jobs: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<RebuildJob> {
+        self.jobs.lock().get(id).cloned()
+    }
+
+    // Queue a job for the targets and start it in the background. Err with the
+    // id of an unfinished job that already covers one of them.
+    pub fn start(rebuilds: &web::Data<Rebuilds>, targets: &[RebuildTarget], admin: &str) -> Result<RebuildJob, Uuid> {
+        let mut unique = Vec::with_capacity(targets.len());
+        for target in targets {
+            if !unique.contains(target) {
+                unique.push(*target);
+            }
+        }
+
+        let job = {
+            let mut jobs = rebuilds.jobs.lock();
+            let busy = jobs.values().find(|job| {
+                matches!(job.status, JobStatus::Pending | JobStatus::Running)
+                    && job.targets.iter().any(|progress| unique.contains(&progress.target))
+            });
+            if let Some(busy) = busy {
+                return Err(busy.id);
+            }
+
+            let job = RebuildJob {
+                id: Uuid::new_v4(),
+                requested_by: admin.to_string(),
+                status: JobStatus::Pending,
+                targets: unique
+                    .iter()
+                    .map(|target| TargetProgress { target: *target, status: JobStatus::Pending, users: None, error: None, finished_at: None })
+                    .collect(),
+                created_at: Utc::now(),
+                finished_at: None,
+            };
+            jobs.insert(job.id, job.clone());
+            prune(&mut jobs);
+            job
+        };
+
+        let rebuilds = rebuilds.clone();
+        let id = job.id;
+        actix_web::rt::spawn(async move {
+            rebuilds.run(id, unique).await;
+        });
+        Ok(job)
+    }
+
+    async fn run(&self, id: Uuid, targets: Vec<RebuildTarget>) {
+        self.update(id, |job| job.status = JobStatus::Running);
+
+        let mut failed = false;
+        for (i, target) in targets.into_iter().enumerate() {
+            self.update(id, |job| job.targets[i].status = JobStatus::Running);
+            let result = self.rebuild(target).await;
+            if let Err(e) = &result {
+                log::error!("Rebuild of {:?} failed: {}", target, e);
+            }
+            failed |= result.is_err();
+            self.update(id, |job| {
+                let progress = &mut job.targets[i];
+                progress.finished_at = Some(Utc::now());
+                match result {
+                    Ok(users) => {
+                        progress.status = JobStatus::Done;
+                        progress.users = users;
+                    }
+                    Err(e) => {
+                        progress.status = JobStatus::Failed;
+                        progress.error = Some(e.to_string());
+                    }
+                }
+            });
+        }
+
+        self.update(id, |job| {
+            job.status = if failed { JobStatus::Failed } else { JobStatus::Done };
+            job.finished_at = Some(Utc::now());
+        });
+        log::info!(target: "audit", "Rebuild {} finished{}", id, if failed { " with failures" } else { "" });
+    }
+
+    async fn rebuild(&self, target: RebuildTarget) -> Result<Option<usize>, Box<dyn StdError>> {
+        match target {
+            RebuildTarget::Cache => self.users.reload_cache().await.map(Some),
+            RebuildTarget::Suggestions => self.users.rebuild_suggestions().await.map(Some),
+            RebuildTarget::SearchIndex => self.users.reindex_search().await.map(|_| None),
+        }
+    }
+
+    fn update(&self, id: Uuid, f: impl FnOnce(&mut RebuildJob)) {
+        if let Some(job) = self.jobs.lock().get_mut(&id) {
+            f(job);
+        }
+    }
+}
+
+fn prune(jobs: &mut HashMap<Uuid, RebuildJob>) {
+    let mut finished: Vec<(DateTime<Utc>, Uuid)> = jobs
+        .values()
+        .filter(|job| job.finished_at.is_some())
+        .map(|job| (job.created_at, job.id))
+        .collect();
+    if finished.len() <= MAX_FINISHED_JOBS {
+        return;
+    }
+    finished.sort();
+    for (_, id) in &finished[..finished.len() - MAX_FINISHED_JOBS] {
+        jobs.remove(id);
+    }
+}
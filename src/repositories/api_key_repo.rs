@@ -0,0 +1,133 @@
+use deadpool_postgres::Pool;
+use std::error::Error as StdError;
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+use crate::models::api_key::ApiKey;
+
+const API_KEY_COLUMNS: &str = "id, name, prefix, created_by, created_at, last_used_at, revoked_at";
+
+pub struct ApiKeyRepository {
+    pool: Pool,
+}
+
+fn api_key_from_row(row: &Row) -> ApiKey {
+    ApiKey {
+        id: row.get("id"),
+        name: row.get("name"),
+        prefix: row.get("prefix"),
+        created_by: row.get("created_by"),
+        created_at: row.get("created_at"),
+        last_used_at: row.get("last_used_at"),
+        revoked_at: row.get("revoked_at"),
+    }
+}
+
+impl ApiKeyRepository {
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn create(&self, name: &str, prefix: &str, key_hash: &[u8], created_by: &str) -> Result<ApiKey, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let row = client
+            .query_one(
+                &format!(
+                    "INSERT INTO api_keys (id, name, prefix, key_hash, created_by)
+                     VALUES ($1, $2, $3, $4, $5)
+                     RETURNING {}",
+                    API_KEY_COLUMNS
+                ),
+                &[&Uuid::new_v4(), &name, &prefix, &key_hash, &created_by],
+            )
+            .await?;
+
+        Ok(api_key_from_row(&row))
+    }
+
+    // Newest first, revoked keys included
+    pub async fn list(&self) -> Result<Vec<ApiKey>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let rows = client
+            .query(&format!("SELECT {} FROM api_keys ORDER BY created_at DESC, id", API_KEY_COLUMNS), &[])
+            .await?;
+
+        Ok(rows.iter().map(api_key_from_row).collect())
+    }
+
+    pub async fn get(&self, id: &Uuid) -> Result<Option<ApiKey>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let row = client
+            .query_opt(&format!("SELECT {} FROM api_keys WHERE id = $1", API_KEY_COLUMNS), &[id])
+            .await?;
+
+        Ok(row.map(|row| api_key_from_row(&row)))
+    }
+
+    // None if the key doesn't exist or is already revoked
+    pub async fn revoke(&self, id: &Uuid) -> Result<Option<ApiKey>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let row = client
+            .query_opt(
+                &format!(
+                    "UPDATE api_keys SET revoked_at = now() WHERE id = $1 AND revoked_at IS NULL RETURNING {}",
+                    API_KEY_COLUMNS
+                ),
+                &[id],
+            )
+            .await?;
+
+        Ok(row.map(|row| api_key_from_row(&row)))
+    }
+
+    // The unrevoked key with this hash, marked as used
+    pub async fn authenticate(&self, key_hash: &[u8]) -> Result<Option<ApiKey>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let row = client
+            .query_opt(
+                &format!(
+                    "UPDATE api_keys SET last_used_at = now() WHERE key_hash = $1 AND revoked_at IS NULL RETURNING {}",
+                    API_KEY_COLUMNS
+                ),
+                &[&key_hash],
+            )
+            .await?;
+
+        Ok(row.map(|row| api_key_from_row(&row)))
+    }
+}
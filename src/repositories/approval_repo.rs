@@ -0,0 +1,180 @@
+use deadpool_postgres::Pool;
+use std::error::Error as StdError;
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+use crate::models::approval::{Approval, ApprovalAction, ApprovalStatus};
+use crate::models::user::UpdateUserRequest;
+
+const APPROVAL_COLUMNS: &str =
+    "id, action, user_id, changes::text AS changes, requested_by, requested_at, status, decided_by, decided_at";
+
+pub struct ApprovalRepository {
+    pool: Pool,
+}
+
+fn approval_from_row(row: &Row) -> Result<Approval, Box<dyn StdError>> {
+    let action: String = row.get("action");
+    let status: String = row.get("status");
+    let changes: Option<String> = row.get("changes");
+
+    Ok(Approval {
+        id: row.get("id"),
+        action: ApprovalAction::parse(&action).ok_or_else(|| format!("Unknown approval action {}", action))?,
+        user_id: row.get("user_id"),
+        changes: changes.map(|changes| serde_json::from_str(&changes)).transpose()?,
+        requested_by: row.get("requested_by"),
+        requested_at: row.get("requested_at"),
+        status: ApprovalStatus::parse(&status).ok_or_else(|| format!("Unknown approval status {}", status))?,
+        decided_by: row.get("decided_by"),
+        decided_at: row.get("decided_at"),
+    })
+}
+
+impl ApprovalRepository {
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    // Hold a change for approval. None if the same kind of change is already
+    // pending for the user.
+    pub async fn create(
+        &self,
+        action: ApprovalAction,
+        user_id: &Uuid,
+        changes: Option<&UpdateUserRequest>,
+        requested_by: &str,
+    ) -> Result<Option<Approval>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let changes = changes.map(serde_json::to_string).transpose()?;
+        let row = client
+            .query_opt(
+                &format!(
+                    "INSERT INTO approvals (id, action, user_id, changes, requested_by)
+                     VALUES ($1, $2, $3, $4::text::jsonb, $5)
+                     ON CONFLICT (user_id, action) WHERE status = 'pending' DO NOTHING
+                     RETURNING {}",
+                    APPROVAL_COLUMNS
+                ),
+                &[&Uuid::new_v4(), &action.as_str(), user_id, &changes, &requested_by],
+            )
+            .await?;
+
+        row.map(|row| approval_from_row(&row)).transpose()
+    }
+
+    pub async fn get(&self, id: &Uuid) -> Result<Option<Approval>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let row = client
+            .query_opt(&format!("SELECT {} FROM approvals WHERE id = $1", APPROVAL_COLUMNS), &[id])
+            .await?;
+
+        row.map(|row| approval_from_row(&row)).transpose()
+    }
+
+    pub async fn pending_for(&self, user_id: &Uuid, action: ApprovalAction) -> Result<Option<Approval>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let row = client
+            .query_opt(
+                &format!(
+                    "SELECT {} FROM approvals WHERE user_id = $1 AND action = $2 AND status = 'pending'",
+                    APPROVAL_COLUMNS
+                ),
+                &[user_id, &action.as_str()],
+            )
+            .await?;
+
+        row.map(|row| approval_from_row(&row)).transpose()
+    }
+
+    // Oldest first, so the queue is worked through in order
+    pub async fn list(&self, status: Option<ApprovalStatus>) -> Result<Vec<Approval>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let status = status.map(|status| status.as_str());
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT {} FROM approvals WHERE $1::varchar IS NULL OR status = $1 ORDER BY requested_at, id",
+                    APPROVAL_COLUMNS
+                ),
+                &[&status],
+            )
+            .await?;
+
+        rows.iter().map(approval_from_row).collect()
+    }
+
+    // Move a pending request to `status`, recording who decided. Approvals only
+    // succeed for someone other than the requester. None if the request isn't
+    // pending any more or the decider isn't allowed to make this decision.
+    pub async fn decide(&self, id: &Uuid, status: ApprovalStatus, decided_by: &str) -> Result<Option<Approval>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let row = client
+            .query_opt(
+                &format!(
+                    "UPDATE approvals SET status = $2, decided_by = $3, decided_at = now()
+                     WHERE id = $1 AND status = 'pending' AND ($2 <> 'approved' OR requested_by <> $3)
+                     RETURNING {}",
+                    APPROVAL_COLUMNS
+                ),
+                &[id, &status.as_str(), &decided_by],
+            )
+            .await?;
+
+        row.map(|row| approval_from_row(&row)).transpose()
+    }
+
+    // Put a decided request back in the queue, when carrying it out failed
+    pub async fn reopen(&self, id: &Uuid) -> Result<(), Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        client
+            .execute(
+                "UPDATE approvals SET status = 'pending', decided_by = NULL, decided_at = NULL WHERE id = $1",
+                &[id],
+            )
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use deadpool_postgres::Pool;
+use std::error::Error as StdError;
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+use crate::models::audit::{AuditAction, AuditEntry};
+use crate::models::user::User;
+
+// Snapshots come back as text, the Postgres client here has no JSON support
+const AUDIT_COLUMNS: &str = "id, user_id, action, actor, request_id, recorded_at, before::text AS before, after::text AS after";
+
+// Reads the audit log the users table's trigger writes
+pub struct AuditRepository {
+    pool: Pool,
+}
+
+fn snapshot(row: &Row, column: &str) -> Result<Option<User>, Box<dyn StdError>> {
+    match row.get::<_, Option<String>>(column) {
+        Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+        None => Ok(None),
+    }
+}
+
+fn entry_from_row(row: &Row) -> Result<AuditEntry, Box<dyn StdError>> {
+    let action: String = row.get("action");
+    Ok(AuditEntry {
+        id: row.get("id"),
+        user_id: row.get("user_id"),
+        action: AuditAction::parse(&action).ok_or_else(|| format!("Unknown audit action {}", action))?,
+        actor: row.get("actor"),
+        request_id: row.get("request_id"),
+        recorded_at: row.get("recorded_at"),
+        before: snapshot(row, "before")?,
+        after: snapshot(row, "after")?,
+    })
+}
+
+impl AuditRepository {
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    // Up to `limit` entries older than entry `before`, newest first, for one user or all of them
+    pub async fn page(&self, user_id: Option<&Uuid>, before: Option<i64>, limit: i64) -> Result<Vec<AuditEntry>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT {} FROM audit_log
+                     WHERE ($1::uuid IS NULL OR user_id = $1) AND ($2::bigint IS NULL OR id < $2)
+                     ORDER BY id DESC LIMIT $3",
+                    AUDIT_COLUMNS
+                ),
+                &[&user_id, &before, &limit],
+            )
+            .await?;
+
+        rows.iter().map(entry_from_row).collect()
+    }
+}
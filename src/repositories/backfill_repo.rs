@@ -0,0 +1,209 @@
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{Pool, Transaction};
+use serde::Serialize;
+use std::error::Error as StdError;
+use uuid::Uuid;
+
+use crate::backfill::{self, BackfillKind};
+use crate::repositories::transaction::with_transaction;
+
+#[derive(Debug, Serialize)]
+pub struct BackfillProgress {
+    pub name: String,
+    // pending, running, done or failed
+    pub status: String,
+    // Users looked at so far, out of about `total`
+    pub processed: i64,
+    pub changed: i64,
+    pub total: i64,
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+pub struct Batch {
+    // Users changed by the batch, for the cache
+    pub changed: Vec<Uuid>,
+    pub done: bool,
+}
+
+// Lowercase and trim emails. When several users would end up with the same
+// email only the oldest is changed, and none if another user already has it,
+// those are left for an operator to merge.
+const NORMALIZE_EMAILS: &str = "UPDATE users SET email = lower(btrim(users.email)), updated_at = now()
+     FROM (
+         SELECT DISTINCT ON (lower(btrim(email))) id FROM users
+         WHERE id = ANY($1) AND email <> lower(btrim(email))
+         ORDER BY lower(btrim(email)), created_at, id
+     ) AS pick
+     WHERE users.id = pick.id
+       AND NOT EXISTS (SELECT 1 FROM users AS other WHERE other.email = lower(btrim(users.email)))
+     RETURNING users.id";
+
+pub struct BackfillRepository {
+    pool: Pool,
+}
+
+impl BackfillRepository {
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn list(&self) -> Result<Vec<BackfillProgress>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let rows = client
+            .query(
+                "SELECT name, status, processed, changed, total, error, started_at, updated_at, finished_at
+                 FROM backfills ORDER BY started_at, name",
+                &[],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| BackfillProgress {
+                name: row.get("name"),
+                status: row.get("status"),
+                processed: row.get("processed"),
+                changed: row.get("changed"),
+                total: row.get("total"),
+                error: row.get("error"),
+                started_at: row.get("started_at"),
+                updated_at: row.get("updated_at"),
+                finished_at: row.get("finished_at"),
+            })
+            .collect())
+    }
+
+    // Record the backfill, or re-estimate what's left of one started before.
+    // False if it has finished already.
+    pub async fn start(&self, kind: BackfillKind) -> Result<bool, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let row = client
+            .query_opt(
+                "INSERT INTO backfills (name, total) VALUES ($1, (SELECT COUNT(*) FROM users))
+                 ON CONFLICT (name) DO UPDATE SET
+                     total = backfills.processed
+                         + (SELECT COUNT(*) FROM users WHERE backfills.cursor IS NULL OR users.id > backfills.cursor),
+                     updated_at = now()
+                 WHERE backfills.status <> 'done'
+                 RETURNING name",
+                &[&kind.name()],
+            )
+            .await?;
+        Ok(row.is_some())
+    }
+
+    // Apply the backfill to the next `size` users after the saved cursor and
+    // move the cursor past them, in one transaction. The backfill's row is
+    // locked meanwhile, so instances sharing the database take turns.
+    pub async fn run_batch(&self, kind: BackfillKind, size: i64) -> Result<Batch, Box<dyn StdError>> {
+        with_transaction(&self.pool, |tx| {
+            Box::pin(async move {
+                let row = tx
+                    .query_opt("SELECT cursor, status FROM backfills WHERE name = $1 FOR UPDATE", &[&kind.name()])
+                    .await?
+                    .ok_or_else(|| format!("Backfill {} was never started", kind.name()))?;
+                if row.get::<_, String>("status") == "done" {
+                    return Ok(Batch { changed: Vec::new(), done: true });
+                }
+                let cursor: Option<Uuid> = row.get("cursor");
+
+                let ids: Vec<Uuid> = tx
+                    .query(
+                        "SELECT id FROM users WHERE $1::uuid IS NULL OR id > $1 ORDER BY id LIMIT $2",
+                        &[&cursor, &size],
+                    )
+                    .await?
+                    .iter()
+                    .map(|row| row.get(0))
+                    .collect();
+
+                let changed = match kind {
+                    BackfillKind::NormalizeEmails => {
+                        tx.query(NORMALIZE_EMAILS, &[&ids]).await?.iter().map(|row| row.get(0)).collect()
+                    }
+                    BackfillKind::PopulateUsernames => populate_usernames(tx, &ids).await?,
+                };
+
+                let done = (ids.len() as i64) < size;
+                tx.execute(
+                    "UPDATE backfills SET
+                         cursor = COALESCE($2, cursor),
+                         processed = processed + $3,
+                         changed = changed + $4,
+                         status = CASE WHEN $5 THEN 'done' ELSE 'running' END,
+                         error = NULL,
+                         updated_at = now(),
+                         finished_at = CASE WHEN $5 THEN now() END
+                     WHERE name = $1",
+                    &[&kind.name(), &ids.last(), &(ids.len() as i64), &(changed.len() as i64), &done],
+                )
+                .await?;
+
+                Ok(Batch { changed, done })
+            })
+        })
+        .await
+    }
+
+    pub async fn fail(&self, kind: BackfillKind, error: &str) -> Result<(), Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        client
+            .execute(
+                "UPDATE backfills SET status = 'failed', error = $2, updated_at = now() WHERE name = $1",
+                &[&kind.name(), &error],
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+// Give users without a username one derived from their email. A taken name
+// gets part of the id appended, if that's taken too the user is skipped.
+async fn populate_usernames(tx: &Transaction<'_>, ids: &[Uuid]) -> Result<Vec<Uuid>, Box<dyn StdError>> {
+    let rows = tx
+        .query("SELECT id, email FROM users WHERE id = ANY($1) AND username IS NULL FOR UPDATE", &[&ids])
+        .await?;
+
+    let mut changed = Vec::new();
+    for row in rows {
+        let id: Uuid = row.get("id");
+        let email: String = row.get("email");
+        for username in backfill::usernames_for(&email, &id) {
+            let updated = tx
+                .execute(
+                    "UPDATE users SET username = $2, updated_at = now()
+                     WHERE id = $1 AND NOT EXISTS (SELECT 1 FROM users WHERE lower(username) = lower($2))",
+                    &[&id, &username],
+                )
+                .await?;
+            if updated > 0 {
+                changed.push(id);
+                break;
+            }
+        }
+    }
+    Ok(changed)
+}
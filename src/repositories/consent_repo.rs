@@ -0,0 +1,137 @@
+use deadpool_postgres::Pool;
+use std::error::Error as StdError;
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+use crate::models::consent::Consent;
+
+const CONSENT_COLUMNS: &str = "purpose, granted_at, revoked_at, source";
+
+pub struct ConsentRepository {
+    pool: Pool,
+}
+
+fn consent_from_row(row: &Row) -> Consent {
+    Consent {
+        purpose: row.get("purpose"),
+        granted_at: row.get("granted_at"),
+        revoked_at: row.get("revoked_at"),
+        source: row.get("source"),
+    }
+}
+
+impl ConsentRepository {
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    // Every consent the user has given, current and revoked, newest first
+    pub async fn list_for_user(&self, user_id: &Uuid) -> Result<Vec<Consent>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT {} FROM consents WHERE user_id = $1 ORDER BY granted_at DESC, id DESC",
+                    CONSENT_COLUMNS
+                ),
+                &[user_id],
+            )
+            .await?;
+
+        Ok(rows.iter().map(consent_from_row).collect())
+    }
+
+    // Whether the user currently consents to the purpose
+    pub async fn has_consent(&self, user_id: &Uuid, purpose: &str) -> Result<bool, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let row = client
+            .query_one(
+                "SELECT EXISTS(SELECT 1 FROM consents WHERE user_id = $1 AND purpose = $2 AND revoked_at IS NULL)",
+                &[user_id, &purpose],
+            )
+            .await?;
+        Ok(row.get(0))
+    }
+
+    // Record consent for the purpose. Returns the active consent and whether it
+    // was created by this call; granting again keeps the original record.
+    pub async fn grant(&self, user_id: &Uuid, purpose: &str, source: &str) -> Result<(Consent, bool), Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        // A revoke can land between the insert and the lookup, then the grant is retried
+        loop {
+            let inserted = client
+                .query_opt(
+                    &format!(
+                        "INSERT INTO consents (user_id, purpose, source) VALUES ($1, $2, $3)
+                         ON CONFLICT (user_id, purpose) WHERE revoked_at IS NULL DO NOTHING
+                         RETURNING {}",
+                        CONSENT_COLUMNS
+                    ),
+                    &[user_id, &purpose, &source],
+                )
+                .await?;
+            if let Some(row) = inserted {
+                return Ok((consent_from_row(&row), true));
+            }
+
+            let existing = client
+                .query_opt(
+                    &format!(
+                        "SELECT {} FROM consents WHERE user_id = $1 AND purpose = $2 AND revoked_at IS NULL",
+                        CONSENT_COLUMNS
+                    ),
+                    &[user_id, &purpose],
+                )
+                .await?;
+            if let Some(row) = existing {
+                return Ok((consent_from_row(&row), false));
+            }
+        }
+    }
+
+    // Revoke the active consent for the purpose, None if there wasn't one
+    pub async fn revoke(&self, user_id: &Uuid, purpose: &str) -> Result<Option<Consent>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let row = client
+            .query_opt(
+                &format!(
+                    "UPDATE consents SET revoked_at = now()
+                     WHERE user_id = $1 AND purpose = $2 AND revoked_at IS NULL
+                     RETURNING {}",
+                    CONSENT_COLUMNS
+                ),
+                &[user_id, &purpose],
+            )
+            .await?;
+
+        Ok(row.map(|row| consent_from_row(&row)))
+    }
+}
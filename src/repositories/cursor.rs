@@ -0,0 +1,143 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use sha2::Sha256;
+use std::fmt;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::clock::Clock;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const SIGNED: u8 = b's';
+const SEALED: u8 = b'e';
+// Truncated HMAC, still far beyond guessing range
+const MAC_LEN: usize = 16;
+// Expiry and the id of the last row
+const PAYLOAD_LEN: usize = 8 + 16;
+
+#[derive(Debug, Clone)]
+pub struct CursorConfig {
+    // (key id, secret), the first signs new cursors, the others only verify old ones
+    pub keys: Vec<(String, Vec<u8>)>,
+    // Hide the boundary id instead of only signing it
+    pub encrypt: bool,
+    pub ttl_secs: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CursorError {
+    Invalid,
+    Expired,
+}
+
+impl fmt::Display for CursorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid => f.write_str("Invalid cursor, use the next_cursor of a previous page"),
+            Self::Expired => f.write_str("Cursor expired, start again from the first page"),
+        }
+    }
+}
+
+struct CursorKey {
+    id: String,
+    mac_key: Vec<u8>,
+    sealing_key: LessSafeKey,
+}
+
+// Separate keys for signing and sealing, both from the configured secret
+fn derive(secret: &[u8], purpose: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(purpose.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+// Keyset cursors as opaque `{key id}.{body}` strings, so clients can't move a
+// page boundary or read one. The body is the expiry and the last id, followed
+// by an HMAC, or sealed with AES-256-GCM when encryption is on. Both kinds are
+// accepted whatever the setting, so turning encryption on or off and rotating
+// keys don't break cursors that are still being followed.
+pub struct CursorCodec {
+    keys: Vec<CursorKey>,
+    encrypt: bool,
+    ttl_secs: u64,
+    clock: Arc<dyn Clock>,
+}
+
+impl CursorCodec {
+    pub fn new(config: &CursorConfig, clock: Arc<dyn Clock>) -> Self {
+        let keys = config
+            .keys
+            .iter()
+            .map(|(id, secret)| CursorKey {
+                id: id.clone(),
+                mac_key: derive(secret, "cursor-mac"),
+                sealing_key: LessSafeKey::new(
+                    UnboundKey::new(&AES_256_GCM, &derive(secret, "cursor-seal")).expect("derived keys are 32 bytes"),
+                ),
+            })
+            .collect();
+        Self { keys, encrypt: config.encrypt, ttl_secs: config.ttl_secs, clock }
+    }
+
+    pub fn encode(&self, id: &Uuid) -> String {
+        let key = &self.keys[0];
+        let mut payload = Vec::with_capacity(PAYLOAD_LEN);
+        payload.extend_from_slice(&(self.clock.unix_now() + self.ttl_secs).to_be_bytes());
+        payload.extend_from_slice(id.as_bytes());
+
+        let body = if self.encrypt {
+            let nonce: [u8; NONCE_LEN] = rand::random();
+            key.sealing_key
+                .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(key.id.as_bytes()), &mut payload)
+                .expect("payloads are far below the AES-GCM limit");
+            [&[SEALED][..], &nonce, &payload].concat()
+        } else {
+            let mut body = [&[SIGNED][..], &payload].concat();
+            let mac = Self::mac(key, &body);
+            body.extend_from_slice(&mac[..MAC_LEN]);
+            body
+        };
+        format!("{}.{}", key.id, URL_SAFE_NO_PAD.encode(body))
+    }
+
+    pub fn decode(&self, cursor: &str) -> Result<Uuid, CursorError> {
+        let (key_id, body) = cursor.split_once('.').ok_or(CursorError::Invalid)?;
+        let key = self.keys.iter().find(|key| key.id == key_id).ok_or(CursorError::Invalid)?;
+        let body = URL_SAFE_NO_PAD.decode(body).map_err(|_| CursorError::Invalid)?;
+
+        let payload = match body.split_first() {
+            Some((&SIGNED, rest)) if rest.len() == PAYLOAD_LEN + MAC_LEN => {
+                let mut mac = HmacSha256::new_from_slice(&key.mac_key).expect("HMAC accepts keys of any length");
+                mac.update(&body[..1 + PAYLOAD_LEN]);
+                mac.verify_truncated_left(&rest[PAYLOAD_LEN..]).map_err(|_| CursorError::Invalid)?;
+                rest[..PAYLOAD_LEN].to_vec()
+            }
+            Some((&SEALED, rest)) if rest.len() == NONCE_LEN + PAYLOAD_LEN + AES_256_GCM.tag_len() => {
+                let (nonce, sealed) = rest.split_at(NONCE_LEN);
+                let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| CursorError::Invalid)?;
+                let mut sealed = sealed.to_vec();
+                key.sealing_key
+                    .open_in_place(nonce, Aad::from(key.id.as_bytes()), &mut sealed)
+                    .map_err(|_| CursorError::Invalid)?
+                    .to_vec()
+            }
+            _ => return Err(CursorError::Invalid),
+        };
+
+        let (expires, id) = payload.split_at(8);
+        if u64::from_be_bytes(expires.try_into().expect("8 bytes")) < self.clock.unix_now() {
+            return Err(CursorError::Expired);
+        }
+        Uuid::from_slice(id).map_err(|_| CursorError::Invalid)
+    }
+
+    fn mac(key: &CursorKey, body: &[u8]) -> Vec<u8> {
+        let mut mac = HmacSha256::new_from_slice(&key.mac_key).expect("HMAC accepts keys of any length");
+        mac.update(body);
+        mac.finalize().into_bytes().to_vec()
+    }
+}
@@ -0,0 +1,132 @@
+use deadpool_postgres::Pool;
+use std::error::Error as StdError;
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+use crate::models::identity::Identity;
+
+// Result of linking an identity to a user
+pub enum LinkOutcome {
+    Linked(Identity),
+    // Already linked to this user, linking again is a no-op
+    AlreadyLinked(Identity),
+    // Linked to a different user, which has to unlink it first
+    LinkedElsewhere(Uuid),
+}
+
+pub struct IdentityRepository {
+    pool: Pool,
+}
+
+fn identity_from_row(row: &Row) -> Identity {
+    Identity {
+        provider: row.get("provider"),
+        subject: row.get("subject"),
+        user_id: row.get("user_id"),
+        created_at: row.get("created_at"),
+    }
+}
+
+impl IdentityRepository {
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn list_for_user(&self, user_id: &Uuid) -> Result<Vec<Identity>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let rows = client
+            .query(
+                "SELECT provider, subject, user_id, created_at FROM identities
+                 WHERE user_id = $1 ORDER BY provider, subject",
+                &[user_id],
+            )
+            .await?;
+
+        Ok(rows.iter().map(identity_from_row).collect())
+    }
+
+    // The user an identity is linked to
+    pub async fn find_user(&self, provider: &str, subject: &str) -> Result<Option<Uuid>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let row = client
+            .query_opt(
+                "SELECT user_id FROM identities WHERE provider = $1 AND subject = $2",
+                &[&provider, &subject],
+            )
+            .await?;
+
+        Ok(row.map(|row| row.get("user_id")))
+    }
+
+    pub async fn link(&self, user_id: &Uuid, provider: &str, subject: &str) -> Result<LinkOutcome, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        // An unlink can slip in between the insert and the lookup, leaving
+        // nothing to report, in which case the insert is simply tried again
+        loop {
+            let inserted = client
+                .query_opt(
+                    "INSERT INTO identities (provider, subject, user_id) VALUES ($1, $2, $3)
+                     ON CONFLICT (provider, subject) DO NOTHING
+                     RETURNING provider, subject, user_id, created_at",
+                    &[&provider, &subject, user_id],
+                )
+                .await?;
+            if let Some(row) = inserted {
+                return Ok(LinkOutcome::Linked(identity_from_row(&row)));
+            }
+
+            let existing = client
+                .query_opt(
+                    "SELECT provider, subject, user_id, created_at FROM identities
+                     WHERE provider = $1 AND subject = $2",
+                    &[&provider, &subject],
+                )
+                .await?;
+            match existing.map(|row| identity_from_row(&row)) {
+                Some(identity) if identity.user_id == *user_id => return Ok(LinkOutcome::AlreadyLinked(identity)),
+                Some(identity) => return Ok(LinkOutcome::LinkedElsewhere(identity.user_id)),
+                None => continue,
+            }
+        }
+    }
+
+    pub async fn unlink(&self, user_id: &Uuid, provider: &str, subject: &str) -> Result<bool, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let rows_affected = client
+            .execute(
+                "DELETE FROM identities WHERE user_id = $1 AND provider = $2 AND subject = $3",
+                &[user_id, &provider, &subject],
+            )
+            .await?;
+
+        Ok(rows_affected > 0)
+    }
+}
@@ -0,0 +1,89 @@
+use deadpool_postgres::Pool;
+use std::error::Error as StdError;
+use tokio_postgres::Row;
+
+use crate::ledger::{Call, LedgerEntry, GENESIS};
+use crate::repositories::transaction::with_transaction;
+
+const LEDGER_COLUMNS: &str = "seq, recorded_at, actor, method, path, status, request_id, prev_hash, hash";
+
+// Held while appending, so each row is chained to the one written just before it
+const APPEND_LOCK_KEY: i64 = 0x6c65_6467_6572_0001;
+
+pub struct LedgerRepository {
+    pool: Pool,
+}
+
+fn entry_from_row(row: &Row) -> LedgerEntry {
+    LedgerEntry {
+        seq: row.get("seq"),
+        recorded_at: row.get("recorded_at"),
+        actor: row.get("actor"),
+        method: row.get("method"),
+        path: row.get("path"),
+        status: row.get("status"),
+        request_id: row.get("request_id"),
+        prev_hash: row.get("prev_hash"),
+        hash: row.get("hash"),
+    }
+}
+
+impl LedgerRepository {
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    // Chain the call to the current last row and store it
+    pub async fn append(&self, call: &Call, status: i16) -> Result<LedgerEntry, Box<dyn StdError>> {
+        with_transaction(&self.pool, |tx| {
+            let call = call.clone();
+            Box::pin(async move {
+                tx.execute("SELECT pg_advisory_xact_lock($1)", &[&APPEND_LOCK_KEY]).await?;
+                let last = tx.query_opt("SELECT seq, hash FROM admin_ledger ORDER BY seq DESC LIMIT 1", &[]).await?;
+                let (seq, prev_hash) = match last {
+                    Some(row) => (row.get::<_, i64>("seq") + 1, row.get("hash")),
+                    None => (1, GENESIS.to_vec()),
+                };
+
+                let entry = LedgerEntry::chain(seq, prev_hash, call, status);
+                tx.execute(
+                    &format!("INSERT INTO admin_ledger ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)", LEDGER_COLUMNS),
+                    &[
+                        &entry.seq,
+                        &entry.recorded_at,
+                        &entry.actor,
+                        &entry.method,
+                        &entry.path,
+                        &entry.status,
+                        &entry.request_id,
+                        &entry.prev_hash,
+                        &entry.hash,
+                    ],
+                )
+                .await?;
+                Ok(entry)
+            })
+        })
+        .await
+    }
+
+    // Up to `limit` rows following row `after`, in order
+    pub async fn after(&self, after: i64, limit: i64) -> Result<Vec<LedgerEntry>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let rows = client
+            .query(
+                &format!("SELECT {} FROM admin_ledger WHERE seq > $1 ORDER BY seq LIMIT $2", LEDGER_COLUMNS),
+                &[&after, &limit],
+            )
+            .await?;
+
+        Ok(rows.iter().map(entry_from_row).collect())
+    }
+}
@@ -0,0 +1,245 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use futures_util::stream::{self, StreamExt};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::sync::RwLock;
+use uuid::Uuid;
+
+use crate::models::sort::{SortField, SortSpec};
+use crate::models::user::{CreateUserRequest, Role, UpdateUserRequest, User, UserFilter};
+use crate::password_hash;
+use crate::repositories::user_store::{UserStore, UserStream, VersionConflict};
+
+// Users kept in process memory instead of Postgres, for STORAGE_BACKEND=memory.
+// Mirrors what the users table enforces and its triggers do: unique emails and
+// usernames, region stamps, the username history, soft deletes and versions.
+// Everything is gone on restart.
+pub struct InMemoryUserRepository {
+    users: RwLock<HashMap<Uuid, User>>,
+    // Lowercased former username to the user who most recently gave it up
+    renamed: RwLock<HashMap<String, Uuid>>,
+    region: Option<String>,
+}
+
+impl InMemoryUserRepository {
+    pub fn new(region: Option<String>) -> Self {
+        Self { users: RwLock::new(HashMap::new()), renamed: RwLock::new(HashMap::new()), region }
+    }
+
+    // Matching users in the order the sort asks for, id breaking ties
+    fn select(&self, filter: &UserFilter, sort: &SortSpec) -> Vec<User> {
+        let mut users: Vec<User> = self.users.read().unwrap().values().filter(|user| self.matches(filter, user)).cloned().collect();
+        users.sort_by(|a, b| self.compare(sort, a, b));
+        users
+    }
+
+    // The conditions of filter_clause, with SQL's NULL semantics
+    fn matches(&self, filter: &UserFilter, user: &User) -> bool {
+        let local = self.region.as_deref();
+        (filter.include_deleted || user.deleted_at.is_none())
+            && filter.email.as_ref().is_none_or(|email| &user.email == email)
+            && filter.min_age.is_none_or(|min| user.age.is_some_and(|age| age >= min))
+            && filter.max_age.is_none_or(|max| user.age.is_some_and(|age| age <= max))
+            && filter.name_contains.as_ref().is_none_or(|name| user.name.to_lowercase().contains(&name.to_lowercase()))
+            && match filter.region.as_deref() {
+                Some("local") => local.is_some() && user.origin_region.as_deref() == local,
+                Some(region) => user.origin_region.as_deref() == Some(region),
+                None => true,
+            }
+    }
+
+    // The order of order_by_clause. Missing ages sort as the largest, like NULLs do in Postgres.
+    fn compare(&self, sort: &SortSpec, a: &User, b: &User) -> Ordering {
+        let by_key = sort.keys.iter().map(|key| {
+            let ordering = match key.field {
+                SortField::Id => a.id.cmp(&b.id),
+                SortField::Name => a.name.cmp(&b.name),
+                SortField::Email => a.email.cmp(&b.email),
+                SortField::Age => (a.age.is_none(), a.age).cmp(&(b.age.is_none(), b.age)),
+                SortField::CreatedAt => a.created_at.cmp(&b.created_at),
+                SortField::UpdatedAt => a.updated_at.cmp(&b.updated_at),
+                SortField::LocalRegion => (a.origin_region != self.region).cmp(&(b.origin_region != self.region)),
+            };
+            if key.descending { ordering.reverse() } else { ordering }
+        });
+        by_key.chain([a.id.cmp(&b.id)]).find(|ordering| ordering.is_ne()).unwrap_or(Ordering::Equal)
+    }
+
+    // A user that isn't deleted
+    fn live<'a>(users: &'a HashMap<Uuid, User>, id: &Uuid) -> Option<&'a User> {
+        users.get(id).filter(|user| user.deleted_at.is_none())
+    }
+
+    // Unique like the users table's email and lower(username) indexes, which
+    // deleted users keep their place in
+    fn check_unique(users: &HashMap<Uuid, User>, id: &Uuid, email: Option<&str>, username: Option<&str>) -> Result<(), Box<dyn StdError>> {
+        for user in users.values().filter(|user| &user.id != id) {
+            if email.is_some_and(|email| user.email == email) {
+                return Err("A user with this email already exists".into());
+            }
+            if username.is_some_and(|username| user.username.as_ref().is_some_and(|taken| taken.eq_ignore_ascii_case(username))) {
+                return Err("This username is already taken".into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl UserStore for InMemoryUserRepository {
+    async fn stream_all(&self, sort: &SortSpec) -> Result<UserStream, Box<dyn StdError>> {
+        Ok(stream::iter(self.select(&UserFilter::default(), sort).into_iter().map(Ok)).boxed_local())
+    }
+
+    async fn find(&self, filter: &UserFilter, sort: &SortSpec) -> Result<Vec<User>, Box<dyn StdError>> {
+        Ok(self.select(filter, sort))
+    }
+
+    async fn get_paginated(&self, filter: &UserFilter, sort: &SortSpec, offset: i64, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        Ok(self.select(filter, sort).into_iter().skip(offset.max(0) as usize).take(limit.max(0) as usize).collect())
+    }
+
+    async fn get_after(&self, filter: &UserFilter, after: Option<&Uuid>, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        Ok(self
+            .select(filter, &SortSpec::default())
+            .into_iter()
+            .filter(|user| after.is_none_or(|after| &user.id > after))
+            .take(limit.max(0) as usize)
+            .collect())
+    }
+
+    async fn count(&self, filter: &UserFilter) -> Result<i64, Box<dyn StdError>> {
+        Ok(self.users.read().unwrap().values().filter(|user| self.matches(filter, user)).count() as i64)
+    }
+
+    async fn get_by_id(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
+        Ok(Self::live(&self.users.read().unwrap(), id).cloned())
+    }
+
+    async fn get_by_ids(&self, ids: &[Uuid]) -> Result<Vec<Option<User>>, Box<dyn StdError>> {
+        let users = self.users.read().unwrap();
+        Ok(ids.iter().map(|id| Self::live(&users, id).cloned()).collect())
+    }
+
+    async fn get_by_username(&self, username: &str) -> Result<Option<User>, Box<dyn StdError>> {
+        Ok(self
+            .users
+            .read()
+            .unwrap()
+            .values()
+            .find(|user| user.deleted_at.is_none() && user.username.as_ref().is_some_and(|name| name.eq_ignore_ascii_case(username)))
+            .cloned())
+    }
+
+    async fn renamed_to(&self, old_username: &str) -> Result<Option<String>, Box<dyn StdError>> {
+        let Some(id) = self.renamed.read().unwrap().get(&old_username.to_lowercase()).copied() else {
+            return Ok(None);
+        };
+        Ok(Self::live(&self.users.read().unwrap(), &id).and_then(|user| user.username.clone()))
+    }
+
+    async fn exists(&self, id: &Uuid) -> Result<bool, Box<dyn StdError>> {
+        Ok(Self::live(&self.users.read().unwrap(), id).is_some())
+    }
+
+    async fn exists_by_email(&self, email: &str) -> Result<bool, Box<dyn StdError>> {
+        Ok(self.users.read().unwrap().values().any(|user| user.email == email))
+    }
+
+    async fn username_taken(&self, username: &str, except: Option<&Uuid>) -> Result<bool, Box<dyn StdError>> {
+        Ok(self
+            .users
+            .read()
+            .unwrap()
+            .values()
+            .any(|user| Some(&user.id) != except && user.username.as_ref().is_some_and(|name| name.eq_ignore_ascii_case(username))))
+    }
+
+    async fn create(&self, user_req: &CreateUserRequest) -> Result<User, Box<dyn StdError>> {
+        // Hashed before taking the lock, it's slow on purpose
+        let password_hash = match &user_req.password {
+            Some(password) => Some(password_hash::hash(password).await?),
+            None => None,
+        };
+        let now = Utc::now();
+        let user = User {
+            id: Uuid::new_v4(),
+            username: user_req.username.clone(),
+            name: user_req.name.clone(),
+            email: user_req.email.clone(),
+            age: user_req.age,
+            role: Role::default(),
+            password_hash,
+            created_at: now,
+            updated_at: now,
+            origin_region: self.region.clone(),
+            updated_region: self.region.clone(),
+            deleted_at: None,
+            version: 1,
+        };
+
+        let mut users = self.users.write().unwrap();
+        Self::check_unique(&users, &user.id, Some(&user.email), user.username.as_deref())?;
+        users.insert(user.id, user.clone());
+        Ok(user)
+    }
+
+    async fn update(&self, id: &Uuid, user_req: &UpdateUserRequest) -> Result<Option<User>, Box<dyn StdError>> {
+        let mut users = self.users.write().unwrap();
+        let Some(current) = Self::live(&users, id) else { return Ok(None) };
+        VersionConflict::check(user_req, current)?;
+        if user_req.is_empty() {
+            return Ok(Some(current.clone()));
+        }
+        Self::check_unique(&users, id, user_req.email.as_deref(), user_req.username.as_deref())?;
+
+        let user = users.get_mut(id).expect("checked above");
+        if let Some(username) = &user_req.username {
+            if let Some(old) = user.username.as_ref().filter(|old| !old.eq_ignore_ascii_case(username)) {
+                self.renamed.write().unwrap().insert(old.to_lowercase(), *id);
+            }
+            user.username = Some(username.clone());
+        }
+        if let Some(name) = &user_req.name {
+            user.name = name.clone();
+        }
+        if let Some(email) = &user_req.email {
+            user.email = email.clone();
+        }
+        if user_req.age.is_some() {
+            user.age = user_req.age;
+        }
+        user.updated_at = Utc::now();
+        user.updated_region = self.region.clone();
+        user.version += 1;
+        Ok(Some(user.clone()))
+    }
+
+    async fn delete(&self, id: &Uuid) -> Result<bool, Box<dyn StdError>> {
+        Ok(!self.delete_many(&[*id]).await?.is_empty())
+    }
+
+    async fn delete_many(&self, ids: &[Uuid]) -> Result<Vec<Uuid>, Box<dyn StdError>> {
+        let now = Utc::now();
+        let mut users = self.users.write().unwrap();
+        let mut deleted = Vec::new();
+        for id in ids {
+            if let Some(user) = users.get_mut(id).filter(|user| user.deleted_at.is_none()) {
+                user.deleted_at = Some(now);
+                user.version += 1;
+                deleted.push(*id);
+            }
+        }
+        Ok(deleted)
+    }
+
+    async fn restore(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
+        let mut users = self.users.write().unwrap();
+        let Some(user) = users.get_mut(id).filter(|user| user.deleted_at.is_some()) else { return Ok(None) };
+        user.deleted_at = None;
+        user.version += 1;
+        Ok(Some(user.clone()))
+    }
+}
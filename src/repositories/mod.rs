@@ -1 +1,24 @@
-pub mod user_repo;
\ No newline at end of file
+pub mod api_key_repo;
+pub mod approval_repo;
+pub mod audit_repo;
+pub mod backfill_repo;
+pub mod consent_repo;
+pub mod cursor;
+pub mod identity_repo;
+pub mod ledger_repo;
+pub mod memory_user_repo;
+#[cfg(feature = "mysql")]
+pub mod mysql_user_repo;
+#[cfg(feature = "admin-query")]
+pub mod query_repo;
+pub mod refresh_token_repo;
+pub mod retention_repo;
+pub mod schedule_repo;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_user_repo;
+pub mod sync_repo;
+pub mod tenant_repo;
+pub mod transaction;
+pub mod user_cache;
+pub mod user_repo;
+pub mod user_store;
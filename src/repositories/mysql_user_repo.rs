@@ -0,0 +1,432 @@
+use async_trait::async_trait;
+use chrono::{SubsecRound, Utc};
+use futures_util::stream::{self, StreamExt};
+use mysql_async::prelude::{FromValue, Queryable};
+use mysql_async::{Pool, Row, TxOpts, Value};
+use std::error::Error as StdError;
+use uuid::Uuid;
+
+use crate::models::sort::{SortField, SortSpec};
+use crate::models::user::{CreateUserRequest, Role, UpdateUserRequest, User, UserFilter};
+use crate::password_hash;
+use crate::repositories::user_store::{UserStore, UserStream, VersionConflict};
+
+const COLUMNS: &str = "id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version";
+
+// The Postgres schema in MySQL terms, for MySQL 5.7+ and MariaDB 10.2+. Ids are
+// hyphenated text with a binary collation, so they sort like the uuid type, and
+// the text columns compare case-sensitively like in Postgres. Usernames are kept
+// unique ignoring case through a generated lowercase column.
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS users (
+        id CHAR(36) CHARACTER SET ascii COLLATE ascii_bin NOT NULL PRIMARY KEY,
+        username VARCHAR(32) COLLATE utf8mb4_bin NULL,
+        username_lower VARCHAR(32) COLLATE utf8mb4_bin AS (LOWER(username)) STORED,
+        name VARCHAR(100) COLLATE utf8mb4_bin NOT NULL,
+        email VARCHAR(255) COLLATE utf8mb4_bin NOT NULL,
+        age SMALLINT NULL,
+        role VARCHAR(16) NOT NULL DEFAULT 'user',
+        password_hash TEXT NULL,
+        created_at DATETIME(6) NOT NULL,
+        updated_at DATETIME(6) NOT NULL,
+        origin_region VARCHAR(32) NULL,
+        updated_region VARCHAR(32) NULL,
+        deleted_at DATETIME(6) NULL,
+        version INT UNSIGNED NOT NULL DEFAULT 1,
+        UNIQUE KEY users_email (email),
+        UNIQUE KEY users_username_lower (username_lower)
+    ) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4",
+    // Written by update() rather than a trigger, creating triggers needs privileges
+    // managed databases often don't hand out
+    "CREATE TABLE IF NOT EXISTS username_history (
+        id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+        username_lower VARCHAR(32) COLLATE utf8mb4_bin NOT NULL,
+        user_id CHAR(36) CHARACTER SET ascii COLLATE ascii_bin NOT NULL,
+        KEY username_history_username (username_lower),
+        CONSTRAINT username_history_user FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+    ) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4",
+];
+
+fn take<T: FromValue>(row: &mut Row, column: &str) -> Result<T, Box<dyn StdError>> {
+    match row.take_opt(column) {
+        Some(value) => Ok(value?),
+        None => Err(format!("users row has no {} column", column).into()),
+    }
+}
+
+fn user_from_row(mut row: Row) -> Result<User, Box<dyn StdError>> {
+    let id: String = take(&mut row, "id")?;
+    let role: String = take(&mut row, "role")?;
+    Ok(User {
+        id: Uuid::parse_str(&id)?,
+        username: take(&mut row, "username")?,
+        name: take(&mut row, "name")?,
+        email: take(&mut row, "email")?,
+        age: take(&mut row, "age")?,
+        // A role this build doesn't know gets the fewest rights
+        role: Role::parse(&role).unwrap_or(Role::Readonly),
+        password_hash: take(&mut row, "password_hash")?,
+        created_at: take::<chrono::NaiveDateTime>(&mut row, "created_at")?.and_utc(),
+        updated_at: take::<chrono::NaiveDateTime>(&mut row, "updated_at")?.and_utc(),
+        origin_region: take(&mut row, "origin_region")?,
+        updated_region: take(&mut row, "updated_region")?,
+        deleted_at: take::<Option<chrono::NaiveDateTime>>(&mut row, "deleted_at")?.map(|at| at.and_utc()),
+        version: take(&mut row, "version")?,
+    })
+}
+
+fn text(value: Option<&str>) -> Value {
+    value.map_or(Value::NULL, Value::from)
+}
+
+// Users in MySQL or MariaDB, used when DATABASE_URL is a mysql:// URL. Like the
+// other stores it only serves the user routes, the rest still need Postgres.
+// Timestamps are stored in UTC.
+pub struct MysqlUserRepository {
+    pool: Pool,
+    region: Option<String>,
+}
+
+impl MysqlUserRepository {
+    pub fn new(pool: Pool, region: Option<String>) -> Self {
+        Self { pool, region }
+    }
+
+    // Run at startup in place of the migrations, every statement is idempotent
+    pub async fn create_schema(&self) -> Result<(), Box<dyn StdError>> {
+        let mut conn = self.pool.get_conn().await?;
+        for statement in SCHEMA {
+            conn.query_drop(*statement).await?;
+        }
+        // Tables created before soft deletes and versions, MySQL has no ADD COLUMN IF NOT EXISTS
+        for (column, definition) in [("deleted_at", "DATETIME(6) NULL"), ("version", "INT UNSIGNED NOT NULL DEFAULT 1")] {
+            let exists: Option<i64> = conn
+                .exec_first(
+                    "SELECT 1 FROM information_schema.columns
+                     WHERE table_schema = DATABASE() AND table_name = 'users' AND column_name = ?",
+                    (column,),
+                )
+                .await?;
+            if exists.is_none() {
+                conn.query_drop(format!("ALTER TABLE users ADD COLUMN {} {}", column, definition)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // filter_clause of the Postgres repository with MySQL placeholders
+    fn filter_clause(&self, filter: &UserFilter) -> (String, Vec<Value>) {
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+
+        if !filter.include_deleted {
+            conditions.push("deleted_at IS NULL");
+        }
+
+        if let Some(email) = &filter.email {
+            params.push(Value::from(email));
+            conditions.push("email = ?");
+        }
+        if let Some(min_age) = filter.min_age {
+            params.push(Value::from(min_age));
+            conditions.push("age >= ?");
+        }
+        if let Some(max_age) = filter.max_age {
+            params.push(Value::from(max_age));
+            conditions.push("age <= ?");
+        }
+        if let Some(name) = &filter.name_contains {
+            // `!` as the escape character, backslashes depend on the server's sql_mode
+            let escaped = name.to_lowercase().replace('!', "!!").replace('%', "!%").replace('_', "!_");
+            params.push(Value::from(format!("%{}%", escaped)));
+            conditions.push("LOWER(name) LIKE ? ESCAPE '!'");
+        }
+        match filter.region.as_deref() {
+            Some("local") => {
+                params.push(text(self.region.as_deref()));
+                conditions.push("origin_region = ?");
+            }
+            Some(region) => {
+                params.push(Value::from(region));
+                conditions.push("origin_region = ?");
+            }
+            None => {}
+        }
+
+        if conditions.is_empty() {
+            (String::new(), params)
+        } else {
+            (format!("WHERE {}", conditions.join(" AND ")), params)
+        }
+    }
+
+    // order_by_clause of the Postgres repository, its params following the filter's.
+    // MySQL sorts NULLs first when ascending, missing ages are moved to where
+    // Postgres puts them.
+    fn order_by_clause(&self, sort: &SortSpec, params: &mut Vec<Value>) -> String {
+        let mut keys: Vec<String> = sort
+            .keys
+            .iter()
+            .map(|key| {
+                let direction = if key.descending { "DESC" } else { "ASC" };
+                match key.field {
+                    SortField::Id => format!("id {}", direction),
+                    SortField::Name => format!("name {}", direction),
+                    SortField::Email => format!("email {}", direction),
+                    SortField::Age => format!("age IS NULL {}, age {}", direction, direction),
+                    SortField::CreatedAt => format!("created_at {}", direction),
+                    SortField::UpdatedAt => format!("updated_at {}", direction),
+                    SortField::LocalRegion => {
+                        params.push(text(self.region.as_deref()));
+                        format!("NOT (origin_region <=> ?) {}", direction)
+                    }
+                }
+            })
+            .collect();
+
+        if !sort.keys.iter().any(|key| key.field == SortField::Id) {
+            keys.push("id ASC".to_string());
+        }
+
+        format!("ORDER BY {}", keys.join(", "))
+    }
+
+    async fn query(&self, sql: String, params: Vec<Value>) -> Result<Vec<User>, Box<dyn StdError>> {
+        let mut conn = self.pool.get_conn().await?;
+        let rows: Vec<Row> = conn.exec(sql, params).await?;
+        rows.into_iter().map(user_from_row).collect()
+    }
+
+    async fn query_one(&self, condition: &str, param: Value) -> Result<Option<User>, Box<dyn StdError>> {
+        let mut conn = self.pool.get_conn().await?;
+        let row: Option<Row> = conn.exec_first(format!("SELECT {} FROM users WHERE {}", COLUMNS, condition), vec![param]).await?;
+        row.map(user_from_row).transpose()
+    }
+}
+
+#[async_trait(?Send)]
+impl UserStore for MysqlUserRepository {
+    async fn stream_all(&self, sort: &SortSpec) -> Result<UserStream, Box<dyn StdError>> {
+        let users = self.find(&UserFilter::default(), sort).await?;
+        Ok(stream::iter(users.into_iter().map(Ok)).boxed_local())
+    }
+
+    async fn find(&self, filter: &UserFilter, sort: &SortSpec) -> Result<Vec<User>, Box<dyn StdError>> {
+        let (where_clause, mut params) = self.filter_clause(filter);
+        let order_by = self.order_by_clause(sort, &mut params);
+        self.query(format!("SELECT {} FROM users {} {}", COLUMNS, where_clause, order_by), params).await
+    }
+
+    async fn get_paginated(&self, filter: &UserFilter, sort: &SortSpec, offset: i64, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        let (where_clause, mut params) = self.filter_clause(filter);
+        let order_by = self.order_by_clause(sort, &mut params);
+        params.extend([Value::from(limit), Value::from(offset)]);
+        self.query(format!("SELECT {} FROM users {} {} LIMIT ? OFFSET ?", COLUMNS, where_clause, order_by), params).await
+    }
+
+    async fn get_after(&self, filter: &UserFilter, after: Option<&Uuid>, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        let (mut where_clause, mut params) = self.filter_clause(filter);
+        if let Some(after) = after {
+            where_clause = if where_clause.is_empty() { "WHERE id > ?".to_string() } else { format!("{} AND id > ?", where_clause) };
+            params.push(Value::from(after.to_string()));
+        }
+        params.push(Value::from(limit));
+        self.query(format!("SELECT {} FROM users {} ORDER BY id LIMIT ?", COLUMNS, where_clause), params).await
+    }
+
+    async fn count(&self, filter: &UserFilter) -> Result<i64, Box<dyn StdError>> {
+        let (where_clause, params) = self.filter_clause(filter);
+        let mut conn = self.pool.get_conn().await?;
+        let count: Option<i64> = conn.exec_first(format!("SELECT COUNT(*) FROM users {}", where_clause), params).await?;
+        Ok(count.unwrap_or(0))
+    }
+
+    async fn get_by_id(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
+        self.query_one("id = ? AND deleted_at IS NULL", Value::from(id.to_string())).await
+    }
+
+    async fn get_by_ids(&self, ids: &[Uuid]) -> Result<Vec<Option<User>>, Box<dyn StdError>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        let params = ids.iter().map(|id| Value::from(id.to_string())).collect();
+        let found = self.query(format!("SELECT {} FROM users WHERE id IN ({}) AND deleted_at IS NULL", COLUMNS, placeholders), params).await?;
+        Ok(ids.iter().map(|id| found.iter().find(|user| &user.id == id).cloned()).collect())
+    }
+
+    async fn get_by_username(&self, username: &str) -> Result<Option<User>, Box<dyn StdError>> {
+        self.query_one("username_lower = LOWER(?) AND deleted_at IS NULL", Value::from(username)).await
+    }
+
+    async fn renamed_to(&self, old_username: &str) -> Result<Option<String>, Box<dyn StdError>> {
+        let mut conn = self.pool.get_conn().await?;
+        Ok(conn
+            .exec_first(
+                "SELECT u.username FROM username_history h JOIN users u ON u.id = h.user_id
+                 WHERE h.username_lower = LOWER(?) AND u.username IS NOT NULL AND u.deleted_at IS NULL
+                 ORDER BY h.id DESC LIMIT 1",
+                (old_username,),
+            )
+            .await?)
+    }
+
+    async fn exists(&self, id: &Uuid) -> Result<bool, Box<dyn StdError>> {
+        let mut conn = self.pool.get_conn().await?;
+        let found: Option<i64> = conn.exec_first("SELECT 1 FROM users WHERE id = ? AND deleted_at IS NULL", (id.to_string(),)).await?;
+        Ok(found.is_some())
+    }
+
+    // Deleted users count for both, they keep their email and username
+    async fn exists_by_email(&self, email: &str) -> Result<bool, Box<dyn StdError>> {
+        let mut conn = self.pool.get_conn().await?;
+        let found: Option<i64> = conn.exec_first("SELECT 1 FROM users WHERE email = ?", (email,)).await?;
+        Ok(found.is_some())
+    }
+
+    async fn username_taken(&self, username: &str, except: Option<&Uuid>) -> Result<bool, Box<dyn StdError>> {
+        let owner = self.query_one("username_lower = LOWER(?)", Value::from(username)).await?;
+        Ok(owner.is_some_and(|user| Some(&user.id) != except))
+    }
+
+    async fn create(&self, user_req: &CreateUserRequest) -> Result<User, Box<dyn StdError>> {
+        let password_hash = match &user_req.password {
+            Some(password) => Some(password_hash::hash(password).await?),
+            None => None,
+        };
+        // Stored with microseconds, so the returned user matches what a read gives back
+        let now = Utc::now().trunc_subsecs(6);
+        let user = User {
+            id: Uuid::new_v4(),
+            username: user_req.username.clone(),
+            name: user_req.name.clone(),
+            email: user_req.email.clone(),
+            age: user_req.age,
+            role: Role::default(),
+            password_hash,
+            created_at: now,
+            updated_at: now,
+            origin_region: self.region.clone(),
+            updated_region: self.region.clone(),
+            deleted_at: None,
+            version: 1,
+        };
+
+        let mut conn = self.pool.get_conn().await?;
+        conn.exec_drop(
+            format!("INSERT INTO users ({}) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)", COLUMNS),
+            vec![
+                Value::from(user.id.to_string()),
+                text(user.username.as_deref()),
+                Value::from(&user.name),
+                Value::from(&user.email),
+                user.age.map_or(Value::NULL, Value::from),
+                Value::from(user.role.as_str()),
+                text(user.password_hash.as_deref()),
+                Value::from(user.created_at.naive_utc()),
+                Value::from(user.updated_at.naive_utc()),
+                text(user.origin_region.as_deref()),
+                text(user.updated_region.as_deref()),
+                Value::NULL,
+                Value::from(user.version),
+            ],
+        )
+        .await?;
+        Ok(user)
+    }
+
+    async fn update(&self, id: &Uuid, user_req: &UpdateUserRequest) -> Result<Option<User>, Box<dyn StdError>> {
+        let mut sets = Vec::new();
+        let mut params = Vec::new();
+        if let Some(username) = &user_req.username {
+            sets.push("username = ?");
+            params.push(Value::from(username));
+        }
+        if let Some(name) = &user_req.name {
+            sets.push("name = ?");
+            params.push(Value::from(name));
+        }
+        if let Some(email) = &user_req.email {
+            sets.push("email = ?");
+            params.push(Value::from(email));
+        }
+        if let Some(age) = user_req.age {
+            sets.push("age = ?");
+            params.push(Value::from(age));
+        }
+        if sets.is_empty() {
+            // Nothing to update, hand back the current row
+            let current = self.get_by_id(id).await?;
+            if let Some(current) = &current {
+                VersionConflict::check(user_req, current)?;
+            }
+            return Ok(current);
+        }
+        sets.extend(["updated_at = ?", "updated_region = ?", "version = version + 1"]);
+        params.push(Value::from(Utc::now().naive_utc()));
+        params.push(text(self.region.as_deref()));
+        params.push(Value::from(id.to_string()));
+
+        // The old username and the version are read under a row lock, for the
+        // history entry and so the version stays the one checked
+        let mut conn = self.pool.get_conn().await?;
+        let mut tx = conn.start_transaction(TxOpts::default()).await?;
+        let current: Option<Row> = tx.exec_first(format!("SELECT {} FROM users WHERE id = ? AND deleted_at IS NULL FOR UPDATE", COLUMNS), (id.to_string(),)).await?;
+        let Some(current) = current.map(user_from_row).transpose()? else {
+            return Ok(None);
+        };
+        VersionConflict::check(user_req, &current)?;
+        let old_username = current.username;
+
+        tx.exec_drop(format!("UPDATE users SET {} WHERE id = ?", sets.join(", ")), params).await?;
+        if let (Some(old), Some(new)) = (&old_username, &user_req.username) {
+            if old.to_lowercase() != new.to_lowercase() {
+                tx.exec_drop("INSERT INTO username_history (username_lower, user_id) VALUES (LOWER(?), ?)", (old, id.to_string())).await?;
+            }
+        }
+        let row: Option<Row> = tx.exec_first(format!("SELECT {} FROM users WHERE id = ?", COLUMNS), (id.to_string(),)).await?;
+        tx.commit().await?;
+        row.map(user_from_row).transpose()
+    }
+
+    async fn delete(&self, id: &Uuid) -> Result<bool, Box<dyn StdError>> {
+        let mut conn = self.pool.get_conn().await?;
+        conn.exec_drop(
+            "UPDATE users SET deleted_at = ?, version = version + 1 WHERE id = ? AND deleted_at IS NULL",
+            (Utc::now().naive_utc(), id.to_string()),
+        )
+        .await?;
+        Ok(conn.affected_rows() > 0)
+    }
+
+    async fn delete_many(&self, ids: &[Uuid]) -> Result<Vec<Uuid>, Box<dyn StdError>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        let params: Vec<Value> = ids.iter().map(|id| Value::from(id.to_string())).collect();
+
+        // MySQL has no UPDATE ... RETURNING, the rows are locked while they are read
+        let mut conn = self.pool.get_conn().await?;
+        let mut tx = conn.start_transaction(TxOpts::default()).await?;
+        let found: Vec<String> = tx
+            .exec(format!("SELECT id FROM users WHERE id IN ({}) AND deleted_at IS NULL FOR UPDATE", placeholders), params)
+            .await?;
+        if !found.is_empty() {
+            let placeholders = vec!["?"; found.len()].join(", ");
+            let params: Vec<Value> = [Value::from(Utc::now().naive_utc())].into_iter().chain(found.iter().map(Value::from)).collect();
+            tx.exec_drop(format!("UPDATE users SET deleted_at = ?, version = version + 1 WHERE id IN ({})", placeholders), params).await?;
+        }
+        tx.commit().await?;
+        Ok(found.iter().map(|id| Uuid::parse_str(id)).collect::<Result<_, _>>()?)
+    }
+
+    async fn restore(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
+        let mut conn = self.pool.get_conn().await?;
+        conn.exec_drop("UPDATE users SET deleted_at = NULL, version = version + 1 WHERE id = ? AND deleted_at IS NOT NULL", (id.to_string(),)).await?;
+        if conn.affected_rows() == 0 {
+            return Ok(None);
+        }
+        self.get_by_id(id).await
+    }
+}
@@ -0,0 +1,302 @@
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{Object, Pool};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::error::Error as StdError;
+use std::time::Duration;
+use tokio_postgres::types::{ToSql, Type};
+use uuid::Uuid;
+
+// Granted only what support may read, by migration 0006. Queries log in as it
+// on their own pool, ADMIN_QUERY_DATABASE_URL.
+pub const QUERY_ROLE: &str = "hello_world_query";
+
+// Whether the session can become a role, itself included, that is a superuser
+// or may read password hashes. SET ROLE and set_config('role', ...) only reach
+// roles the login role is a member of, so this covers what a query could switch to.
+const PRIVILEGED_ROLES: &str = "SELECT EXISTS (
+    SELECT 1 FROM pg_roles r
+    WHERE pg_has_role(session_user, r.oid, 'MEMBER')
+        AND (r.rolsuper OR has_column_privilege(r.oid, 'users', 'password_hash', 'SELECT'))
+)";
+
+#[derive(Debug, Clone, Copy)]
+pub struct QueryLimits {
+    pub timeout: Duration,
+    pub max_rows: u32,
+}
+
+#[derive(Serialize)]
+pub struct Column {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_name: String,
+}
+
+#[derive(Serialize)]
+pub struct QueryResult {
+    // In the order of the select list, the keys of rows are sorted
+    pub columns: Vec<Column>,
+    pub rows: Vec<Map<String, Value>>,
+    // More rows matched than were returned
+    pub truncated: bool,
+}
+
+// Ad-hoc reads for support engineers. The query runs as a subquery, so it must
+// be one SELECT (or WITH, VALUES, TABLE), inside a read-only transaction with a
+// statement timeout, and Postgres turns each row into JSON. It runs on a pool
+// of its own that logs in as QUERY_ROLE rather than switching roles on one of
+// the server's connections, where a query could switch back. The transaction is
+// always rolled back and the connection closed afterwards, so nothing a query
+// does to its session, like taking an advisory lock, outlives it.
+pub struct QueryRepository {
+    // None without ADMIN_QUERY_DATABASE_URL
+    pool: Option<Pool>,
+    limits: QueryLimits,
+}
+
+impl QueryRepository {
+    pub fn new(pool: Option<Pool>, limits: QueryLimits) -> Self {
+        Self { pool, limits }
+    }
+
+    pub fn max_rows(&self) -> u32 {
+        self.limits.max_rows
+    }
+
+    // The outer error is ours, the inner one the query being refused or failing, with the reason
+    pub async fn run(&self, sql: &str, params: &[Value], limit: u32) -> Result<Result<QueryResult, String>, Box<dyn StdError>> {
+        let sql = sql.trim().trim_end_matches(';').trim_end();
+        if sql.is_empty() {
+            return Ok(Err("The query is empty".to_string()));
+        }
+
+        let pool = self.pool.as_ref().ok_or("ADMIN_QUERY_DATABASE_URL is not set")?;
+        let client = match pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+        let mut client = Object::take(client);
+
+        let tx = client.build_transaction().read_only(true).start().await?;
+        tx.batch_execute(&format!("SET LOCAL statement_timeout = {}", self.limits.timeout.as_millis())).await?;
+        // A URL with the server's own user, or one granted too much, would let queries read everything
+        if tx.query_one(PRIVILEGED_ROLES, &[]).await?.get::<_, bool>(0) {
+            log::error!("ADMIN_QUERY_DATABASE_URL logs in as a role that can read password hashes, use {}", QUERY_ROLE);
+            return Err("The admin query role is too privileged".into());
+        }
+
+        let result = async {
+            let columns = match tx.prepare(sql).await {
+                Ok(statement) => statement
+                    .columns()
+                    .iter()
+                    .map(|column| Column { name: column.name().to_string(), type_name: column.type_().name().to_string() })
+                    .collect::<Vec<_>>(),
+                Err(e) => return refused(e),
+            };
+            // On its own line, so a trailing comment in the query can't swallow it
+            let wrapped = format!("SELECT row_to_json(q)::text FROM (\n{}\n) AS q LIMIT {}", sql, u64::from(limit) + 1);
+            let statement = match tx.prepare(&wrapped).await {
+                Ok(statement) => statement,
+                Err(e) => return refused(e),
+            };
+
+            if statement.params().len() != params.len() {
+                return Ok(Err(format!("The query takes {} parameters, got {}", statement.params().len(), params.len())));
+            }
+            let mut values = Vec::with_capacity(params.len());
+            for (i, (value, ty)) in params.iter().zip(statement.params()).enumerate() {
+                match param(value, ty) {
+                    Some(value) => values.push(value),
+                    None => {
+                        return Ok(Err(format!(
+                            "Parameter ${} must be a {}, got {}. Cast other types from text, e.g. ${}::text::inet",
+                            i + 1, ty.name(), value, i + 1
+                        )))
+                    }
+                }
+            }
+            let refs: Vec<&(dyn ToSql + Sync)> = values.iter().map(|value| value.as_ref()).collect();
+
+            let rows = match tx.query(&statement, &refs).await {
+                Ok(rows) => rows,
+                Err(e) => return refused(e),
+            };
+            // Rows read under a role the query switched to aren't returned
+            if !tx.query_one("SELECT current_user = session_user", &[]).await?.get::<_, bool>(0) {
+                return Ok(Err("Queries may not change the role".to_string()));
+            }
+            let truncated = rows.len() > limit as usize;
+            let rows = rows
+                .iter()
+                .take(limit as usize)
+                .map(|row| serde_json::from_str(row.get(0)))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok::<_, Box<dyn StdError>>(Ok(QueryResult { columns, rows, truncated }))
+        }
+        .await;
+
+        let _ = tx.rollback().await;
+        result
+    }
+}
+
+// The query with its literals and comments replaced by `?`, for the audit log:
+// a value typed into the SQL, such as an email to look up, is as personal as
+// the parameters. Unsure spots, like an unterminated literal, are scrubbed to
+// the end.
+pub fn scrub(sql: &str) -> String {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut out = String::with_capacity(sql.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let after_word = out.chars().last().is_some_and(|last| last.is_alphanumeric() || last == '_' || last == '$');
+
+        if c == '\'' {
+            // A backslash escapes in E'' strings; taking it as one everywhere only scrubs more
+            i += 1;
+            while i < chars.len() {
+                match chars[i] {
+                    '\\' => i += 2,
+                    '\'' if chars.get(i + 1) == Some(&'\'') => i += 2,
+                    '\'' => break,
+                    _ => i += 1,
+                }
+            }
+            out.push('?');
+            i += 1;
+        } else if c == '"' {
+            // Quoted identifiers stay
+            let end = chars[i + 1..].iter().position(|&c| c == '"').map_or(chars.len(), |end| i + end + 2);
+            out.extend(&chars[i..end.min(chars.len())]);
+            i = end;
+        } else if c == '-' && next == Some('-') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            let end = sql_find(&chars, i + 2, &['*', '/']);
+            out.push(' ');
+            i = end.map_or(chars.len(), |end| end + 2);
+        } else if c == '$' && !after_word && next.is_some_and(|next| next == '$' || next.is_alphabetic() || next == '_') {
+            // Dollar quoting, $$...$$ or $tag$...$tag$; $1 is a parameter and stays
+            let tag_end = chars[i + 1..].iter().position(|&c| !(c.is_alphanumeric() || c == '_')).map(|end| i + 1 + end);
+            match tag_end {
+                Some(tag_end) if chars[tag_end] == '$' => {
+                    let tag = &chars[i..=tag_end];
+                    let end = sql_find(&chars, tag_end + 1, tag);
+                    out.push('?');
+                    i = end.map_or(chars.len(), |end| end + tag.len());
+                }
+                _ => {
+                    out.push(c);
+                    i += 1;
+                }
+            }
+        } else if c.is_ascii_digit() && !after_word {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                i += 1;
+            }
+            out.push('?');
+        } else {
+            out.push(c);
+            i += 1;
+        }
+    }
+    out
+}
+
+// Where `needle` starts at or after `from`
+fn sql_find(chars: &[char], from: usize, needle: &[char]) -> Option<usize> {
+    (from..chars.len()).find(|&i| chars[i..].starts_with(needle))
+}
+
+// Errors Postgres reports for the query are the caller's, losing the connection is ours
+fn refused<T>(e: tokio_postgres::Error) -> Result<Result<T, String>, Box<dyn StdError>> {
+    match e.as_db_error() {
+        Some(db) => Ok(Err(db.message().to_string())),
+        None => Err(Box::new(e)),
+    }
+}
+
+// A JSON value for a parameter of the type Postgres inferred for it
+fn param(value: &Value, ty: &Type) -> Option<Box<dyn ToSql + Sync>> {
+    fn typed<T: ToSql + Sync + 'static>(value: &Value, convert: impl Fn(&Value) -> Option<T>) -> Option<Box<dyn ToSql + Sync>> {
+        if value.is_null() {
+            return Some(Box::new(None::<T>));
+        }
+        convert(value).map(|value| Box::new(value) as Box<dyn ToSql + Sync>)
+    }
+
+    match *ty {
+        Type::BOOL => typed(value, Value::as_bool),
+        Type::INT2 => typed(value, |value| value.as_i64().and_then(|n| i16::try_from(n).ok())),
+        Type::INT4 => typed(value, |value| value.as_i64().and_then(|n| i32::try_from(n).ok())),
+        Type::INT8 => typed(value, Value::as_i64),
+        Type::FLOAT4 => typed(value, |value| value.as_f64().map(|n| n as f32)),
+        Type::FLOAT8 => typed(value, Value::as_f64),
+        Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME => typed(value, |value| value.as_str().map(str::to_string)),
+        Type::UUID => typed(value, |value| value.as_str().and_then(|s| Uuid::parse_str(s).ok())),
+        Type::TIMESTAMPTZ => typed(value, |value| value.as_str().and_then(|s| s.parse::<DateTime<Utc>>().ok())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations::Migrator;
+    use deadpool_postgres::{Manager, ManagerConfig};
+    use tokio_postgres::NoTls;
+
+    fn pool(config: tokio_postgres::Config) -> Pool {
+        let manager = Manager::from_config(config, NoTls, ManagerConfig::default());
+        Pool::builder(manager).max_size(2).build().unwrap()
+    }
+
+    fn limits() -> QueryLimits {
+        QueryLimits { timeout: Duration::from_secs(5), max_rows: 10 }
+    }
+
+    // TEST_DATABASE_URL is the server's user, which needs CREATEROLE, and
+    // pg_hba.conf must let QUERY_ROLE log in without a password
+    #[tokio::test]
+    #[ignore = "needs a Postgres in TEST_DATABASE_URL"]
+    async fn queries_cannot_switch_back_to_the_server_role() {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL");
+        let server_config: tokio_postgres::Config = url.parse().unwrap();
+        let server = pool(server_config.clone());
+        Migrator::new(server.clone()).run().await.unwrap();
+        let client = server.get().await.unwrap();
+        client.batch_execute(&format!("ALTER ROLE {} LOGIN", QUERY_ROLE)).await.unwrap();
+        let server_role: String = client.query_one("SELECT current_user::text", &[]).await.unwrap().get(0);
+
+        let mut query_config = server_config.clone();
+        query_config.user(QUERY_ROLE);
+        let queries = QueryRepository::new(Some(pool(query_config)), limits());
+
+        let allowed = queries.run("SELECT count(*) FROM users", &[], 10).await.unwrap();
+        assert!(allowed.is_ok());
+
+        let escape = format!(
+            "SELECT set_config('role', '{}', true), query_to_xml('select password_hash from users', true, false, '')",
+            server_role
+        );
+        assert!(queries.run(&escape, &[], 10).await.unwrap().is_err());
+        let same_session = "SELECT set_config('role', session_user, true), query_to_xml('select password_hash from users', true, false, '')";
+        assert!(queries.run(same_session, &[], 10).await.unwrap().is_err());
+        assert!(queries.run("SELECT password_hash FROM users", &[], 10).await.unwrap().is_err());
+
+        // The server's own login is refused outright
+        let misconfigured = QueryRepository::new(Some(server), limits());
+        assert!(misconfigured.run("SELECT 1", &[], 10).await.is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use deadpool_postgres::Pool;
+use std::error::Error as StdError;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::api_keys::hash;
+use crate::clock::Clock;
+use crate::repositories::transaction::with_transaction;
+
+// Marks strings as refresh tokens of this service, like the hwk_ of API keys
+const TOKEN_PREFIX: &str = "hwr_";
+
+pub struct RefreshTokenRepository {
+    pool: Pool,
+    clock: Arc<dyn Clock>,
+}
+
+pub struct IssuedRefreshToken {
+    pub token: String,
+    pub user_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+}
+
+pub enum Rotation {
+    Rotated(IssuedRefreshToken),
+    // The token was used before, so it leaked: its whole family is revoked now
+    Reused { user_id: Uuid, family_id: Uuid },
+    // Unknown, expired or revoked
+    Invalid,
+}
+
+// Stored hashed like API keys, they are just as random
+fn generate() -> (String, Vec<u8>) {
+    let token = format!("{}{}", TOKEN_PREFIX, URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>()));
+    let token_hash = hash(&token);
+    (token, token_hash)
+}
+
+impl RefreshTokenRepository {
+    pub fn new(pool: Pool, clock: Arc<dyn Clock>) -> Self {
+        Self { pool, clock }
+    }
+
+    // First token of a new family, handed out at login
+    pub async fn issue(&self, user_id: &Uuid, ttl: Duration) -> Result<IssuedRefreshToken, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let (token, token_hash) = generate();
+        let expires_at = self.clock.now() + ttl;
+        client
+            .execute(
+                "INSERT INTO refresh_tokens (id, family_id, user_id, token_hash, expires_at) VALUES ($1, $2, $3, $4, $5)",
+                &[&Uuid::new_v4(), &Uuid::new_v4(), user_id, &token_hash, &expires_at],
+            )
+            .await?;
+
+        Ok(IssuedRefreshToken { token, user_id: *user_id, expires_at })
+    }
+
+    // Use up a token and issue its successor in the same family. Presenting a
+    // token that was already used revokes the family, so whichever of the
+    // legitimate client and the thief refreshes next is logged out.
+    pub async fn rotate(&self, token: &str, ttl: Duration) -> Result<Rotation, Box<dyn StdError>> {
+        let token_hash = hash(token);
+        let now = self.clock.now();
+
+        with_transaction(&self.pool, |tx| {
+            let token_hash = token_hash.clone();
+            Box::pin(async move {
+                let row = tx
+                    .query_opt(
+                        "SELECT family_id, user_id, expires_at, used_at, revoked_at FROM refresh_tokens WHERE token_hash = $1 FOR UPDATE",
+                        &[&token_hash],
+                    )
+                    .await?;
+                let Some(row) = row else { return Ok(Rotation::Invalid) };
+
+                let family_id: Uuid = row.get("family_id");
+                let user_id: Uuid = row.get("user_id");
+                let expires_at: DateTime<Utc> = row.get("expires_at");
+                let used_at: Option<DateTime<Utc>> = row.get("used_at");
+                let revoked_at: Option<DateTime<Utc>> = row.get("revoked_at");
+
+                if revoked_at.is_some() || expires_at <= now {
+                    return Ok(Rotation::Invalid);
+                }
+                if used_at.is_some() {
+                    tx.execute(
+                        "UPDATE refresh_tokens SET revoked_at = now() WHERE family_id = $1 AND revoked_at IS NULL",
+                        &[&family_id],
+                    )
+                    .await?;
+                    return Ok(Rotation::Reused { user_id, family_id });
+                }
+
+                tx.execute("UPDATE refresh_tokens SET used_at = now() WHERE token_hash = $1", &[&token_hash]).await?;
+                let (token, next_hash) = generate();
+                let expires_at = now + ttl;
+                tx.execute(
+                    "INSERT INTO refresh_tokens (id, family_id, user_id, token_hash, expires_at) VALUES ($1, $2, $3, $4, $5)",
+                    &[&Uuid::new_v4(), &family_id, &user_id, &next_hash, &expires_at],
+                )
+                .await?;
+
+                Ok(Rotation::Rotated(IssuedRefreshToken { token, user_id, expires_at }))
+            })
+        })
+        .await
+    }
+}
@@ -0,0 +1,136 @@
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Pool;
+use std::error::Error as StdError;
+
+use crate::retention::{RetentionAction, RetentionPolicy};
+
+// Tables retention policies may target, with the timestamp their age is measured
+// from and, where the rows hold personal data, how to anonymize them
+pub struct RetentionTable {
+    pub name: &'static str,
+    pub age_column: &'static str,
+    pub anonymize: Option<Anonymization>,
+}
+
+pub struct Anonymization {
+    // SET clause scrubbing the personal data
+    pub set: &'static str,
+    // Matches rows not anonymized yet, so they aren't rewritten on every run
+    pub pending: &'static str,
+}
+
+pub const TABLES: &[RetentionTable] = &[
+    RetentionTable {
+        name: "users",
+        age_column: "updated_at",
+        // The email must stay unique, so it is derived from the id
+        anonymize: Some(Anonymization {
+            set: "username = NULL, name = 'Anonymized user', email = 'anonymized-' || id || '@invalid', age = NULL, updated_at = now()",
+            pending: "email NOT LIKE 'anonymized-%@invalid'",
+        }),
+    },
+    RetentionTable {
+        name: "identities",
+        age_column: "created_at",
+        anonymize: None,
+    },
+    RetentionTable {
+        name: "user_changes",
+        age_column: "changed_at",
+        anonymize: None,
+    },
+    // Snapshots hold personal data, even of users anonymized since
+    RetentionTable {
+        name: "audit_log",
+        age_column: "recorded_at",
+        anonymize: None,
+    },
+];
+
+pub fn table(name: &str) -> Option<&'static RetentionTable> {
+    TABLES.iter().find(|table| table.name == name)
+}
+
+pub struct RetentionRepository {
+    pool: Pool,
+}
+
+impl RetentionRepository {
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    // Rows the policy would act on at `now`
+    pub async fn count(&self, policy: &RetentionPolicy, now: DateTime<Utc>) -> Result<i64, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let (table, filter) = target(policy)?;
+        let row = client
+            .query_one(
+                &format!("SELECT COUNT(*) FROM {} WHERE {}", table.name, filter),
+                &[&policy.max_age_days, &now],
+            )
+            .await?;
+        Ok(row.get(0))
+    }
+
+    // Delete, anonymize or archive the matching rows, returning how many were affected
+    pub async fn apply(&self, policy: &RetentionPolicy, now: DateTime<Utc>) -> Result<u64, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let (table, filter) = target(policy)?;
+        let sql = match (&policy.action, &table.anonymize) {
+            (RetentionAction::Delete, _) => format!("DELETE FROM {} WHERE {}", table.name, filter),
+            (RetentionAction::Anonymize, Some(anonymize)) => {
+                format!("UPDATE {} SET {} WHERE {}", table.name, anonymize.set, filter)
+            }
+            (RetentionAction::Anonymize, None) => {
+                return Err(format!("Rows in {} can't be anonymized", table.name).into());
+            }
+            (RetentionAction::Archive, _) => {
+                client
+                    .batch_execute(&format!(
+                        "CREATE TABLE IF NOT EXISTS {0}_archive (LIKE {0}, archived_at TIMESTAMPTZ NOT NULL DEFAULT now())",
+                        table.name
+                    ))
+                    .await?;
+                format!(
+                    "WITH moved AS (DELETE FROM {0} WHERE {1} RETURNING *)
+                     INSERT INTO {0}_archive SELECT *, now() FROM moved",
+                    table.name, filter
+                )
+            }
+        };
+
+        Ok(client.execute(&sql, &[&policy.max_age_days, &now]).await?)
+    }
+}
+
+// The policy's table and the rows it applies to: older than the cutoff from $2, matching
+// its own condition and, when anonymizing, not anonymized yet. Conditions come
+// from the operator's policy file and are trusted like the rest of the configuration.
+fn target(policy: &RetentionPolicy) -> Result<(&'static RetentionTable, String), Box<dyn StdError>> {
+    let table = table(&policy.table).ok_or_else(|| format!("Unknown retention table {}", policy.table))?;
+
+    let mut filter = format!("{} < $2::timestamptz - make_interval(days => $1)", table.age_column);
+    if let Some(condition) = &policy.condition {
+        filter.push_str(&format!(" AND ({})", condition));
+    }
+    if let (RetentionAction::Anonymize, Some(anonymize)) = (&policy.action, &table.anonymize) {
+        filter.push_str(&format!(" AND {}", anonymize.pending));
+    }
+
+    Ok((table, filter))
+}
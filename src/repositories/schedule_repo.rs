@@ -0,0 +1,194 @@
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Pool;
+use std::error::Error as StdError;
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+use crate::models::schedule::{ScheduleStatus, ScheduledAction, ScheduledOperation};
+use crate::models::user::UpdateUserRequest;
+
+const OPERATION_COLUMNS: &str =
+    "id, user_id, action, changes::text AS changes, execute_at, status, error, created_at, finished_at";
+
+pub struct ScheduleRepository {
+    pool: Pool,
+}
+
+fn operation_from_row(row: &Row) -> Result<ScheduledOperation, Box<dyn StdError>> {
+    let action: String = row.get("action");
+    let status: String = row.get("status");
+    let changes: Option<String> = row.get("changes");
+
+    Ok(ScheduledOperation {
+        id: row.get("id"),
+        user_id: row.get("user_id"),
+        action: ScheduledAction::parse(&action).ok_or_else(|| format!("Unknown scheduled action {}", action))?,
+        changes: changes.map(|changes| serde_json::from_str(&changes)).transpose()?,
+        execute_at: row.get("execute_at"),
+        status: ScheduleStatus::parse(&status).ok_or_else(|| format!("Unknown schedule status {}", status))?,
+        error: row.get("error"),
+        created_at: row.get("created_at"),
+        finished_at: row.get("finished_at"),
+    })
+}
+
+impl ScheduleRepository {
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn create(
+        &self,
+        user_id: &Uuid,
+        action: ScheduledAction,
+        changes: Option<&UpdateUserRequest>,
+        execute_at: &DateTime<Utc>,
+    ) -> Result<ScheduledOperation, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let changes = changes.map(serde_json::to_string).transpose()?;
+        let row = client
+            .query_one(
+                &format!(
+                    "INSERT INTO scheduled_operations (id, user_id, action, changes, execute_at)
+                     VALUES ($1, $2, $3, $4::text::jsonb, $5)
+                     RETURNING {}",
+                    OPERATION_COLUMNS
+                ),
+                &[&Uuid::new_v4(), user_id, &action.as_str(), &changes, execute_at],
+            )
+            .await?;
+
+        operation_from_row(&row)
+    }
+
+    // Every operation scheduled for a user, the next one to run first
+    pub async fn list_for_user(&self, user_id: &Uuid) -> Result<Vec<ScheduledOperation>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT {} FROM scheduled_operations WHERE user_id = $1 ORDER BY execute_at, id",
+                    OPERATION_COLUMNS
+                ),
+                &[user_id],
+            )
+            .await?;
+
+        rows.iter().map(operation_from_row).collect()
+    }
+
+    pub async fn get(&self, user_id: &Uuid, id: &Uuid) -> Result<Option<ScheduledOperation>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let row = client
+            .query_opt(
+                &format!("SELECT {} FROM scheduled_operations WHERE id = $1 AND user_id = $2", OPERATION_COLUMNS),
+                &[id, user_id],
+            )
+            .await?;
+
+        row.map(|row| operation_from_row(&row)).transpose()
+    }
+
+    // None if the operation isn't pending any more
+    pub async fn cancel(&self, user_id: &Uuid, id: &Uuid) -> Result<Option<ScheduledOperation>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let row = client
+            .query_opt(
+                &format!(
+                    "UPDATE scheduled_operations SET status = 'cancelled', finished_at = now()
+                     WHERE id = $1 AND user_id = $2 AND status = 'pending'
+                     RETURNING {}",
+                    OPERATION_COLUMNS
+                ),
+                &[id, user_id],
+            )
+            .await?;
+
+        row.map(|row| operation_from_row(&row)).transpose()
+    }
+
+    // Mark up to `limit` due operations as running and return them, oldest
+    // first. Rows locked by another instance are skipped so each runs once.
+    pub async fn claim_due(&self, now: DateTime<Utc>, limit: i64) -> Result<Vec<ScheduledOperation>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let rows = client
+            .query(
+                &format!(
+                    "UPDATE scheduled_operations SET status = 'running'
+                     WHERE id IN (
+                         SELECT id FROM scheduled_operations
+                         WHERE status = 'pending' AND execute_at <= $1
+                         ORDER BY execute_at
+                         LIMIT $2
+                         FOR UPDATE SKIP LOCKED
+                     )
+                     RETURNING {}",
+                    OPERATION_COLUMNS
+                ),
+                &[&now, &limit],
+            )
+            .await?;
+
+        let mut operations = rows.iter().map(operation_from_row).collect::<Result<Vec<_>, _>>()?;
+        operations.sort_by_key(|operation| operation.execute_at);
+        Ok(operations)
+    }
+
+    // Record how a running operation ended, `error` is set when it failed
+    pub async fn finish(&self, id: &Uuid, error: Option<&str>) -> Result<(), Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let status = match error {
+            Some(_) => ScheduleStatus::Failed,
+            None => ScheduleStatus::Done,
+        };
+        client
+            .execute(
+                "UPDATE scheduled_operations SET status = $2, error = $3, finished_at = now() WHERE id = $1",
+                &[id, &status.as_str(), &error],
+            )
+            .await?;
+        Ok(())
+    }
+}
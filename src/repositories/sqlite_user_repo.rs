@@ -0,0 +1,413 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use futures_util::stream::{self, StreamExt};
+use parking_lot::Mutex;
+use rusqlite::types::{Type, Value};
+use rusqlite::{params_from_iter, Connection, OptionalExtension, Row};
+use std::error::Error as StdError;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::models::sort::{SortField, SortSpec};
+use crate::models::user::{CreateUserRequest, Role, UpdateUserRequest, User, UserFilter};
+use crate::password_hash;
+use crate::repositories::user_store::{UserStore, UserStream, VersionConflict};
+
+const COLUMNS: &str = "id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version";
+
+// The Postgres schema in SQLite terms. Ids are stored as hyphenated text, which
+// sorts like the uuid type does.
+const SCHEMA: &str = "
+    PRAGMA foreign_keys = ON;
+    PRAGMA journal_mode = WAL;
+
+    CREATE TABLE IF NOT EXISTS users (
+        id TEXT PRIMARY KEY,
+        username TEXT,
+        name TEXT NOT NULL,
+        email TEXT NOT NULL UNIQUE,
+        age INTEGER,
+        role TEXT NOT NULL DEFAULT 'user',
+        password_hash TEXT,
+        created_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL,
+        origin_region TEXT,
+        updated_region TEXT,
+        deleted_at TEXT,
+        version INTEGER NOT NULL DEFAULT 1
+    );
+    CREATE UNIQUE INDEX IF NOT EXISTS idx_users_username_lower ON users (lower(username));
+
+    CREATE TABLE IF NOT EXISTS username_history (
+        username TEXT NOT NULL,
+        user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE
+    );
+    CREATE INDEX IF NOT EXISTS idx_username_history_lower ON username_history (lower(username));
+
+    CREATE TRIGGER IF NOT EXISTS users_clear_username_history
+        AFTER UPDATE OF username ON users FOR EACH ROW WHEN NEW.username IS NULL
+    BEGIN
+        DELETE FROM username_history WHERE user_id = NEW.id;
+    END;
+    CREATE TRIGGER IF NOT EXISTS users_record_username_change
+        AFTER UPDATE OF username ON users FOR EACH ROW
+        WHEN OLD.username IS NOT NULL AND NEW.username IS NOT NULL AND lower(OLD.username) <> lower(NEW.username)
+    BEGIN
+        INSERT INTO username_history (username, user_id) VALUES (OLD.username, NEW.id);
+    END;
+";
+
+fn user_from_row(row: &Row) -> rusqlite::Result<User> {
+    let id: String = row.get("id")?;
+    Ok(User {
+        id: Uuid::parse_str(&id).map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, Type::Text, Box::new(e)))?,
+        username: row.get("username")?,
+        name: row.get("name")?,
+        email: row.get("email")?,
+        age: row.get::<_, Option<i64>>("age")?.map(|age| age as u8),
+        // A role this build doesn't know gets the fewest rights
+        role: Role::parse(&row.get::<_, String>("role")?).unwrap_or(Role::Readonly),
+        password_hash: row.get("password_hash")?,
+        created_at: row.get("created_at")?,
+        updated_at: row.get("updated_at")?,
+        origin_region: row.get("origin_region")?,
+        updated_region: row.get("updated_region")?,
+        deleted_at: row.get("deleted_at")?,
+        version: row.get("version")?,
+    })
+}
+
+// Users in a single SQLite file, for STORAGE_BACKEND=sqlite on hosts without
+// Postgres. The connection is shared and used from the blocking thread pool,
+// SQLite only has one writer anyway.
+pub struct SqliteUserRepository {
+    conn: Arc<Mutex<Connection>>,
+    region: Option<String>,
+}
+
+impl SqliteUserRepository {
+    // Open or create the database file and its schema
+    pub fn open(path: &str, region: Option<String>) -> Result<Self, Box<dyn StdError>> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        // Files created before soft deletes and versions
+        for (column, definition) in [("deleted_at", "TEXT"), ("version", "INTEGER NOT NULL DEFAULT 1")] {
+            let exists: bool = conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM pragma_table_info('users') WHERE name = ?)",
+                [column],
+                |row| row.get(0),
+            )?;
+            if !exists {
+                conn.execute_batch(&format!("ALTER TABLE users ADD COLUMN {} {}", column, definition))?;
+            }
+        }
+        Ok(Self { conn: Arc::new(Mutex::new(conn)), region })
+    }
+
+    async fn call<T, F>(&self, f: F) -> Result<T, Box<dyn StdError>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        Ok(tokio::task::spawn_blocking(move || f(&mut conn.lock())).await??)
+    }
+
+    // filter_clause of the Postgres repository with SQLite placeholders
+    fn filter_clause(&self, filter: &UserFilter) -> (String, Vec<Value>) {
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+
+        if !filter.include_deleted {
+            conditions.push("deleted_at IS NULL");
+        }
+
+        if let Some(email) = &filter.email {
+            params.push(Value::Text(email.clone()));
+            conditions.push("email = ?");
+        }
+        if let Some(min_age) = filter.min_age {
+            params.push(Value::Integer(min_age as i64));
+            conditions.push("age >= ?");
+        }
+        if let Some(max_age) = filter.max_age {
+            params.push(Value::Integer(max_age as i64));
+            conditions.push("age <= ?");
+        }
+        if let Some(name) = &filter.name_contains {
+            // LIKE ignores ASCII case in SQLite
+            let escaped = name.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+            params.push(Value::Text(format!("%{}%", escaped)));
+            conditions.push("name LIKE ? ESCAPE '\\'");
+        }
+        match filter.region.as_deref() {
+            Some("local") => {
+                params.push(self.region.clone().map_or(Value::Null, Value::Text));
+                conditions.push("origin_region = ?");
+            }
+            Some(region) => {
+                params.push(Value::Text(region.to_string()));
+                conditions.push("origin_region = ?");
+            }
+            None => {}
+        }
+
+        if conditions.is_empty() {
+            (String::new(), params)
+        } else {
+            (format!("WHERE {}", conditions.join(" AND ")), params)
+        }
+    }
+
+    // order_by_clause of the Postgres repository, NULLs placed the way Postgres places them
+    fn order_by_clause(&self, sort: &SortSpec) -> String {
+        let region = match &self.region {
+            Some(region) => format!("'{}'", region.replace('\'', "''")),
+            None => "NULL".to_string(),
+        };
+        let mut keys: Vec<String> = sort
+            .keys
+            .iter()
+            .map(|key| {
+                let column = match key.field {
+                    SortField::Id => "id".to_string(),
+                    SortField::Name => "name".to_string(),
+                    SortField::Email => "email".to_string(),
+                    SortField::Age => "age".to_string(),
+                    SortField::CreatedAt => "created_at".to_string(),
+                    SortField::UpdatedAt => "updated_at".to_string(),
+                    SortField::LocalRegion => format!("(origin_region IS NOT {})", region),
+                };
+                format!("{} {}", column, if key.descending { "DESC NULLS FIRST" } else { "ASC NULLS LAST" })
+            })
+            .collect();
+
+        if !sort.keys.iter().any(|key| key.field == SortField::Id) {
+            keys.push("id ASC".to_string());
+        }
+
+        format!("ORDER BY {}", keys.join(", "))
+    }
+
+    async fn query(&self, sql: String, params: Vec<Value>) -> Result<Vec<User>, Box<dyn StdError>> {
+        self.call(move |conn| {
+            let mut statement = conn.prepare(&sql)?;
+            let users = statement.query_map(params_from_iter(params), user_from_row)?.collect();
+            users
+        })
+        .await
+    }
+
+    async fn query_one(&self, sql: &'static str, param: String) -> Result<Option<User>, Box<dyn StdError>> {
+        self.call(move |conn| conn.query_row(sql, [param], user_from_row).optional()).await
+    }
+}
+
+#[async_trait(?Send)]
+impl UserStore for SqliteUserRepository {
+    async fn stream_all(&self, sort: &SortSpec) -> Result<UserStream, Box<dyn StdError>> {
+        let users = self.find(&UserFilter::default(), sort).await?;
+        Ok(stream::iter(users.into_iter().map(Ok)).boxed_local())
+    }
+
+    async fn find(&self, filter: &UserFilter, sort: &SortSpec) -> Result<Vec<User>, Box<dyn StdError>> {
+        let (where_clause, params) = self.filter_clause(filter);
+        let sql = format!("SELECT {} FROM users {} {}", COLUMNS, where_clause, self.order_by_clause(sort));
+        self.query(sql, params).await
+    }
+
+    async fn get_paginated(&self, filter: &UserFilter, sort: &SortSpec, offset: i64, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        let (where_clause, mut params) = self.filter_clause(filter);
+        params.extend([Value::Integer(limit), Value::Integer(offset)]);
+        let sql = format!("SELECT {} FROM users {} {} LIMIT ? OFFSET ?", COLUMNS, where_clause, self.order_by_clause(sort));
+        self.query(sql, params).await
+    }
+
+    async fn get_after(&self, filter: &UserFilter, after: Option<&Uuid>, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        let (mut where_clause, mut params) = self.filter_clause(filter);
+        if let Some(after) = after {
+            where_clause = if where_clause.is_empty() { "WHERE id > ?".to_string() } else { format!("{} AND id > ?", where_clause) };
+            params.push(Value::Text(after.to_string()));
+        }
+        params.push(Value::Integer(limit));
+        let sql = format!("SELECT {} FROM users {} ORDER BY id LIMIT ?", COLUMNS, where_clause);
+        self.query(sql, params).await
+    }
+
+    async fn count(&self, filter: &UserFilter) -> Result<i64, Box<dyn StdError>> {
+        let (where_clause, params) = self.filter_clause(filter);
+        let sql = format!("SELECT COUNT(*) FROM users {}", where_clause);
+        self.call(move |conn| conn.query_row(&sql, params_from_iter(params), |row| row.get(0))).await
+    }
+
+    async fn get_by_id(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
+        self.query_one("SELECT * FROM users WHERE id = ? AND deleted_at IS NULL", id.to_string()).await
+    }
+
+    async fn get_by_ids(&self, ids: &[Uuid]) -> Result<Vec<Option<User>>, Box<dyn StdError>> {
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        let params = ids.iter().map(|id| Value::Text(id.to_string())).collect();
+        let found = self.query(format!("SELECT {} FROM users WHERE id IN ({}) AND deleted_at IS NULL", COLUMNS, placeholders), params).await?;
+        Ok(ids.iter().map(|id| found.iter().find(|user| &user.id == id).cloned()).collect())
+    }
+
+    async fn get_by_username(&self, username: &str) -> Result<Option<User>, Box<dyn StdError>> {
+        self.query_one("SELECT * FROM users WHERE lower(username) = lower(?) AND deleted_at IS NULL", username.to_string()).await
+    }
+
+    async fn renamed_to(&self, old_username: &str) -> Result<Option<String>, Box<dyn StdError>> {
+        let old_username = old_username.to_string();
+        self.call(move |conn| {
+            conn.query_row(
+                "SELECT u.username FROM username_history h JOIN users u ON u.id = h.user_id
+                 WHERE lower(h.username) = lower(?) AND u.username IS NOT NULL AND u.deleted_at IS NULL
+                 ORDER BY h.rowid DESC LIMIT 1",
+                [old_username],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn exists(&self, id: &Uuid) -> Result<bool, Box<dyn StdError>> {
+        Ok(self.get_by_id(id).await?.is_some())
+    }
+
+    // Deleted users count for both, they keep their email and username
+    async fn exists_by_email(&self, email: &str) -> Result<bool, Box<dyn StdError>> {
+        Ok(self.query_one("SELECT * FROM users WHERE email = ?", email.to_string()).await?.is_some())
+    }
+
+    async fn username_taken(&self, username: &str, except: Option<&Uuid>) -> Result<bool, Box<dyn StdError>> {
+        let owner = self.query_one("SELECT * FROM users WHERE lower(username) = lower(?)", username.to_string()).await?;
+        Ok(owner.is_some_and(|user| Some(&user.id) != except))
+    }
+
+    async fn create(&self, user_req: &CreateUserRequest) -> Result<User, Box<dyn StdError>> {
+        let password_hash = match &user_req.password {
+            Some(password) => Some(password_hash::hash(password).await?),
+            None => None,
+        };
+        let now = Utc::now();
+        let user = User {
+            id: Uuid::new_v4(),
+            username: user_req.username.clone(),
+            name: user_req.name.clone(),
+            email: user_req.email.clone(),
+            age: user_req.age,
+            role: Role::default(),
+            password_hash,
+            created_at: now,
+            updated_at: now,
+            origin_region: self.region.clone(),
+            updated_region: self.region.clone(),
+            deleted_at: None,
+            version: 1,
+        };
+
+        let row = user.clone();
+        self.call(move |conn| {
+            conn.execute(
+                &format!("INSERT INTO users ({}) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)", COLUMNS),
+                rusqlite::params![
+                    row.id.to_string(),
+                    row.username,
+                    row.name,
+                    row.email,
+                    row.age,
+                    row.role.as_str(),
+                    row.password_hash,
+                    row.created_at,
+                    row.updated_at,
+                    row.origin_region,
+                    row.updated_region,
+                    row.deleted_at,
+                    row.version,
+                ],
+            )
+        })
+        .await?;
+        Ok(user)
+    }
+
+    async fn update(&self, id: &Uuid, user_req: &UpdateUserRequest) -> Result<Option<User>, Box<dyn StdError>> {
+        let mut sets = Vec::new();
+        let mut params = Vec::new();
+        if let Some(username) = &user_req.username {
+            sets.push("username = ?");
+            params.push(Value::Text(username.clone()));
+        }
+        if let Some(name) = &user_req.name {
+            sets.push("name = ?");
+            params.push(Value::Text(name.clone()));
+        }
+        if let Some(email) = &user_req.email {
+            sets.push("email = ?");
+            params.push(Value::Text(email.clone()));
+        }
+        if let Some(age) = user_req.age {
+            sets.push("age = ?");
+            params.push(Value::Integer(age as i64));
+        }
+        if sets.is_empty() {
+            // Nothing to update, hand back the current row
+            let current = self.get_by_id(id).await?;
+            if let Some(current) = &current {
+                VersionConflict::check(user_req, current)?;
+            }
+            return Ok(current);
+        }
+        sets.extend(["updated_at = ?", "updated_region = ?", "version = version + 1"]);
+        params.push(Value::Text(Utc::now().format("%F %T%.f%:z").to_string()));
+        params.push(self.region.clone().map_or(Value::Null, Value::Text));
+        params.push(Value::Text(id.to_string()));
+
+        let mut sql = format!("UPDATE users SET {} WHERE id = ? AND deleted_at IS NULL", sets.join(", "));
+        if let Some(version) = user_req.version {
+            sql.push_str(" AND version = ?");
+            params.push(Value::Integer(version as i64));
+        }
+        let updated = self.call(move |conn| conn.execute(&sql, params_from_iter(params))).await?;
+        if updated == 0 {
+            // No such user, or it is at another version than the request's
+            if let Some(current) = self.get_by_id(id).await? {
+                VersionConflict::check(user_req, &current)?;
+            }
+            return Ok(None);
+        }
+        self.get_by_id(id).await
+    }
+
+    async fn delete(&self, id: &Uuid) -> Result<bool, Box<dyn StdError>> {
+        Ok(!self.delete_many(&[*id]).await?.is_empty())
+    }
+
+    async fn delete_many(&self, ids: &[Uuid]) -> Result<Vec<Uuid>, Box<dyn StdError>> {
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        let now = Utc::now().format("%F %T%.f%:z").to_string();
+        let params: Vec<String> = [now].into_iter().chain(ids.iter().map(Uuid::to_string)).collect();
+        let deleted: Vec<String> = self
+            .call(move |conn| {
+                let sql = format!("UPDATE users SET deleted_at = ?, version = version + 1 WHERE id IN ({}) AND deleted_at IS NULL RETURNING id", placeholders);
+                let mut statement = conn.prepare(&sql)?;
+                let ids = statement.query_map(params_from_iter(params), |row| row.get(0))?;
+                ids.collect()
+            })
+            .await?;
+        Ok(deleted.iter().map(|id| Uuid::parse_str(id)).collect::<Result<_, _>>()?)
+    }
+
+    async fn restore(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
+        let id = id.to_string();
+        self.call(move |conn| {
+            conn.query_row(
+                &format!("UPDATE users SET deleted_at = NULL, version = version + 1 WHERE id = ? AND deleted_at IS NOT NULL RETURNING {}", COLUMNS),
+                [id],
+                user_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+}
@@ -0,0 +1,98 @@
+use deadpool_postgres::Pool;
+use std::error::Error as StdError;
+use uuid::Uuid;
+
+use crate::models::user::User;
+use crate::repositories::user_repo::user_from_row;
+
+// Net effect of the changes to one user within a range of the change log
+pub enum UserChange {
+    Created(User),
+    Updated(User),
+    Deleted(Uuid),
+}
+
+// Access to the user change log backing differential sync. Entries are
+// written by a trigger on the users table, so every writer is captured,
+// including ones that bypass this service.
+pub struct SyncRepository {
+    pool: Pool,
+}
+
+impl SyncRepository {
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    // Sequence number of the latest change, handed to clients as their sync token
+    pub async fn current_token(&self) -> Result<i64, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let row = client
+            .query_one("SELECT COALESCE(MAX(seq), 0) FROM user_changes", &[])
+            .await?;
+
+        Ok(row.get(0))
+    }
+
+    // Changes after `since` up to and including `until`, collapsed to one entry
+    // per user. Soft-deleted users count as deleted.
+    pub async fn changes_between(&self, since: i64, until: i64) -> Result<Vec<UserChange>, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let rows = client
+            .query(
+                "SELECT c.user_id, c.created, u.*
+                 FROM (
+                     SELECT user_id, bool_or(op = 'insert') AS created
+                     FROM user_changes
+                     WHERE seq > $1 AND seq <= $2
+                     GROUP BY user_id
+                 ) c
+                 LEFT JOIN users u ON u.id = c.user_id AND u.deleted_at IS NULL",
+                &[&since, &until],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| match row.get::<_, Option<Uuid>>("id") {
+                None => UserChange::Deleted(row.get("user_id")),
+                Some(_) if row.get("created") => UserChange::Created(user_from_row(row)),
+                Some(_) => UserChange::Updated(user_from_row(row)),
+            })
+            .collect())
+    }
+
+    // Whether the user changed on the server after the client's sync token
+    pub async fn changed_since(&self, id: &Uuid, since: i64) -> Result<bool, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let row = client
+            .query_one(
+                "SELECT EXISTS (SELECT 1 FROM user_changes WHERE user_id = $1 AND seq > $2)",
+                &[id, &since],
+            )
+            .await?;
+
+        Ok(row.get(0))
+    }
+}
@@ -0,0 +1,91 @@
+use deadpool_postgres::Pool;
+use parking_lot::RwLock;
+use std::error::Error as StdError;
+
+use crate::models::tenant::TenantSettings;
+
+// This service runs one tenant per deployment, its settings live in this row
+const TENANT: &str = "default";
+
+// Tenant settings, read on every rendered page, so they are loaded once and kept
+// in memory. Other instances pick up changes when they restart.
+pub struct TenantSettingsRepository {
+    pool: Pool,
+    current: RwLock<TenantSettings>,
+}
+
+impl TenantSettingsRepository {
+    pub fn new(pool: Pool) -> Self {
+        Self {
+            pool,
+            current: RwLock::new(TenantSettings::default()),
+        }
+    }
+
+    // Read the stored settings into memory, defaults until an admin saves some
+    pub async fn load(&self) -> Result<(), Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let row = client
+            .query_opt(
+                "SELECT logo_url, primary_color, support_email, email_footer
+                 FROM tenant_settings WHERE tenant = $1",
+                &[&TENANT],
+            )
+            .await?;
+        if let Some(row) = row {
+            *self.current.write() = TenantSettings {
+                logo_url: row.get("logo_url"),
+                primary_color: row.get("primary_color"),
+                support_email: row.get("support_email"),
+                email_footer: row.get("email_footer"),
+            };
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self) -> TenantSettings {
+        self.current.read().clone()
+    }
+
+    // Replace the settings, every field is written including unset ones
+    pub async fn save(&self, settings: TenantSettings) -> Result<TenantSettings, Box<dyn StdError>> {
+        let client = match self.pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        client
+            .execute(
+                "INSERT INTO tenant_settings (tenant, logo_url, primary_color, support_email, email_footer)
+                 VALUES ($1, $2, $3, $4, $5)
+                 ON CONFLICT (tenant) DO UPDATE SET
+                    logo_url = EXCLUDED.logo_url,
+                    primary_color = EXCLUDED.primary_color,
+                    support_email = EXCLUDED.support_email,
+                    email_footer = EXCLUDED.email_footer,
+                    updated_at = now()",
+                &[
+                    &TENANT,
+                    &settings.logo_url,
+                    &settings.primary_color,
+                    &settings.support_email,
+                    &settings.email_footer,
+                ],
+            )
+            .await?;
+
+        *self.current.write() = settings.clone();
+        Ok(settings)
+    }
+}
@@ -0,0 +1,137 @@
+use deadpool_postgres::{Pool, Transaction};
+use rand::Rng;
+use sqlx::{PgConnection, PgPool};
+use std::error::Error as StdError;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio_postgres::error::SqlState;
+
+use crate::audit;
+use crate::request_id;
+
+// Attempts per transaction before a serialization failure or deadlock is returned
+const MAX_ATTEMPTS: u32 = 5;
+
+// First retry waits about this long, doubling with every further attempt
+const BASE_BACKOFF: Duration = Duration::from_millis(10);
+
+// Process-wide number of retried transactions
+static RETRIES: AtomicU64 = AtomicU64::new(0);
+
+pub type TxFuture<'t, T> = Pin<Box<dyn Future<Output = Result<T, Box<dyn StdError>>> + 't>>;
+
+// Run `f` inside a transaction and commit it. When Postgres aborts the
+// transaction with a serialization failure (40001) or a deadlock (40P01) the
+// whole closure is run again on a fresh transaction, with jittered exponential
+// backoff and a bounded number of attempts, so `f` must be safe to repeat.
+pub async fn with_transaction<T, F>(pool: &Pool, mut f: F) -> Result<T, Box<dyn StdError>>
+where
+    F: for<'t> FnMut(&'t Transaction<'t>) -> TxFuture<'t, T>,
+{
+    let mut attempt = 1;
+
+    loop {
+        let mut client = match pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to get DB client: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let tx = client.transaction().await?;
+        if let Some((actor, request_id)) = audit_tags() {
+            tx.execute(TAG_AUDIT, &[&actor, &request_id]).await?;
+        }
+        let result = match f(&tx).await {
+            Ok(value) => tx.commit().await.map(|_| value).map_err(Into::into),
+            Err(e) => {
+                let _ = tx.rollback().await;
+                Err(e)
+            }
+        };
+
+        match result {
+            Err(e) if attempt < MAX_ATTEMPTS && is_retryable(e.as_ref()) => {
+                back_off(attempt, e.as_ref()).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// with_transaction for the sqlx pool, with the same retries
+pub async fn with_sqlx_transaction<T, F>(pool: &PgPool, mut f: F) -> Result<T, Box<dyn StdError>>
+where
+    F: for<'t> FnMut(&'t mut PgConnection) -> TxFuture<'t, T>,
+{
+    let mut attempt = 1;
+
+    loop {
+        let mut tx = pool.begin().await?;
+        tag_audit(&mut tx).await?;
+        let result = match f(&mut tx).await {
+            Ok(value) => tx.commit().await.map(|_| value).map_err(Into::into),
+            Err(e) => {
+                let _ = tx.rollback().await;
+                Err(e)
+            }
+        };
+
+        match result {
+            Err(e) if attempt < MAX_ATTEMPTS && is_retryable(e.as_ref()) => {
+                back_off(attempt, e.as_ref()).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Tells the audit log trigger who is writing. Local to the transaction, so the
+// pooled connection doesn't carry it over to the next one.
+const TAG_AUDIT: &str = "SELECT set_config('app.actor', COALESCE($1::text, ''), true), set_config('app.request_id', COALESCE($2::text, ''), true)";
+
+// Actor and request id of the request being handled, None outside of one
+fn audit_tags() -> Option<(Option<String>, Option<String>)> {
+    let (actor, request_id) = (audit::current_actor(), request_id::current());
+    (actor.is_some() || request_id.is_some()).then_some((actor, request_id))
+}
+
+// Tag a transaction on the sqlx pool, the ones with_sqlx_transaction and
+// UserRepository::begin start
+pub async fn tag_audit(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    if let Some((actor, request_id)) = audit_tags() {
+        sqlx::query(TAG_AUDIT).bind(actor).bind(request_id).execute(conn).await?;
+    }
+    Ok(())
+}
+
+async fn back_off(attempt: u32, e: &dyn StdError) {
+    let retries = RETRIES.fetch_add(1, Ordering::Relaxed) + 1;
+    let backoff = BASE_BACKOFF * 2u32.pow(attempt - 1);
+    let backoff = backoff + backoff.mul_f64(rand::thread_rng().gen_range(0.0..0.5));
+    log::warn!(
+        "Retrying transaction in {:?} after {} (attempt {} of {}, {} retries since start)",
+        backoff, e, attempt, MAX_ATTEMPTS, retries
+    );
+    tokio::time::sleep(backoff).await;
+}
+
+// Retried transactions since the process started
+pub fn retries() -> u64 {
+    RETRIES.load(Ordering::Relaxed)
+}
+
+fn is_retryable(e: &(dyn StdError + 'static)) -> bool {
+    if let Some(e) = e.downcast_ref::<sqlx::Error>() {
+        let code = e.as_database_error().and_then(|e| e.code());
+        return code.is_some_and(|code| code == SqlState::T_R_SERIALIZATION_FAILURE.code() || code == SqlState::T_R_DEADLOCK_DETECTED.code());
+    }
+    e.downcast_ref::<tokio_postgres::Error>()
+        .and_then(|e| e.code())
+        .is_some_and(|code| *code == SqlState::T_R_SERIALIZATION_FAILURE || *code == SqlState::T_R_DEADLOCK_DETECTED)
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{PoisonError, RwLock};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::models::user::User;
+
+// How long the cache is skipped after it fails when CACHE_COOLDOWN_SECS isn't set
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+// The users cached by id, behind a circuit breaker. A failing cache (for the
+// in-process map, a lock poisoned by a panic while it was held) trips the
+// breaker: for the cool-down every read falls through to the database and
+// writes skip the cache. Those writes are missing from the entries, so the
+// cache starts over empty once the cool-down is up.
+pub struct UserCache {
+    entries: RwLock<HashMap<Uuid, User>>,
+    cooldown: Duration,
+    tripped: parking_lot::Mutex<Option<Trip>>,
+    trips: AtomicU64,
+    bypassed: AtomicU64,
+}
+
+struct Trip {
+    at: Instant,
+    error: String,
+}
+
+// Why and until when the cache is being skipped
+pub struct Degraded {
+    pub error: String,
+    pub retry_in: Duration,
+}
+
+impl UserCache {
+    pub fn new(cooldown: Duration) -> Self {
+        Self {
+            entries: RwLock::new(HashMap::new()),
+            cooldown,
+            tripped: parking_lot::Mutex::new(None),
+            trips: AtomicU64::new(0),
+            bypassed: AtomicU64::new(0),
+        }
+    }
+
+    // `f` over the entries, None while the cache is skipped
+    pub fn read<T>(&self, f: impl FnOnce(&HashMap<Uuid, User>) -> T) -> Option<T> {
+        if !self.available() {
+            return None;
+        }
+        let result = match self.entries.read() {
+            Ok(entries) => Ok(f(&entries)),
+            Err(e) => Err(e.to_string()),
+        };
+        result.map_err(|e| self.trip(e)).ok()
+    }
+
+    // Apply `f` to the entries, unless the cache is being skipped
+    pub fn write(&self, f: impl FnOnce(&mut HashMap<Uuid, User>)) {
+        if !self.available() {
+            return;
+        }
+        let error = match self.entries.write() {
+            Ok(mut entries) => {
+                f(&mut entries);
+                return;
+            }
+            Err(e) => e.to_string(),
+        };
+        self.trip(error);
+    }
+
+    pub fn entry_count(&self) -> usize {
+        self.read(HashMap::len).unwrap_or(0)
+    }
+
+    // None while the cache is in use
+    pub fn degraded(&self) -> Option<Degraded> {
+        let tripped = self.tripped.lock();
+        let trip = tripped.as_ref()?;
+        Some(Degraded {
+            error: trip.error.clone(),
+            retry_in: self.cooldown.saturating_sub(trip.at.elapsed()),
+        })
+    }
+
+    // Times the breaker tripped since startup
+    pub fn trips(&self) -> u64 {
+        self.trips.load(Ordering::Relaxed)
+    }
+
+    // Cache reads and writes skipped while it was tripped
+    pub fn bypassed(&self) -> u64 {
+        self.bypassed.load(Ordering::Relaxed)
+    }
+
+    fn available(&self) -> bool {
+        let mut tripped = self.tripped.lock();
+        match &*tripped {
+            None => true,
+            Some(trip) if trip.at.elapsed() < self.cooldown => {
+                self.bypassed.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+            Some(_) => {
+                let mut entries = self.entries.write().unwrap_or_else(PoisonError::into_inner);
+                entries.clear();
+                self.entries.clear_poison();
+                *tripped = None;
+                log::info!("User cache back in use after its cool-down, starting empty");
+                true
+            }
+        }
+    }
+
+    fn trip(&self, error: String) {
+        log::error!("User cache failed, reading users from the database for {:?}: {}", self.cooldown, error);
+        self.trips.fetch_add(1, Ordering::Relaxed);
+        *self.tripped.lock() = Some(Trip { at: Instant::now(), error });
+    }
+}
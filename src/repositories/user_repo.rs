@@ -1,268 +1,995 @@
+use chrono::{DateTime, Utc};
 use deadpool_postgres::Pool;
+use futures_util::stream;
+use futures_util::{Stream, StreamExt};
+use serde::Serialize;
+use sqlx::{PgConnection, PgPool, Postgres, QueryBuilder};
+use tokio_postgres::Row;
 use uuid::Uuid;
 use std::error::Error as StdError;
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 
-use crate::models::user::{User, CreateUserRequest, UpdateUserRequest};
+use crate::events::{DomainEvent, EventBus};
+use crate::models::sort::{SortField, SortSpec};
+use crate::models::user::{Role, User, CreateUserRequest, Suggestion, UpdateUserRequest, UserFilter};
+use crate::password_hash;
+use crate::repositories::transaction::{tag_audit, with_sqlx_transaction};
+use crate::repositories::user_cache::{Degraded, UserCache, DEFAULT_COOLDOWN};
+use crate::repositories::user_store::VersionConflict;
+use crate::request_id;
+use crate::suggest::SuggestIndex;
 
-// Original repository for database operations
+// Columns selected by every user query, read back by name in user_from_row. The
+// sqlx queries below spell them out, the macros need the query as one literal.
+pub const USER_COLUMNS: &str = "id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version";
+
+pub fn user_from_row(row: &Row) -> User {
+    User {
+        id: row.get("id"),
+        username: row.get("username"),
+        name: row.get("name"),
+        email: row.get("email"),
+        age: row.get::<_, Option<i16>>("age").map(|age| age as u8),
+        // A role this build doesn't know gets the fewest rights
+        role: Role::parse(row.get("role")).unwrap_or(Role::Readonly),
+        password_hash: row.get("password_hash"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+        origin_region: row.get("origin_region"),
+        updated_region: row.get("updated_region"),
+        deleted_at: row.get("deleted_at"),
+        version: row.get::<_, i32>("version") as u32,
+    }
+}
+
+// A users row as sqlx reads it
+#[derive(sqlx::FromRow)]
+struct UserRow {
+    id: Uuid,
+    username: Option<String>,
+    name: String,
+    email: String,
+    age: Option<i16>,
+    role: String,
+    password_hash: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    origin_region: Option<String>,
+    updated_region: Option<String>,
+    deleted_at: Option<DateTime<Utc>>,
+    version: i32,
+}
+
+impl From<UserRow> for User {
+    fn from(row: UserRow) -> Self {
+        Self {
+            id: row.id,
+            username: row.username,
+            name: row.name,
+            email: row.email,
+            age: row.age.map(|age| age as u8),
+            role: Role::parse(&row.role).unwrap_or(Role::Readonly),
+            password_hash: row.password_hash,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+            origin_region: row.origin_region,
+            updated_region: row.updated_region,
+            deleted_at: row.deleted_at,
+            version: row.version as u32,
+        }
+    }
+}
+
+fn users_from_rows(rows: Vec<UserRow>) -> Vec<User> {
+    rows.into_iter().map(User::from).collect()
+}
+
+// This instance's region, set on every connection by the pool when REGION is configured
+const LOCAL_REGION: &str = "NULLIF(current_setting('app.region', true), '')";
+
+// Rows buffered between the query and a slow streaming client
+const STREAM_BUFFER: usize = 64;
+
+// Compile a sort specification into an ORDER BY clause. Columns come from a
+// fixed whitelist, and id is always the final key so the order is deterministic.
+pub fn order_by_clause(sort: &SortSpec) -> String {
+    let mut keys: Vec<String> = sort
+        .keys
+        .iter()
+        .map(|key| {
+            let column = match key.field {
+                SortField::Id => "id".to_string(),
+                SortField::Name => "name".to_string(),
+                SortField::Email => "email".to_string(),
+                SortField::Age => "age".to_string(),
+                SortField::CreatedAt => "created_at".to_string(),
+                SortField::UpdatedAt => "updated_at".to_string(),
+                // false sorts first, so ascending puts local users first
+                SortField::LocalRegion => format!("(origin_region IS DISTINCT FROM {})", LOCAL_REGION),
+            };
+            format!("{} {}", column, if key.descending { "DESC" } else { "ASC" })
+        })
+        .collect();
+
+    if !sort.keys.iter().any(|key| key.field == SortField::Id) {
+        keys.push("id ASC".to_string());
+    }
+
+    format!("ORDER BY {}", keys.join(", "))
+}
+
+// Append the filter's WHERE clause to `query`, leaving out deleted users unless
+// the filter includes them. Returns false when there was nothing to filter and
+// no clause was added. Values are always bound, never spliced in.
+pub fn filter_clause(query: &mut QueryBuilder<'_, Postgres>, filter: &UserFilter) -> bool {
+    if filter.include_deleted && !filter.has_conditions() {
+        return false;
+    }
+    query.push(" WHERE ");
+    let mut conditions = query.separated(" AND ");
+    if !filter.include_deleted {
+        conditions.push("deleted_at IS NULL");
+    }
+    if let Some(email) = &filter.email {
+        conditions.push("email = ").push_bind_unseparated(email.clone());
+    }
+    if let Some(min_age) = filter.min_age {
+        conditions.push("age >= ").push_bind_unseparated(min_age as i16);
+    }
+    if let Some(max_age) = filter.max_age {
+        conditions.push("age <= ").push_bind_unseparated(max_age as i16);
+    }
+    if let Some(name) = &filter.name_contains {
+        conditions.push("name ILIKE ").push_bind_unseparated(contains_pattern(name));
+    }
+    match filter.region.as_deref() {
+        Some("local") => {
+            conditions.push(format!("origin_region = {}", LOCAL_REGION));
+        }
+        Some(region) => {
+            conditions.push("origin_region = ").push_bind_unseparated(region.to_string());
+        }
+        None => {}
+    }
+    true
+}
+
+fn select_users() -> QueryBuilder<'static, Postgres> {
+    QueryBuilder::new(format!("SELECT {} FROM users", USER_COLUMNS))
+}
+
+// LIKE pattern matching values that contain `text`, with any wildcards in it
+// matched literally
+fn contains_pattern(text: &str) -> String {
+    let escaped = text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+// Original repository for database operations. Queries go through sqlx and are
+// checked against the schema at compile time; the deadpool pool is kept for
+// the pool metrics and the repositories not moved over yet.
 pub struct UserRepository {
     pool: Pool,
+    db: PgPool,
+    hedge_delay: Option<Duration>,
+    hedge_stats: HedgeStats,
 }
 
-// New cached repository that wraps the original
+// Counters for hedged reads, used to keep an eye on the hedge rate
+#[derive(Default)]
+struct HedgeStats {
+    reads: AtomicU64,
+    hedged: AtomicU64,
+    hedge_wins: AtomicU64,
+}
+
+// Lookups by id answered from the cache and those that weren't
+#[derive(Default)]
+struct CacheStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+// New cached repository that wraps the original. Every write through it is
+// published as a domain event; writes made around it, like seeding and
+// retention policies, are not.
 pub struct CachedUserRepository {
     repo: UserRepository,
-    cache: Arc<RwLock<HashMap<Uuid, User>>>,
+    cache: UserCache,
+    suggestions: SuggestIndex,
+    events: EventBus,
+    cache_stats: CacheStats,
 }
 
 impl UserRepository {
-    pub fn new(pool: Pool) -> Self {
-        Self { pool }
+    pub fn new(pool: Pool, db: PgPool) -> Self {
+        Self {
+            pool,
+            db,
+            hedge_delay: None,
+            hedge_stats: HedgeStats::default(),
+        }
     }
 
-    pub async fn init_db(&self) -> Result<(), Box<dyn StdError>> {
-        let client = match self.pool.get().await {
-            Ok(client) => client,
-            Err(e) => {
-                log::error!("Failed to get DB client: {}", e);
-                return Err(Box::new(e));
-            }
-        };
-        
-        // Create users table if it doesn't exist
-        client
-            .execute(
-                "CREATE TABLE IF NOT EXISTS users (
-                    id UUID PRIMARY KEY,
-                    name VARCHAR(100) NOT NULL,
-                    email VARCHAR(255) NOT NULL UNIQUE,
-                    age SMALLINT
-                )",
-                &[],
-            )
-            .await?;
+    // Enable hedged reads: if a lookup hasn't returned after `delay`, a second
+    // attempt is issued on another pooled connection
+    pub fn with_hedge_delay(mut self, delay: Option<Duration>) -> Self {
+        self.hedge_delay = delay;
+        self
+    }
 
+    // Cheap round trip used by readiness checks
+    pub async fn ping(&self) -> Result<(), Box<dyn StdError>> {
+        sqlx::query("SELECT 1").execute(&self.db).await?;
         Ok(())
     }
 
+    pub fn pool_status(&self) -> String {
+        let status = self.pool.status();
+        format!("{} of {} connections available", status.available, status.max_size)
+    }
+
+    pub fn pool_usage(&self) -> deadpool_postgres::Status {
+        self.pool.status()
+    }
+
     pub async fn get_all(&self) -> Result<Vec<User>, Box<dyn StdError>> {
-        let client = match self.pool.get().await {
-            Ok(client) => client,
-            Err(e) => {
-                log::error!("Failed to get DB client: {}", e);
-                return Err(Box::new(e));
+        let rows = sqlx::query_as!(
+            UserRow,
+            "SELECT id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version
+             FROM users WHERE deleted_at IS NULL"
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(users_from_rows(rows))
+    }
+
+    // Just what the suggestion index needs, without the rest of each row
+    pub async fn get_all_names(&self) -> Result<Vec<(Uuid, String, DateTime<Utc>)>, Box<dyn StdError>> {
+        let rows = sqlx::query!("SELECT id, name, updated_at FROM users WHERE deleted_at IS NULL").fetch_all(&self.db).await?;
+
+        Ok(rows.into_iter().map(|row| (row.id, row.name, row.updated_at)).collect())
+    }
+
+    // Users written since `since`, by anyone, going by the change log the
+    // users table's trigger keeps. At most `limit` of them, in no particular order.
+    pub async fn changed_since(&self, since: DateTime<Utc>, limit: i64) -> Result<Vec<Uuid>, Box<dyn StdError>> {
+        let ids = sqlx::query_scalar!(
+            "SELECT user_id FROM user_changes WHERE changed_at >= $1 GROUP BY user_id LIMIT $2",
+            since,
+            limit
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(ids)
+    }
+
+    // Rebuild the full-text search index without blocking writes, for when it has bloated
+    pub async fn reindex_search(&self) -> Result<(), Box<dyn StdError>> {
+        sqlx::raw_sql("REINDEX INDEX CONCURRENTLY idx_users_search").execute(&self.db).await?;
+        Ok(())
+    }
+
+    // Stream users row by row instead of collecting them into a Vec. A task
+    // reads the rows into a small buffer and keeps its connection checked out
+    // until the last row has been sent or the client has gone away.
+    pub async fn stream_all(&self, sort: &SortSpec) -> Result<impl Stream<Item = Result<User, sqlx::Error>> + 'static, Box<dyn StdError>> {
+        let sql = format!("SELECT {} FROM users WHERE deleted_at IS NULL {}", USER_COLUMNS, order_by_clause(sort));
+        let db = self.db.clone();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(request_id::scope(request_id::current(), async move {
+            let mut rows = sqlx::query_as::<_, UserRow>(&sql).fetch(&db);
+            while let Some(row) = rows.next().await {
+                if tx.send(row.map(User::from)).await.is_err() {
+                    break;
+                }
             }
+        }));
+
+        // A query that fails outright is an error here rather than in the stream
+        let first = match rx.recv().await {
+            Some(Err(e)) => return Err(Box::new(e)),
+            first => first,
         };
-        
-        let rows = client
-            .query("SELECT id, name, email, age FROM users", &[])
-            .await?;
+        let rest = stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|row| (row, rx)) });
+        Ok(stream::iter(first).chain(rest))
+    }
 
-        Ok(rows
-            .iter()
-            .map(|row| User {
-                id: row.get(0),
-                name: row.get(1),
-                email: row.get(2),
-                age: row.get::<_, Option<i16>>(3).map(|age| age as u8),
-            })
-            .collect())
+    // Users matching the filter, in the requested order
+    pub async fn find(&self, filter: &UserFilter, sort: &SortSpec) -> Result<Vec<User>, Box<dyn StdError>> {
+        let mut query = select_users();
+        filter_clause(&mut query, filter);
+        query.push(" ").push(order_by_clause(sort));
+        let rows = query.build_query_as::<UserRow>().fetch_all(&self.db).await?;
+
+        Ok(users_from_rows(rows))
+    }
+
+    pub async fn get_paginated(&self, filter: &UserFilter, sort: &SortSpec, offset: i64, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        let mut query = select_users();
+        filter_clause(&mut query, filter);
+        query.push(" ").push(order_by_clause(sort));
+        query.push(" OFFSET ").push_bind(offset).push(" LIMIT ").push_bind(limit);
+        let rows = query.build_query_as::<UserRow>().fetch_all(&self.db).await?;
+
+        Ok(users_from_rows(rows))
+    }
+
+    // Up to `limit` users ordered by id, starting after `after`. Seeks through
+    // the primary key, so late pages cost the same as the first.
+    pub async fn get_after(&self, filter: &UserFilter, after: Option<&Uuid>, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        let mut query = select_users();
+        let filtered = filter_clause(&mut query, filter);
+        if let Some(after) = after {
+            query.push(if filtered { " AND " } else { " WHERE " });
+            query.push("id > ").push_bind(*after);
+        }
+        query.push(" ORDER BY id LIMIT ").push_bind(limit);
+        let rows = query.build_query_as::<UserRow>().fetch_all(&self.db).await?;
+
+        Ok(users_from_rows(rows))
     }
 
     pub async fn get_by_id(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
-        let client = match self.pool.get().await {
-            Ok(client) => client,
-            Err(e) => {
-                log::error!("Failed to get DB client: {}", e);
-                return Err(Box::new(e));
-            }
-        };
-        
-        let row = client
-            .query_opt(
-                "SELECT id, name, email, age FROM users WHERE id = $1",
-                &[id],
-            )
+        let row = sqlx::query_as!(
+            UserRow,
+            "SELECT id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version
+             FROM users WHERE id = $1 AND deleted_at IS NULL",
+            id
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.map(User::from))
+    }
+
+    pub async fn get_by_email(&self, email: &str) -> Result<Option<User>, Box<dyn StdError>> {
+        let row = sqlx::query_as!(
+            UserRow,
+            "SELECT id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version
+             FROM users WHERE email = $1 AND deleted_at IS NULL",
+            email
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.map(User::from))
+    }
+
+    pub async fn get_by_username(&self, username: &str) -> Result<Option<User>, Box<dyn StdError>> {
+        let row = sqlx::query_as!(
+            UserRow,
+            "SELECT id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version
+             FROM users WHERE lower(username) = lower($1) AND deleted_at IS NULL",
+            username
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.map(User::from))
+    }
+
+    // Id of the user with the username ignoring case, deleted or not
+    pub async fn username_owner(&self, username: &str) -> Result<Option<Uuid>, Box<dyn StdError>> {
+        let id = sqlx::query_scalar!("SELECT id FROM users WHERE lower(username) = lower($1)", username)
+            .fetch_optional(&self.db)
             .await?;
+        Ok(id)
+    }
+
+    // Current username of whoever most recently gave up `old_username`
+    pub async fn renamed_to(&self, old_username: &str) -> Result<Option<String>, Box<dyn StdError>> {
+        let username = sqlx::query_scalar!(
+            r#"SELECT u.username AS "username!" FROM username_history h JOIN users u ON u.id = h.user_id
+             WHERE lower(h.username) = lower($1) AND u.username IS NOT NULL AND u.deleted_at IS NULL
+             ORDER BY h.renamed_at DESC LIMIT 1"#,
+            old_username
+        )
+        .fetch_optional(&self.db)
+        .await?;
 
-        Ok(row.map(|row| User {
-            id: row.get(0),
-            name: row.get(1),
-            email: row.get(2),
-            age: row.get::<_, Option<i16>>(3).map(|age| age as u8),
-        }))
+        Ok(username)
     }
 
-    pub async fn create(&self, user_req: &CreateUserRequest) -> Result<User, Box<dyn StdError>> {
-        let client = match self.pool.get().await {
-            Ok(client) => client,
-            Err(e) => {
-                log::error!("Failed to get DB client: {}", e);
-                return Err(Box::new(e));
-            }
-        };
-        
-        let user_id = Uuid::new_v4();
-        let age: Option<i16> = user_req.age.map(|a| a as i16);
-        
-        client
-            .execute(
-                "INSERT INTO users (id, name, email, age) VALUES ($1, $2, $3, $4)",
-                &[&user_id, &user_req.name, &user_req.email, &age],
-            )
+    // Users matching the query's words or containing it as a substring of the
+    // name or email (for partial words and fragments like "@example"). Full-text
+    // matches rank first. The tsvector must stay the expression of
+    // idx_users_search in the migrations, or the index isn't used.
+    pub async fn search(&self, query: &str, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        let rows = sqlx::query_as!(
+            UserRow,
+            "SELECT id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version
+             FROM users
+             WHERE (to_tsvector('simple', name || ' ' || email) @@ plainto_tsquery('simple', $1) OR name ILIKE $2 OR email ILIKE $2)
+               AND deleted_at IS NULL
+             ORDER BY ts_rank(to_tsvector('simple', name || ' ' || email), plainto_tsquery('simple', $1)) DESC, name, id
+             LIMIT $3",
+            query,
+            contains_pattern(query),
+            limit
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(users_from_rows(rows))
+    }
+
+    pub async fn count(&self, filter: &UserFilter) -> Result<i64, Box<dyn StdError>> {
+        let mut query = QueryBuilder::new("SELECT COUNT(*) FROM users");
+        filter_clause(&mut query, filter);
+        let count = query.build_query_scalar().fetch_one(&self.db).await?;
+        Ok(count)
+    }
+
+    pub async fn exists(&self, id: &Uuid) -> Result<bool, Box<dyn StdError>> {
+        let exists = sqlx::query_scalar!(r#"SELECT EXISTS(SELECT 1 FROM users WHERE id = $1 AND deleted_at IS NULL) AS "exists!""#, id)
+            .fetch_one(&self.db)
             .await?;
+        Ok(exists)
+    }
 
-        Ok(User {
-            id: user_id,
-            name: user_req.name.clone(),
-            email: user_req.email.clone(),
-            age: user_req.age,
-        })
+    // Deleted users count, they keep their email
+    pub async fn exists_by_email(&self, email: &str) -> Result<bool, Box<dyn StdError>> {
+        let exists = sqlx::query_scalar!(r#"SELECT EXISTS(SELECT 1 FROM users WHERE email = $1) AS "exists!""#, email)
+            .fetch_one(&self.db)
+            .await?;
+        Ok(exists)
     }
 
-    pub async fn update(&self, id: &Uuid, user_req: &UpdateUserRequest) -> Result<Option<User>, Box<dyn StdError>> {
-        let client = match self.pool.get().await {
-            Ok(client) => client,
-            Err(e) => {
-                log::error!("Failed to get DB client: {}", e);
-                return Err(Box::new(e));
-            }
+    // Like get_by_id, but races a second attempt against a slow primary query
+    // when hedging is enabled and returns whichever finishes first
+    pub async fn get_by_id_hedged(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
+        let delay = match self.hedge_delay {
+            Some(delay) => delay,
+            None => return self.get_by_id(id).await,
         };
         
-        // First check if the user exists
-        let existing_user = self.get_by_id(id).await?;
-        if existing_user.is_none() {
-            return Ok(None);
-        }
-
-        let existing_user = existing_user.unwrap();
-        
-        // Build update query dynamically based on provided fields
-        let mut query_parts = Vec::new();
-        let mut param_values: Vec<Box<dyn tokio_postgres::types::ToSql + Sync>> = Vec::new();
-        
-        let mut param_idx = 1;
-        
-        if let Some(name) = &user_req.name {
-            query_parts.push(format!("name = ${}", param_idx));
-            param_values.push(Box::new(name.clone()));
-            param_idx += 1;
-        }
-        
-        if let Some(email) = &user_req.email {
-            query_parts.push(format!("email = ${}", param_idx));
-            param_values.push(Box::new(email.clone()));
-            param_idx += 1;
-        }
+        let stats = &self.hedge_stats;
+        stats.reads.fetch_add(1, Ordering::Relaxed);
         
-        if user_req.age.is_some() {
-            query_parts.push(format!("age = ${}", param_idx));
-            let age: Option<i16> = user_req.age.map(|a| a as i16);
-            param_values.push(Box::new(age));
-            param_idx += 1;
-        }
+        let primary = self.get_by_id(id);
+        tokio::pin!(primary);
         
-        if query_parts.is_empty() {
-            // Nothing to update
-            return Ok(Some(existing_user));
+        tokio::select! {
+            result = &mut primary => return result,
+            _ = tokio::time::sleep(delay) => {}
         }
         
-        // Build the full query
-        let query = format!(
-            "UPDATE users SET {} WHERE id = ${}",
-            query_parts.join(", "),
-            param_idx
+        let hedged = stats.hedged.fetch_add(1, Ordering::Relaxed) + 1;
+        log::debug!(
+            "Hedging read for user {} after {:?} ({} of {} reads hedged, {} won by the hedge)",
+            id,
+            delay,
+            hedged,
+            stats.reads.load(Ordering::Relaxed),
+            stats.hedge_wins.load(Ordering::Relaxed)
         );
         
-        // Add the id as the last parameter
-        param_values.push(Box::new(*id));
-        
-        // Convert param_values to a slice of &(dyn ToSql + Sync)
-        let params: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> = param_values
-            .iter()
-            .map(|p| p.as_ref())
-            .collect();
-
-        // Execute the query
-        let rows_affected = client.execute(&query, &params[..]).await?;
+        let hedge = self.get_by_id(id);
+        tokio::pin!(hedge);
         
-        if rows_affected == 0 {
-            return Ok(None);
+        tokio::select! {
+            result = &mut primary => result,
+            result = &mut hedge => {
+                stats.hedge_wins.fetch_add(1, Ordering::Relaxed);
+                result
+            }
         }
-        
-        // Construct the updated user
-        let updated_user = User {
-            id: existing_user.id,
-            name: user_req.name.clone().unwrap_or(existing_user.name),
-            email: user_req.email.clone().unwrap_or(existing_user.email),
-            age: user_req.age.or(existing_user.age),
-        };
-        
-        Ok(Some(updated_user))
     }
 
-    pub async fn delete(&self, id: &Uuid) -> Result<bool, Box<dyn StdError>> {
-        let client = match self.pool.get().await {
-            Ok(client) => client,
-            Err(e) => {
-                log::error!("Failed to get DB client: {}", e);
-                return Err(Box::new(e));
-            }
-        };
-        
-        let rows_affected = client
-            .execute("DELETE FROM users WHERE id = $1", &[id])
-            .await?;
-            
-        Ok(rows_affected > 0)
+    // Fetch several users with one query instead of one per id. Results are in
+    // the order of `ids`.
+    pub async fn get_by_ids(&self, ids: &[Uuid]) -> Result<Vec<Option<User>>, Box<dyn StdError>> {
+        let rows = sqlx::query_as!(
+            UserRow,
+            "SELECT id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version
+             FROM users WHERE id = ANY($1) AND deleted_at IS NULL",
+            ids
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        // In request order, an id asked for twice gets its user twice
+        let found: HashMap<Uuid, User> = rows.into_iter().map(|row| (row.id, User::from(row))).collect();
+        Ok(ids.iter().map(|id| found.get(id).cloned()).collect())
+    }
+
+    pub async fn create(&self, user_req: &CreateUserRequest) -> Result<User, Box<dyn StdError>> {
+        self.create_with_id(&Uuid::new_v4(), user_req).await
+    }
+
+    // Create a user with an id chosen by the caller, e.g. one generated offline by a sync client
+    pub async fn create_with_id(&self, id: &Uuid, user_req: &CreateUserRequest) -> Result<User, Box<dyn StdError>> {
+        with_sqlx_transaction(&self.db, |tx| {
+            let id = *id;
+            let user_req = user_req.clone();
+            Box::pin(async move { insert_user(tx, &id, &user_req).await })
+        }).await
+    }
+
+    // Insert a batch of users with a single statement. Rows whose email or
+    // username already exists are skipped instead of failing the whole batch, so
+    // only the rows actually inserted are returned.
+    pub async fn create_many(&self, user_reqs: &[CreateUserRequest]) -> Result<Vec<User>, Box<dyn StdError>> {
+        let columns = Columns::new(user_reqs.iter().map(|u| (Uuid::new_v4(), u)));
+
+        with_sqlx_transaction(&self.db, |tx| {
+            let Columns { ids, usernames, names, emails, ages } = columns.clone();
+            Box::pin(async move {
+                let rows = sqlx::query_as!(
+                    UserRow,
+                    r#"INSERT INTO users (id, username, name, email, age)
+                     SELECT * FROM UNNEST($1::uuid[], $2::varchar[], $3::varchar[], $4::varchar[], $5::smallint[])
+                     ON CONFLICT DO NOTHING
+                     RETURNING id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version"#,
+                    &ids,
+                    &usernames as &[Option<String>],
+                    &names,
+                    &emails,
+                    &ages as &[Option<i16>]
+                )
+                .fetch_all(&mut *tx)
+                .await?;
+                Ok(users_from_rows(rows))
+            })
+        }).await
+    }
+
+    // Insert or update users keyed by email. Only rows that actually changed are
+    // returned, each with the row as it was before, None when newly inserted.
+    // Usernames are only set on insert, existing users keep theirs. A deleted
+    // user's email is skipped, the import doesn't bring them back.
+    pub async fn upsert_many(&self, user_reqs: &[CreateUserRequest]) -> Result<Vec<(User, Option<User>)>, Box<dyn StdError>> {
+        let columns = Columns::new(user_reqs.iter().map(|u| (Uuid::new_v4(), u)));
+
+        with_sqlx_transaction(&self.db, |tx| {
+            let Columns { ids, usernames, names, emails, ages } = columns.clone();
+            Box::pin(async move {
+                // In a snapshot, a row inserted by someone else after the previous rows
+                // were read makes the upsert fail and retry instead of updating it unseen
+                sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ").execute(&mut *tx).await?;
+                let mut previous: HashMap<Uuid, User> = sqlx::query_as!(
+                    UserRow,
+                    "SELECT id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version
+                     FROM users WHERE email = ANY($1) FOR UPDATE",
+                    &emails
+                )
+                .fetch_all(&mut *tx)
+                .await?
+                .into_iter()
+                .map(|row| (row.id, User::from(row)))
+                .collect();
+
+                let rows = sqlx::query_as!(
+                    UserRow,
+                    "INSERT INTO users (id, username, name, email, age)
+                     SELECT * FROM UNNEST($1::uuid[], $2::varchar[], $3::varchar[], $4::varchar[], $5::smallint[])
+                     ON CONFLICT (email) DO UPDATE SET name = EXCLUDED.name, age = EXCLUDED.age, updated_at = now()
+                     WHERE users.deleted_at IS NULL AND (users.name, users.age) IS DISTINCT FROM (EXCLUDED.name, EXCLUDED.age)
+                     RETURNING id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version",
+                    &ids,
+                    &usernames as &[Option<String>],
+                    &names,
+                    &emails,
+                    &ages as &[Option<i16>]
+                )
+                .fetch_all(&mut *tx)
+                .await?;
+
+                Ok(rows
+                    .into_iter()
+                    .map(|row| {
+                        let user = User::from(row);
+                        let before = previous.remove(&user.id);
+                        (user, before)
+                    })
+                    .collect())
+            })
+        }).await
+    }
+
+    // Swap the whole table for the given users in one transaction, readers see
+    // either the old rows or the new ones. Returns the removed and the new rows.
+    pub async fn replace_all(&self, users: &[(Uuid, CreateUserRequest)]) -> Result<(Vec<User>, Vec<User>), Box<dyn StdError>> {
+        let columns = Columns::new(users.iter().map(|(id, u)| (*id, u)));
+
+        with_sqlx_transaction(&self.db, |tx| {
+            let Columns { ids, usernames, names, emails, ages } = columns.clone();
+            Box::pin(async move {
+                let removed = sqlx::query_as!(
+                    UserRow,
+                    "DELETE FROM users
+                     RETURNING id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version"
+                )
+                .fetch_all(&mut *tx)
+                .await?;
+                let inserted = sqlx::query_as!(
+                    UserRow,
+                    "INSERT INTO users (id, username, name, email, age)
+                     SELECT * FROM UNNEST($1::uuid[], $2::varchar[], $3::varchar[], $4::varchar[], $5::smallint[])
+                     RETURNING id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version",
+                    &ids,
+                    &usernames as &[Option<String>],
+                    &names,
+                    &emails,
+                    &ages as &[Option<i16>]
+                )
+                .fetch_all(&mut *tx)
+                .await?;
+                Ok((users_from_rows(removed), users_from_rows(inserted)))
+            })
+        }).await
+    }
+
+    // The row as it was and as it is now, the previous one is None when there was
+    // nothing to change. with_sqlx_transaction also gives retries when the server
+    // enforces serializable isolation. The closure may run more than once, so each
+    // attempt gets its own copy of the input.
+    pub async fn update(&self, id: &Uuid, user_req: &UpdateUserRequest) -> Result<Option<(Option<User>, User)>, Box<dyn StdError>> {
+        with_sqlx_transaction(&self.db, |tx| {
+            let id = *id;
+            let user_req = user_req.clone();
+            Box::pin(async move { update_user(tx, &id, &user_req).await })
+        }).await
+    }
+
+    // The row as it was and as it is now
+    pub async fn set_role(&self, id: &Uuid, role: Role) -> Result<Option<(User, User)>, Box<dyn StdError>> {
+        with_sqlx_transaction(&self.db, |tx| {
+            let id = *id;
+            Box::pin(async move { set_user_role(tx, &id, role).await })
+        }).await
+    }
+
+    // The deleted row, None when there was none
+    pub async fn delete(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
+        with_sqlx_transaction(&self.db, |tx| {
+            let id = *id;
+            Box::pin(async move { delete_user(tx, &id).await })
+        }).await
+    }
+
+    // The restored row, None unless the user exists and is deleted
+    pub async fn restore(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
+        with_sqlx_transaction(&self.db, |tx| {
+            let id = *id;
+            Box::pin(async move {
+                let row = sqlx::query_as!(
+                    UserRow,
+                    "UPDATE users SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL
+                     RETURNING id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version",
+                    id
+                )
+                .fetch_optional(&mut *tx)
+                .await?;
+                Ok(row.map(User::from))
+            })
+        }).await
+    }
+
+    // The deleted rows, ids without a user are skipped
+    pub async fn delete_many(&self, ids: &[Uuid]) -> Result<Vec<User>, Box<dyn StdError>> {
+        with_sqlx_transaction(&self.db, |tx| {
+            let ids = ids.to_vec();
+            Box::pin(async move {
+                let rows = sqlx::query_as!(
+                    UserRow,
+                    "UPDATE users SET deleted_at = now() WHERE id = ANY($1) AND deleted_at IS NULL
+                     RETURNING id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version",
+                    &ids
+                )
+                .fetch_all(&mut *tx)
+                .await?;
+                Ok(users_from_rows(rows))
+            })
+        }).await
+    }
+
+    // Start a transaction for several writes that must happen together
+    pub async fn begin(&self) -> Result<UserTx, Box<dyn StdError>> {
+        let mut tx = self.db.begin().await?;
+        tag_audit(&mut tx).await?;
+        Ok(UserTx { tx, changes: Vec::new() })
+    }
+
+    // The changes the transaction made, now that they are committed
+    pub async fn commit(&self, tx: UserTx) -> Result<Vec<DomainEvent>, Box<dyn StdError>> {
+        tx.tx.commit().await?;
+        Ok(tx.changes)
     }
 
     pub async fn seed_sample_data(&self) -> Result<(), Box<dyn StdError>> {
         // Check if we already have users
-        let users = self.get_all().await?;
-        if !users.is_empty() {
+        let seeded = sqlx::query_scalar!(r#"SELECT EXISTS(SELECT 1 FROM users) AS "exists!""#).fetch_one(&self.db).await?;
+        if seeded {
             return Ok(());
         }
-        
-        let client = match self.pool.get().await {
-            Ok(client) => client,
-            Err(e) => {
-                log::error!("Failed to get DB client: {}", e);
-                return Err(Box::new(e));
-            }
-        };
-        
-        let sample_id = Uuid::new_v4();
-        let age: Option<i16> = Some(30);
-        
-        client
-            .execute(
-                "INSERT INTO users (id, name, email, age) VALUES ($1, $2, $3, $4)",
-                &[
-                    &sample_id,
-                    &"John Doe".to_string(),
-                    &"john@example.com".to_string(),
-                    &age,
-                ],
-            )
-            .await?;
-            
+
+        sqlx::query!(
+            "INSERT INTO users (id, name, email, age) VALUES ($1, $2, $3, $4)",
+            Uuid::new_v4(),
+            "John Doe",
+            "john@example.com",
+            30i16
+        )
+        .execute(&self.db)
+        .await?;
+
         Ok(())
     }
 }
 
+// Users split into one array per column, for the UNNEST inserts
+#[derive(Clone)]
+struct Columns {
+    ids: Vec<Uuid>,
+    usernames: Vec<Option<String>>,
+    names: Vec<String>,
+    emails: Vec<String>,
+    ages: Vec<Option<i16>>,
+}
+
+impl Columns {
+    fn new<'a>(users: impl Iterator<Item = (Uuid, &'a CreateUserRequest)>) -> Self {
+        let mut columns = Self { ids: Vec::new(), usernames: Vec::new(), names: Vec::new(), emails: Vec::new(), ages: Vec::new() };
+        for (id, user) in users {
+            columns.ids.push(id);
+            columns.usernames.push(user.username.clone());
+            columns.names.push(user.name.clone());
+            columns.emails.push(user.email.clone());
+            columns.ages.push(user.age.map(|a| a as i16));
+        }
+        columns
+    }
+}
+
+// The statements behind the writes, shared by UserRepository and UserTx
+async fn insert_user(conn: &mut PgConnection, id: &Uuid, user_req: &CreateUserRequest) -> Result<User, Box<dyn StdError>> {
+    let age: Option<i16> = user_req.age.map(|a| a as i16);
+    let password_hash = match &user_req.password {
+        Some(password) => Some(password_hash::hash(password).await?),
+        None => None,
+    };
+
+    let row = sqlx::query_as!(
+        UserRow,
+        "INSERT INTO users (id, username, name, email, age, password_hash) VALUES ($1, $2, $3, $4, $5, $6)
+         RETURNING id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version",
+        id,
+        user_req.username,
+        user_req.name,
+        user_req.email,
+        age,
+        password_hash
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(row.into())
+}
+
+// The row is locked while it is read, so no other write can come between the
+// two and the version the request names is still the row's when it's written
+async fn update_user(conn: &mut PgConnection, id: &Uuid, user_req: &UpdateUserRequest) -> Result<Option<(Option<User>, User)>, Box<dyn StdError>> {
+    let row = sqlx::query_as!(
+        UserRow,
+        "SELECT id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version
+         FROM users WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(before) = row.map(User::from) else { return Ok(None) };
+    VersionConflict::check(user_req, &before)?;
+    if user_req.is_empty() {
+        // Nothing to update, hand back the current row
+        return Ok(Some((None, before)));
+    }
+
+    // Fields left out of the request keep their value
+    let row = sqlx::query_as!(
+        UserRow,
+        "UPDATE users SET
+            username = COALESCE($2, username),
+            name = COALESCE($3, name),
+            email = COALESCE($4, email),
+            age = COALESCE($5, age),
+            updated_at = now()
+         WHERE id = $1
+         RETURNING id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version",
+        id,
+        user_req.username,
+        user_req.name,
+        user_req.email,
+        user_req.age.map(|a| a as i16)
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(Some((Some(before), row.into())))
+}
+
+async fn set_user_role(conn: &mut PgConnection, id: &Uuid, role: Role) -> Result<Option<(User, User)>, Box<dyn StdError>> {
+    let row = sqlx::query_as!(
+        UserRow,
+        "SELECT id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version
+         FROM users WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(before) = row.map(User::from) else { return Ok(None) };
+
+    let row = sqlx::query_as!(
+        UserRow,
+        "UPDATE users SET role = $2, updated_at = now() WHERE id = $1
+         RETURNING id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version",
+        id,
+        role.as_str()
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(Some((before, row.into())))
+}
+
+// Only stamps deleted_at, the row stays for restore()
+async fn delete_user(conn: &mut PgConnection, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
+    let row = sqlx::query_as!(
+        UserRow,
+        "UPDATE users SET deleted_at = now() WHERE id = $1 AND deleted_at IS NULL
+         RETURNING id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version",
+        id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    Ok(row.map(User::from))
+}
+
+// Writes of UserRepository in one transaction, from begin() to commit(). They
+// are all committed together, or rolled back together when the UserTx is
+// dropped without committing, e.g. by a `?` on a later step.
+pub struct UserTx {
+    tx: sqlx::Transaction<'static, Postgres>,
+    // For the cached repository to catch up once committed
+    changes: Vec<DomainEvent>,
+}
+
+impl UserTx {
+    // Locked until the transaction ends, so what is decided on it stays true
+    pub async fn get_by_id(&mut self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
+        let row = sqlx::query_as!(
+            UserRow,
+            "SELECT id, username, name, email, age, role, password_hash, created_at, updated_at, origin_region, updated_region, deleted_at, version
+             FROM users WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+            id
+        )
+        .fetch_optional(&mut *self.tx)
+        .await?;
+        Ok(row.map(User::from))
+    }
+
+    pub async fn create_with_id(&mut self, id: &Uuid, user_req: &CreateUserRequest) -> Result<User, Box<dyn StdError>> {
+        let user = insert_user(&mut self.tx, id, user_req).await?;
+        self.changes.push(DomainEvent::UserCreated { user: user.clone() });
+        Ok(user)
+    }
+
+    pub async fn update(&mut self, id: &Uuid, user_req: &UpdateUserRequest) -> Result<Option<User>, Box<dyn StdError>> {
+        Ok(match update_user(&mut self.tx, id, user_req).await? {
+            Some((Some(before), after)) => {
+                self.changes.push(DomainEvent::UserUpdated { before, after: after.clone() });
+                Some(after)
+            }
+            Some((None, unchanged)) => Some(unchanged),
+            None => None,
+        })
+    }
+
+    pub async fn delete(&mut self, id: &Uuid) -> Result<bool, Box<dyn StdError>> {
+        let Some(user) = delete_user(&mut self.tx, id).await? else { return Ok(false) };
+        self.changes.push(DomainEvent::UserDeleted { user });
+        Ok(true)
+    }
+}
+
+// What bringing cache entries up to date did: users read again from the
+// database, and cache entries dropped
+#[derive(Debug, Default, Serialize)]
+pub struct CacheRefresh {
+    pub reloaded: usize,
+    pub evicted: usize,
+}
+
 impl CachedUserRepository {
-    pub fn new(pool: Pool) -> Self {
+    pub fn new(pool: Pool, db: PgPool) -> Self {
         Self {
-            repo: UserRepository::new(pool),
-            cache: Arc::new(RwLock::new(HashMap::new())),
+            repo: UserRepository::new(pool, db),
+            cache: UserCache::new(DEFAULT_COOLDOWN),
+            suggestions: SuggestIndex::default(),
+            events: EventBus::default(),
+            cache_stats: CacheStats::default(),
         }
     }
 
-    pub async fn init_db(&self) -> Result<(), Box<dyn StdError>> {
-        self.repo.init_db().await
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    pub fn with_hedge_delay(mut self, delay: Option<Duration>) -> Self {
+        self.repo = self.repo.with_hedge_delay(delay);
+        self
+    }
+
+    // How long the cache is skipped after it fails
+    pub fn with_cache_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cache = UserCache::new(cooldown);
+        self
+    }
+
+    pub async fn ping(&self) -> Result<(), Box<dyn StdError>> {
+        self.repo.ping().await
+    }
+
+    pub fn pool_status(&self) -> String {
+        self.repo.pool_status()
+    }
+
+    pub fn pool_usage(&self) -> deadpool_postgres::Status {
+        self.repo.pool_usage()
+    }
+
+    pub fn cache_len(&self) -> usize {
+        self.cache.entry_count()
+    }
+
+    // Hits and misses since startup
+    pub fn cache_stats(&self) -> (u64, u64) {
+        (self.cache_stats.hits.load(Ordering::Relaxed), self.cache_stats.misses.load(Ordering::Relaxed))
+    }
+
+    // Set while the cache has failed and is being skipped
+    pub fn cache_degraded(&self) -> Option<Degraded> {
+        self.cache.degraded()
+    }
+
+    // Times the cache failed and reads or writes that skipped it since, since startup
+    pub fn cache_breaker_stats(&self) -> (u64, u64) {
+        (self.cache.trips(), self.cache.bypassed())
+    }
+
+    pub fn suggest(&self, query: &str, limit: usize) -> Vec<Suggestion> {
+        self.suggestions.suggest(query, limit)
+    }
+
+    // Reload the suggestion index from the database, for writes made around this
+    // repository such as startup seeding or retention policies
+    pub async fn rebuild_suggestions(&self) -> Result<usize, Box<dyn StdError>> {
+        let names = self.repo.get_all_names().await?;
+        let count = names.len();
+        log::info!("Suggestion index rebuilt with {} users", count);
+        self.suggestions.rebuild(names);
+        Ok(count)
+    }
+
+    // Replace the cache with every user as stored now, returning how many there are.
+    // Reads keep being answered from the old contents until the new ones are loaded.
+    pub async fn reload_cache(&self) -> Result<usize, Box<dyn StdError>> {
+        let users = self.repo.get_all().await?;
+        let cache: HashMap<Uuid, User> = users.into_iter().map(|user| (user.id, user)).collect();
+        let count = cache.len();
+        self.cache.write(|entries| *entries = cache);
+        log::info!("User cache reloaded with {} users", count);
+        Ok(count)
+    }
+
+    pub async fn reindex_search(&self) -> Result<(), Box<dyn StdError>> {
+        self.repo.reindex_search().await
     }
 
     pub async fn get_all(&self) -> Result<Vec<User>, Box<dyn StdError>> {
@@ -270,109 +997,447 @@ impl CachedUserRepository {
         let users = self.repo.get_all().await?;
         
         // Update cache with all users
-        {
-            let mut cache = self.cache.write().unwrap();
+        self.cache.write(|cache| {
             for user in &users {
                 cache.insert(user.id, user.clone());
             }
-        }
+        });
+        
+        Ok(users)
+    }
+
+    pub async fn stream_all(&self, sort: &SortSpec) -> Result<impl Stream<Item = Result<User, sqlx::Error>> + 'static, Box<dyn StdError>> {
+        // Streamed reads bypass the cache, filling it would mean holding every row anyway
+        self.repo.stream_all(sort).await
+    }
+
+    pub async fn find(&self, filter: &UserFilter, sort: &SortSpec) -> Result<Vec<User>, Box<dyn StdError>> {
+        let users = self.repo.find(filter, sort).await?;
+        self.remember(&users);
+        Ok(users)
+    }
+
+    pub async fn get_paginated(&self, filter: &UserFilter, sort: &SortSpec, offset: i64, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        let users = self.repo.get_paginated(filter, sort, offset, limit).await?;
+        self.remember(&users);
+        Ok(users)
+    }
+
+    pub async fn get_after(&self, filter: &UserFilter, after: Option<&Uuid>, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        let users = self.repo.get_after(filter, after, limit).await?;
+        self.remember(&users);
+        Ok(users)
+    }
+
+    // Deleted users, listed with include_deleted, stay out of the cache
+    fn remember(&self, users: &[User]) {
+        self.cache.write(|cache| {
+            for user in users.iter().filter(|user| user.deleted_at.is_none()) {
+                cache.insert(user.id, user.clone());
+            }
+        });
+    }
+
+    pub async fn search(&self, query: &str, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        let users = self.repo.search(query, limit).await?;
+        
+        self.cache.write(|cache| {
+            for user in &users {
+                cache.insert(user.id, user.clone());
+            }
+        });
         
         Ok(users)
     }
 
     pub async fn get_by_id(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
         // Check cache first
-        {
-            let cache = self.cache.read().unwrap();
-            if let Some(user) = cache.get(id) {
-                log::debug!("Cache hit for user with id: {}", id);
-                return Ok(Some(user.clone()));
-            }
+        if let Some(user) = self.cache.read(|cache| cache.get(id).cloned()).flatten() {
+            log::debug!("Cache hit for user with id: {}", id);
+            self.cache_stats.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(Some(user));
         }
         
         // If not in cache, get from DB
         log::debug!("Cache miss for user with id: {}", id);
-        let user_option = self.repo.get_by_id(id).await?;
+        self.cache_stats.misses.fetch_add(1, Ordering::Relaxed);
+        let user_option = self.repo.get_by_id_hedged(id).await?;
         
         // If found, update cache
         if let Some(ref user) = user_option {
-            let mut cache = self.cache.write().unwrap();
-            cache.insert(user.id, user.clone());
+            self.cache.write(|cache| {
+                cache.insert(user.id, user.clone());
+            });
+        }
+        
+        Ok(user_option)
+    }
+
+    pub async fn get_by_email(&self, email: &str) -> Result<Option<User>, Box<dyn StdError>> {
+        // The cache is keyed by id, so email lookups always go to the database
+        let user_option = self.repo.get_by_email(email).await?;
+        
+        if let Some(ref user) = user_option {
+            self.cache.write(|cache| {
+                cache.insert(user.id, user.clone());
+            });
         }
         
         Ok(user_option)
     }
 
+    pub async fn get_by_username(&self, username: &str) -> Result<Option<User>, Box<dyn StdError>> {
+        // Like email lookups, these always go to the database
+        let user_option = self.repo.get_by_username(username).await?;
+        
+        if let Some(ref user) = user_option {
+            self.cache.write(|cache| {
+                cache.insert(user.id, user.clone());
+            });
+        }
+        
+        Ok(user_option)
+    }
+
+    // Whether a user other than `except` already has the username, ignoring case.
+    // Deleted users keep theirs.
+    pub async fn username_taken(&self, username: &str, except: Option<&Uuid>) -> Result<bool, Box<dyn StdError>> {
+        Ok(self
+            .repo
+            .username_owner(username)
+            .await?
+            .is_some_and(|id| Some(&id) != except))
+    }
+
+    pub async fn renamed_to(&self, old_username: &str) -> Result<Option<String>, Box<dyn StdError>> {
+        self.repo.renamed_to(old_username).await
+    }
+
+    pub async fn count(&self, filter: &UserFilter) -> Result<i64, Box<dyn StdError>> {
+        self.repo.count(filter).await
+    }
+
+    pub async fn exists(&self, id: &Uuid) -> Result<bool, Box<dyn StdError>> {
+        // A cached user is known to exist, anything else needs the database
+        if self.cache.read(|cache| cache.contains_key(id)).unwrap_or(false) {
+            return Ok(true);
+        }
+        self.repo.exists(id).await
+    }
+
+    pub async fn exists_by_email(&self, email: &str) -> Result<bool, Box<dyn StdError>> {
+        self.repo.exists_by_email(email).await
+    }
+
+    pub async fn get_by_ids(&self, ids: &[Uuid]) -> Result<Vec<Option<User>>, Box<dyn StdError>> {
+        // Serve what we can from the cache
+        let mut users: Vec<Option<User>> = self
+            .cache
+            .read(|cache| ids.iter().map(|id| cache.get(id).cloned()).collect())
+            .unwrap_or_else(|| vec![None; ids.len()]);
+        
+        // Fetch the misses with one query
+        let missing: Vec<Uuid> = ids
+            .iter()
+            .zip(&users)
+            .filter(|(_, user)| user.is_none())
+            .map(|(id, _)| *id)
+            .collect();
+        self.cache_stats.hits.fetch_add((ids.len() - missing.len()) as u64, Ordering::Relaxed);
+        self.cache_stats.misses.fetch_add(missing.len() as u64, Ordering::Relaxed);
+        
+        if missing.is_empty() {
+            return Ok(users);
+        }
+        
+        log::debug!("Cache miss for {} of {} users", missing.len(), ids.len());
+        let mut fetched = self.repo.get_by_ids(&missing).await?.into_iter();
+        
+        let mut found = Vec::new();
+        for slot in users.iter_mut().filter(|user| user.is_none()) {
+            if let Some(user) = fetched.next().flatten() {
+                found.push(user.clone());
+                *slot = Some(user);
+            }
+        }
+        self.cache.write(|cache| {
+            for user in found {
+                cache.insert(user.id, user);
+            }
+        });
+        
+        Ok(users)
+    }
+
     pub async fn create(&self, user_req: &CreateUserRequest) -> Result<User, Box<dyn StdError>> {
         // Create in DB first
         let user = self.repo.create(user_req).await?;
         
         // Then update cache
-        {
-            let mut cache = self.cache.write().unwrap();
+        self.cache.write(|cache| {
             cache.insert(user.id, user.clone());
-        }
+        });
+        self.suggestions.upsert(&user);
+        self.events.publish(DomainEvent::UserCreated { user: user.clone() });
         
         Ok(user)
     }
 
+    pub async fn create_many(&self, user_reqs: &[CreateUserRequest]) -> Result<Vec<User>, Box<dyn StdError>> {
+        let users = self.repo.create_many(user_reqs).await?;
+        
+        self.cache.write(|cache| {
+            for user in &users {
+                cache.insert(user.id, user.clone());
+            }
+        });
+        for user in &users {
+            self.suggestions.upsert(user);
+        }
+        for user in &users {
+            self.events.publish(DomainEvent::UserCreated { user: user.clone() });
+        }
+        
+        Ok(users)
+    }
+
+    pub async fn upsert_many(&self, user_reqs: &[CreateUserRequest]) -> Result<Vec<(User, bool)>, Box<dyn StdError>> {
+        let users = self.repo.upsert_many(user_reqs).await?;
+        
+        self.cache.write(|cache| {
+            for (user, _) in &users {
+                cache.insert(user.id, user.clone());
+            }
+        });
+        for (user, _) in &users {
+            self.suggestions.upsert(user);
+        }
+        
+        Ok(users
+            .into_iter()
+            .map(|(user, before)| {
+                let inserted = before.is_none();
+                self.events.publish(match before {
+                    Some(before) => DomainEvent::UserUpdated { before, after: user.clone() },
+                    None => DomainEvent::UserCreated { user: user.clone() },
+                });
+                (user, inserted)
+            })
+            .collect())
+    }
+
+    pub async fn replace_all(&self, users: &[(Uuid, CreateUserRequest)]) -> Result<u64, Box<dyn StdError>> {
+        let (removed, inserted) = self.repo.replace_all(users).await?;
+        
+        // Every cached entry is stale now
+        self.cache.write(HashMap::clear);
+        self.rebuild_suggestions().await?;
+        for user in removed {
+            self.events.publish(DomainEvent::UserDeleted { user });
+        }
+        let count = inserted.len() as u64;
+        for user in inserted {
+            self.events.publish(DomainEvent::UserCreated { user });
+        }
+        
+        Ok(count)
+    }
+
     pub async fn update(&self, id: &Uuid, user_req: &UpdateUserRequest) -> Result<Option<User>, Box<dyn StdError>> {
         // Update in DB first
-        let updated_user = self.repo.update(id, user_req).await?;
+        let updated = self.repo.update(id, user_req).await?;
+        let updated_user = match updated {
+            Some((Some(before), after)) => {
+                self.events.publish(DomainEvent::UserUpdated { before, after: after.clone() });
+                Some(after)
+            }
+            Some((None, unchanged)) => Some(unchanged),
+            None => None,
+        };
         
         // Then update cache if user exists
         if let Some(ref user) = updated_user {
-            let mut cache = self.cache.write().unwrap();
-            cache.insert(user.id, user.clone());
+            self.cache.write(|cache| {
+                cache.insert(user.id, user.clone());
+            });
+            self.suggestions.upsert(user);
         } else {
             // If user doesn't exist anymore, remove from cache
-            let mut cache = self.cache.write().unwrap();
-            cache.remove(id);
+            self.cache.write(|cache| {
+                cache.remove(id);
+            });
+            self.suggestions.remove(id);
         }
         
         Ok(updated_user)
     }
 
+    pub async fn set_role(&self, id: &Uuid, role: Role) -> Result<Option<User>, Box<dyn StdError>> {
+        let updated_user = self.repo.set_role(id, role).await?.map(|(before, after)| {
+            self.events.publish(DomainEvent::UserUpdated { before, after: after.clone() });
+            after
+        });
+
+        self.cache.write(|cache| {
+            match &updated_user {
+                Some(user) => cache.insert(user.id, user.clone()),
+                None => cache.remove(id),
+            };
+        });
+
+        Ok(updated_user)
+    }
+
     pub async fn delete(&self, id: &Uuid) -> Result<bool, Box<dyn StdError>> {
         // Delete from DB first
         let deleted = self.repo.delete(id).await?;
         
         // If deleted, remove from cache
-        if deleted {
-            let mut cache = self.cache.write().unwrap();
+        let Some(user) = deleted else { return Ok(false) };
+        self.cache.write(|cache| {
             cache.remove(id);
-        }
+        });
+        self.suggestions.remove(id);
+        self.events.publish(DomainEvent::UserDeleted { user });
         
-        Ok(deleted)
+        Ok(true)
+    }
+
+    pub async fn restore(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
+        let Some(user) = self.repo.restore(id).await? else { return Ok(None) };
+        self.cache.write(|cache| {
+            cache.insert(user.id, user.clone());
+        });
+        self.suggestions.upsert(&user);
+        self.events.publish(DomainEvent::UserRestored { user: user.clone() });
+        Ok(Some(user))
+    }
+
+    pub async fn begin(&self) -> Result<UserTx, Box<dyn StdError>> {
+        self.repo.begin().await
+    }
+
+    // The cache and the events only hear of a transaction's writes once it is committed
+    pub async fn commit(&self, tx: UserTx) -> Result<(), Box<dyn StdError>> {
+        let changes = self.repo.commit(tx).await?;
+        for change in changes {
+            match &change {
+                DomainEvent::UserCreated { user } | DomainEvent::UserUpdated { after: user, .. } | DomainEvent::UserRestored { user } => {
+                    self.cache.write(|cache| {
+                        cache.insert(user.id, user.clone());
+                    });
+                    self.suggestions.upsert(user);
+                }
+                DomainEvent::UserDeleted { user } => {
+                    self.cache.write(|cache| {
+                        cache.remove(&user.id);
+                    });
+                    self.suggestions.remove(&user.id);
+                }
+            }
+            self.events.publish(change);
+        }
+        Ok(())
+    }
+
+    // Ids of the users that were deleted
+    pub async fn delete_many(&self, ids: &[Uuid]) -> Result<Vec<Uuid>, Box<dyn StdError>> {
+        let deleted = self.repo.delete_many(ids).await?;
+
+        self.cache.write(|cache| {
+            for user in &deleted {
+                cache.remove(&user.id);
+            }
+        });
+        for user in &deleted {
+            self.suggestions.remove(&user.id);
+        }
+        Ok(deleted
+            .into_iter()
+            .map(|user| {
+                let id = user.id;
+                self.events.publish(DomainEvent::UserDeleted { user });
+                id
+            })
+            .collect())
     }
 
     pub async fn seed_sample_data(&self) -> Result<(), Box<dyn StdError>> {
         // Seed data in DB
-        let result = self.repo.seed_sample_data().await?;
+        self.repo.seed_sample_data().await?;
         
         // Then refresh cache with all users
         let _ = self.get_all().await?;
         
-        Ok(result)
+        Ok(())
     }
     
     // Method to manually invalidate cache for testing or administrative purposes
     pub fn invalidate_cache(&self) {
-        let mut cache = self.cache.write().unwrap();
-        cache.clear();
+        self.cache.write(HashMap::clear);
         log::info!("User cache invalidated");
     }
     
+    // Drop users changed behind the repository's back, they're reloaded on the
+    // next read. Returns how many were cached.
+    pub fn evict(&self, ids: &[Uuid]) -> usize {
+        if ids.is_empty() {
+            return 0;
+        }
+        let mut evicted = 0;
+        self.cache.write(|cache| {
+            evicted = ids.iter().filter(|id| cache.remove(id).is_some()).count();
+        });
+        evicted
+    }
+
+    // Re-read the users written since `since`, through this repository or around
+    // it, and bring their cache entries and suggestions up to date; users deleted
+    // since drop out. None when more than `limit` users changed, reloading the
+    // whole cache is the better deal then.
+    pub async fn refresh_changed_since(&self, since: DateTime<Utc>, limit: usize) -> Result<Option<CacheRefresh>, Box<dyn StdError>> {
+        let ids = self.repo.changed_since(since, limit as i64 + 1).await?;
+        if ids.len() > limit {
+            return Ok(None);
+        }
+        let users = self.repo.get_by_ids(&ids).await?;
+
+        let mut evicted = 0;
+        self.cache.write(|cache| {
+            for (id, user) in ids.iter().zip(&users) {
+                match user {
+                    // A write through the repository since the read is newer
+                    Some(user) if cache.get(id).is_some_and(|cached| cached.version > user.version) => {}
+                    Some(user) => {
+                        cache.insert(*id, user.clone());
+                    }
+                    None => evicted += usize::from(cache.remove(id).is_some()),
+                }
+            }
+        });
+        for (id, user) in ids.iter().zip(&users) {
+            match user {
+                Some(user) => self.suggestions.upsert(user),
+                None => self.suggestions.remove(id),
+            }
+        }
+
+        Ok(Some(CacheRefresh { reloaded: users.iter().flatten().count(), evicted }))
+    }
+    
     // Method to refresh single cache entry
+    #[allow(dead_code)]
     pub async fn refresh_cache_entry(&self, id: &Uuid) -> Result<(), Box<dyn StdError>> {
         let user_option = self.repo.get_by_id(id).await?;
         
-        let mut cache = self.cache.write().unwrap();
-        if let Some(user) = user_option {
-            cache.insert(user.id, user.clone());
-        } else {
-            cache.remove(id);
-        }
+        self.cache.write(|cache| match user_option {
+            Some(user) => {
+                cache.insert(user.id, user);
+            }
+            None => {
+                cache.remove(id);
+            }
+        });
         
         Ok(())
     }
@@ -0,0 +1,323 @@
+use async_trait::async_trait;
+use futures_util::stream::{LocalBoxStream, StreamExt};
+use std::error::Error as StdError;
+use std::fmt;
+use uuid::Uuid;
+
+use crate::models::sort::SortSpec;
+use crate::models::user::{CreateUserRequest, Suggestion, UpdateUserRequest, User, UserFilter};
+use crate::repositories::user_repo::{CachedUserRepository, UserRepository};
+use crate::suggest::SuggestIndex;
+
+pub type UserStream = LocalBoxStream<'static, Result<User, Box<dyn StdError>>>;
+
+// The user reads and writes the user routes need, so handlers take a
+// `web::Data<dyn UserStore>` and don't care which implementation serves them.
+// The store is shared by every worker, its futures stay on one like the rest
+// of a request.
+#[async_trait(?Send)]
+pub trait UserStore: Send + Sync {
+    async fn stream_all(&self, sort: &SortSpec) -> Result<UserStream, Box<dyn StdError>>;
+
+    async fn find(&self, filter: &UserFilter, sort: &SortSpec) -> Result<Vec<User>, Box<dyn StdError>>;
+
+    async fn get_paginated(&self, filter: &UserFilter, sort: &SortSpec, offset: i64, limit: i64) -> Result<Vec<User>, Box<dyn StdError>>;
+
+    // Users with an id after `after` in id order, matching the filter
+    async fn get_after(&self, filter: &UserFilter, after: Option<&Uuid>, limit: i64) -> Result<Vec<User>, Box<dyn StdError>>;
+
+    async fn count(&self, filter: &UserFilter) -> Result<i64, Box<dyn StdError>>;
+
+    async fn get_by_id(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>>;
+
+    // One entry per id, in the same order
+    async fn get_by_ids(&self, ids: &[Uuid]) -> Result<Vec<Option<User>>, Box<dyn StdError>>;
+
+    async fn get_by_username(&self, username: &str) -> Result<Option<User>, Box<dyn StdError>>;
+
+    // Current username of whoever used to have `old_username`
+    async fn renamed_to(&self, old_username: &str) -> Result<Option<String>, Box<dyn StdError>>;
+
+    async fn exists(&self, id: &Uuid) -> Result<bool, Box<dyn StdError>>;
+
+    async fn exists_by_email(&self, email: &str) -> Result<bool, Box<dyn StdError>>;
+
+    // Whether a user other than `except` already has the username, ignoring case
+    async fn username_taken(&self, username: &str, except: Option<&Uuid>) -> Result<bool, Box<dyn StdError>>;
+
+    async fn create(&self, user_req: &CreateUserRequest) -> Result<User, Box<dyn StdError>>;
+
+    // None if there is no such user, a VersionConflict error if the request
+    // names a version and the user is at another one
+    async fn update(&self, id: &Uuid, user_req: &UpdateUserRequest) -> Result<Option<User>, Box<dyn StdError>>;
+
+    // False if there is no such user
+    async fn delete(&self, id: &Uuid) -> Result<bool, Box<dyn StdError>>;
+
+    // The ids that had a user to delete, in no particular order
+    async fn delete_many(&self, ids: &[Uuid]) -> Result<Vec<Uuid>, Box<dyn StdError>>;
+
+    // Undoes a delete, None unless the user exists and is deleted
+    async fn restore(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>>;
+
+    // Inserts the users whose email and username are still free and returns
+    // those, the others are skipped. Stores without a bulk insert create them one by one.
+    async fn create_many(&self, user_reqs: &[CreateUserRequest]) -> Result<Vec<User>, Box<dyn StdError>> {
+        let mut created = Vec::with_capacity(user_reqs.len());
+        for user_req in user_reqs {
+            let username_taken = match &user_req.username {
+                Some(username) => self.username_taken(username, None).await?,
+                None => false,
+            };
+            if !username_taken && !self.exists_by_email(&user_req.email).await? {
+                created.push(self.create(user_req).await?);
+            }
+        }
+        Ok(created)
+    }
+
+    // Typeahead over names, ranked like SuggestIndex. Stores without an index
+    // of their own build one from every user for the request.
+    async fn suggest(&self, query: &str, limit: usize) -> Result<Vec<Suggestion>, Box<dyn StdError>> {
+        let users = self.find(&UserFilter::default(), &SortSpec::default()).await?;
+        let index = SuggestIndex::default();
+        index.rebuild(users.into_iter().map(|user| (user.id, user.name, user.updated_at)).collect());
+        Ok(index.suggest(query, limit))
+    }
+
+    // Users whose name or email has every word of the query, then those that
+    // contain it as a substring, by name. Stores without a text index scan every user.
+    async fn search(&self, query: &str, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        let query = query.to_lowercase();
+        let words: Vec<&str> = query.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()).collect();
+        let mut matches: Vec<(bool, User)> = self
+            .find(&UserFilter::default(), &SortSpec::default())
+            .await?
+            .into_iter()
+            .filter_map(|user| {
+                let (name, email) = (user.name.to_lowercase(), user.email.to_lowercase());
+                let text: Vec<&str> = name.split(|c: char| !c.is_alphanumeric()).chain(email.split(|c: char| !c.is_alphanumeric())).collect();
+                let all_words = !words.is_empty() && words.iter().all(|word| text.contains(word));
+                (all_words || name.contains(&query) || email.contains(&query)).then_some((all_words, user))
+            })
+            .collect();
+        matches.sort_by(|(a_words, a), (b_words, b)| b_words.cmp(a_words).then_with(|| a.name.cmp(&b.name)).then(a.id.cmp(&b.id)));
+        Ok(matches.into_iter().take(limit.max(0) as usize).map(|(_, user)| user).collect())
+    }
+}
+
+// An update made against a version of the user that is no longer its current
+// one, someone else wrote the user in between. Handlers answer it with a 409.
+#[derive(Debug)]
+pub struct VersionConflict {
+    pub expected: u32,
+    pub current: u32,
+}
+
+impl VersionConflict {
+    // Err when the update names a version other than the user's
+    pub fn check(user_req: &UpdateUserRequest, user: &User) -> Result<(), Box<dyn StdError>> {
+        match user_req.version {
+            Some(expected) if expected != user.version => Err(Box::new(Self { expected, current: user.version })),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for VersionConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The user was changed after version {}, it is at version {} now. Fetch it again and reapply the update.",
+            self.expected, self.current
+        )
+    }
+}
+
+impl StdError for VersionConflict {}
+
+// Same sample user as the Postgres repository seeds into an empty table, for
+// the stores that have no migrations of their own
+pub async fn seed_sample_data(store: &dyn UserStore) -> Result<(), Box<dyn StdError>> {
+    // A table of only deleted users isn't empty
+    let everyone = UserFilter { include_deleted: true, ..Default::default() };
+    if store.count(&everyone).await? > 0 {
+        return Ok(());
+    }
+    let sample = CreateUserRequest {
+        username: None,
+        name: "John Doe".to_string(),
+        email: "john@example.com".to_string(),
+        age: Some(30),
+        password: None,
+    };
+    store.create(&sample).await?;
+    Ok(())
+}
+
+#[async_trait(?Send)]
+impl UserStore for UserRepository {
+    async fn stream_all(&self, sort: &SortSpec) -> Result<UserStream, Box<dyn StdError>> {
+        let users = UserRepository::stream_all(self, sort).await?;
+        Ok(users.map(|user| user.map_err(Into::into)).boxed_local())
+    }
+
+    async fn find(&self, filter: &UserFilter, sort: &SortSpec) -> Result<Vec<User>, Box<dyn StdError>> {
+        UserRepository::find(self, filter, sort).await
+    }
+
+    async fn get_paginated(&self, filter: &UserFilter, sort: &SortSpec, offset: i64, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        UserRepository::get_paginated(self, filter, sort, offset, limit).await
+    }
+
+    async fn get_after(&self, filter: &UserFilter, after: Option<&Uuid>, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        UserRepository::get_after(self, filter, after, limit).await
+    }
+
+    async fn count(&self, filter: &UserFilter) -> Result<i64, Box<dyn StdError>> {
+        UserRepository::count(self, filter).await
+    }
+
+    async fn get_by_id(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
+        UserRepository::get_by_id(self, id).await
+    }
+
+    async fn get_by_ids(&self, ids: &[Uuid]) -> Result<Vec<Option<User>>, Box<dyn StdError>> {
+        UserRepository::get_by_ids(self, ids).await
+    }
+
+    async fn get_by_username(&self, username: &str) -> Result<Option<User>, Box<dyn StdError>> {
+        UserRepository::get_by_username(self, username).await
+    }
+
+    async fn renamed_to(&self, old_username: &str) -> Result<Option<String>, Box<dyn StdError>> {
+        UserRepository::renamed_to(self, old_username).await
+    }
+
+    async fn exists(&self, id: &Uuid) -> Result<bool, Box<dyn StdError>> {
+        UserRepository::exists(self, id).await
+    }
+
+    async fn exists_by_email(&self, email: &str) -> Result<bool, Box<dyn StdError>> {
+        UserRepository::exists_by_email(self, email).await
+    }
+
+    async fn username_taken(&self, username: &str, except: Option<&Uuid>) -> Result<bool, Box<dyn StdError>> {
+        Ok(UserRepository::username_owner(self, username)
+            .await?
+            .is_some_and(|id| Some(&id) != except))
+    }
+
+    async fn create(&self, user_req: &CreateUserRequest) -> Result<User, Box<dyn StdError>> {
+        UserRepository::create(self, user_req).await
+    }
+
+    async fn update(&self, id: &Uuid, user_req: &UpdateUserRequest) -> Result<Option<User>, Box<dyn StdError>> {
+        Ok(UserRepository::update(self, id, user_req).await?.map(|(_, user)| user))
+    }
+
+    async fn delete(&self, id: &Uuid) -> Result<bool, Box<dyn StdError>> {
+        Ok(UserRepository::delete(self, id).await?.is_some())
+    }
+
+    async fn delete_many(&self, ids: &[Uuid]) -> Result<Vec<Uuid>, Box<dyn StdError>> {
+        Ok(UserRepository::delete_many(self, ids).await?.into_iter().map(|user| user.id).collect())
+    }
+
+    async fn restore(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
+        UserRepository::restore(self, id).await
+    }
+
+    async fn create_many(&self, user_reqs: &[CreateUserRequest]) -> Result<Vec<User>, Box<dyn StdError>> {
+        UserRepository::create_many(self, user_reqs).await
+    }
+
+    async fn search(&self, query: &str, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        UserRepository::search(self, query, limit).await
+    }
+}
+
+#[async_trait(?Send)]
+impl UserStore for CachedUserRepository {
+    async fn stream_all(&self, sort: &SortSpec) -> Result<UserStream, Box<dyn StdError>> {
+        let users = CachedUserRepository::stream_all(self, sort).await?;
+        Ok(users.map(|user| user.map_err(Into::into)).boxed_local())
+    }
+
+    async fn find(&self, filter: &UserFilter, sort: &SortSpec) -> Result<Vec<User>, Box<dyn StdError>> {
+        CachedUserRepository::find(self, filter, sort).await
+    }
+
+    async fn get_paginated(&self, filter: &UserFilter, sort: &SortSpec, offset: i64, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        CachedUserRepository::get_paginated(self, filter, sort, offset, limit).await
+    }
+
+    async fn get_after(&self, filter: &UserFilter, after: Option<&Uuid>, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        CachedUserRepository::get_after(self, filter, after, limit).await
+    }
+
+    async fn count(&self, filter: &UserFilter) -> Result<i64, Box<dyn StdError>> {
+        CachedUserRepository::count(self, filter).await
+    }
+
+    async fn get_by_id(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
+        CachedUserRepository::get_by_id(self, id).await
+    }
+
+    async fn get_by_ids(&self, ids: &[Uuid]) -> Result<Vec<Option<User>>, Box<dyn StdError>> {
+        CachedUserRepository::get_by_ids(self, ids).await
+    }
+
+    async fn get_by_username(&self, username: &str) -> Result<Option<User>, Box<dyn StdError>> {
+        CachedUserRepository::get_by_username(self, username).await
+    }
+
+    async fn renamed_to(&self, old_username: &str) -> Result<Option<String>, Box<dyn StdError>> {
+        CachedUserRepository::renamed_to(self, old_username).await
+    }
+
+    async fn exists(&self, id: &Uuid) -> Result<bool, Box<dyn StdError>> {
+        CachedUserRepository::exists(self, id).await
+    }
+
+    async fn exists_by_email(&self, email: &str) -> Result<bool, Box<dyn StdError>> {
+        CachedUserRepository::exists_by_email(self, email).await
+    }
+
+    async fn username_taken(&self, username: &str, except: Option<&Uuid>) -> Result<bool, Box<dyn StdError>> {
+        CachedUserRepository::username_taken(self, username, except).await
+    }
+
+    async fn create(&self, user_req: &CreateUserRequest) -> Result<User, Box<dyn StdError>> {
+        CachedUserRepository::create(self, user_req).await
+    }
+
+    async fn update(&self, id: &Uuid, user_req: &UpdateUserRequest) -> Result<Option<User>, Box<dyn StdError>> {
+        CachedUserRepository::update(self, id, user_req).await
+    }
+
+    async fn delete(&self, id: &Uuid) -> Result<bool, Box<dyn StdError>> {
+        CachedUserRepository::delete(self, id).await
+    }
+
+    async fn delete_many(&self, ids: &[Uuid]) -> Result<Vec<Uuid>, Box<dyn StdError>> {
+        CachedUserRepository::delete_many(self, ids).await
+    }
+
+    async fn restore(&self, id: &Uuid) -> Result<Option<User>, Box<dyn StdError>> {
+        CachedUserRepository::restore(self, id).await
+    }
+
+    async fn create_many(&self, user_reqs: &[CreateUserRequest]) -> Result<Vec<User>, Box<dyn StdError>> {
+        CachedUserRepository::create_many(self, user_reqs).await
+    }
+
+    // From the index kept up to date on every write, never waits on the database
+    async fn suggest(&self, query: &str, limit: usize) -> Result<Vec<Suggestion>, Box<dyn StdError>> {
+        Ok(CachedUserRepository::suggest(self, query, limit))
+    }
+
+    async fn search(&self, query: &str, limit: i64) -> Result<Vec<User>, Box<dyn StdError>> {
+        CachedUserRepository::search(self, query, limit).await
+    }
+}
@@ -0,0 +1,104 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use std::future::Future;
+use std::rc::Rc;
+use uuid::Uuid;
+
+use crate::errors::with_headers;
+
+pub const HEADER: &str = "x-request-id";
+
+// Longest request id accepted from clients
+const MAX_LEN: usize = 64;
+
+tokio::task_local! {
+    static REQUEST_ID: Option<String>;
+}
+
+// Request id of the request currently being handled, if there is one
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok().flatten()
+}
+
+// Run `f` with `id` as the current request id
+pub async fn scope<F: Future>(id: Option<String>, f: F) -> F::Output {
+    REQUEST_ID.scope(id, f).await
+}
+
+// Request id propagated by the client or an upstream proxy. Ids that could
+// smuggle odd characters into logs or the database session are ignored.
+pub fn from_request(req: &ServiceRequest) -> Option<String> {
+    let id = req.headers().get(HEADER)?.to_str().ok()?;
+
+    let valid = !id.is_empty()
+        && id.len() <= MAX_LEN
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+
+    valid.then(|| id.to_string())
+}
+
+// For requests that come without a usable id
+pub fn generate() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+// Gives every request an id, the propagated one or a new one, runs it with that
+// id as the current one and echoes it in the X-Request-Id response header.
+// Errors from inner middleware carry it as well.
+#[derive(Clone, Default)]
+pub struct RequestId;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestId
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Transform = RequestIdMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestIdMiddleware { service: Rc::new(service) }))
+    }
+}
+
+pub struct RequestIdMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let id = from_request(&req).unwrap_or_else(generate);
+
+        Box::pin(async move {
+            let result = scope(Some(id.clone()), service.call(req)).await;
+            let value = HeaderValue::from_str(&id).expect("validated or generated ids are valid header values");
+            match result {
+                Ok(mut res) => {
+                    res.headers_mut().insert(HeaderName::from_static(HEADER), value);
+                    Ok(res.map_into_boxed_body())
+                }
+                Err(e) => {
+                    let mut headers = HeaderMap::new();
+                    headers.insert(HeaderName::from_static(HEADER), value);
+                    Err(with_headers(e, headers))
+                }
+            }
+        })
+    }
+}
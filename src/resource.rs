@@ -0,0 +1,173 @@
+use actix_web::http::header::{self, EntityTag, Header, HttpDate};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::time::SystemTime;
+
+use crate::models::api_key::{ApiKey, IssuedApiKey};
+use crate::models::approval::Approval;
+use crate::models::consent::Consent;
+use crate::models::identity::Identity;
+use crate::models::public_id;
+use crate::models::schedule::ScheduledOperation;
+use crate::models::user::User;
+
+pub const VERSION_HEADER: &str = "X-Resource-Version";
+
+// Metadata every single-resource response carries as headers: Last-Modified,
+// X-Resource-Version and, when the resource was just created, Location. JSON
+// bodies also carry the ETag of resources that have one. Handlers build those
+// responses with `json` or `response` so new resources only need an impl here.
+pub trait Resource {
+    // Path the resource can be addressed at, None if it has none of its own
+    fn location(&self) -> Option<String>;
+
+    fn last_modified(&self) -> DateTime<Utc>;
+
+    // Changes whenever the resource does
+    fn version(&self) -> String {
+        self.last_modified().timestamp_micros().to_string()
+    }
+
+    // Identifies the JSON body, for conditional requests. None for resources
+    // without a version of their own.
+    fn etag(&self) -> Option<EntityTag> {
+        None
+    }
+}
+
+// Response builder with the metadata headers of `resource` set, for bodies other than JSON
+pub fn response(status: StatusCode, resource: &impl Resource) -> HttpResponseBuilder {
+    let mut builder = HttpResponse::build(status);
+    builder
+        .insert_header(header::LastModified(HttpDate::from(SystemTime::from(resource.last_modified()))))
+        .insert_header((VERSION_HEADER, resource.version()));
+    if status == StatusCode::CREATED {
+        if let Some(location) = resource.location() {
+            builder.insert_header((header::LOCATION, location));
+        }
+    }
+    builder
+}
+
+// `resource` as the JSON body, with its metadata headers. Only JSON gets the
+// ETag, other bodies of the same resource would need one of their own.
+pub fn json<R: Resource + Serialize>(status: StatusCode, resource: &R) -> HttpResponse {
+    let mut builder = response(status, resource);
+    if let Some(etag) = resource.etag() {
+        builder.insert_header(header::ETag(etag));
+    }
+    builder.json(resource)
+}
+
+// `json` for a GET, or a 304 without a body when the request's If-None-Match
+// already names the resource's ETag, so polling clients don't fetch it again
+pub fn conditional_json<R: Resource + Serialize>(req: &HttpRequest, resource: &R) -> HttpResponse {
+    let matched = match (header::IfNoneMatch::parse(req), resource.etag()) {
+        (Ok(header::IfNoneMatch::Any), Some(_)) => true,
+        (Ok(header::IfNoneMatch::Items(tags)), Some(etag)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+        _ => false,
+    };
+    if !matched {
+        return json(StatusCode::OK, resource);
+    }
+    let mut builder = response(StatusCode::NOT_MODIFIED, resource);
+    if let Some(etag) = resource.etag() {
+        builder.insert_header(header::ETag(etag));
+    }
+    builder.finish()
+}
+
+// Percent-encode anything but unreserved characters, for free-form values in a path
+pub fn path_segment(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+impl Resource for User {
+    fn location(&self) -> Option<String> {
+        Some(format!("/users/{}", public_id::encode(&self.id)))
+    }
+
+    fn last_modified(&self) -> DateTime<Utc> {
+        self.updated_at
+    }
+
+    // The version updates send back as If-Match
+    fn etag(&self) -> Option<EntityTag> {
+        Some(EntityTag::new_strong(self.version.to_string()))
+    }
+}
+
+impl Resource for Identity {
+    fn location(&self) -> Option<String> {
+        Some(format!(
+            "/users/{}/identities/{}/{}",
+            public_id::encode(&self.user_id),
+            path_segment(&self.provider),
+            path_segment(&self.subject)
+        ))
+    }
+
+    fn last_modified(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+}
+
+// Consents don't know their user, the handler sets Location
+impl Resource for Consent {
+    fn location(&self) -> Option<String> {
+        None
+    }
+
+    fn last_modified(&self) -> DateTime<Utc> {
+        self.revoked_at.unwrap_or(self.granted_at)
+    }
+}
+
+impl Resource for Approval {
+    fn location(&self) -> Option<String> {
+        Some(format!("/admin/approvals/{}", self.id))
+    }
+
+    fn last_modified(&self) -> DateTime<Utc> {
+        self.decided_at.unwrap_or(self.requested_at)
+    }
+}
+
+impl Resource for ScheduledOperation {
+    fn location(&self) -> Option<String> {
+        Some(format!("/users/{}/schedule/{}", public_id::encode(&self.user_id), self.id))
+    }
+
+    fn last_modified(&self) -> DateTime<Utc> {
+        self.finished_at.unwrap_or(self.created_at)
+    }
+}
+
+// Using a key doesn't change it, so last_used_at doesn't count
+impl Resource for ApiKey {
+    fn location(&self) -> Option<String> {
+        Some(format!("/admin/api-keys/{}", self.id))
+    }
+
+    fn last_modified(&self) -> DateTime<Utc> {
+        self.revoked_at.unwrap_or(self.created_at)
+    }
+}
+
+impl Resource for IssuedApiKey {
+    fn location(&self) -> Option<String> {
+        self.api_key.location()
+    }
+
+    fn last_modified(&self) -> DateTime<Utc> {
+        self.api_key.last_modified()
+    }
+}
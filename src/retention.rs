@@ -0,0 +1,188 @@
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clock::Clock;
+use crate::repositories::retention_repo::{self, RetentionRepository};
+use crate::repositories::user_repo::CachedUserRepository;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RetentionAction {
+    Delete,
+    Anonymize,
+    // Move the rows into <table>_archive
+    Archive,
+}
+
+// One rule from the policy file, e.g.
+// { "name": "inactive-users", "table": "users", "max_age_days": 730,
+//   "condition": "age IS NULL", "action": "anonymize" }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionPolicy {
+    pub name: String,
+    pub table: String,
+    pub max_age_days: i32,
+    // Extra SQL condition on the table's rows
+    #[serde(default)]
+    pub condition: Option<String>,
+    pub action: RetentionAction,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct PolicyStats {
+    pub runs: u64,
+    pub failures: u64,
+    pub rows_affected: u64,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub last_rows_affected: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PolicyReport {
+    #[serde(flatten)]
+    pub policy: RetentionPolicy,
+    // Rows the policy would act on if it ran now
+    pub matching_rows: Option<i64>,
+    pub error: Option<String>,
+    pub stats: PolicyStats,
+}
+
+// Every retention rule in one place, run on a schedule instead of scattered
+// cron jobs. Policies are read from a JSON file at startup.
+pub struct Retention {
+    policies: Vec<RetentionPolicy>,
+    repo: RetentionRepository,
+    users: web::Data<CachedUserRepository>,
+    stats: Mutex<HashMap<String, PolicyStats>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Retention {
+    pub fn new(
+        policies: Vec<RetentionPolicy>,
+        repo: RetentionRepository,
+        users: web::Data<CachedUserRepository>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self {
+            policies,
+            repo,
+            users,
+            stats: Mutex::new(HashMap::new()),
+            clock,
+        }
+    }
+
+    // Read and check the policy file, so mistakes show up at startup rather than at 3am
+    pub fn load(path: &str) -> Result<Vec<RetentionPolicy>, Box<dyn StdError>> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read retention policies {}: {}", path, e))?;
+        let policies: Vec<RetentionPolicy> = serde_json::from_str(&contents)?;
+
+        for (i, policy) in policies.iter().enumerate() {
+            let table = retention_repo::table(&policy.table).ok_or_else(|| {
+                let known: Vec<_> = retention_repo::TABLES.iter().map(|t| t.name).collect();
+                format!("Policy {}: table must be one of {}", policy.name, known.join(", "))
+            })?;
+            if policy.action == RetentionAction::Anonymize && table.anonymize.is_none() {
+                return Err(format!("Policy {}: rows in {} can't be anonymized", policy.name, policy.table).into());
+            }
+            if policy.max_age_days < 0 {
+                return Err(format!("Policy {}: max_age_days can't be negative", policy.name).into());
+            }
+            if policies[..i].iter().any(|other| other.name == policy.name) {
+                return Err(format!("Policy name {} is used twice", policy.name).into());
+            }
+        }
+
+        Ok(policies)
+    }
+
+    // Apply every policy once. A failing policy doesn't stop the others.
+    pub async fn run(&self) {
+        let mut users_changed = false;
+
+        for policy in &self.policies {
+            let started = self.clock.now();
+            let result = self.repo.apply(policy, started).await;
+
+            {
+                let mut stats = self.stats.lock();
+                let stats = stats.entry(policy.name.clone()).or_default();
+                stats.runs += 1;
+                stats.last_run_at = Some(started);
+                match &result {
+                    Ok(rows) => {
+                        stats.rows_affected += rows;
+                        stats.last_rows_affected = Some(*rows);
+                        stats.last_error = None;
+                    }
+                    Err(e) => {
+                        stats.failures += 1;
+                        stats.last_rows_affected = None;
+                        stats.last_error = Some(e.to_string());
+                    }
+                }
+            }
+
+            match result {
+                Ok(0) => log::debug!("Retention policy {} matched no rows", policy.name),
+                Ok(rows) => {
+                    users_changed |= policy.table == "users";
+                    log::info!(
+                        target: "audit",
+                        "Retention policy {} applied {:?} to {} rows of {}",
+                        policy.name, policy.action, rows, policy.table
+                    );
+                }
+                Err(e) => log::error!("Retention policy {} failed: {}", policy.name, e),
+            }
+        }
+
+        // Cached users may have just been deleted or anonymized
+        if users_changed {
+            self.users.invalidate_cache();
+            if let Err(e) = self.users.rebuild_suggestions().await {
+                log::error!("Failed to rebuild suggestions after retention: {}", e);
+            }
+        }
+    }
+
+    // What each policy would do right now, without changing anything
+    pub async fn report(&self) -> Vec<PolicyReport> {
+        let now = self.clock.now();
+        let mut reports = Vec::with_capacity(self.policies.len());
+        for policy in &self.policies {
+            let (matching_rows, error) = match self.repo.count(policy, now).await {
+                Ok(rows) => (Some(rows), None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            reports.push(PolicyReport {
+                policy: policy.clone(),
+                matching_rows,
+                error,
+                stats: self.stats.lock().get(&policy.name).cloned().unwrap_or_default(),
+            });
+        }
+        reports
+    }
+
+    // Run the policies now and then every `interval`
+    pub fn spawn_schedule(retention: web::Data<Retention>, interval: Duration) {
+        actix_web::rt::spawn(async move {
+            let mut ticker = actix_web::rt::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                retention.run().await;
+            }
+        });
+    }
+}
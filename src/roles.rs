@@ -0,0 +1,185 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, header::HeaderMap, Method};
+use actix_web::{web, HttpMessage, HttpRequest};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use std::rc::Rc;
+use uuid::Uuid;
+
+use crate::api_keys;
+use crate::approvals;
+use crate::errors::{AppError, Context};
+use crate::models::user::{Role, User};
+use crate::repositories::user_repo::CachedUserRepository;
+use crate::routes::admin::admin_required;
+use crate::signing::Signer;
+
+// The user of the `Authorization: Bearer` session token or password login JWT,
+// None without a valid one
+pub fn session_user(headers: &HeaderMap, signer: &Signer) -> Option<Uuid> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .and_then(|token| signer.verify_session(token).or_else(|| signer.verify_jwt(token)))
+}
+
+// The admin behind the request's session, for calls that must not rest on
+// X-Admin-User alone, which any client can send. Taken from the request
+// extensions when a guard already loaded it, else from the bearer token;
+// 401 without a session, 403 for other roles.
+pub async fn session_admin(req: &HttpRequest, signer: &Signer, users: &CachedUserRepository) -> Result<User, AppError> {
+    let cookie_user = req.extensions().get::<User>().cloned();
+    let user = match cookie_user {
+        Some(user) => user,
+        None => {
+            let user_id = session_user(req.headers(), signer).ok_or_else(|| AppError::unauthorized("A valid session token is required"))?;
+            users
+                .get_by_id(&user_id)
+                .await
+                .context("Failed to check session")?
+                .ok_or_else(|| AppError::unauthorized("A valid session token is required"))?
+        }
+    };
+
+    if user.role != Role::Admin {
+        log::warn!(target: "audit", "Refused {} {} for user {} with role {}", req.method(), req.path(), user.id, user.role.as_str());
+        return Err(AppError::forbidden("This request needs the admin role"));
+    }
+    Ok(user)
+}
+
+// Who is acting in an admin call. While roles are enforced or logins can give
+// out sessions (OIDC or a session store) that is the admin of the request's
+// session, by user id, as it is whenever a session comes along; otherwise the
+// X-Admin-User header names them. Approvals compare these to keep the requester
+// from approving their own change, so they must not be whatever a client sends.
+pub struct Admins {
+    sessions: bool,
+    signer: web::Data<Signer>,
+    users: web::Data<CachedUserRepository>,
+}
+
+impl Admins {
+    pub fn new(sessions: bool, signer: web::Data<Signer>, users: web::Data<CachedUserRepository>) -> Self {
+        Self { sessions, signer, users }
+    }
+
+    pub async fn acting(&self, req: &HttpRequest) -> Result<String, AppError> {
+        let has_session = req.extensions().get::<User>().is_some() || session_user(req.headers(), &self.signer).is_some();
+        if self.sessions || has_session {
+            return Ok(session_admin(req, &self.signer, &self.users).await?.id.to_string());
+        }
+        approvals::admin(req).ok_or_else(admin_required)
+    }
+}
+
+// Routes that only read, whatever their method
+fn read_only(method: &Method, path: &str) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) || path == "/users/batch-get" || path == "/users/exports"
+}
+
+// Admin routes need the admin role for reads too
+fn admin_only(path: &str) -> bool {
+    path.starts_with("/admin/")
+}
+
+// Besides the routes that work without an API key, apart from the admin routes:
+// signed verification links are opened by whoever scanned the QR code, actors are
+// fetched by other servers, signed export links are handed to whoever should
+// download the file
+fn open(path: &str) -> bool {
+    (api_keys::exempt(path) && !admin_only(path))
+        || path.strip_prefix("/users/").is_some_and(|rest| rest.ends_with("/verify") || rest.ends_with("/actor") || rest.ends_with("/download"))
+}
+
+// While roles are enforced every other request needs a session token or a
+// session cookie: reads are allowed for any role, everything that writes and
+// every admin route needs an admin. The session's user is put in the request extensions.
+#[derive(Clone)]
+pub struct RoleGuard {
+    enforced: bool,
+    signer: web::Data<Signer>,
+    users: web::Data<CachedUserRepository>,
+}
+
+impl RoleGuard {
+    pub fn new(enforced: bool, signer: web::Data<Signer>, users: web::Data<CachedUserRepository>) -> Self {
+        Self { enforced, signer, users }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RoleGuard
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Transform = RoleGuardMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RoleGuardMiddleware {
+            service: Rc::new(service),
+            guard: self.clone(),
+        }))
+    }
+}
+
+pub struct RoleGuardMiddleware<S> {
+    service: Rc<S>,
+    guard: RoleGuard,
+}
+
+impl<S, B> Service<ServiceRequest> for RoleGuardMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        if !self.guard.enforced || open(req.path()) {
+            return Box::pin(async move { Ok(service.call(req).await?.map_into_boxed_body()) });
+        }
+
+        let users = self.guard.users.clone();
+        // Already there when SessionLoader found a session cookie
+        let cookie_user = req.extensions().get::<User>().cloned();
+        let user_id = session_user(req.headers(), &self.guard.signer);
+
+        Box::pin(async move {
+            let user = match cookie_user {
+                Some(user) => user,
+                None => {
+                    let user_id = user_id.ok_or_else(|| AppError::unauthorized("A valid session token is required"))?;
+                    users
+                        .get_by_id(&user_id)
+                        .await
+                        .context("Failed to check session")?
+                        .ok_or_else(|| AppError::unauthorized("A valid session token is required"))?
+                }
+            };
+
+            if user.role != Role::Admin && (admin_only(req.path()) || !read_only(req.method(), req.path())) {
+                log::warn!(
+                    target: "audit",
+                    "Refused {} {} for user {} with role {}",
+                    req.method(), req.path(), user.id, user.role.as_str()
+                );
+                return Err(AppError::forbidden("This request needs the admin role").into());
+            }
+
+            req.extensions_mut().insert::<User>(user);
+            Ok(service.call(req).await?.map_into_boxed_body())
+        })
+    }
+}
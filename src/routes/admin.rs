@@ -0,0 +1,282 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder, get, post, put, delete};
+use actix_web::http::{header, StatusCode};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::api_keys;
+use crate::approvals::{self, Approvals, Decided};
+use crate::backfill::Backfills;
+use crate::errors::{AppError, Context};
+use crate::extractors::{problem, ValidatedJson};
+use crate::ledger::Ledger;
+use crate::models::api_key::{IssueApiKeyRequest, IssuedApiKey};
+use crate::models::approval::{ApprovalListQuery, ApprovalStatus};
+use crate::models::public_id::PublicId;
+use crate::models::tenant::TenantSettings;
+use crate::models::user::SetRoleRequest;
+use crate::rebuild::{RebuildTarget, Rebuilds};
+use crate::repositories::api_key_repo::ApiKeyRepository;
+use crate::resource;
+use crate::repositories::tenant_repo::TenantSettingsRepository;
+use crate::repositories::user_repo::{CacheRefresh, CachedUserRepository};
+use crate::retention::Retention;
+use crate::roles::{self, Admins};
+use crate::signing::Signer;
+
+// GET /admin/tenant-settings - White-label settings of this instance
+#[get("/admin/tenant-settings")]
+pub async fn get_tenant_settings(settings: web::Data<TenantSettingsRepository>) -> impl Responder {
+    HttpResponse::Ok().json(settings.get())
+}
+
+// PUT /admin/tenant-settings - Replace the white-label settings
+#[put("/admin/tenant-settings")]
+pub async fn put_tenant_settings(
+    new_settings: ValidatedJson<TenantSettings>,
+    settings: web::Data<TenantSettingsRepository>
+) -> Result<HttpResponse, AppError> {
+    let saved = settings.save(new_settings.0).await.context("Failed to save tenant settings")?;
+    log::info!(target: "audit", "Tenant settings updated: {:?}", saved);
+    Ok(HttpResponse::Ok().json(saved))
+}
+
+// GET /admin/retention/report - Dry run: rows each retention policy would act on, with its run history
+#[get("/admin/retention/report")]
+pub async fn retention_report(retention: web::Data<Retention>) -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({
+        "policies": retention.report().await
+    }))
+}
+
+// GET /admin/backfills - Progress of the data backfills
+#[get("/admin/backfills")]
+pub async fn list_backfills(backfills: web::Data<Backfills>) -> Result<HttpResponse, AppError> {
+    let progress = backfills.progress().await.context("Failed to retrieve backfill progress")?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "backfills": progress })))
+}
+
+#[derive(Deserialize)]
+pub struct RebuildRequest {
+    pub targets: Vec<RebuildTarget>,
+}
+
+// POST /admin/rebuild - Rebuild caches and indexes from the database in the background
+#[post("/admin/rebuild")]
+pub async fn start_rebuild(
+    req: HttpRequest,
+    body: web::Json<RebuildRequest>,
+    rebuilds: web::Data<Rebuilds>,
+    admins: web::Data<Admins>
+) -> Result<HttpResponse, AppError> {
+    let admin = admins.acting(&req).await?;
+    if body.targets.is_empty() {
+        return Err(AppError::bad_request("Name at least one target: cache, suggestions or search_index"));
+    }
+
+    let job = Rebuilds::start(&rebuilds, &body.targets, &admin)
+        .map_err(|busy| AppError::conflict(format!("Rebuild {} of one of these targets is still running", busy)))?;
+    log::info!(target: "audit", "Admin {} started rebuild {} of {:?}", admin, job.id, body.targets);
+    Ok(HttpResponse::Accepted()
+        .insert_header((header::LOCATION, format!("/admin/rebuild/{}", job.id)))
+        .json(job))
+}
+
+// GET /admin/rebuild/{id} - Progress of a rebuild started on this instance
+#[get("/admin/rebuild/{id}")]
+pub async fn get_rebuild(path: web::Path<Uuid>, rebuilds: web::Data<Rebuilds>) -> Result<HttpResponse, AppError> {
+    let job = rebuilds.get(&path.into_inner()).ok_or_else(|| AppError::not_found("Rebuild not found"))?;
+    Ok(HttpResponse::Ok().json(job))
+}
+
+// Ids per batch, and users changed since `since` before a full reload is the way to go
+const MAX_INVALIDATE_USERS: usize = 1000;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InvalidateBatchRequest {
+    #[serde(default, with = "crate::models::public_id::vec")]
+    pub ids: Vec<Uuid>,
+    pub since: Option<DateTime<Utc>>,
+}
+
+// POST /admin/cache/invalidate-batch - For services writing to the users table
+// directly: drop the given users from this instance's cache, and re-read the
+// users written since `since`
+#[post("/admin/cache/invalidate-batch")]
+pub async fn invalidate_cache_batch(
+    req: HttpRequest,
+    body: web::Json<InvalidateBatchRequest>,
+    users: web::Data<CachedUserRepository>,
+    admins: web::Data<Admins>
+) -> Result<HttpResponse, AppError> {
+    let admin = admins.acting(&req).await?;
+    let InvalidateBatchRequest { ids, since } = body.into_inner();
+    if ids.is_empty() && since.is_none() {
+        return Err(AppError::bad_request("Send the ids of the changed users, a since timestamp, or both"));
+    }
+    if ids.len() > MAX_INVALIDATE_USERS {
+        return Err(AppError::bad_request(format!("At most {} ids can be invalidated at once", MAX_INVALIDATE_USERS)));
+    }
+
+    let mut refresh = CacheRefresh::default();
+    if let Some(since) = since {
+        refresh = users
+            .refresh_changed_since(since, MAX_INVALIDATE_USERS)
+            .await
+            .context("Failed to read the changed users")?
+            .ok_or_else(|| {
+                AppError::bad_request(format!(
+                    "More than {} users changed since {}, reload the whole cache with POST /admin/rebuild",
+                    MAX_INVALIDATE_USERS, since
+                ))
+            })?;
+    }
+    refresh.evicted += users.evict(&ids);
+
+    log::info!(
+        target: "audit",
+        "Admin {} invalidated cached users: {} ids, since {:?}, {} reloaded, {} evicted",
+        admin, ids.len(), since, refresh.reloaded, refresh.evicted
+    );
+    Ok(HttpResponse::Ok().json(refresh))
+}
+
+// GET /admin/approvals?status=pending - Held deletes and email changes, oldest first
+#[get("/admin/approvals")]
+pub async fn list_approvals(query: web::Query<ApprovalListQuery>, approvals: web::Data<Approvals>) -> Result<HttpResponse, AppError> {
+    let items = approvals.list(query.status).await.context("Failed to retrieve approvals")?;
+    Ok(HttpResponse::Ok().json(items))
+}
+
+// POST /admin/approvals/{id}/approve - Carry out a held change, by an admin other than the requester
+#[post("/admin/approvals/{id}/approve")]
+pub async fn approve(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    approvals: web::Data<Approvals>,
+    admins: web::Data<Admins>
+) -> Result<HttpResponse, AppError> {
+    let id = path.into_inner();
+    let admin = admins.acting(&req).await?;
+
+    let decided = approvals.approve(&id, &admin).await.context("Failed to approve request")?;
+    decided_response(decided)
+}
+
+// POST /admin/approvals/{id}/reject - Drop a held change, the requester may withdraw their own
+#[post("/admin/approvals/{id}/reject")]
+pub async fn reject(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    approvals: web::Data<Approvals>,
+    admins: web::Data<Admins>
+) -> Result<HttpResponse, AppError> {
+    let id = path.into_inner();
+    let admin = admins.acting(&req).await?;
+
+    let decided = approvals.reject(&id, &admin).await.context("Failed to reject request")?;
+    decided_response(decided)
+}
+
+// GET /admin/api-keys - Issued API keys, newest first, without their secrets
+#[get("/admin/api-keys")]
+pub async fn list_api_keys(keys: web::Data<ApiKeyRepository>) -> Result<HttpResponse, AppError> {
+    let items = keys.list().await.context("Failed to retrieve API keys")?;
+    Ok(HttpResponse::Ok().json(items))
+}
+
+// POST /admin/api-keys - Issue a key for a machine client, the secret is only shown in this response
+#[post("/admin/api-keys")]
+pub async fn issue_api_key(
+    req: HttpRequest,
+    issue_req: ValidatedJson<IssueApiKeyRequest>,
+    keys: web::Data<ApiKeyRepository>,
+    admins: web::Data<Admins>
+) -> Result<HttpResponse, AppError> {
+    let admin = admins.acting(&req).await?;
+
+    let (key, prefix, key_hash) = api_keys::generate();
+    let api_key = keys
+        .create(&issue_req.name, &prefix, &key_hash, &admin)
+        .await
+        .context("Failed to issue API key")?;
+    log::info!(target: "audit", "Admin {} issued API key {} ({}) for {}", admin, api_key.prefix, api_key.id, api_key.name);
+    Ok(resource::json(StatusCode::CREATED, &IssuedApiKey { api_key, key }))
+}
+
+// DELETE /admin/api-keys/{id} - Revoke a key, requests using it are refused from then on
+#[delete("/admin/api-keys/{id}")]
+pub async fn revoke_api_key(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    keys: web::Data<ApiKeyRepository>,
+    admins: web::Data<Admins>
+) -> Result<HttpResponse, AppError> {
+    let id = path.into_inner();
+    let admin = admins.acting(&req).await?;
+
+    if let Some(api_key) = keys.revoke(&id).await.context("Failed to revoke API key")? {
+        log::info!(target: "audit", "Admin {} revoked API key {} ({}) for {}", admin, api_key.prefix, api_key.id, api_key.name);
+        return Ok(resource::json(StatusCode::OK, &api_key));
+    }
+
+    match keys.get(&id).await.context("Failed to revoke API key")? {
+        Some(_) => Err(AppError::conflict("This API key is already revoked")),
+        None => Err(AppError::not_found("API key not found")),
+    }
+}
+
+// PUT /admin/users/{id}/role - Make a user an admin, a plain user or read-only.
+// Needs an admin session even while roles aren't enforced, X-Admin-User alone
+// would let anyone make themselves an admin.
+#[put("/admin/users/{id}/role")]
+pub async fn set_user_role(
+    req: HttpRequest,
+    path: web::Path<PublicId>,
+    body: web::Json<SetRoleRequest>,
+    users: web::Data<CachedUserRepository>,
+    signer: web::Data<Signer>
+) -> Result<HttpResponse, AppError> {
+    let user_id = path.into_inner().0;
+    let admin = roles::session_admin(&req, &signer, &users).await?.id;
+    let role = body.into_inner().role;
+
+    match users.set_role(&user_id, role).await.context("Failed to update role")? {
+        Some(user) => {
+            log::info!(target: "audit", "Admin {} gave user {} the {} role", admin, user.id, role.as_str());
+            Ok(resource::json(StatusCode::OK, &user))
+        }
+        None => Err(AppError::not_found("User not found")),
+    }
+}
+
+fn decided_response(decided: Decided) -> Result<HttpResponse, AppError> {
+    match decided {
+        Decided::Done(approval) => Ok(resource::json(StatusCode::OK, &approval)),
+        Decided::NotFound => Err(AppError::not_found("Approval request not found")),
+        Decided::NotAllowed(approval) if approval.status == ApprovalStatus::Pending => {
+            Err(AppError::forbidden("A different admin must approve this request"))
+        }
+        // Carries the approval, so it isn't an AppError
+        Decided::NotAllowed(approval) => Ok(problem(
+            StatusCode::CONFLICT,
+            "Conflict",
+            &format!("This request was already {}", approval.status.as_str()),
+            serde_json::json!({ "approval": approval }),
+        )),
+        Decided::EmailTaken => Err(AppError::conflict("The new email belongs to another user by now")),
+    }
+}
+
+// For requests that need an admin name, such as changes while approvals are required
+pub fn admin_required() -> AppError {
+    AppError::unauthorized(format!("The {} header is required", approvals::ADMIN_HEADER))
+}
+
+// GET /admin/ledger/verify - Check the admin ledger's hash chain for tampering
+#[get("/admin/ledger/verify")]
+pub async fn verify_ledger(ledger: web::Data<Ledger>) -> Result<HttpResponse, AppError> {
+    let verification = ledger.verify().await.context("Failed to verify the admin ledger")?;
+    Ok(HttpResponse::Ok().json(verification))
+}
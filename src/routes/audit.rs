@@ -0,0 +1,49 @@
+use actix_web::{web, HttpRequest, HttpResponse, get};
+
+use crate::errors::{AppError, Context};
+use crate::extractors::ValidatedQuery;
+use crate::models::audit::{AuditPage, AuditQuery};
+use crate::models::public_id::PublicId;
+use crate::repositories::audit_repo::AuditRepository;
+use crate::roles::Admins;
+
+// Entries per page when no limit is given
+const DEFAULT_AUDIT_LIMIT: u32 = 50;
+
+// GET /audit?before=&limit= - Every write to any user, newest first
+#[get("/audit")]
+pub async fn audit_log(
+    req: HttpRequest,
+    query: ValidatedQuery<AuditQuery>,
+    repo: web::Data<AuditRepository>,
+    admins: web::Data<Admins>
+) -> Result<HttpResponse, AppError> {
+    admins.acting(&req).await?;
+    
+    let page = load_page(&repo, None, &query).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+// GET /users/{id}/audit?before=&limit= - Every write to one user, newest first
+// Purged users keep their history, so the user doesn't have to exist anymore.
+#[get("/users/{id}/audit")]
+pub async fn user_audit_log(
+    req: HttpRequest,
+    path: web::Path<PublicId>,
+    query: ValidatedQuery<AuditQuery>,
+    repo: web::Data<AuditRepository>,
+    admins: web::Data<Admins>
+) -> Result<HttpResponse, AppError> {
+    let user_id = path.into_inner().0;
+    admins.acting(&req).await?;
+    
+    let page = load_page(&repo, Some(&user_id), &query).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+async fn load_page(repo: &AuditRepository, user_id: Option<&uuid::Uuid>, query: &AuditQuery) -> Result<AuditPage, AppError> {
+    let limit = query.limit.unwrap_or(DEFAULT_AUDIT_LIMIT);
+    let items = repo.page(user_id, query.before, limit as i64).await.context("Failed to retrieve the audit log")?;
+    let next_before = (items.len() == limit as usize).then(|| items.last().map(|entry| entry.id)).flatten();
+    Ok(AuditPage { items, next_before })
+}
@@ -0,0 +1,348 @@
+use actix_web::cookie::{time, Cookie, SameSite};
+use actix_web::http::header;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder, get, post};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::Deserialize;
+
+use crate::clock::Clock;
+use crate::errors::{AppError, Context};
+use crate::models::user::{CreateUserRequest, User};
+use crate::models::validate::Validate;
+use crate::models::password::{LoginRequest, PasswordCheckRequest};
+use crate::oidc::{Claims, Oidc};
+use crate::password_hash;
+use crate::password_policy::PasswordChecker;
+use crate::repositories::identity_repo::{IdentityRepository, LinkOutcome};
+use crate::repositories::refresh_token_repo::{RefreshTokenRepository, Rotation};
+use crate::repositories::user_repo::CachedUserRepository;
+use crate::roles;
+use crate::sessions::{self, Sessions};
+use crate::signing::Signer;
+
+// Carries state and nonce from /auth/login to the callback, signed so it can't be forged
+const STATE_COOKIE: &str = "oidc_state";
+// How long a login may take at the provider
+const STATE_TTL_SECS: u64 = 10 * 60;
+
+pub struct SessionSettings {
+    pub ttl_secs: u64,
+    pub refresh_ttl_secs: u64,
+}
+
+impl SessionSettings {
+    fn refresh_ttl(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.refresh_ttl_secs as i64)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    refresh_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+fn random_token() -> String {
+    URL_SAFE_NO_PAD.encode(rand::random::<[u8; 16]>())
+}
+
+fn state_message(state: &str, nonce: &str, expires: u64) -> String {
+    format!("oidc:{}:{}:{}", state, nonce, expires)
+}
+
+// Nonce from a state cookie, if it's signed, unexpired and for `state`
+fn verify_state<'a>(signer: &Signer, now: u64, value: &'a str, state: &str) -> Option<&'a str> {
+    let mut parts = value.splitn(4, '.');
+    let (cookie_state, nonce) = (parts.next()?, parts.next()?);
+    let expires = parts.next()?.parse().ok()?;
+    let signature = parts.next()?;
+    (cookie_state == state && expires >= now && signer.verify(&state_message(cookie_state, nonce, expires), signature))
+        .then_some(nonce)
+}
+
+// GET /auth/login - Start logging in at the configured OpenID Connect provider
+#[get("/auth/login")]
+pub async fn login(oidc: web::Data<Oidc>, signer: web::Data<Signer>, clock: web::Data<dyn Clock>) -> Result<HttpResponse, AppError> {
+    let config = oidc.config().ok_or_else(|| AppError::not_found("Login is not configured"))?;
+    
+    let (state, nonce) = (random_token(), random_token());
+    let expires = clock.unix_now() + STATE_TTL_SECS;
+    let signature = signer.sign(&state_message(&state, &nonce, expires));
+    let url = oidc.authorize_url(&state, &nonce).await.context("Failed to reach the identity provider")?;
+    
+    let cookie = Cookie::build(STATE_COOKIE, format!("{}.{}.{}.{}", state, nonce, expires, signature))
+        .path("/auth")
+        .http_only(true)
+        .secure(config.redirect_url.starts_with("https://"))
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::seconds(STATE_TTL_SECS as i64))
+        .finish();
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, url))
+        .cookie(cookie)
+        .finish())
+}
+
+// GET /auth/callback?code=&state= - Finish a login: find or create the local user and return a session token
+#[get("/auth/callback")]
+#[allow(clippy::too_many_arguments)]
+pub async fn callback(
+    req: HttpRequest,
+    query: web::Query<CallbackQuery>,
+    oidc: web::Data<Oidc>,
+    signer: web::Data<Signer>,
+    sessions: web::Data<SessionSettings>,
+    session_store: web::Data<Sessions>,
+    users: web::Data<CachedUserRepository>,
+    identities: web::Data<IdentityRepository>,
+    clock: web::Data<dyn Clock>
+) -> Result<HttpResponse, AppError> {
+    let config = oidc.config().ok_or_else(|| AppError::not_found("Login is not configured"))?;
+    
+    if let Some(error) = &query.error {
+        log::warn!(target: "audit", "OIDC login failed at the provider: {} {:?}", error, query.error_description);
+        return Err(AppError::bad_request(format!("Login failed: {}", error)));
+    }
+    let (code, state) = match (&query.code, &query.state) {
+        (Some(code), Some(state)) => (code, state),
+        _ => return Err(AppError::bad_request("code and state are required")),
+    };
+    
+    // The state must match the one this browser got at /auth/login
+    let cookie = req.cookie(STATE_COOKIE).ok_or_else(|| AppError::bad_request("Login expired, start again"))?;
+    let nonce = verify_state(&signer, clock.unix_now(), cookie.value(), state).ok_or_else(|| AppError::bad_request("Login expired, start again"))?;
+    
+    let claims = match oidc.exchange(code, nonce).await {
+        Ok(claims) => claims,
+        Err(e) => {
+            log::warn!(target: "audit", "OIDC login failed: {}", e);
+            return Err(AppError::bad_request("Login failed, the identity provider's response was not accepted"));
+        }
+    };
+    
+    let user = local_user(&users, &identities, &config.provider_name, &claims).await?;
+    let (token, expires_at) = signer.session_token(&user.id, sessions.ttl_secs);
+    let session_cookie = session_store.start(&user.id, sessions.ttl_secs).await.context("Failed to start session")?;
+    log::info!(target: "audit", "User {} logged in with {} identity {}", user.id, config.provider_name, claims.sub);
+    
+    let mut removal = Cookie::build(STATE_COOKIE, "").path("/auth").finish();
+    removal.make_removal();
+    let mut response = HttpResponse::Ok();
+    response.cookie(removal);
+    if let Some(cookie) = session_cookie {
+        response.cookie(cookie);
+    }
+    Ok(response
+        .json(serde_json::json!({
+            "token": token,
+            "token_type": "Bearer",
+            "expires_at": expires_at,
+            "user": user
+        })))
+}
+
+// POST /auth/login - Log in with email and password, returns a JWT for the Authorization header
+#[post("/auth/login")]
+pub async fn password_login(
+    login_req: web::Json<LoginRequest>,
+    signer: web::Data<Signer>,
+    sessions: web::Data<SessionSettings>,
+    session_store: web::Data<Sessions>,
+    users: web::Data<CachedUserRepository>,
+    refresh_tokens: web::Data<RefreshTokenRepository>
+) -> Result<HttpResponse, AppError> {
+    let user = users.get_by_email(&login_req.email).await.context("Failed to log in")?;
+    
+    // Unknown emails take as long as wrong passwords, so they can't be told apart
+    let verified = match user.as_ref().and_then(|user| user.password_hash.as_deref()) {
+        Some(password_hash) => password_hash::verify(&login_req.password, password_hash).await.context("Failed to log in")?,
+        None => {
+            password_hash::verify_nothing(&login_req.password).await;
+            false
+        }
+    };
+    let user = match user {
+        Some(user) if verified => user,
+        _ => {
+            log::warn!(target: "audit", "Failed password login for {}", login_req.email);
+            return Err(AppError::unauthorized("Invalid email or password"));
+        }
+    };
+    
+    let (token, expires_at) = signer.jwt(&user, sessions.ttl_secs);
+    let refresh_token = refresh_tokens.issue(&user.id, sessions.refresh_ttl()).await.context("Failed to log in")?;
+    let session_cookie = session_store.start(&user.id, sessions.ttl_secs).await.context("Failed to start session")?;
+    log::info!(target: "audit", "User {} logged in with a password", user.id);
+    
+    let mut response = HttpResponse::Ok();
+    if let Some(cookie) = session_cookie {
+        response.cookie(cookie);
+    }
+    Ok(response.json(serde_json::json!({
+        "token": token,
+        "token_type": "Bearer",
+        "expires_at": expires_at,
+        "refresh_token": refresh_token.token,
+        "refresh_expires_at": refresh_token.expires_at.timestamp(),
+        "user": user
+    })))
+}
+
+// POST /auth/refresh - Trade a refresh token for a new JWT and a new refresh token
+#[post("/auth/refresh")]
+pub async fn refresh(
+    refresh_req: web::Json<RefreshRequest>,
+    signer: web::Data<Signer>,
+    sessions: web::Data<SessionSettings>,
+    users: web::Data<CachedUserRepository>,
+    refresh_tokens: web::Data<RefreshTokenRepository>
+) -> Result<HttpResponse, AppError> {
+    let refresh_token = match refresh_tokens.rotate(&refresh_req.refresh_token, sessions.refresh_ttl()).await.context("Failed to refresh session")? {
+        Rotation::Rotated(refresh_token) => refresh_token,
+        Rotation::Reused { user_id, family_id } => {
+            log::warn!(target: "audit", "Refresh token reused for user {}, revoked token family {}", user_id, family_id);
+            return Err(AppError::unauthorized("Invalid refresh token"));
+        }
+        Rotation::Invalid => return Err(AppError::unauthorized("Invalid refresh token")),
+    };
+    
+    // The JWT carries the role, so it is read fresh rather than copied from the old token
+    let user = users
+        .get_by_id(&refresh_token.user_id)
+        .await
+        .context("Failed to refresh session")?
+        .ok_or_else(|| AppError::unauthorized("Invalid refresh token"))?;
+    let (token, expires_at) = signer.jwt(&user, sessions.ttl_secs);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "token": token,
+        "token_type": "Bearer",
+        "expires_at": expires_at,
+        "refresh_token": refresh_token.token,
+        "refresh_expires_at": refresh_token.expires_at.timestamp(),
+        "user": user
+    })))
+}
+
+// GET /auth/session - The user a session cookie or bearer session token belongs to
+#[get("/auth/session")]
+pub async fn session(
+    req: HttpRequest,
+    signer: web::Data<Signer>,
+    users: web::Data<CachedUserRepository>
+) -> Result<HttpResponse, AppError> {
+    if let Some(user) = req.extensions().get::<User>() {
+        return Ok(HttpResponse::Ok().json(user));
+    }
+    
+    let user_id = roles::session_user(req.headers(), &signer)
+        .ok_or_else(|| AppError::unauthorized("A valid session token is required"))?;
+    
+    let user = users
+        .get_by_id(&user_id)
+        .await
+        .context("Failed to retrieve user")?
+        .ok_or_else(|| AppError::unauthorized("A valid session token is required"))?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+// POST /auth/logout - End the session of the session cookie
+#[post("/auth/logout")]
+pub async fn logout(req: HttpRequest, session_store: web::Data<Sessions>) -> Result<HttpResponse, AppError> {
+    let Some(cookie) = req.cookie(sessions::COOKIE) else {
+        return Ok(HttpResponse::NoContent().finish());
+    };
+    session_store.end(cookie.value()).await.context("Failed to end session")?;
+    if let Some(user) = req.extensions().get::<User>() {
+        log::info!(target: "audit", "User {} logged out", user.id);
+    }
+    
+    let mut removal = Cookie::build(sessions::COOKIE, "").path("/").finish();
+    removal.make_removal();
+    Ok(HttpResponse::NoContent().cookie(removal).finish())
+}
+
+// GET /auth/password-policy - Requirements for new passwords, for UIs to show
+#[get("/auth/password-policy")]
+pub async fn password_policy(passwords: web::Data<PasswordChecker>) -> impl Responder {
+    HttpResponse::Ok().json(passwords.policy())
+}
+
+// POST /auth/password-policy/check - Whether a password would be accepted, and why not
+#[post("/auth/password-policy/check")]
+pub async fn check_password(
+    check_req: web::Json<PasswordCheckRequest>,
+    passwords: web::Data<PasswordChecker>
+) -> impl Responder {
+    let problems = passwords.check(&check_req.password, check_req.email.as_deref()).await;
+    HttpResponse::Ok().json(serde_json::json!({
+        "acceptable": problems.is_empty(),
+        "problems": problems
+    }))
+}
+
+// The user linked to the identity. A first login links to the user with the
+// same email, if the provider verified it, or creates a new user.
+async fn local_user(
+    users: &CachedUserRepository,
+    identities: &IdentityRepository,
+    provider: &str,
+    claims: &Claims
+) -> Result<User, AppError> {
+    if let Some(user_id) = identities.find_user(provider, &claims.sub).await.context("Failed to log in")? {
+        return users
+            .get_by_id(&user_id)
+            .await
+            .context("Failed to log in")?
+            .ok_or_else(|| AppError::not_found("User not found"));
+    }
+    
+    let email = claims
+        .email
+        .as_deref()
+        .ok_or_else(|| AppError::bad_request("The identity provider didn't share an email address"))?;
+    
+    let user = match users.get_by_email(email).await.context("Failed to log in")? {
+        // Linking on an unverified email would let anyone claim an account
+        Some(_) if !claims.email_verified => {
+            return Err(AppError::conflict("An account with this email exists, link this identity to it first"));
+        }
+        Some(user) => user,
+        None => {
+            let name = claims
+                .name
+                .as_deref()
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| email.split('@').next().unwrap_or(email));
+            let user_req = CreateUserRequest {
+                username: None,
+                name: name.to_string(),
+                email: email.to_string(),
+                age: None,
+                password: None,
+            };
+            if let Err(errors) = user_req.validate() {
+                return Err(AppError::Validation {
+                    detail: "The identity provider's profile can't be used for an account",
+                    errors,
+                });
+            }
+            let user = users.create(&user_req).await.context("Failed to log in")?;
+            log::info!(target: "audit", "Created user {} on first {} login", user.id, provider);
+            user
+        }
+    };
+    
+    match identities.link(&user.id, provider, &claims.sub).await.context("Failed to log in")? {
+        LinkOutcome::Linked(_) | LinkOutcome::AlreadyLinked(_) => Ok(user),
+        // Another login for the same identity got there first
+        LinkOutcome::LinkedElsewhere(_) => Err(AppError::conflict("This identity is already linked to another account")),
+    }
+}
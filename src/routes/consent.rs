@@ -0,0 +1,82 @@
+use actix_web::{web, HttpResponse, get, post};
+use actix_web::http::{header, StatusCode};
+
+use crate::errors::{AppError, Context};
+use crate::extractors::ValidatedJson;
+use crate::models::consent::ConsentRequest;
+use crate::models::public_id::{self, PublicId};
+use crate::repositories::consent_repo::ConsentRepository;
+use crate::repositories::user_repo::CachedUserRepository;
+use crate::resource;
+use crate::routes::identity::require_user;
+
+// GET /users/{id}/consents - Consent history of a user, newest first
+#[get("/users/{id}/consents")]
+pub async fn list_consents(
+    path: web::Path<PublicId>,
+    users: web::Data<CachedUserRepository>,
+    consents: web::Data<ConsentRepository>
+) -> Result<HttpResponse, AppError> {
+    let user_id = path.into_inner().0;
+
+    require_user(&users, &user_id).await?;
+
+    let items = consents.list_for_user(&user_id).await.context("Failed to retrieve consents")?;
+    Ok(HttpResponse::Ok().json(items))
+}
+
+// GET /users/{id}/consents/{purpose} - Whether the user currently consents to a purpose
+// Senders of marketing and other consent-bound messages check this before sending
+#[get("/users/{id}/consents/{purpose}")]
+pub async fn check_consent(
+    path: web::Path<(PublicId, String)>,
+    users: web::Data<CachedUserRepository>,
+    consents: web::Data<ConsentRepository>
+) -> Result<HttpResponse, AppError> {
+    let (PublicId(user_id), purpose) = path.into_inner();
+    let purpose = purpose.trim().to_lowercase();
+
+    require_user(&users, &user_id).await?;
+
+    let granted = consents.has_consent(&user_id, &purpose).await.context("Failed to check consent")?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "purpose": purpose,
+        "granted": granted
+    })))
+}
+
+// POST /users/{id}/consents - Grant or revoke consent for a purpose
+#[post("/users/{id}/consents")]
+pub async fn record_consent(
+    path: web::Path<PublicId>,
+    consent_req: ValidatedJson<ConsentRequest>,
+    users: web::Data<CachedUserRepository>,
+    consents: web::Data<ConsentRepository>
+) -> Result<HttpResponse, AppError> {
+    let user_id = path.into_inner().0;
+    // Purposes are matched exactly, so "Marketing" and "marketing " are the same one
+    let purpose = consent_req.purpose.trim().to_lowercase();
+    let source = consent_req.source.trim();
+
+    require_user(&users, &user_id).await?;
+
+    if consent_req.granted {
+        let (consent, created) = consents.grant(&user_id, &purpose, source).await.context("Failed to record consent")?;
+        if !created {
+            return Ok(resource::json(StatusCode::OK, &consent));
+        }
+        log::info!(target: "audit", "User {} granted {} consent via {}", user_id, purpose, source);
+        let location = format!("/users/{}/consents/{}", public_id::encode(&user_id), resource::path_segment(&purpose));
+        Ok(resource::response(StatusCode::CREATED, &consent)
+            .insert_header((header::LOCATION, location))
+            .json(&consent))
+    } else {
+        let consent = consents
+            .revoke(&user_id, &purpose)
+            .await
+            .context("Failed to revoke consent")?
+            .ok_or_else(|| AppError::not_found("No active consent for this purpose"))?;
+        log::info!(target: "audit", "User {} revoked {} consent via {}", user_id, purpose, source);
+        Ok(resource::json(StatusCode::OK, &consent))
+    }
+}
@@ -0,0 +1,86 @@
+use actix_web::{web, HttpRequest, HttpResponse, get, post};
+use actix_web::http::header;
+use actix_web::middleware::Compress;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::errors::{AppError, Context};
+use crate::exports::{self, CsvOptions, ExportFormat, Exports};
+use crate::extractors::ValidatedQuery;
+use crate::models::user::{UserFilter, VerifyQuery};
+use crate::roles::Admins;
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+    // The rest only apply to CSV, see CsvOptions
+    delimiter: Option<char>,
+    #[serde(default)]
+    bom: bool,
+    #[serde(default)]
+    quote_headers: bool,
+    // Comma-separated column names
+    columns: Option<String>,
+}
+
+impl ExportQuery {
+    fn csv_options(&self) -> Result<Option<CsvOptions>, AppError> {
+        let given = self.delimiter.is_some() || self.bom || self.quote_headers || self.columns.is_some();
+        match self.format {
+            ExportFormat::Csv => CsvOptions::new(self.delimiter, self.bom, self.quote_headers, self.columns.as_deref())
+                .map(Some)
+                .map_err(AppError::bad_request),
+            ExportFormat::Ndjson if given => Err(AppError::bad_request("delimiter, bom, quote_headers and columns only apply to format=csv")),
+            ExportFormat::Ndjson => Ok(None),
+        }
+    }
+}
+
+// POST /users/exports?format=csv&delimiter=;&bom=true&min_age=18 - Export the
+// users matching the GET /users filters to a file in the background
+#[post("/users/exports")]
+pub async fn start_export(
+    req: HttpRequest,
+    query: web::Query<ExportQuery>,
+    filter: ValidatedQuery<UserFilter>,
+    exports: web::Data<Exports>,
+    admins: web::Data<Admins>
+) -> Result<HttpResponse, AppError> {
+    if filter.include_deleted {
+        admins.acting(&req).await?;
+    }
+    let csv = query.csv_options()?;
+    let job = Exports::start(&exports, query.format, csv, filter.0);
+    log::info!("Started export {} ({:?})", job.id, job.format);
+    Ok(HttpResponse::Accepted()
+        .insert_header((header::LOCATION, format!("/users/exports/{}", job.id)))
+        .json(job))
+}
+
+// GET /users/exports/{id} - Progress of an export, with a download link once it's done
+#[get("/users/exports/{id}")]
+pub async fn get_export(path: web::Path<Uuid>, exports: web::Data<Exports>) -> Result<HttpResponse, AppError> {
+    let job = exports.get(&path.into_inner()).ok_or_else(|| AppError::not_found("Export not found"))?;
+    Ok(HttpResponse::Ok().json(job))
+}
+
+// GET /users/exports/{id}/download?exp=&sig= - The finished file, through a
+// signed link. Compressed on the way out when the client accepts gzip, brotli or zstd.
+#[get("/users/exports/{id}/download", wrap = "Compress::default()")]
+pub async fn download_export(
+    path: web::Path<Uuid>,
+    query: web::Query<VerifyQuery>,
+    exports: web::Data<Exports>
+) -> Result<HttpResponse, AppError> {
+    let id = path.into_inner();
+    let (file, format) = exports
+        .artifact(&id, query.exp, &query.sig)
+        .ok_or_else(|| AppError::forbidden("Invalid or expired download link"))?;
+    let name = file.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let chunks = exports::read_chunks(file).await.context("Failed to open export")?;
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((header::CONTENT_DISPOSITION, format!("attachment; filename=\"users-{}\"", name)))
+        .streaming(chunks))
+}
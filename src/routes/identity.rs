@@ -0,0 +1,83 @@
+use actix_web::{web, HttpResponse, get, post, delete};
+use actix_web::http::StatusCode;
+use uuid::Uuid;
+
+use crate::errors::{AppError, Context};
+use crate::extractors::ValidatedJson;
+use crate::models::identity::LinkIdentityRequest;
+use crate::models::public_id::PublicId;
+use crate::repositories::identity_repo::{IdentityRepository, LinkOutcome};
+use crate::repositories::user_repo::CachedUserRepository;
+use crate::resource;
+
+// GET /users/{id}/identities - External identities linked to a user
+#[get("/users/{id}/identities")]
+pub async fn list_identities(
+    path: web::Path<PublicId>,
+    users: web::Data<CachedUserRepository>,
+    identities: web::Data<IdentityRepository>
+) -> Result<HttpResponse, AppError> {
+    let user_id = path.into_inner().0;
+
+    require_user(&users, &user_id).await?;
+
+    let items = identities.list_for_user(&user_id).await.context("Failed to retrieve identities")?;
+    Ok(HttpResponse::Ok().json(items))
+}
+
+// POST /users/{id}/identities - Link an external identity to a user
+#[post("/users/{id}/identities")]
+pub async fn link_identity(
+    path: web::Path<PublicId>,
+    link_req: ValidatedJson<LinkIdentityRequest>,
+    users: web::Data<CachedUserRepository>,
+    identities: web::Data<IdentityRepository>
+) -> Result<HttpResponse, AppError> {
+    let user_id = path.into_inner().0;
+    let provider = link_req.provider.trim();
+    let subject = link_req.subject.trim();
+
+    require_user(&users, &user_id).await?;
+
+    match identities.link(&user_id, provider, subject).await.context("Failed to link identity")? {
+        LinkOutcome::Linked(identity) => {
+            log::info!(target: "audit", "Linked {} identity {} to user {}", provider, subject, user_id);
+            Ok(resource::json(StatusCode::CREATED, &identity))
+        }
+        LinkOutcome::AlreadyLinked(identity) => Ok(resource::json(StatusCode::OK, &identity)),
+        LinkOutcome::LinkedElsewhere(other) => {
+            // The other account is only logged, not revealed to the caller
+            log::warn!(
+                target: "audit",
+                "Refused to link {} identity {} to user {}, already linked to user {}",
+                provider, subject, user_id, other
+            );
+            Err(AppError::conflict("This identity is already linked to another account"))
+        }
+    }
+}
+
+// DELETE /users/{id}/identities/{provider}/{subject} - Unlink an external identity
+#[delete("/users/{id}/identities/{provider}/{subject}")]
+pub async fn unlink_identity(
+    path: web::Path<(PublicId, String, String)>,
+    identities: web::Data<IdentityRepository>
+) -> Result<HttpResponse, AppError> {
+    let (PublicId(user_id), provider, subject) = path.into_inner();
+
+    if !identities.unlink(&user_id, &provider, &subject).await.context("Failed to unlink identity")? {
+        return Err(AppError::not_found("Identity not linked to this user"));
+    }
+
+    log::info!(target: "audit", "Unlinked {} identity {} from user {}", provider, subject, user_id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// 404 when the user doesn't exist
+pub async fn require_user(users: &CachedUserRepository, user_id: &Uuid) -> Result<(), AppError> {
+    if users.exists(user_id).await.context("Failed to look up user")? {
+        Ok(())
+    } else {
+        Err(AppError::not_found("User not found"))
+    }
+}
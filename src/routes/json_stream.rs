@@ -0,0 +1,34 @@
+use actix_web::web::Bytes;
+use futures_util::{stream, Stream, StreamExt};
+use serde::Serialize;
+use std::error::Error as StdError;
+
+// Serialize a stream of items as the chunks of a JSON array, so large lists are
+// written into the response body element by element instead of through an
+// intermediate Vec. Once the status line is sent an error can't change it, so a
+// failing item is logged and aborts the body.
+pub fn json_array<S, T, E>(items: S) -> impl Stream<Item = Result<Bytes, Box<dyn StdError>>> + 'static
+where
+    S: Stream<Item = Result<T, E>> + 'static,
+    T: Serialize,
+    E: Into<Box<dyn StdError>>,
+{
+    let elements = items.enumerate().map(|(idx, item)| {
+        let item = item.map_err(|e| {
+            let e = e.into();
+            log::error!("Failed to stream response: {}", e);
+            e
+        })?;
+
+        let mut buf = Vec::with_capacity(128);
+        if idx > 0 {
+            buf.push(b',');
+        }
+        serde_json::to_writer(&mut buf, &item)?;
+        Ok(Bytes::from(buf))
+    });
+
+    stream::once(async { Ok(Bytes::from_static(b"[")) })
+        .chain(elements)
+        .chain(stream::once(async { Ok(Bytes::from_static(b"]")) }))
+}
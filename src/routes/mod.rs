@@ -1 +1,13 @@
+pub mod admin;
+pub mod audit;
+pub mod auth;
+pub mod consent;
+pub mod export;
+pub mod identity;
+pub mod json_stream;
+#[cfg(feature = "admin-query")]
+pub mod query;
+pub mod schedule;
+pub mod status;
+pub mod sync;
 pub mod user;
\ No newline at end of file
@@ -0,0 +1,56 @@
+use actix_web::{web, HttpRequest, HttpResponse, post};
+use serde::Deserialize;
+
+use crate::errors::{AppError, Context};
+use crate::repositories::query_repo::{self, QueryRepository};
+use crate::repositories::user_repo::CachedUserRepository;
+use crate::roles;
+use crate::signing::Signer;
+
+#[derive(Deserialize)]
+pub struct RunQueryRequest {
+    pub sql: String,
+    // For $1, $2, ... in order
+    #[serde(default)]
+    pub params: Vec<serde_json::Value>,
+    // Fewer rows than the configured maximum
+    pub limit: Option<u32>,
+}
+
+// POST /admin/query - Run a parameterized read-only SELECT and return its rows as JSON.
+// Needs an admin session, X-Admin-User alone can be sent by anyone.
+#[post("/admin/query")]
+pub async fn run_query(
+    req: HttpRequest,
+    body: web::Json<RunQueryRequest>,
+    queries: web::Data<QueryRepository>,
+    users: web::Data<CachedUserRepository>,
+    signer: web::Data<Signer>
+) -> Result<HttpResponse, AppError> {
+    let admin = roles::session_admin(&req, &signer, &users).await?.id;
+    let body = body.into_inner();
+    let limit = body.limit.unwrap_or(u32::MAX).clamp(1, queries.max_rows());
+
+    // Logged before running, so a query that takes the process down is still on record.
+    // Literals and parameter values are personal data, so they stay out.
+    log::info!(
+        target: "audit",
+        "Admin {} running query {:?} with {} params",
+        admin, query_repo::scrub(&body.sql), body.params.len()
+    );
+    match queries.run(&body.sql, &body.params, limit).await.context("Failed to run query")? {
+        Ok(result) => {
+            log::info!(
+                target: "audit",
+                "Query by admin {} returned {} rows{}",
+                admin, result.rows.len(), if result.truncated { ", truncated" } else { "" }
+            );
+            Ok(HttpResponse::Ok().json(result))
+        }
+        Err(reason) => {
+            // The reason can quote a value from the query
+            log::warn!(target: "audit", "Query by admin {} was refused by Postgres", admin);
+            Err(AppError::bad_request(reason))
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use actix_web::{web, HttpResponse, get, post, delete};
+use actix_web::http::StatusCode;
+use uuid::Uuid;
+
+use crate::approvals::Approvals;
+use crate::clock::Clock;
+use crate::demo::{self, DemoMode};
+use crate::errors::{AppError, Context};
+use crate::extractors::ValidatedJson;
+use crate::models::public_id::PublicId;
+use crate::models::schedule::{ScheduleRequest, ScheduledAction};
+use crate::models::validate::FieldError;
+use crate::repositories::user_repo::CachedUserRepository;
+use crate::resource;
+use crate::routes::identity::require_user;
+use crate::scheduler::Scheduler;
+
+// POST /users/{id}/schedule - Schedule an update or delete of a user for a later time
+#[post("/users/{id}/schedule")]
+pub async fn schedule_operation(
+    path: web::Path<PublicId>,
+    schedule_req: ValidatedJson<ScheduleRequest>,
+    users: web::Data<CachedUserRepository>,
+    scheduler: web::Data<Scheduler>,
+    demo: web::Data<DemoMode>,
+    approvals: web::Data<Approvals>,
+    clock: web::Data<dyn Clock>
+) -> Result<HttpResponse, AppError> {
+    let user_id = path.into_inner().0;
+    
+    if schedule_req.execute_at <= clock.now() {
+        return Err(AppError::Validation {
+            detail: "One or more fields are invalid",
+            errors: vec![FieldError {
+                field: "execute_at".into(),
+                message: "must be in the future".into(),
+            }],
+        });
+    }
+    
+    if demo.0 && schedule_req.action == ScheduledAction::Delete {
+        log::warn!(target: "audit", "Refused to schedule delete of user {} on the demo instance", user_id);
+        return Err(demo::forbidden());
+    }
+    
+    let current = users
+        .get_by_id(&user_id)
+        .await
+        .context("Failed to schedule operation")?
+        .ok_or_else(|| AppError::not_found("User not found"))?;
+    
+    // Nobody is around to approve when the scheduler runs, so these go through /users
+    let needs_approval = match (&schedule_req.action, &schedule_req.changes) {
+        (ScheduledAction::Delete, _) => true,
+        (ScheduledAction::Update, Some(changes)) => Approvals::changes_email(&current, changes),
+        (ScheduledAction::Update, None) => false,
+    };
+    if approvals.required() && needs_approval {
+        return Err(AppError::forbidden("Deletes and email changes need approval and can't be scheduled"));
+    }
+    
+    let operation = scheduler
+        .schedule(&user_id, schedule_req.action, schedule_req.changes.as_ref(), &schedule_req.execute_at)
+        .await
+        .context("Failed to schedule operation")?;
+    Ok(resource::json(StatusCode::CREATED, &operation))
+}
+
+// GET /users/{id}/schedule - Operations scheduled for a user, with their status
+#[get("/users/{id}/schedule")]
+pub async fn list_operations(
+    path: web::Path<PublicId>,
+    users: web::Data<CachedUserRepository>,
+    scheduler: web::Data<Scheduler>
+) -> Result<HttpResponse, AppError> {
+    let user_id = path.into_inner().0;
+    
+    require_user(&users, &user_id).await?;
+    
+    let operations = scheduler.list(&user_id).await.context("Failed to retrieve scheduled operations")?;
+    Ok(HttpResponse::Ok().json(operations))
+}
+
+// DELETE /users/{id}/schedule/{operation_id} - Cancel an operation that hasn't run yet
+#[delete("/users/{id}/schedule/{operation_id}")]
+pub async fn cancel_operation(
+    path: web::Path<(PublicId, Uuid)>,
+    scheduler: web::Data<Scheduler>
+) -> Result<HttpResponse, AppError> {
+    let (PublicId(user_id), operation_id) = path.into_inner();
+    
+    if let Some(operation) = scheduler.cancel(&user_id, &operation_id).await.context("Failed to cancel operation")? {
+        return Ok(resource::json(StatusCode::OK, &operation));
+    }
+    
+    match scheduler.get(&user_id, &operation_id).await.context("Failed to cancel operation")? {
+        Some(operation) => Err(AppError::conflict(format!("This operation is already {}", operation.status.as_str()))),
+        None => Err(AppError::not_found("Scheduled operation not found")),
+    }
+}
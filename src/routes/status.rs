@@ -0,0 +1,56 @@
+use actix_web::{web, HttpResponse, Responder, get};
+
+use crate::health::{self, RequestStats};
+use crate::metrics::Metrics;
+use crate::models::user::UserFilter;
+use crate::repositories::tenant_repo::TenantSettingsRepository;
+use crate::repositories::user_repo::CachedUserRepository;
+use crate::templates;
+
+// GET /health/ready - Readiness check covering every dependency
+#[get("/health/ready")]
+pub async fn readiness(users: web::Data<CachedUserRepository>) -> impl Responder {
+    let components = health::check_components(&users).await;
+    
+    let status = if !components.iter().all(|c| c.healthy) {
+        "unavailable"
+    } else if components.iter().any(|c| c.degraded) {
+        "degraded"
+    } else {
+        "ok"
+    };
+    let body = serde_json::json!({
+        "status": status,
+        "components": components
+    });
+    
+    if components.iter().all(|c| c.healthy) {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+// GET /status - Auto-refreshing HTML status page
+#[get("/status")]
+pub async fn status_page(
+    users: web::Data<CachedUserRepository>,
+    stats: web::Data<RequestStats>,
+    tenant: web::Data<TenantSettingsRepository>
+) -> impl Responder {
+    let components = health::check_components(&users).await;
+    // A failed count just shows as unknown, the database row already reports the outage
+    let user_count = users.count(&UserFilter::default()).await.ok();
+    
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(templates::status::status_page(&components, user_count, stats.recent(), stats.window_secs(), &tenant.get()))
+}
+
+// GET /metrics - Request, database pool and cache metrics in the Prometheus text format
+#[get("/metrics")]
+pub async fn metrics(metrics: web::Data<Metrics>, users: web::Data<CachedUserRepository>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(metrics.render(&users))
+}
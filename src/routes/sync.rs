@@ -0,0 +1,73 @@
+use actix_web::{web, HttpResponse, get, post};
+
+use crate::approvals::Approvals;
+use crate::demo::{self, DemoMode};
+use crate::errors::{AppError, Context};
+use crate::models::sync::{ClientChange, SyncPushRequest, SyncQuery};
+use crate::repositories::sync_repo::SyncRepository;
+use crate::repositories::user_repo::CachedUserRepository;
+use crate::sync;
+
+// GET /sync/users?since_token= - Users created, updated and deleted since the token
+#[get("/sync/users")]
+pub async fn pull_users(
+    query: web::Query<SyncQuery>,
+    users: web::Data<CachedUserRepository>,
+    changes: web::Data<SyncRepository>
+) -> Result<HttpResponse, AppError> {
+    let since = match query.since_token.as_deref() {
+        Some(token) => Some(sync::parse_token(token).ok_or_else(invalid_token)?),
+        None => None,
+    };
+    
+    let response = sync::pull(&users, &changes, since).await.context("Failed to retrieve changes")?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// POST /sync/users - Apply changes made by a client while offline
+#[post("/sync/users")]
+pub async fn push_users(
+    push_req: web::Json<SyncPushRequest>,
+    users: web::Data<CachedUserRepository>,
+    changes: web::Data<SyncRepository>,
+    demo: web::Data<DemoMode>,
+    approvals: web::Data<Approvals>
+) -> Result<HttpResponse, AppError> {
+    // Offline deletes are still deletes
+    if demo.0 && push_req.changes.iter().any(|c| matches!(c, ClientChange::Delete { .. })) {
+        log::warn!(target: "audit", "Refused sync push with deletes on the demo instance");
+        return Err(demo::forbidden());
+    }
+    
+    // Sync clients can't name an admin, so changes needing approval go through the HTTP API
+    if approvals.required() && needs_approval(&users, &push_req).await.context("Failed to apply changes")? {
+        log::warn!(target: "audit", "Refused sync push with deletes or email changes while approvals are required");
+        return Err(AppError::forbidden("Deletes and email changes need approval, send them through /users"));
+    }
+    
+    let since = sync::parse_token(&push_req.since_token).ok_or_else(invalid_token)?;
+    
+    let response = sync::push(&users, &changes, since, &push_req).await.context("Failed to apply changes")?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+async fn needs_approval(users: &CachedUserRepository, push_req: &SyncPushRequest) -> Result<bool, Box<dyn std::error::Error>> {
+    for change in &push_req.changes {
+        let needed = match change {
+            ClientChange::Delete { .. } => true,
+            ClientChange::Update { id, email: Some(email), .. } => users
+                .get_by_id(id)
+                .await?
+                .is_some_and(|user| !user.email.eq_ignore_ascii_case(email)),
+            _ => false,
+        };
+        if needed {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn invalid_token() -> AppError {
+    AppError::bad_request("Invalid sync token")
+}
@@ -1,9 +1,33 @@
-use actix_web::{web, HttpResponse, Responder, get, post, put, delete};
+use actix_web::{web, HttpRequest, HttpResponse, Responder, get, post, put, patch, delete};
+use actix_web::http::header::{self, Header};
+use actix_web::http::StatusCode;
 use uuid::Uuid;
-use log::error;
 
-use crate::models::user::{CreateUserRequest, UpdateUserRequest};
-use crate::repositories::user_repo::CachedUserRepository;
+use crate::activitypub;
+use crate::approvals::{Approvals, Requested};
+use crate::change_guard::ChangeGuard;
+use crate::config::PublicUrl;
+use crate::demo::{self, DemoMode};
+use crate::errors::{AppError, Context};
+use crate::extractors::{problem, FilterSchema, ListParams, Paging, ValidatedJson, ValidatedQuery};
+use crate::import;
+use crate::json_patch::{self, Operation, PatchError};
+use crate::models::public_id::{self, PublicId};
+use crate::models::sort::{SortField, SortSpec};
+use crate::models::tenant::TenantSettings;
+use crate::models::user::{BatchGetRequest, CreateUserRequest, DeleteUsersRequest, QrQuery, QrTarget, SearchQuery, SuggestQuery, UpdateUserRequest, UserCursorPage, User, UserFilter, UserPage, VerifyQuery};
+use crate::models::validate::{FieldError, Validate};
+use crate::password_policy::PasswordChecker;
+use crate::qr;
+use crate::repositories::cursor::CursorCodec;
+use crate::repositories::tenant_repo::TenantSettingsRepository;
+use crate::resource;
+use crate::roles::Admins;
+use crate::repositories::user_store::UserStore;
+use crate::routes::json_stream;
+use crate::signing::Signer;
+use crate::templates;
+use crate::vcard;
 
 // GET /health - Health check endpoint
 #[get("/health")]
@@ -11,91 +35,667 @@ pub async fn health_check() -> impl Responder {
     HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
 }
 
-// GET /users - List all users
+// GET /users?sort=name,-created_at&page=2&per_page=50&min_age=18 - List users
+// GET /users?after={cursor}&limit=50 - Keyset pagination by id
+// Without filters or pagination every user is streamed as rows arrive from the database
+// Browsers asking for text/html get the paged admin list with filter and sort controls
 #[get("/users")]
-pub async fn get_users(repo: web::Data<CachedUserRepository>) -> impl Responder {
-    match repo.get_all().await {
-        Ok(users) => HttpResponse::Ok().json(users),
-        Err(e) => {
-            error!("Failed to get users: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to retrieve users"
-            }))
+pub async fn get_users(
+    req: HttpRequest,
+    params: ListParams<UserFilter>,
+    repo: web::Data<dyn UserStore>,
+    tenant: web::Data<TenantSettingsRepository>,
+    cursors: web::Data<CursorCodec>,
+    admins: web::Data<Admins>
+) -> Result<HttpResponse, AppError> {
+    let ListParams { filter, sort, raw_sort, paging } = params;
+    if filter.include_deleted {
+        admins.acting(&req).await?;
+    }
+    
+    // Browsers get the admin list page, which is always paged so it stays usable on large tables
+    let html = templates::wants_html(&req).then(|| (tenant.get(), raw_sort.as_deref()));
+    let paging = match paging {
+        Paging::Unpaged if html.is_some() => Paging::first_page(),
+        paging => paging,
+    };
+    
+    match paging {
+        Paging::Keyset { after, limit } => {
+            let after = match after.as_deref() {
+                Some(cursor) => Some(cursors.decode(cursor).map_err(|e| AppError::bad_request(e.to_string()))?),
+                None => None,
+            };
+            get_users_after(repo.get_ref(), &cursors, &filter, after, limit).await
+        }
+        Paging::Offset { page, per_page } => get_users_page(repo.get_ref(), &filter, &sort, page, per_page, html).await,
+        Paging::Unpaged if !filter.is_empty() => {
+            let users = repo.find(&filter, &sort).await.context("Failed to retrieve users")?;
+            Ok(HttpResponse::Ok().json(users))
+        }
+        Paging::Unpaged => {
+            let users = repo.stream_all(&sort).await.context("Failed to retrieve users")?;
+            Ok(HttpResponse::Ok()
+                .content_type("application/json")
+                .streaming(json_stream::json_array(users)))
         }
     }
 }
 
-// GET /users/{id} - Get a specific user
+impl FilterSchema for UserFilter {
+    type SortField = SortField;
+    const PARAMS: &'static [&'static str] = &["email", "min_age", "max_age", "name_contains", "region", "include_deleted"];
+}
+
+// `html` has the branding and raw sort parameter when rendering the admin list page
+async fn get_users_page(
+    repo: &dyn UserStore,
+    filter: &UserFilter,
+    sort: &SortSpec,
+    page: u32,
+    per_page: u32,
+    html: Option<(TenantSettings, Option<&str>)>
+) -> Result<HttpResponse, AppError> {
+    let offset = (page as i64 - 1) * per_page as i64;
+    let total = repo.count(filter).await.context("Failed to retrieve users")?;
+    let items = repo
+        .get_paginated(filter, sort, offset, per_page as i64)
+        .await
+        .context("Failed to retrieve users")?;
+    let page = UserPage { total, page, per_page, items };
+    
+    Ok(match html {
+        Some((branding, raw_sort)) => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(templates::user::list(&page, filter, raw_sort, &branding)),
+        None => HttpResponse::Ok().json(page),
+    })
+}
+
+async fn get_users_after(
+    repo: &dyn UserStore,
+    cursors: &CursorCodec,
+    filter: &UserFilter,
+    after: Option<Uuid>,
+    limit: u32
+) -> Result<HttpResponse, AppError> {
+    // One extra row tells whether there is a next page
+    let mut items = repo
+        .get_after(filter, after.as_ref(), limit as i64 + 1)
+        .await
+        .context("Failed to retrieve users")?;
+    let next_cursor = if items.len() > limit as usize {
+        items.truncate(limit as usize);
+        items.last().map(|user| cursors.encode(&user.id))
+    } else {
+        None
+    };
+    Ok(HttpResponse::Ok().json(UserCursorPage { items, next_cursor }))
+}
+
+// GET /users/{id} - Get a specific user, a 304 if If-None-Match has its ETag
+// Browsers asking for text/html get a rendered profile page instead of JSON
 #[get("/users/{id}")]
-pub async fn get_user(path: web::Path<Uuid>, repo: web::Data<CachedUserRepository>) -> impl Responder {
-    let user_id = path.into_inner();
-    
-    match repo.get_by_id(&user_id).await {
-        Ok(Some(user)) => HttpResponse::Ok().json(user),
-        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({
-            "error": "User not found"
-        })),
-        Err(e) => {
-            error!("Failed to get user {}: {}", user_id, e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to retrieve user"
-            }))
-        }
+pub async fn get_user(
+    req: HttpRequest,
+    path: web::Path<PublicId>,
+    repo: web::Data<dyn UserStore>,
+    tenant: web::Data<TenantSettingsRepository>
+) -> Result<HttpResponse, AppError> {
+    let user_id = path.into_inner().0;
+    
+    let user = find_user(repo.get_ref(), &user_id).await?;
+    Ok(if templates::wants_html(&req) {
+        resource::response(StatusCode::OK, &user)
+            .content_type("text/html; charset=utf-8")
+            .body(templates::user::profile(&user, &tenant.get()))
+    } else {
+        resource::conditional_json(&req, &user)
+    })
+}
+
+// Typeahead results per request
+const SUGGEST_LIMIT: usize = 10;
+
+// GET /users/suggest?q= - Typeahead: id and name of up to 10 users matching a name prefix
+// Served from the in-memory index with Postgres, other stores rank a scan of their users
+#[get("/users/suggest")]
+pub async fn suggest_users(query: web::Query<SuggestQuery>, repo: web::Data<dyn UserStore>) -> Result<HttpResponse, AppError> {
+    let suggestions = repo.suggest(&query.q, SUGGEST_LIMIT).await.context("Failed to suggest users")?;
+    Ok(HttpResponse::Ok().json(suggestions))
+}
+
+// Search results when no limit is given
+const DEFAULT_SEARCH_LIMIT: u32 = 20;
+
+// GET /users/search?q=&limit= - Search users by name and email, best matches first
+#[get("/users/search")]
+pub async fn search_users(query: ValidatedQuery<SearchQuery>, repo: web::Data<dyn UserStore>) -> Result<HttpResponse, AppError> {
+    let q = query.q.trim();
+    let limit = query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+    
+    let users = repo.search(q, limit as i64).await.context("Failed to search users")?;
+    Ok(HttpResponse::Ok().json(users))
+}
+
+// GET /users/by-username/{name} - Get a user by username, ignoring case
+// Usernames a user has since changed away from redirect to their current one
+#[get("/users/by-username/{name}")]
+pub async fn get_user_by_username(
+    req: HttpRequest,
+    path: web::Path<String>,
+    repo: web::Data<dyn UserStore>,
+    tenant: web::Data<TenantSettingsRepository>
+) -> Result<HttpResponse, AppError> {
+    let username = path.into_inner();
+    
+    match repo.get_by_username(&username).await.context("Failed to retrieve user")? {
+        Some(user) if templates::wants_html(&req) => return Ok(resource::response(StatusCode::OK, &user)
+            .content_type("text/html; charset=utf-8")
+            .body(templates::user::profile(&user, &tenant.get()))),
+        Some(user) => return Ok(resource::json(StatusCode::OK, &user)),
+        None => {}
     }
+    
+    let current = repo
+        .renamed_to(&username)
+        .await
+        .context("Failed to retrieve user")?
+        .ok_or_else(|| AppError::not_found("User not found"))?;
+    Ok(HttpResponse::MovedPermanently()
+        .insert_header((header::LOCATION, format!("/users/by-username/{}", current)))
+        .finish())
+}
+
+// GET /users/{id}.vcf - Export a user as a vCard
+#[get("/users/{id}.vcf")]
+pub async fn get_user_vcard(path: web::Path<PublicId>, repo: web::Data<dyn UserStore>) -> Result<HttpResponse, AppError> {
+    let user_id = path.into_inner().0;
+    
+    let user = find_user(repo.get_ref(), &user_id).await?;
+    Ok(resource::response(StatusCode::OK, &user)
+        .content_type("text/vcard; charset=utf-8")
+        .insert_header((header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}.vcf\"", public_id::encode(&user.id))))
+        .body(vcard::from_user(&user)))
+}
+
+// GET /users/{id}/actor - ActivityPub actor document for a user
+#[get("/users/{id}/actor")]
+pub async fn get_user_actor(
+    req: HttpRequest,
+    path: web::Path<PublicId>,
+    repo: web::Data<dyn UserStore>,
+    public_url: web::Data<PublicUrl>
+) -> Result<HttpResponse, AppError> {
+    let user_id = path.into_inner().0;
+    
+    let accepted: Vec<String> = header::Accept::parse(&req)
+        .map(|accept| accept.ranked().iter().map(|mime| mime.essence_str().to_string()).collect())
+        .unwrap_or_default();
+    let content_type = match activitypub::negotiate(&accepted) {
+        Some(content_type) => content_type,
+        None => return Ok(problem(
+            StatusCode::NOT_ACCEPTABLE,
+            "Not Acceptable",
+            "Actor documents are available as application/activity+json or application/ld+json",
+            serde_json::json!({}),
+        )),
+    };
+    
+    let user = find_user(repo.get_ref(), &user_id).await?;
+    Ok(resource::response(StatusCode::OK, &user)
+        .content_type(content_type)
+        .body(activitypub::actor_document(&user, &public_url.0).to_string()))
+}
+
+pub struct QrSettings {
+    pub link_ttl_secs: u64,
+}
+
+// GET /users/{id}/qr?target=verify|profile&format=png|svg - QR code for check-in staff
+#[get("/users/{id}/qr")]
+pub async fn get_user_qr(
+    path: web::Path<PublicId>,
+    query: web::Query<QrQuery>,
+    repo: web::Data<dyn UserStore>,
+    signer: web::Data<Signer>,
+    public_url: web::Data<PublicUrl>,
+    settings: web::Data<QrSettings>
+) -> Result<HttpResponse, AppError> {
+    let user_id = path.into_inner().0;
+    
+    let user = find_user(repo.get_ref(), &user_id).await?;
+    let link = match query.target {
+        QrTarget::Verify => signer.verification_url(&public_url.0, &user.id, settings.link_ttl_secs),
+        QrTarget::Profile => format!("{}/users/{}", public_url.0, public_id::encode(&user.id)),
+    };
+    
+    let (image, content_type) = qr::render(&link, query.format).context("Failed to render QR code")?;
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .body(image))
+}
+
+// GET /users/{id}/verify?exp=&sig= - Open a signed verification link from a QR code
+#[get("/users/{id}/verify")]
+pub async fn verify_user(
+    req: HttpRequest,
+    path: web::Path<PublicId>,
+    query: web::Query<VerifyQuery>,
+    repo: web::Data<dyn UserStore>,
+    signer: web::Data<Signer>,
+    tenant: web::Data<TenantSettingsRepository>
+) -> Result<HttpResponse, AppError> {
+    let user_id = path.into_inner().0;
+    
+    if !signer.verify_link(&user_id, query.exp, &query.sig) {
+        return Err(AppError::forbidden("Invalid or expired verification link"));
+    }
+    
+    let user = find_user(repo.get_ref(), &user_id).await?;
+    Ok(if templates::wants_html(&req) {
+        resource::response(StatusCode::OK, &user)
+            .content_type("text/html; charset=utf-8")
+            .body(templates::user::profile(&user, &tenant.get()))
+    } else {
+        resource::json(StatusCode::OK, &user)
+    })
+}
+
+// Upper bound on ids accepted by a single batch read
+const MAX_BATCH_IDS: usize = 100;
+
+// POST /users/batch-get - Get several users by id in one request
+#[post("/users/batch-get")]
+pub async fn batch_get_users(batch_req: web::Json<BatchGetRequest>, repo: web::Data<dyn UserStore>) -> Result<HttpResponse, AppError> {
+    if batch_req.ids.len() > MAX_BATCH_IDS {
+        return Err(AppError::bad_request(format!("At most {} ids can be requested at once", MAX_BATCH_IDS)));
+    }
+    
+    let users = repo.get_by_ids(&batch_req.ids).await.context("Failed to retrieve users")?;
+    let not_found: Vec<String> = batch_req
+        .ids
+        .iter()
+        .zip(&users)
+        .filter(|(_, user)| user.is_none())
+        .map(|(id, _)| public_id::encode(id))
+        .collect();
+    let items: Vec<_> = users.into_iter().flatten().collect();
+    
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "items": items,
+        "not_found": not_found
+    })))
 }
 
 // POST /users - Create a new user
 #[post("/users")]
-pub async fn create_user(user_req: web::Json<CreateUserRequest>, repo: web::Data<CachedUserRepository>) -> impl Responder {
-    match repo.create(&user_req).await {
-        Ok(user) => HttpResponse::Created().json(user),
-        Err(e) => {
-            error!("Failed to create user: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to create user"
-            }))
+pub async fn create_user(
+    user_req: ValidatedJson<CreateUserRequest>,
+    repo: web::Data<dyn UserStore>,
+    passwords: web::Data<PasswordChecker>
+) -> Result<HttpResponse, AppError> {
+    if let Some(password) = &user_req.password {
+        let problems = passwords.check(password, Some(&user_req.email)).await;
+        if !problems.is_empty() {
+            return Err(AppError::Validation {
+                detail: "The password doesn't meet the password policy",
+                errors: problems.into_iter().map(|message| FieldError { field: "password".to_string(), message }).collect(),
+            });
         }
     }
+    
+    if repo.exists_by_email(&user_req.email).await.context("Failed to create user")? {
+        return Err(AppError::conflict("A user with this email already exists"));
+    }
+    
+    if let Some(username) = &user_req.username {
+        if repo.username_taken(username, None).await.context("Failed to create user")? {
+            return Err(AppError::conflict("This username is already taken"));
+        }
+    }
+    
+    let user = repo.create(&user_req).await.context("Failed to create user")?;
+    Ok(resource::json(StatusCode::CREATED, &user))
+}
+
+// POST /users/import - Bulk import users from an NDJSON body (one user per line)
+#[post("/users/import")]
+pub async fn import_users(payload: web::Payload, repo: web::Data<dyn UserStore>) -> Result<HttpResponse, AppError> {
+    let summary = import::import_ndjson(payload, repo.get_ref()).await.context("Failed to import users")?;
+    Ok(HttpResponse::Ok().json(summary))
 }
 
-// PUT /users/{id} - Update a user
+// PUT /users/{id} - Update a user, made against the version in If-Match or the
+// body. A 409 when the user has changed since.
 #[put("/users/{id}")]
 pub async fn update_user(
-    path: web::Path<Uuid>,
-    user_req: web::Json<UpdateUserRequest>,
-    repo: web::Data<CachedUserRepository>
-) -> impl Responder {
-    let user_id = path.into_inner();
-    
-    match repo.update(&user_id, &user_req).await {
-        Ok(Some(user)) => HttpResponse::Ok().json(user),
-        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({
-            "error": "User not found"
-        })),
+    req: HttpRequest,
+    path: web::Path<PublicId>,
+    user_req: ValidatedJson<UpdateUserRequest>,
+    repo: web::Data<dyn UserStore>,
+    guard: web::Data<ChangeGuard>,
+    approvals: web::Data<Approvals>,
+    admins: web::Data<Admins>
+) -> Result<HttpResponse, AppError> {
+    let user_id = path.into_inner().0;
+    let mut user_req = user_req.0;
+    user_req.version = match (if_match(&req)?, user_req.version) {
+        (Some(IfMatch::Version(header)), Some(body)) if header != body => {
+            return Err(AppError::bad_request("If-Match and the body name different versions"));
+        }
+        (Some(IfMatch::Version(version)), _) | (_, Some(version)) => Some(version),
+        (Some(IfMatch::Any), None) => None,
+        (None, None) => {
+            return Err(AppError::precondition_required(
+                "Send the version of the user the update is based on, as If-Match or as version in the body",
+            ));
+        }
+    };
+    
+    // Unknown ids get a 404 up front rather than counting against the change limits
+    let current = repo
+        .get_by_id(&user_id)
+        .await
+        .context("Failed to update user")?
+        .ok_or_else(|| AppError::not_found("User not found"))?;
+    
+    apply_update(&req, &current, &user_req, repo.get_ref(), &guard, &approvals, &admins).await
+}
+
+enum IfMatch {
+    // `*`, whatever the version
+    Any,
+    Version(u32),
+}
+
+// The version in If-Match, a quoted number like GET /users/{id} returns as its version
+fn if_match(req: &HttpRequest) -> Result<Option<IfMatch>, AppError> {
+    let invalid = || AppError::bad_request("If-Match must be * or one quoted user version, like \"3\"");
+    match header::IfMatch::parse(req).map_err(|_| invalid())? {
+        header::IfMatch::Any => Ok(Some(IfMatch::Any)),
+        header::IfMatch::Items(tags) => match tags.as_slice() {
+            // Values that aren't entity tags are dropped while parsing
+            [] if req.headers().contains_key(header::IF_MATCH) => Err(invalid()),
+            [] => Ok(None),
+            [tag] => tag.tag().parse().map(|version| Some(IfMatch::Version(version))).map_err(|_| invalid()),
+            _ => Err(invalid()),
+        },
+    }
+}
+
+// PATCH /users/{id} - Partially update a user, with a JSON Patch (RFC 6902) or
+// a merge patch (RFC 7396) applied to the user as GET /users/{id} returns it.
+// Made against the version in If-Match if there is one, else the version the
+// patch was applied to.
+#[patch("/users/{id}")]
+pub async fn patch_user(
+    req: HttpRequest,
+    path: web::Path<PublicId>,
+    body: web::Bytes,
+    repo: web::Data<dyn UserStore>,
+    guard: web::Data<ChangeGuard>,
+    approvals: web::Data<Approvals>,
+    admins: web::Data<Admins>
+) -> Result<HttpResponse, AppError> {
+    let user_id = path.into_inner().0;
+    
+    let media_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.split(';').next().unwrap_or_default().trim().to_ascii_lowercase());
+    let patch = match media_type.as_deref() {
+        Some(json_patch::JSON_PATCH) => serde_json::from_slice::<Vec<Operation>>(&body).map(Patch::Json),
+        Some(json_patch::MERGE_PATCH) => serde_json::from_slice(&body).map(Patch::Merge),
+        _ => {
+            let accepted = format!("{}, {}", json_patch::JSON_PATCH, json_patch::MERGE_PATCH);
+            let detail = format!("Send the patch as {}", accepted);
+            let mut response = problem(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Unsupported Media Type", &detail, serde_json::json!({}));
+            if let Ok(accepted) = header::HeaderValue::from_str(&accepted) {
+                response.headers_mut().insert(header::HeaderName::from_static("accept-patch"), accepted);
+            }
+            return Ok(response);
+        }
+    };
+    let patch = patch.map_err(|e| AppError::bad_request(format!("Invalid patch: {}", e)))?;
+    
+    let current = repo
+        .get_by_id(&user_id)
+        .await
+        .context("Failed to update user")?
+        .ok_or_else(|| AppError::not_found("User not found"))?;
+    
+    let document = serde_json::to_value(&current).map_err(|e| e.into()).context("Failed to update user")?;
+    let patched = match patch {
+        Patch::Json(operations) => json_patch::apply(&document, &operations).map_err(|e| match e {
+            PatchError::Invalid(_) => AppError::bad_request(e.to_string()),
+            PatchError::Conflict(_) | PatchError::TestFailed(_) => AppError::conflict(e.to_string()),
+        })?,
+        Patch::Merge(patch) => {
+            let mut patched = document.clone();
+            json_patch::merge(&mut patched, &patch);
+            patched
+        }
+    };
+    
+    let mut user_req = patched_fields(&document, &patched)?;
+    user_req.version = match if_match(&req)? {
+        Some(IfMatch::Version(version)) => Some(version),
+        Some(IfMatch::Any) | None => Some(current.version),
+    };
+    if user_req.is_empty() {
+        return Ok(resource::json(StatusCode::OK, &current));
+    }
+    if let Err(errors) = user_req.validate() {
+        return Err(AppError::Validation { detail: "One or more fields are invalid", errors });
+    }
+    
+    apply_update(&req, &current, &user_req, repo.get_ref(), &guard, &approvals, &admins).await
+}
+
+enum Patch {
+    Json(Vec<Operation>),
+    Merge(serde_json::Value),
+}
+
+// The changes between the user and its patched document as an update. Only the
+// fields PUT accepts may change and none of them can be removed.
+fn patched_fields(document: &serde_json::Value, patched: &serde_json::Value) -> Result<UpdateUserRequest, AppError> {
+    let (Some(document), Some(patched)) = (document.as_object(), patched.as_object()) else {
+        return Err(AppError::bad_request("The patched user must be an object"));
+    };
+    
+    let mut changes = serde_json::Map::new();
+    let mut errors = Vec::new();
+    let fields = document.keys().chain(patched.keys().filter(|key| !document.contains_key(*key)));
+    for field in fields {
+        let new = patched.get(field).filter(|value| !value.is_null());
+        if new == document.get(field).filter(|value| !value.is_null()) {
+            continue;
+        }
+        match new {
+            _ if !matches!(field.as_str(), "username" | "name" | "email" | "age") => {
+                errors.push(FieldError { field: field.clone(), message: "is read-only".to_string() });
+            }
+            None => errors.push(FieldError { field: field.clone(), message: "can't be removed".to_string() }),
+            Some(value) => {
+                changes.insert(field.clone(), value.clone());
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(AppError::Validation { detail: "One or more fields can't be changed", errors });
+    }
+    
+    serde_json::from_value(serde_json::Value::Object(changes))
+        .map_err(|e| AppError::bad_request(format!("Invalid patched user: {}", e)))
+}
+
+// The part of an update shared by PUT and PATCH, once the current user is known
+async fn apply_update(
+    req: &HttpRequest,
+    current: &User,
+    user_req: &UpdateUserRequest,
+    repo: &dyn UserStore,
+    guard: &ChangeGuard,
+    approvals: &Approvals,
+    admins: &Admins
+) -> Result<HttpResponse, AppError> {
+    let user_id = current.id;
+    
+    if let Some(username) = &user_req.username {
+        if repo.username_taken(username, Some(&user_id)).await.context("Failed to update user")? {
+            return Err(AppError::conflict("This username is already taken"));
+        }
+    }
+    
+    let claimed = match guard.claim(&user_id, user_req) {
+        Ok(claimed) => claimed,
+        Err(violation) => {
+            log::warn!(
+                target: "audit",
+                "Rejected update of user {}: more than {} {} changes per hour",
+                user_id, violation.limit, violation.field
+            );
+            return Err(AppError::TooManyRequests {
+                message: format!("Too many {} changes for this user, try again later", violation.field),
+                retry_after: violation.retry_after,
+            });
+        }
+    };
+    
+    // An email change holds the whole update until a second admin approves it
+    if approvals.required() && Approvals::changes_email(current, user_req) {
+        guard.release(&user_id, user_req, claimed);
+        let admin = admins.acting(req).await?;
+        return held_response(approvals.request_update(&user_id, user_req, &admin).await);
+    }
+    
+    let updated = repo
+        .update(&user_id, user_req)
+        .await
+        .context("Failed to update user")
+        .and_then(|user| user.ok_or_else(|| AppError::not_found("User not found")));
+    match updated {
+        Ok(user) => Ok(resource::json(StatusCode::OK, &user)),
         Err(e) => {
-            error!("Failed to update user {}: {}", user_id, e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to update user"
-            }))
+            guard.release(&user_id, user_req, claimed);
+            Err(e)
         }
     }
 }
 
 // DELETE /users/{id} - Delete a user
 #[delete("/users/{id}")]
-pub async fn delete_user(path: web::Path<Uuid>, repo: web::Data<CachedUserRepository>) -> impl Responder {
-    let user_id = path.into_inner();
-    
-    match repo.delete(&user_id).await {
-        Ok(true) => HttpResponse::NoContent().finish(),
-        Ok(false) => HttpResponse::NotFound().json(serde_json::json!({
-            "error": "User not found"
-        })),
-        Err(e) => {
-            error!("Failed to delete user {}: {}", user_id, e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to delete user"
-            }))
+pub async fn delete_user(
+    req: HttpRequest,
+    path: web::Path<PublicId>,
+    repo: web::Data<dyn UserStore>,
+    demo: web::Data<DemoMode>,
+    approvals: web::Data<Approvals>,
+    admins: web::Data<Admins>
+) -> Result<HttpResponse, AppError> {
+    let user_id = path.into_inner().0;
+    
+    if demo.0 {
+        log::warn!(target: "audit", "Refused to delete user {} on the demo instance", user_id);
+        return Err(demo::forbidden());
+    }
+    
+    // Held for a second admin instead of deleting right away
+    if approvals.required() {
+        let admin = admins.acting(&req).await?;
+        if !repo.exists(&user_id).await.context("Failed to delete user")? {
+            return Err(AppError::not_found("User not found"));
         }
+        return held_response(approvals.request_delete(&user_id, &admin).await);
+    }
+    
+    if !repo.delete(&user_id).await.context("Failed to delete user")? {
+        return Err(AppError::not_found("User not found"));
     }
-}
\ No newline at end of file
+    log::info!(target: "audit", "Deleted user {}", user_id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// POST /users/{id}/restore - Undo a delete, admins only. 404 unless the user
+// exists and is deleted.
+#[post("/users/{id}/restore")]
+pub async fn restore_user(
+    req: HttpRequest,
+    path: web::Path<PublicId>,
+    repo: web::Data<dyn UserStore>,
+    admins: web::Data<Admins>
+) -> Result<HttpResponse, AppError> {
+    let user_id = path.into_inner().0;
+    let admin = admins.acting(&req).await?;
+    
+    let user = repo
+        .restore(&user_id)
+        .await
+        .context("Failed to restore user")?
+        .ok_or_else(|| AppError::not_found("No deleted user with this id"))?;
+    log::info!(target: "audit", "{} restored user {}", admin, user_id);
+    Ok(resource::json(StatusCode::OK, &user))
+}
+
+// DELETE /users - Delete several users by id in one request
+#[delete("/users")]
+pub async fn delete_users(
+    delete_req: web::Json<DeleteUsersRequest>,
+    repo: web::Data<dyn UserStore>,
+    demo: web::Data<DemoMode>,
+    approvals: web::Data<Approvals>
+) -> Result<HttpResponse, AppError> {
+    if delete_req.ids.len() > MAX_BATCH_IDS {
+        return Err(AppError::bad_request(format!("At most {} ids can be deleted at once", MAX_BATCH_IDS)));
+    }
+    
+    if demo.0 {
+        log::warn!(target: "audit", "Refused to delete {} users on the demo instance", delete_req.ids.len());
+        return Err(demo::forbidden());
+    }
+    
+    // Each delete would need its own approval
+    if approvals.required() {
+        return Err(AppError::forbidden("Deletes need approval, send them one at a time through /users/{id}"));
+    }
+    
+    let mut ids = delete_req.into_inner().ids;
+    let mut seen = std::collections::HashSet::new();
+    ids.retain(|id| seen.insert(*id));
+    
+    let deleted = repo.delete_many(&ids).await.context("Failed to delete users")?;
+    for id in &deleted {
+        log::info!(target: "audit", "Deleted user {}", id);
+    }
+    let (deleted, not_found): (Vec<Uuid>, Vec<Uuid>) = ids.into_iter().partition(|id| deleted.contains(id));
+    
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "deleted": deleted.iter().map(public_id::encode).collect::<Vec<_>>(),
+        "not_found": not_found.iter().map(public_id::encode).collect::<Vec<_>>()
+    })))
+}
+
+// The user with this id, or a 404
+async fn find_user(repo: &dyn UserStore, user_id: &Uuid) -> Result<User, AppError> {
+    repo.get_by_id(user_id)
+        .await
+        .context("Failed to retrieve user")?
+        .ok_or_else(|| AppError::not_found("User not found"))
+}
+
+// 202 with the approval request holding a change, or 409 if one is already waiting
+fn held_response(result: Result<Requested, Box<dyn std::error::Error>>) -> Result<HttpResponse, AppError> {
+    Ok(match result.context("Failed to request approval")? {
+        Requested::Created(approval) => resource::json(StatusCode::ACCEPTED, &approval),
+        Requested::AlreadyPending(approval) => problem(
+            StatusCode::CONFLICT,
+            "Conflict",
+            "A change of this kind is already waiting for approval",
+            serde_json::json!({ "approval": approval }),
+        ),
+    })
+}
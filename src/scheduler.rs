@@ -0,0 +1,151 @@
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use std::error::Error as StdError;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::clock::Clock;
+use crate::models::schedule::{ScheduledAction, ScheduledOperation};
+use crate::models::user::UpdateUserRequest;
+use crate::repositories::schedule_repo::ScheduleRepository;
+use crate::repositories::user_repo::CachedUserRepository;
+
+// Due operations claimed per query, a tick keeps claiming until none are left
+const CLAIM_BATCH: i64 = 100;
+
+// Changes to users deferred to a later time, e.g. removing an account at the
+// end of a contract. Operations are stored when scheduled and carried out by a
+// background task once they're due.
+pub struct Scheduler {
+    repo: ScheduleRepository,
+    users: web::Data<CachedUserRepository>,
+    // Decides what's due
+    clock: Arc<dyn Clock>,
+}
+
+impl Scheduler {
+    pub fn new(repo: ScheduleRepository, users: web::Data<CachedUserRepository>, clock: Arc<dyn Clock>) -> Self {
+        Self { repo, users, clock }
+    }
+
+    pub async fn schedule(
+        &self,
+        user_id: &Uuid,
+        action: ScheduledAction,
+        changes: Option<&UpdateUserRequest>,
+        execute_at: &DateTime<Utc>,
+    ) -> Result<ScheduledOperation, Box<dyn StdError>> {
+        let operation = self.repo.create(user_id, action, changes, execute_at).await?;
+        log::info!(
+            target: "audit",
+            "Scheduled {} of user {} for {} ({})",
+            action.as_str(), user_id, execute_at, operation.id
+        );
+        Ok(operation)
+    }
+
+    pub async fn list(&self, user_id: &Uuid) -> Result<Vec<ScheduledOperation>, Box<dyn StdError>> {
+        self.repo.list_for_user(user_id).await
+    }
+
+    pub async fn get(&self, user_id: &Uuid, id: &Uuid) -> Result<Option<ScheduledOperation>, Box<dyn StdError>> {
+        self.repo.get(user_id, id).await
+    }
+
+    // None if the operation isn't pending any more
+    pub async fn cancel(&self, user_id: &Uuid, id: &Uuid) -> Result<Option<ScheduledOperation>, Box<dyn StdError>> {
+        let operation = self.repo.cancel(user_id, id).await?;
+        if let Some(operation) = &operation {
+            log::info!(
+                target: "audit",
+                "Cancelled scheduled {} of user {} ({})",
+                operation.action.as_str(), user_id, operation.id
+            );
+        }
+        Ok(operation)
+    }
+
+    // Carry out every operation that is due
+    pub async fn run_due(&self) {
+        loop {
+            let operations = match self.repo.claim_due(self.clock.now(), CLAIM_BATCH).await {
+                Ok(operations) => operations,
+                Err(e) => {
+                    log::error!("Failed to claim scheduled operations: {}", e);
+                    return;
+                }
+            };
+            let claimed = operations.len();
+
+            for operation in operations {
+                let error = match self.execute(&operation).await {
+                    Ok(()) => {
+                        log::info!(
+                            target: "audit",
+                            "Carried out scheduled {} of user {} ({})",
+                            operation.action.as_str(), operation.user_id, operation.id
+                        );
+                        None
+                    }
+                    Err(e) => {
+                        log::warn!(
+                            target: "audit",
+                            "Scheduled {} of user {} failed ({}): {}",
+                            operation.action.as_str(), operation.user_id, operation.id, e
+                        );
+                        Some(e.to_string())
+                    }
+                };
+                if let Err(e) = self.repo.finish(&operation.id, error.as_deref()).await {
+                    log::error!("Failed to record outcome of scheduled operation {}: {}", operation.id, e);
+                }
+            }
+
+            if claimed < CLAIM_BATCH as usize {
+                return;
+            }
+        }
+    }
+
+    async fn execute(&self, operation: &ScheduledOperation) -> Result<(), Box<dyn StdError>> {
+        let user_id = &operation.user_id;
+        match (operation.action, &operation.changes) {
+            (ScheduledAction::Delete, _) => {
+                if !self.users.delete(user_id).await? {
+                    return Err("User not found".into());
+                }
+            }
+            (ScheduledAction::Update, Some(changes)) => {
+                // Checked again here, the values may have been taken since scheduling
+                if let Some(email) = &changes.email {
+                    let owner = self.users.get_by_email(email).await?;
+                    if owner.is_some_and(|owner| owner.id != *user_id) {
+                        return Err("The new email belongs to another user".into());
+                    }
+                }
+                if let Some(username) = &changes.username {
+                    if self.users.username_taken(username, Some(user_id)).await? {
+                        return Err("The new username is already taken".into());
+                    }
+                }
+                if self.users.update(user_id, changes).await?.is_none() {
+                    return Err("User not found".into());
+                }
+            }
+            (ScheduledAction::Update, None) => return Err("No changes to apply".into()),
+        }
+        Ok(())
+    }
+
+    // Check for due operations every `interval` for as long as the server runs
+    pub fn spawn(scheduler: web::Data<Scheduler>, interval: Duration) {
+        actix_web::rt::spawn(async move {
+            let mut ticker = actix_web::rt::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                scheduler.run_due().await;
+            }
+        });
+    }
+}
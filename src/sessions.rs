@@ -0,0 +1,322 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::cookie::{time, Cookie, SameSite};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, HttpMessage};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::error::Error as StdError;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+use uuid::Uuid;
+
+use crate::api_keys;
+use crate::models::user::User;
+use crate::repositories::user_repo::CachedUserRepository;
+use crate::clock::Clock;
+
+pub const COOKIE: &str = "session";
+// Bound on a whole Redis round trip, connecting included
+const REDIS_TIMEOUT: Duration = Duration::from_secs(2);
+// Connections kept open between requests
+const MAX_IDLE: usize = 8;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub user_id: Uuid,
+    pub created_at: u64,
+}
+
+// Where server-side sessions live. Ids are the secret cookie values, stores
+// decide how to keep them; sessions disappear on their own after `ttl_secs`.
+pub trait SessionStore: Send + Sync {
+    fn create<'a>(&'a self, session: &'a Session, ttl_secs: u64) -> LocalBoxFuture<'a, Result<String, Box<dyn StdError>>>;
+    fn load<'a>(&'a self, id: &'a str) -> LocalBoxFuture<'a, Result<Option<Session>, Box<dyn StdError>>>;
+    fn destroy<'a>(&'a self, id: &'a str) -> LocalBoxFuture<'a, Result<(), Box<dyn StdError>>>;
+}
+
+// Connection details from a URL like `redis://:password@localhost:6379/0`
+#[derive(Debug, Clone)]
+pub struct RedisConfig {
+    addr: String,
+    username: Option<String>,
+    password: Option<String>,
+    db: Option<u32>,
+}
+
+impl RedisConfig {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let url = reqwest::Url::parse(url).map_err(|e| format!("Invalid REDIS_URL: {}", e))?;
+        if url.scheme() != "redis" {
+            return Err(format!("REDIS_URL must start with redis://, got {}://", url.scheme()));
+        }
+        let host = url.host_str().filter(|host| !host.is_empty()).ok_or("REDIS_URL has no host")?;
+        let db = match url.path().trim_start_matches('/') {
+            "" => None,
+            db => Some(db.parse().map_err(|_| format!("Invalid database number in REDIS_URL: {}", db))?),
+        };
+
+        Ok(Self {
+            addr: format!("{}:{}", host, url.port().unwrap_or(6379)),
+            username: Some(url.username()).filter(|username| !username.is_empty()).map(str::to_string),
+            password: url.password().map(str::to_string),
+            db,
+        })
+    }
+}
+
+enum Reply {
+    Nil,
+    Status,
+    Integer,
+    Bulk(Vec<u8>),
+}
+
+// Speaks just enough RESP for the string commands sessions need
+pub struct RedisSessionStore {
+    config: RedisConfig,
+    idle: Mutex<Vec<BufStream<TcpStream>>>,
+}
+
+impl RedisSessionStore {
+    pub fn new(config: RedisConfig) -> Self {
+        Self { config, idle: Mutex::new(Vec::new()) }
+    }
+
+    // Stored under the hash of the id, so the keys in Redis can't be used as cookies
+    fn key(id: &str) -> String {
+        format!("session:{}", URL_SAFE_NO_PAD.encode(api_keys::hash(id)))
+    }
+
+    async fn command(&self, args: &[&[u8]]) -> Result<Reply, Box<dyn StdError>> {
+        let reply = tokio::time::timeout(REDIS_TIMEOUT, async {
+            // An idle connection may have been closed by the server, that one gets a second try
+            let idle = self.idle.lock().pop();
+            if let Some(mut conn) = idle {
+                if let Ok(reply) = round_trip(&mut conn, args).await {
+                    return Ok((conn, reply));
+                }
+            }
+            let mut conn = self.connect().await?;
+            let reply = round_trip(&mut conn, args).await?;
+            Ok::<_, Box<dyn StdError>>((conn, reply))
+        })
+        .await
+        .map_err(|_| "Redis did not answer in time")?;
+
+        let (conn, reply) = reply?;
+        let mut idle = self.idle.lock();
+        if idle.len() < MAX_IDLE {
+            idle.push(conn);
+        }
+        reply
+    }
+
+    async fn connect(&self) -> Result<BufStream<TcpStream>, Box<dyn StdError>> {
+        let mut conn = BufStream::new(TcpStream::connect(&self.config.addr).await?);
+        if let Some(password) = &self.config.password {
+            match &self.config.username {
+                Some(username) => round_trip(&mut conn, &[b"AUTH", username.as_bytes(), password.as_bytes()]).await??,
+                None => round_trip(&mut conn, &[b"AUTH", password.as_bytes()]).await??,
+            };
+        }
+        if let Some(db) = self.config.db {
+            round_trip(&mut conn, &[b"SELECT", db.to_string().as_bytes()]).await??;
+        }
+        Ok(conn)
+    }
+}
+
+// Send one command and read its reply. The outer error is the connection
+// failing, the inner one an error reply, after which the connection is still usable.
+async fn round_trip(conn: &mut BufStream<TcpStream>, args: &[&[u8]]) -> Result<Result<Reply, Box<dyn StdError>>, Box<dyn StdError>> {
+    let mut request = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        request.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        request.extend_from_slice(arg);
+        request.extend_from_slice(b"\r\n");
+    }
+    conn.write_all(&request).await?;
+    conn.flush().await?;
+
+    let mut line = String::new();
+    if conn.read_line(&mut line).await? == 0 {
+        return Err("Redis closed the connection".into());
+    }
+    let line = line.trim_end_matches("\r\n");
+    let (kind, rest) = line.split_at_checked(1).ok_or("Empty reply from Redis")?;
+    match kind {
+        "+" => Ok(Ok(Reply::Status)),
+        "-" => Ok(Err(format!("Redis: {}", rest).into())),
+        ":" => Ok(Ok(Reply::Integer)),
+        "$" => {
+            let len: i64 = rest.parse()?;
+            if len < 0 {
+                return Ok(Ok(Reply::Nil));
+            }
+            let mut value = vec![0; len as usize + 2];
+            conn.read_exact(&mut value).await?;
+            value.truncate(len as usize);
+            Ok(Ok(Reply::Bulk(value)))
+        }
+        _ => Err(format!("Unexpected reply from Redis: {}", line).into()),
+    }
+}
+
+impl SessionStore for RedisSessionStore {
+    fn create<'a>(&'a self, session: &'a Session, ttl_secs: u64) -> LocalBoxFuture<'a, Result<String, Box<dyn StdError>>> {
+        Box::pin(async move {
+            let id = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+            let value = serde_json::to_vec(session)?;
+            match self.command(&[b"SET", Self::key(&id).as_bytes(), &value, b"EX", ttl_secs.to_string().as_bytes()]).await? {
+                Reply::Status => Ok(id),
+                _ => Err("Unexpected reply to SET from Redis".into()),
+            }
+        })
+    }
+
+    fn load<'a>(&'a self, id: &'a str) -> LocalBoxFuture<'a, Result<Option<Session>, Box<dyn StdError>>> {
+        Box::pin(async move {
+            match self.command(&[b"GET", Self::key(id).as_bytes()]).await? {
+                Reply::Nil => Ok(None),
+                Reply::Bulk(value) => Ok(Some(serde_json::from_slice(&value)?)),
+                _ => Err("Unexpected reply to GET from Redis".into()),
+            }
+        })
+    }
+
+    fn destroy<'a>(&'a self, id: &'a str) -> LocalBoxFuture<'a, Result<(), Box<dyn StdError>>> {
+        Box::pin(async move {
+            match self.command(&[b"DEL", Self::key(id).as_bytes()]).await? {
+                Reply::Integer => Ok(()),
+                _ => Err("Unexpected reply to DEL from Redis".into()),
+            }
+        })
+    }
+}
+
+// Server-side sessions as an alternative to bearer tokens, off without a store
+pub struct Sessions {
+    store: Option<Box<dyn SessionStore>>,
+    // Only send the cookie over HTTPS when the service is reached that way
+    secure: bool,
+    clock: Arc<dyn Clock>,
+}
+
+impl Sessions {
+    pub fn new(store: Option<Box<dyn SessionStore>>, secure: bool, clock: Arc<dyn Clock>) -> Self {
+        Self { store, secure, clock }
+    }
+
+    // A session cookie for a user who just logged in, None without a store
+    pub async fn start(&self, user_id: &Uuid, ttl_secs: u64) -> Result<Option<Cookie<'static>>, Box<dyn StdError>> {
+        let Some(store) = &self.store else { return Ok(None) };
+
+        let session = Session { user_id: *user_id, created_at: self.clock.unix_now() };
+        let id = store.create(&session, ttl_secs).await?;
+        Ok(Some(
+            Cookie::build(COOKIE, id)
+                .path("/")
+                .http_only(true)
+                .secure(self.secure)
+                .same_site(SameSite::Lax)
+                .max_age(time::Duration::seconds(ttl_secs as i64))
+                .finish(),
+        ))
+    }
+
+    pub async fn load(&self, id: &str) -> Result<Option<Session>, Box<dyn StdError>> {
+        match &self.store {
+            Some(store) => store.load(id).await,
+            None => Ok(None),
+        }
+    }
+
+    pub async fn end(&self, id: &str) -> Result<(), Box<dyn StdError>> {
+        match &self.store {
+            Some(store) => store.destroy(id).await,
+            None => Ok(()),
+        }
+    }
+}
+
+// Loads the session of the session cookie and puts its user in the request
+// extensions, where RoleGuard and /auth/session find it. A store that can't be
+// reached is logged and the request goes on without a session.
+#[derive(Clone)]
+pub struct SessionLoader {
+    sessions: web::Data<Sessions>,
+    users: web::Data<CachedUserRepository>,
+}
+
+impl SessionLoader {
+    pub fn new(sessions: web::Data<Sessions>, users: web::Data<CachedUserRepository>) -> Self {
+        Self { sessions, users }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for SessionLoader
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Transform = SessionLoaderMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SessionLoaderMiddleware {
+            service: Rc::new(service),
+            loader: self.clone(),
+        }))
+    }
+}
+
+pub struct SessionLoaderMiddleware<S> {
+    service: Rc<S>,
+    loader: SessionLoader,
+}
+
+impl<S, B> Service<ServiceRequest> for SessionLoaderMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let cookie = match req.cookie(COOKIE) {
+            Some(cookie) if self.loader.sessions.store.is_some() => cookie,
+            _ => return Box::pin(async move { Ok(service.call(req).await?.map_into_boxed_body()) }),
+        };
+
+        let loader = self.loader.clone();
+        Box::pin(async move {
+            let user = match loader.sessions.load(cookie.value()).await {
+                Ok(Some(session)) => loader.users.get_by_id(&session.user_id).await,
+                Ok(None) => Ok(None),
+                Err(e) => Err(e),
+            };
+            match user {
+                Ok(Some(user)) => {
+                    req.extensions_mut().insert::<User>(user);
+                }
+                Ok(None) => {}
+                Err(e) => log::error!("Failed to load session: {}", e),
+            }
+            Ok(service.call(req).await?.map_into_boxed_body())
+        })
+    }
+}
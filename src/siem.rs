@@ -0,0 +1,352 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::kv::{self, Key, VisitSource};
+use log::{Level, Log, Metadata, Record};
+use std::error::Error as StdError;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::egress;
+use crate::request_id;
+
+// Log target of the security-relevant events that are forwarded
+const AUDIT_TARGET: &str = "audit";
+// Events buffered in memory before new ones are dropped
+const QUEUE_CAPACITY: usize = 10_000;
+const BATCH_SIZE: usize = 100;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+// Once the spool is this large further events are dropped rather than filling the disk
+const MAX_SPOOL_BYTES: u64 = 64 * 1024 * 1024;
+
+static EVENTS: OnceLock<mpsc::Sender<Event>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    // One JSON object per line, for log aggregators
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SiemFormat {
+    Cef,
+    Json,
+}
+
+#[derive(Debug, Clone)]
+pub enum SiemTarget {
+    // Batches are POSTed, as a JSON array or newline-separated CEF
+    Http(String),
+    // host:port of a syslog receiver taking newline-framed messages over TCP
+    Syslog(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct SiemConfig {
+    pub target: SiemTarget,
+    pub format: SiemFormat,
+    pub spool_path: PathBuf,
+}
+
+struct Event {
+    time: DateTime<Utc>,
+    level: Level,
+    message: String,
+    request_id: Option<String>,
+}
+
+// Wraps the regular logger and additionally queues `audit` records for the
+// forwarder. Until the forwarder is started they are only logged.
+struct AuditTap {
+    inner: env_logger::Logger,
+}
+
+impl Log for AuditTap {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata) || metadata.target() == AUDIT_TARGET
+    }
+
+    fn log(&self, record: &Record) {
+        self.inner.log(record);
+
+        if record.target() != AUDIT_TARGET {
+            return;
+        }
+        if let Some(events) = EVENTS.get() {
+            let event = Event {
+                time: Utc::now(),
+                level: record.level(),
+                message: record.args().to_string(),
+                request_id: request_id::current(),
+            };
+            if events.try_send(event).is_err() {
+                // Can't log this under the audit target without recursing
+                self.inner.log(
+                    &Record::builder()
+                        .level(Level::Warn)
+                        .target(module_path!())
+                        .args(format_args!("SIEM queue full, dropped an audit event"))
+                        .build(),
+                );
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+// Install the process logger. Audit events are always captured, whatever RUST_LOG says.
+pub fn init_logger(env: env_logger::Env, format: LogFormat) {
+    let mut builder = env_logger::Builder::from_env(env);
+    match format {
+        // The default format, plus the id of the request a line was logged for
+        LogFormat::Text => builder.format(|buf, record| {
+            let level = buf.default_styled_level(record.level());
+            match request_id::current() {
+                Some(id) => writeln!(buf, "[{} {:<5} {} request_id={}] {}", buf.timestamp(), level, record.target(), id, record.args()),
+                None => writeln!(buf, "[{} {:<5} {}] {}", buf.timestamp(), level, record.target(), record.args()),
+            }
+        }),
+        LogFormat::Json => builder.format(|buf, record| writeln!(buf, "{}", json_record(record))),
+    };
+    let inner = builder.build();
+    let max_level = inner.filter().max(log::LevelFilter::Info);
+    if log::set_boxed_logger(Box::new(AuditTap { inner })).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+// timestamp, level, target, message and the request id, plus the record's
+// key-values, like the route and latency of access log records
+fn json_record(record: &Record) -> serde_json::Value {
+    let mut fields = serde_json::Map::new();
+    fields.insert("timestamp".into(), Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true).into());
+    fields.insert("level".into(), record.level().as_str().into());
+    fields.insert("target".into(), record.target().into());
+    fields.insert("message".into(), record.args().to_string().into());
+    if let Some(id) = request_id::current() {
+        fields.insert("request_id".into(), id.into());
+    }
+    let _ = record.key_values().visit(&mut Fields(&mut fields));
+    serde_json::Value::Object(fields)
+}
+
+struct Fields<'a>(&'a mut serde_json::Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        // Numbers and booleans stay JSON numbers and booleans
+        let value = if let Some(n) = value.to_u64() {
+            n.into()
+        } else if let Some(n) = value.to_i64() {
+            n.into()
+        } else if let Some(n) = value.to_f64() {
+            n.into()
+        } else if let Some(b) = value.to_bool() {
+            b.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.insert(key.as_str().to_string(), value);
+        Ok(())
+    }
+}
+
+// Start forwarding audit events. Events are sent in batches; when the SIEM
+// can't be reached they are appended to the spool file and resent, oldest
+// first, once it is back.
+pub fn spawn_forwarder(config: SiemConfig) {
+    let (sender, mut receiver) = mpsc::channel(QUEUE_CAPACITY);
+    if EVENTS.set(sender).is_err() {
+        return;
+    }
+
+    actix_web::rt::spawn(async move {
+        let forwarder = Forwarder {
+            client: egress::client(),
+            config,
+        };
+        let mut batch: Vec<String> = Vec::with_capacity(BATCH_SIZE);
+        let mut ticker = actix_web::rt::time::interval(FLUSH_INTERVAL);
+
+        loop {
+            tokio::select! {
+                event = receiver.recv() => match event {
+                    Some(event) => {
+                        batch.push(forwarder.format(&event));
+                        if batch.len() < BATCH_SIZE {
+                            continue;
+                        }
+                    }
+                    None => break,
+                },
+                _ = ticker.tick() => {}
+            }
+            forwarder.flush(std::mem::take(&mut batch)).await;
+        }
+    });
+}
+
+struct Forwarder {
+    client: reqwest::Client,
+    config: SiemConfig,
+}
+
+impl Forwarder {
+    // Deliver whatever was spooled earlier, then this batch. Anything that can't
+    // be delivered ends up in the spool.
+    async fn flush(&self, batch: Vec<String>) {
+        let result = match self.drain_spool().await {
+            Ok(()) if batch.is_empty() => return,
+            Ok(()) => self.send(&batch).await,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Err(e) if !batch.is_empty() => {
+                log::warn!("SIEM unreachable, spooling {} events: {}", batch.len(), e);
+                self.spool(&batch);
+            }
+            Err(e) => log::debug!("SIEM still unreachable: {}", e),
+            Ok(()) => {}
+        }
+    }
+
+    async fn drain_spool(&self) -> Result<(), Box<dyn StdError>> {
+        let contents = match tokio::fs::read_to_string(&self.config.spool_path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        let lines: Vec<String> = contents.lines().map(str::to_string).collect();
+        for chunk in lines.chunks(BATCH_SIZE) {
+            self.send(chunk).await?;
+        }
+        // A failure part way through resends the earlier chunks, duplicates beat gaps
+        tokio::fs::remove_file(&self.config.spool_path).await?;
+        log::info!("Delivered {} spooled SIEM events", lines.len());
+        Ok(())
+    }
+
+    fn spool(&self, batch: &[String]) {
+        let path = &self.config.spool_path;
+        let size = std::fs::metadata(path).map_or(0, |m| m.len());
+        if size >= MAX_SPOOL_BYTES {
+            log::error!("SIEM spool {} is full, dropping {} events", path.display(), batch.len());
+            return;
+        }
+
+        let result = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(format!("{}\n", batch.join("\n")).as_bytes()));
+        if let Err(e) = result {
+            log::error!("Failed to spool {} SIEM events to {}: {}", batch.len(), path.display(), e);
+        }
+    }
+
+    async fn send(&self, lines: &[String]) -> Result<(), Box<dyn StdError>> {
+        match &self.config.target {
+            SiemTarget::Http(url) => {
+                let request = self.client.post(url).timeout(SEND_TIMEOUT);
+                let request = match self.config.format {
+                    SiemFormat::Json => request
+                        .header("Content-Type", "application/json")
+                        .body(format!("[{}]", lines.join(","))),
+                    SiemFormat::Cef => request
+                        .header("Content-Type", "text/plain")
+                        .body(lines.join("\n")),
+                };
+                request.send().await?.error_for_status()?;
+            }
+            SiemTarget::Syslog(addr) => {
+                let send = async {
+                    let mut stream = tokio::net::TcpStream::connect(addr).await?;
+                    for line in lines {
+                        stream.write_all(line.as_bytes()).await?;
+                        stream.write_all(b"\n").await?;
+                    }
+                    stream.shutdown().await
+                };
+                tokio::time::timeout(SEND_TIMEOUT, send).await??;
+            }
+        }
+        Ok(())
+    }
+
+    fn format(&self, event: &Event) -> String {
+        let body = match self.config.format {
+            SiemFormat::Json => serde_json::json!({
+                "timestamp": event.time.to_rfc3339_opts(SecondsFormat::Millis, true),
+                "product": env!("CARGO_PKG_NAME"),
+                "severity": event.level.as_str().to_lowercase(),
+                "message": event.message,
+                "request_id": event.request_id,
+            })
+            .to_string(),
+            SiemFormat::Cef => cef(event),
+        };
+
+        match self.config.target {
+            SiemTarget::Http(_) => body,
+            // RFC 5424 header, facility 13 (log audit)
+            SiemTarget::Syslog(_) => format!(
+                "<{}>1 {} - {} - - - {}",
+                13 * 8 + syslog_severity(event.level),
+                event.time.to_rfc3339_opts(SecondsFormat::Millis, true),
+                env!("CARGO_PKG_NAME"),
+                body
+            ),
+        }
+    }
+}
+
+fn cef(event: &Event) -> String {
+    let (signature, name, severity) = match event.level {
+        Level::Error => ("audit-error", "Audit failure", 8),
+        Level::Warn => ("audit-warning", "Audit warning", 6),
+        _ => ("audit", "Audit event", 3),
+    };
+
+    let mut extension = format!("rt={} msg={}", event.time.timestamp_millis(), cef_value(&event.message));
+    if let Some(id) = &event.request_id {
+        extension.push_str(&format!(" cs1Label=requestId cs1={}", cef_value(id)));
+    }
+
+    format!(
+        "CEF:0|{}|{}|{}|{}|{}|{}|{}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        signature,
+        name,
+        severity,
+        extension
+    )
+}
+
+// Escaping for CEF extension values: backslash, equals and line breaks
+fn cef_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+fn syslog_severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
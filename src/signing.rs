@@ -0,0 +1,159 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::clock::Clock;
+use crate::models::public_id;
+use crate::models::user::User;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Signs short messages such as links with HMAC-SHA256. Expiring links and
+// tokens are checked against the clock.
+pub struct Signer {
+    key: Vec<u8>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Signer {
+    pub fn new(key: Vec<u8>, clock: Arc<dyn Clock>) -> Self {
+        Self { key, clock }
+    }
+
+    pub fn sign(&self, message: &str) -> String {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(message.as_bytes());
+        URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    }
+
+    // Constant-time check of a signature produced by `sign`
+    pub fn verify(&self, message: &str, signature: &str) -> bool {
+        let signature = match URL_SAFE_NO_PAD.decode(signature) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(message.as_bytes());
+        mac.verify_slice(&signature).is_ok()
+    }
+
+    // Expiring link that proves the holder was given it by this service
+    pub fn verification_url(&self, base_url: &str, id: &Uuid, ttl_secs: u64) -> String {
+        let expires = self.clock.unix_now() + ttl_secs;
+        let signature = self.sign(&verification_message(id, expires));
+        format!("{}/users/{}/verify?exp={}&sig={}", base_url, public_id::encode(id), expires, signature)
+    }
+
+    pub fn verify_link(&self, id: &Uuid, expires: u64, signature: &str) -> bool {
+        expires >= self.clock.unix_now() && self.verify(&verification_message(id, expires), signature)
+    }
+
+    // Expiring download link for a finished export
+    pub fn export_url(&self, base_url: &str, id: &Uuid, ttl_secs: u64) -> String {
+        let expires = self.clock.unix_now() + ttl_secs;
+        let signature = self.sign(&export_message(id, expires));
+        format!("{}/users/exports/{}/download?exp={}&sig={}", base_url, id, expires, signature)
+    }
+
+    pub fn verify_export_link(&self, id: &Uuid, expires: u64, signature: &str) -> bool {
+        expires >= self.clock.unix_now() && self.verify(&export_message(id, expires), signature)
+    }
+
+    // Bearer token for a logged-in user, `{user id}.{expires}.{signature}`.
+    // Returns it with the unix time it expires at.
+    pub fn session_token(&self, user_id: &Uuid, ttl_secs: u64) -> (String, u64) {
+        let expires = self.clock.unix_now() + ttl_secs;
+        let signature = self.sign(&session_message(user_id, expires));
+        (format!("{}.{}.{}", user_id, expires, signature), expires)
+    }
+
+    // The user a session token belongs to, None if it's forged or expired
+    pub fn verify_session(&self, token: &str) -> Option<Uuid> {
+        let mut parts = token.splitn(3, '.');
+        let user_id = parts.next()?.parse().ok()?;
+        let expires = parts.next()?.parse().ok()?;
+        let signature = parts.next()?;
+        (expires >= self.clock.unix_now() && self.verify(&session_message(&user_id, expires), signature)).then_some(user_id)
+    }
+
+    // HS256 JWT for a password login. The role claim is informational, access
+    // checks look up the user's current role.
+    pub fn jwt(&self, user: &User, ttl_secs: u64) -> (String, u64) {
+        let issued = self.clock.unix_now();
+        let expires = issued + ttl_secs;
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#);
+        let claims = serde_json::json!({ "sub": user.id, "role": user.role, "iat": issued, "exp": expires });
+        let signed = format!("{}.{}", header, URL_SAFE_NO_PAD.encode(claims.to_string()));
+        (format!("{}.{}", signed, self.sign(&signed)), expires)
+    }
+
+    // The user a JWT from `jwt` belongs to, None if it's forged, expired or not HS256
+    pub fn verify_jwt(&self, token: &str) -> Option<Uuid> {
+        let (signed, signature) = token.rsplit_once('.')?;
+        let (header, claims) = signed.split_once('.')?;
+        let header: serde_json::Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header).ok()?).ok()?;
+        if header["alg"] != "HS256" || !self.verify(signed, signature) {
+            return None;
+        }
+
+        let claims: serde_json::Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(claims).ok()?).ok()?;
+        if claims["exp"].as_u64()? < self.clock.unix_now() {
+            return None;
+        }
+        claims["sub"].as_str()?.parse().ok()
+    }
+}
+
+fn session_message(user_id: &Uuid, expires: u64) -> String {
+    format!("session:{}:{}", user_id, expires)
+}
+
+fn verification_message(id: &Uuid, expires: u64) -> String {
+    format!("verify:{}:{}", id, expires)
+}
+
+fn export_message(id: &Uuid, expires: u64) -> String {
+    format!("export:{}:{}", id, expires)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use chrono::{Duration, TimeZone, Utc};
+
+    fn signer() -> (Signer, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(Utc.with_ymd_and_hms(2026, 1, 1, 12, 0, 0).unwrap()));
+        (Signer::new(b"test key".to_vec(), clock.clone()), clock)
+    }
+
+    #[test]
+    fn verification_link_expires_after_its_ttl() {
+        let (signer, clock) = signer();
+        let id = Uuid::from_u128(1);
+        let expires = clock.unix_now() + 60;
+        let signature = signer.sign(&verification_message(&id, expires));
+
+        assert!(signer.verify_link(&id, expires, &signature));
+        clock.advance(Duration::seconds(60));
+        assert!(signer.verify_link(&id, expires, &signature));
+        clock.advance(Duration::seconds(1));
+        assert!(!signer.verify_link(&id, expires, &signature));
+    }
+
+    #[test]
+    fn session_token_expires_after_its_ttl() {
+        let (signer, clock) = signer();
+        let id = Uuid::from_u128(2);
+        let (token, expires) = signer.session_token(&id, 3600);
+
+        assert_eq!(expires, clock.unix_now() + 3600);
+        assert_eq!(signer.verify_session(&token), Some(id));
+        clock.advance(Duration::seconds(3601));
+        assert_eq!(signer.verify_session(&token), None);
+    }
+}
@@ -0,0 +1,215 @@
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+use std::error::Error as StdError;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::api_keys::API_KEY_HEADER;
+use crate::egress;
+
+const USAGE: &str = "Usage: hello_world smoke --base-url <url> [--api-key <key>] [--timeout-secs <secs>]";
+
+pub struct Options {
+    pub base_url: String,
+    // Also read from SMOKE_API_KEY, to keep it out of the process list
+    pub api_key: Option<String>,
+    pub timeout: Duration,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Self, Box<dyn StdError>> {
+        let mut base_url = None;
+        let mut api_key = std::env::var("SMOKE_API_KEY").ok().filter(|key| !key.is_empty());
+        let mut timeout = Duration::from_secs(10);
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().cloned().ok_or(USAGE);
+            match arg.as_str() {
+                "--base-url" => base_url = Some(value()?),
+                "--api-key" => api_key = Some(value()?),
+                "--timeout-secs" => timeout = Duration::from_secs(value()?.parse().map_err(|_| USAGE)?),
+                _ => return Err(USAGE.into()),
+            }
+        }
+        let base_url = base_url.ok_or(USAGE)?;
+        reqwest::Url::parse(&base_url).map_err(|e| format!("--base-url must be a URL, got {}: {}", base_url, e))?;
+        Ok(Self { base_url: base_url.trim_end_matches('/').to_string(), api_key, timeout })
+    }
+}
+
+enum Outcome {
+    Pass,
+    Fail(String),
+    // An earlier step it needs failed
+    Skip,
+}
+
+struct Step {
+    name: &'static str,
+    outcome: Outcome,
+    elapsed: Duration,
+}
+
+pub struct Report {
+    steps: Vec<Step>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.steps.iter().all(|step| matches!(step.outcome, Outcome::Pass))
+    }
+
+    pub fn print(&self) {
+        for step in &self.steps {
+            let elapsed = format!("{}ms", step.elapsed.as_millis());
+            match &step.outcome {
+                Outcome::Pass => println!("PASS  {:<8} {:>7}", step.name, elapsed),
+                Outcome::Fail(reason) => println!("FAIL  {:<8} {:>7}  {}", step.name, elapsed, reason),
+                Outcome::Skip => println!("SKIP  {}", step.name),
+            }
+        }
+        let count = |pass: fn(&Outcome) -> bool| self.steps.iter().filter(|step| pass(&step.outcome)).count();
+        println!(
+            "{} passed, {} failed, {} skipped",
+            count(|outcome| matches!(outcome, Outcome::Pass)),
+            count(|outcome| matches!(outcome, Outcome::Fail(_))),
+            count(|outcome| matches!(outcome, Outcome::Skip)),
+        );
+    }
+}
+
+struct Target {
+    client: reqwest::Client,
+    options: Options,
+}
+
+impl Target {
+    async fn request(&self, method: Method, path: &str, body: Option<&Value>) -> Result<(StatusCode, Value), String> {
+        let mut request = self
+            .client
+            .request(method, format!("{}{}", self.options.base_url, path))
+            .timeout(self.options.timeout)
+            .header("Accept", "application/json");
+        if let Some(key) = &self.options.api_key {
+            request = request.header(API_KEY_HEADER, key);
+        }
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        let status = response.status();
+        let text = response.text().await.map_err(|e| e.to_string())?;
+        // Error bodies and 204s aren't always JSON, keep them readable in the report
+        let body = serde_json::from_str(&text).unwrap_or(Value::String(text));
+        Ok((status, body))
+    }
+
+    // Request and check the status, answering with the body
+    async fn expect(&self, method: Method, path: &str, body: Option<&Value>, expected: StatusCode) -> Result<Value, String> {
+        let (status, body) = self.request(method.clone(), path, body).await?;
+        if status != expected {
+            return Err(format!("{} {} answered {}, expected {}: {}", method, path, status.as_u16(), expected.as_u16(), body));
+        }
+        Ok(body)
+    }
+}
+
+async fn step<T>(steps: &mut Vec<Step>, name: &'static str, check: impl Future<Output = Result<T, String>>) -> Option<T> {
+    let started = Instant::now();
+    let result = check.await;
+    let outcome = match &result {
+        Ok(_) => Outcome::Pass,
+        Err(reason) => Outcome::Fail(reason.clone()),
+    };
+    steps.push(Step { name, outcome, elapsed: started.elapsed() });
+    result.ok()
+}
+
+fn skip(steps: &mut Vec<Step>, name: &'static str) {
+    steps.push(Step { name, outcome: Outcome::Skip, elapsed: Duration::ZERO });
+}
+
+fn field<'a>(body: &'a Value, name: &str) -> Result<&'a Value, String> {
+    body.get(name).ok_or_else(|| format!("response has no {}: {}", name, body))
+}
+
+fn expect_field(body: &Value, name: &str, expected: &Value) -> Result<(), String> {
+    let actual = field(body, name)?;
+    if actual != expected {
+        return Err(format!("{} is {}, expected {}", name, actual, expected));
+    }
+    Ok(())
+}
+
+// `hello_world smoke`: the scripted check the deploy pipeline runs against a fresh
+// release. It creates a throwaway user, reads, updates, lists and deletes it, and
+// the user is deleted again even when a step in between failed.
+pub async fn run(options: Options) -> Report {
+    let target = Target { client: egress::client(), options };
+    let mut steps = Vec::new();
+
+    step(&mut steps, "health", async {
+        let body = target.expect(Method::GET, "/health", None, StatusCode::OK).await?;
+        expect_field(&body, "status", &json!("ok"))
+    })
+    .await;
+
+    let email = format!("smoke-{}@example.com", Uuid::new_v4().simple());
+    let create = json!({ "name": "Smoke Test", "email": email, "age": 41 });
+    let created = step(&mut steps, "create", async {
+        let body = target.expect(Method::POST, "/users", Some(&create), StatusCode::CREATED).await?;
+        expect_field(&body, "email", &json!(email))?;
+        field(&body, "id")?.as_str().map(str::to_string).ok_or_else(|| format!("id is not a string: {}", body))
+    })
+    .await;
+    let Some(id) = created else {
+        for name in ["get", "update", "list", "delete", "gone"] {
+            skip(&mut steps, name);
+        }
+        return Report { steps };
+    };
+    let path = format!("/users/{}", id);
+
+    step(&mut steps, "get", async {
+        let body = target.expect(Method::GET, &path, None, StatusCode::OK).await?;
+        expect_field(&body, "email", &json!(email))?;
+        expect_field(&body, "age", &json!(41))
+    })
+    .await;
+
+    // Made against the version a new user starts at
+    let update = json!({ "name": "Smoke Test Updated", "age": 42, "version": 1 });
+    let updated = step(&mut steps, "update", async {
+        let body = target.expect(Method::PUT, &path, Some(&update), StatusCode::OK).await?;
+        expect_field(&body, "name", &json!("Smoke Test Updated"))?;
+        expect_field(&body, "age", &json!(42))
+    })
+    .await;
+
+    // Only the updated user matches all of these
+    if updated.is_some() {
+        step(&mut steps, "list", async {
+            let query = format!("/users?email={}&min_age=42&max_age=42&name_contains=updated", email);
+            let body = target.expect(Method::GET, &query, None, StatusCode::OK).await?;
+            let users = body.as_array().ok_or_else(|| format!("expected a list: {}", body))?;
+            let ids: Vec<&Value> = users.iter().filter_map(|user| user.get("id")).collect();
+            if ids != [&json!(id)] {
+                return Err(format!("expected only the smoke user {}, got {:?}", id, ids));
+            }
+            Ok(())
+        })
+        .await;
+    } else {
+        skip(&mut steps, "list");
+    }
+
+    let deleted = step(&mut steps, "delete", target.expect(Method::DELETE, &path, None, StatusCode::NO_CONTENT)).await;
+    if deleted.is_some() {
+        step(&mut steps, "gone", target.expect(Method::GET, &path, None, StatusCode::NOT_FOUND)).await;
+    } else {
+        skip(&mut steps, "gone");
+    }
+
+    Report { steps }
+}
@@ -0,0 +1,164 @@
+use deadpool_postgres::Pool;
+use futures_util::future::{join_all, LocalBoxFuture};
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+// Connections opened ahead of the first requests
+const WARM_CONNECTIONS: usize = 4;
+
+type TaskFuture = LocalBoxFuture<'static, Result<(), Box<dyn StdError>>>;
+
+struct Task {
+    name: &'static str,
+    after: &'static [&'static str],
+    future: TaskFuture,
+}
+
+#[derive(Debug)]
+pub enum StartupError {
+    // A dependency on a task that doesn't exist, or a cycle
+    InvalidGraph(String),
+    Failed {
+        task: &'static str,
+        error: String,
+        // Were running and got dropped when the task failed
+        cancelled: Vec<&'static str>,
+        // Were waiting on the failed task or one of the cancelled ones
+        not_started: Vec<&'static str>,
+    },
+}
+
+impl fmt::Display for StartupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidGraph(reason) => write!(f, "Invalid startup task graph: {}", reason),
+            Self::Failed { task, error, cancelled, not_started } => {
+                write!(f, "Startup task {} failed: {}", task, error)?;
+                if !cancelled.is_empty() {
+                    write!(f, "; cancelled {}", cancelled.join(", "))?;
+                }
+                if !not_started.is_empty() {
+                    write!(f, "; not started {}", not_started.join(", "))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl StdError for StartupError {}
+
+// What has to happen before the server takes requests, as named tasks that
+// declare which tasks they run after. Every task runs as soon as the ones it
+// depends on are done, concurrently with any others that are ready, and the
+// first failure cancels the rest. Tasks run on the current thread, so they
+// don't have to be Send.
+#[derive(Default)]
+pub struct Startup {
+    tasks: Vec<Task>,
+}
+
+impl Startup {
+    pub fn task<F>(&mut self, name: &'static str, after: &'static [&'static str], future: F) -> &mut Self
+    where
+        F: Future<Output = Result<(), Box<dyn StdError>>> + 'static,
+    {
+        self.tasks.push(Task { name, after, future: Box::pin(future) });
+        self
+    }
+
+    // Run every task, returning how long each one took in the order they finished
+    pub async fn run(self) -> Result<Vec<(&'static str, Duration)>, StartupError> {
+        check(&self.tasks)?;
+        let started = Instant::now();
+
+        let mut waiting = self.tasks;
+        let mut done = HashSet::new();
+        let mut running_names = HashSet::new();
+        let mut running = FuturesUnordered::new();
+        let mut timings = Vec::new();
+
+        loop {
+            let (ready, rest): (Vec<_>, Vec<_>) =
+                waiting.into_iter().partition(|task| task.after.iter().all(|dependency| done.contains(dependency)));
+            waiting = rest;
+            for task in ready {
+                running_names.insert(task.name);
+                running.push(async move {
+                    let started = Instant::now();
+                    let result = task.future.await;
+                    (task.name, started.elapsed(), result)
+                });
+            }
+
+            let Some((name, elapsed, result)) = running.next().await else { break };
+            running_names.remove(name);
+            if let Err(e) = result {
+                let mut cancelled: Vec<_> = running_names.into_iter().collect();
+                cancelled.sort();
+                return Err(StartupError::Failed {
+                    task: name,
+                    error: e.to_string(),
+                    cancelled,
+                    not_started: waiting.iter().map(|task| task.name).collect(),
+                });
+            }
+            log::debug!("Startup task {} finished in {:?}", name, elapsed);
+            done.insert(name);
+            timings.push((name, elapsed));
+        }
+
+        let summary: Vec<String> = timings.iter().map(|(name, elapsed)| format!("{} {:?}", name, elapsed)).collect();
+        log::info!("Startup finished in {:?} ({})", started.elapsed(), summary.join(", "));
+        Ok(timings)
+    }
+}
+
+// Every dependency exists and there is no cycle, or the run would never finish
+fn check(tasks: &[Task]) -> Result<(), StartupError> {
+    let mut names = HashSet::new();
+    for task in tasks {
+        if !names.insert(task.name) {
+            return Err(StartupError::InvalidGraph(format!("{} is defined twice", task.name)));
+        }
+    }
+    for task in tasks {
+        if let Some(missing) = task.after.iter().find(|dependency| !names.contains(*dependency)) {
+            return Err(StartupError::InvalidGraph(format!("{} runs after unknown task {}", task.name, missing)));
+        }
+    }
+
+    // Drop tasks whose dependencies are all gone until none are left, or none can go
+    let mut remaining: HashMap<&str, &[&str]> = tasks.iter().map(|task| (task.name, task.after)).collect();
+    while !remaining.is_empty() {
+        let free: Vec<&str> = remaining
+            .iter()
+            .filter(|(_, after)| after.iter().all(|dependency| !remaining.contains_key(dependency)))
+            .map(|(name, _)| *name)
+            .collect();
+        if free.is_empty() {
+            let mut stuck: Vec<_> = remaining.keys().copied().collect();
+            stuck.sort();
+            return Err(StartupError::InvalidGraph(format!("cycle between {}", stuck.join(", "))));
+        }
+        for name in free {
+            remaining.remove(name);
+        }
+    }
+    Ok(())
+}
+
+// Open a few database connections at once, so the first requests don't pay for
+// connecting. They stay in the pool as idle connections.
+pub async fn warm_pool(pool: Pool) -> Result<(), Box<dyn StdError>> {
+    let connections = WARM_CONNECTIONS.min(pool.status().max_size);
+    let clients = join_all((0..connections).map(|_| pool.get())).await;
+    for client in clients {
+        client?.simple_query("SELECT 1").await?;
+    }
+    Ok(())
+}
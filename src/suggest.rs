@@ -0,0 +1,107 @@
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use uuid::Uuid;
+
+use crate::models::user::{Suggestion, User};
+
+struct Entry {
+    name: String,
+    // Lowercased name with single spaces, also the first of its keys
+    key: String,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct Inner {
+    users: HashMap<Uuid, Entry>,
+    // Each name is indexed from the start of every word, so "ada ahmed" is found
+    // by "ada" and by "ahm". Prefix lookups are a range scan over the set.
+    keys: BTreeSet<(String, Uuid)>,
+}
+
+// In-memory typeahead over user names, kept up to date by the repository on
+// every write so suggestions don't need a database round trip
+#[derive(Default)]
+pub struct SuggestIndex {
+    inner: RwLock<Inner>,
+}
+
+fn normalize(text: &str) -> String {
+    text.split_whitespace().map(str::to_lowercase).collect::<Vec<_>>().join(" ")
+}
+
+fn word_keys(key: &str) -> impl Iterator<Item = &str> {
+    let starts = key.match_indices(' ').map(|(i, _)| i + 1);
+    std::iter::once(0).chain(starts).map(move |i| &key[i..])
+}
+
+impl Inner {
+    fn insert(&mut self, id: Uuid, name: &str, updated_at: DateTime<Utc>) {
+        self.remove(&id);
+        let key = normalize(name);
+        for word_key in word_keys(&key) {
+            self.keys.insert((word_key.to_string(), id));
+        }
+        self.users.insert(id, Entry { name: name.to_string(), key, updated_at });
+    }
+
+    fn remove(&mut self, id: &Uuid) {
+        if let Some(entry) = self.users.remove(id) {
+            for word_key in word_keys(&entry.key) {
+                self.keys.remove(&(word_key.to_string(), *id));
+            }
+        }
+    }
+}
+
+impl SuggestIndex {
+    // Replace the whole index, e.g. with every user at startup
+    pub fn rebuild(&self, users: Vec<(Uuid, String, DateTime<Utc>)>) {
+        let mut inner = Inner::default();
+        for (id, name, updated_at) in users {
+            inner.insert(id, &name, updated_at);
+        }
+        *self.inner.write() = inner;
+    }
+
+    pub fn upsert(&self, user: &User) {
+        self.inner.write().insert(user.id, &user.name, user.updated_at);
+    }
+
+    pub fn remove(&self, id: &Uuid) {
+        self.inner.write().remove(id);
+    }
+
+    // Users whose name starts with the query rank first, then those with a later
+    // word starting with it; ties go to the most recently updated
+    pub fn suggest(&self, query: &str, limit: usize) -> Vec<Suggestion> {
+        let query = normalize(query);
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        let inner = self.inner.read();
+        let mut best: HashMap<Uuid, bool> = HashMap::new();
+        for (key, id) in inner.keys.range((query.clone(), Uuid::nil())..) {
+            if !key.starts_with(&query) {
+                break;
+            }
+            let whole_name = inner.users[id].key == *key;
+            *best.entry(*id).or_default() |= whole_name;
+        }
+
+        let mut matches: Vec<(bool, Uuid, &Entry)> = best
+            .into_iter()
+            .map(|(id, whole_name)| (whole_name, id, &inner.users[&id]))
+            .collect();
+        matches.sort_by_key(|(whole_name, id, entry)| (Reverse(*whole_name), Reverse(entry.updated_at), *id));
+
+        matches
+            .into_iter()
+            .take(limit)
+            .map(|(_, id, entry)| Suggestion { id, name: entry.name.clone() })
+            .collect()
+    }
+}
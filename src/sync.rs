@@ -0,0 +1,142 @@
+use std::error::Error as StdError;
+
+use crate::models::sync::{
+    ClientChange, ConflictStrategy, SyncPushRequest, SyncPushResponse, SyncResponse, SyncResult, SyncStatus,
+};
+use crate::models::user::{CreateUserRequest, UpdateUserRequest, User};
+use crate::repositories::sync_repo::{SyncRepository, UserChange};
+use crate::repositories::user_repo::{CachedUserRepository, UserTx};
+
+// Sync tokens are change log sequence numbers, opaque to clients
+pub fn parse_token(token: &str) -> Option<i64> {
+    token.parse::<i64>().ok().filter(|seq| *seq >= 0)
+}
+
+// Everything that changed after `since`. Without a token the client gets a full
+// snapshot, since the change log may not go back to the first user.
+pub async fn pull(
+    users: &CachedUserRepository,
+    changes: &SyncRepository,
+    since: Option<i64>,
+) -> Result<SyncResponse, Box<dyn StdError>> {
+    let until = changes.current_token().await?;
+    let mut response = SyncResponse {
+        next_token: until.to_string(),
+        ..SyncResponse::default()
+    };
+
+    match since {
+        None => response.created = users.get_all().await?,
+        Some(since) => {
+            for change in changes.changes_between(since, until).await? {
+                match change {
+                    UserChange::Created(user) => response.created.push(user),
+                    UserChange::Updated(user) => response.updated.push(user),
+                    UserChange::Deleted(id) => response.deleted.push(id),
+                }
+            }
+        }
+    }
+
+    Ok(response)
+}
+
+// Apply client changes in order, resolving conflicts with the requested strategy.
+// They are applied in one transaction, a change that fails rolls back the ones
+// before it too, so the client can retry the whole batch.
+pub async fn push(
+    users: &CachedUserRepository,
+    changes: &SyncRepository,
+    since: i64,
+    push_req: &SyncPushRequest,
+) -> Result<SyncPushResponse, Box<dyn StdError>> {
+    let mut tx = users.begin().await?;
+    let mut results = Vec::with_capacity(push_req.changes.len());
+    for change in &push_req.changes {
+        results.push(apply(&mut tx, changes, since, push_req.strategy, change).await?);
+    }
+    users.commit(tx).await?;
+
+    Ok(SyncPushResponse {
+        results,
+        next_token: changes.current_token().await?.to_string(),
+    })
+}
+
+async fn apply(
+    users: &mut UserTx,
+    changes: &SyncRepository,
+    since: i64,
+    strategy: ConflictStrategy,
+    change: &ClientChange,
+) -> Result<SyncResult, Box<dyn StdError>> {
+    match change {
+        ClientChange::Create { id, name, email, age } => {
+            if let Some(existing) = users.get_by_id(id).await? {
+                return Ok(result(existing.id, SyncStatus::Conflict, Some(existing)));
+            }
+
+            let user_req = CreateUserRequest {
+                username: None,
+                name: name.clone(),
+                email: email.clone(),
+                age: *age,
+                password: None,
+            };
+            let user = users.create_with_id(id, &user_req).await?;
+            Ok(result(user.id, SyncStatus::Applied, Some(user)))
+        }
+        ClientChange::Update { id, base, name, email, age } => {
+            let current = match users.get_by_id(id).await? {
+                Some(user) => user,
+                None => return Ok(result(*id, SyncStatus::NotFound, None)),
+            };
+
+            let mut status = SyncStatus::Applied;
+            let mut user_req = UpdateUserRequest {
+                username: None,
+                name: name.clone(),
+                email: email.clone(),
+                age: *age,
+                version: None,
+            };
+
+            if strategy != ConflictStrategy::ClientWins && changes.changed_since(id, since).await? {
+                match (strategy, base) {
+                    // Three-way merge: only take client values for fields the server left alone
+                    (ConflictStrategy::Merge, Some(base)) => {
+                        user_req.name = user_req.name.filter(|_| current.name == base.name);
+                        user_req.email = user_req.email.filter(|_| current.email == base.email);
+                        user_req.age = user_req.age.filter(|_| current.age == base.age);
+                        status = SyncStatus::Merged;
+                    }
+                    _ => return Ok(result(current.id, SyncStatus::Conflict, Some(current))),
+                }
+            }
+
+            match users.update(id, &user_req).await? {
+                Some(user) => Ok(result(user.id, status, Some(user))),
+                None => Ok(result(*id, SyncStatus::NotFound, None)),
+            }
+        }
+        ClientChange::Delete { id } => {
+            // A record edited on the server since the client's view survives unless the client wins
+            if strategy != ConflictStrategy::ClientWins && changes.changed_since(id, since).await? {
+                if let Some(current) = users.get_by_id(id).await? {
+                    return Ok(result(current.id, SyncStatus::Conflict, Some(current)));
+                }
+            }
+
+            if users.delete(id).await? {
+                log::info!(target: "audit", "Deleted user {} via sync push", id);
+                Ok(result(*id, SyncStatus::Applied, None))
+            } else {
+                Ok(result(*id, SyncStatus::NotFound, None))
+            }
+        }
+    }
+}
+
+fn result(id: uuid::Uuid, status: SyncStatus, user: Option<User>) -> SyncResult {
+    SyncResult { id, status, user }
+}
@@ -0,0 +1,109 @@
+use actix_web::web;
+use serde::Serialize;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::egress;
+use crate::models::public_id::IdFormat;
+use crate::models::user::UserFilter;
+use crate::repositories::user_repo::CachedUserRepository;
+use crate::siem::LogFormat;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct TelemetryConfig {
+    pub url: String,
+    pub interval: Duration,
+}
+
+// Everything that is sent. No hostnames, ids, emails or request data: the
+// instance id is random per process and the user count is only a bucket.
+#[derive(Debug, Serialize)]
+struct Report {
+    instance_id: Uuid,
+    version: &'static str,
+    user_count: &'static str,
+    features: Vec<&'static str>,
+}
+
+fn bucket(count: i64) -> &'static str {
+    match count {
+        i64::MIN..=0 => "0",
+        1..=9 => "1-9",
+        10..=99 => "10-99",
+        100..=999 => "100-999",
+        1_000..=9_999 => "1000-9999",
+        10_000..=99_999 => "10000-99999",
+        _ => "100000+",
+    }
+}
+
+// Names of the optional features this instance runs with
+pub fn features(config: &AppConfig) -> Vec<&'static str> {
+    let enabled = [
+        ("hedged_reads", config.hedge_delay.is_some()),
+        ("change_limits", config.change_limits.name.is_some() || config.change_limits.email.is_some() || config.change_limits.age.is_some()),
+        ("demo_mode", config.demo_mode),
+        ("region", config.region.is_some()),
+        ("approvals", config.approvals_required),
+        ("api_keys_required", config.api_keys_required),
+        ("oidc", config.oidc.is_some()),
+        ("password_breach_check", config.password_policy.breach_check),
+        ("prefixed_ids", config.public_id_format == IdFormat::Prefixed),
+        ("dedup", config.dedup_window.is_some()),
+        ("rate_limits", config.rate_limits.enabled()),
+        ("alerts", config.alerts.is_some()),
+        ("siem", config.siem.is_some()),
+        ("json_logs", config.log_format == LogFormat::Json),
+        ("connector", config.connector_spec.is_some()),
+        ("retention", config.retention_policies.is_some()),
+        ("simd", cfg!(feature = "simd")),
+        ("nats", cfg!(feature = "nats")),
+        ("admin_query", cfg!(feature = "admin-query")),
+    ];
+    enabled.into_iter().filter(|(_, on)| *on).map(|(name, _)| name).collect()
+}
+
+// Report to the configured endpoint once an interval, starting one interval
+// after startup. Failures are only logged at debug level, telemetry must never
+// get in the way of the service.
+pub fn spawn_reporter(config: TelemetryConfig, features: Vec<&'static str>, users: web::Data<CachedUserRepository>) {
+    log::info!(
+        "Telemetry enabled: reporting version, user count bucket and enabled features to {} every {}s (TELEMETRY=off disables it)",
+        config.url,
+        config.interval.as_secs()
+    );
+
+    actix_web::rt::spawn(async move {
+        let client = egress::client();
+        let instance_id = Uuid::new_v4();
+        let mut ticker = actix_web::rt::time::interval(config.interval);
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+
+            let count = match users.count(&UserFilter::default()).await {
+                Ok(count) => count,
+                Err(e) => {
+                    log::debug!("Telemetry skipped, counting users failed: {}", e);
+                    continue;
+                }
+            };
+            let report = Report {
+                instance_id,
+                version: env!("CARGO_PKG_VERSION"),
+                user_count: bucket(count),
+                features: features.clone(),
+            };
+            log::debug!("Sending telemetry {:?}", report);
+
+            let sent = client.post(&config.url).timeout(REQUEST_TIMEOUT).json(&report).send().await;
+            if let Err(e) = sent.and_then(|response| response.error_for_status()) {
+                log::debug!("Telemetry report to {} failed: {}", config.url, e);
+            }
+        }
+    });
+}
@@ -0,0 +1,36 @@
+use actix_web::http::StatusCode;
+
+use crate::models::tenant::TenantSettings;
+use crate::models::validate::FieldError;
+use crate::templates::{escape, layout};
+
+// What a browser gets instead of the JSON error body, with the request id to
+// quote when reporting it
+pub fn error_page(status: StatusCode, message: &str, errors: &[FieldError], request_id: Option<&str>, branding: &TenantSettings) -> String {
+    let title = format!("{} {}", status.as_u16(), status.canonical_reason().unwrap_or("Error"));
+
+    let fields = if errors.is_empty() {
+        String::new()
+    } else {
+        let rows: String = errors
+            .iter()
+            .map(|error| format!("<tr><th><code>{}</code></th><td>{}</td></tr>", escape(&error.field), escape(&error.message)))
+            .collect();
+        format!("\n<table>\n{}\n</table>", rows)
+    };
+    let request_id = match request_id {
+        Some(id) => format!("\n<p>Request id <code>{}</code></p>", escape(id)),
+        None => String::new(),
+    };
+
+    let body = format!(
+        r#"<h1>{title}</h1>
+<p>{message}</p>{fields}{request_id}"#,
+        title = escape(&title),
+        message = escape(message),
+        fields = fields,
+        request_id = request_id,
+    );
+
+    layout(&title, &body, branding)
+}
@@ -0,0 +1,122 @@
+use actix_web::http::header::{self, Header};
+use actix_web::HttpRequest;
+
+use crate::models::tenant::TenantSettings;
+
+pub mod error;
+pub mod status;
+pub mod user;
+
+// Whether the client ranks HTML above JSON, e.g. a browser navigating to an API URL
+pub fn wants_html(req: &HttpRequest) -> bool {
+    let accept = match header::Accept::parse(req) {
+        Ok(accept) => accept,
+        Err(_) => return false,
+    };
+
+    accept
+        .ranked()
+        .iter()
+        .find_map(|mime| match mime.essence_str() {
+            "text/html" => Some(true),
+            "application/json" | "*/*" => Some(false),
+            _ => None,
+        })
+        .unwrap_or(false)
+}
+
+// Used when the tenant hasn't picked a color
+const DEFAULT_COLOR: &str = "#2d3e50";
+
+// Page shell shared by every server-rendered page, branded with the tenant settings
+pub fn layout(title: &str, body: &str, branding: &TenantSettings) -> String {
+    let logo = match &branding.logo_url {
+        Some(url) => format!(r#"<img src="{}" alt="" height="24"> "#, escape(url)),
+        None => String::new(),
+    };
+    let footer = match &branding.support_email {
+        Some(email) => format!(
+            r#"<footer>Need help? <a href="mailto:{email}">{email}</a></footer>"#,
+            email = escape(email)
+        ),
+        None => String::new(),
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<style>
+body {{ font-family: system-ui, sans-serif; margin: 0; color: #222; background: #f5f6f8; }}
+header {{ background: {color}; color: #fff; padding: 0.75rem 1.5rem; }}
+header img {{ vertical-align: middle; }}
+header a {{ color: #fff; text-decoration: none; font-weight: 600; }}
+main {{ max-width: 56rem; margin: 1.5rem auto; padding: 1.5rem; background: #fff; border-radius: 6px; }}
+table {{ border-collapse: collapse; width: 100%; }}
+th, td {{ text-align: left; padding: 0.4rem 0.6rem; border-bottom: 1px solid #e4e6ea; }}
+th {{ width: 10rem; color: #555; font-weight: 500; }}
+table.list th {{ width: auto; }}
+th a {{ color: inherit; }}
+form.filters {{ display: flex; flex-wrap: wrap; gap: 0.5rem; align-items: end; margin-bottom: 1rem; }}
+form.filters label {{ display: flex; flex-direction: column; font-size: 0.85em; color: #555; }}
+nav.pages {{ display: flex; flex-wrap: wrap; gap: 0.4rem; align-items: center; margin-top: 1rem; }}
+code {{ font-size: 0.9em; }}
+footer {{ text-align: center; color: #555; font-size: 0.9em; margin-bottom: 1.5rem; }}
+</style>
+</head>
+<body>
+<header>{logo}<a href="/users">User API</a></header>
+<main>
+{body}
+</main>
+{footer}
+</body>
+</html>
+"#,
+        title = escape(title),
+        // Validated as #rrggbb on save, so it is safe inside the stylesheet
+        color = branding.primary_color.as_deref().unwrap_or(DEFAULT_COLOR),
+        logo = logo,
+        body = body,
+        footer = footer,
+    )
+}
+
+// URL query string from the given parameters, percent-encoding everything
+// but unreserved characters
+pub fn query_string(params: &[(&str, String)]) -> String {
+    let mut query = String::new();
+    for (name, value) in params {
+        if !query.is_empty() {
+            query.push('&');
+        }
+        query.push_str(name);
+        query.push('=');
+        for byte in value.bytes() {
+            match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => query.push(byte as char),
+                _ => query.push_str(&format!("%{:02X}", byte)),
+            }
+        }
+    }
+    query
+}
+
+// Escape text for use in HTML content and attribute values
+pub fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
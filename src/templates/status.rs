@@ -0,0 +1,81 @@
+use crate::health::{ComponentStatus, RequestCounts};
+use crate::models::tenant::TenantSettings;
+use crate::templates::{escape, layout};
+
+// Seconds between automatic reloads of the status page
+const REFRESH_SECS: u32 = 15;
+
+// Human-readable status page for the ops room screen
+pub fn status_page(
+    components: &[ComponentStatus],
+    user_count: Option<i64>,
+    counts: RequestCounts,
+    window_secs: u64,
+    branding: &TenantSettings,
+) -> String {
+    let healthy = components.iter().all(|c| c.healthy);
+    let degraded = components.iter().any(|c| c.degraded);
+
+    let rows: String = components
+        .iter()
+        .map(|c| {
+            format!(
+                r#"<tr><th>{name}</th><td style="color: {color}; font-weight: 600">{state}</td><td>{detail}</td></tr>"#,
+                name = c.name,
+                color = color(c.healthy, c.degraded),
+                state = match (c.healthy, c.degraded) {
+                    (false, _) => "DOWN",
+                    (true, true) => "DEGRADED",
+                    (true, false) => "UP",
+                },
+                detail = escape(&c.detail),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let error_rate = if counts.requests == 0 {
+        0.0
+    } else {
+        counts.errors as f64 * 100.0 / counts.requests as f64
+    };
+
+    let body = format!(
+        r#"<meta http-equiv="refresh" content="{refresh}">
+<h1 style="color: {color}">{overall}</h1>
+<table>
+{rows}
+</table>
+<h2>Data</h2>
+<table>
+<tr><th>Users</th><td>{users}</td></tr>
+</table>
+<h2>Last {minutes} minutes</h2>
+<table>
+<tr><th>Requests</th><td>{requests}</td></tr>
+<tr><th>Server errors</th><td>{errors} ({error_rate:.2}%)</td></tr>
+</table>
+<p>Version {version}, refreshes every {refresh}s</p>"#,
+        refresh = REFRESH_SECS,
+        color = color(healthy, degraded),
+        overall = if healthy && !degraded { "All systems operational" } else { "Degraded" },
+        rows = rows,
+        users = user_count.map_or_else(|| "unknown".to_string(), |n| n.to_string()),
+        minutes = window_secs / 60,
+        requests = counts.requests,
+        errors = counts.errors,
+        error_rate = error_rate,
+        version = env!("CARGO_PKG_VERSION"),
+    );
+
+    layout("Status", &body, branding)
+}
+
+// Red when down, amber when degraded, green otherwise
+fn color(healthy: bool, degraded: bool) -> &'static str {
+    match (healthy, degraded) {
+        (false, _) => "#cf222e",
+        (true, true) => "#9a6700",
+        (true, false) => "#1a7f37",
+    }
+}
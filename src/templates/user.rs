@@ -0,0 +1,177 @@
+use crate::models::public_id;
+use crate::models::tenant::TenantSettings;
+use crate::models::user::{User, UserFilter, UserPage};
+use crate::templates::{escape, layout, query_string};
+
+// Page sizes offered by the user list
+const PER_PAGE_CHOICES: [u32; 4] = [25, 50, 100, 200];
+
+// Sortable columns of the user list, by sort field
+const COLUMNS: [(&str, &str); 4] = [("name", "Name"), ("email", "Email"), ("age", "Age"), ("created_at", "Created")];
+
+// Read-only profile page for support and QA
+pub fn profile(user: &User, branding: &TenantSettings) -> String {
+    let age = user.age.map(|age| age.to_string()).unwrap_or_else(|| "-".to_string());
+
+    let body = format!(
+        r#"<h1>{name}</h1>
+<table>
+<tr><th>ID</th><td><code>{id}</code></td></tr>
+<tr><th>Name</th><td>{name}</td></tr>
+<tr><th>Email</th><td><a href="mailto:{email}">{email}</a></td></tr>
+<tr><th>Age</th><td>{age}</td></tr>
+<tr><th>Created</th><td>{created_at}</td></tr>
+<tr><th>Updated</th><td>{updated_at}</td></tr>
+</table>
+<p><a href="/users/{id}.vcf">Download vCard</a></p>"#,
+        id = public_id::encode(&user.id),
+        name = escape(&user.name),
+        email = escape(&user.email),
+        age = age,
+        created_at = user.created_at.format("%Y-%m-%d %H:%M:%S UTC"),
+        updated_at = user.updated_at.format("%Y-%m-%d %H:%M:%S UTC"),
+    );
+
+    layout(&user.name, &body, branding)
+}
+
+// Admin user list: a filter form, sortable columns and page links. Every control
+// is a plain link or GET form over the GET /users parameters, so it works
+// without JavaScript and the URL can be bookmarked or shared.
+pub fn list(page: &UserPage, filter: &UserFilter, sort: Option<&str>, branding: &TenantSettings) -> String {
+    // Parameters every link keeps, page and sort are added per link
+    let mut kept: Vec<(&str, String)> = Vec::new();
+    if let Some(email) = &filter.email {
+        kept.push(("email", email.clone()));
+    }
+    if let Some(name) = &filter.name_contains {
+        kept.push(("name_contains", name.clone()));
+    }
+    if let Some(min_age) = filter.min_age {
+        kept.push(("min_age", min_age.to_string()));
+    }
+    if let Some(max_age) = filter.max_age {
+        kept.push(("max_age", max_age.to_string()));
+    }
+    if let Some(region) = &filter.region {
+        kept.push(("region", region.clone()));
+    }
+    if filter.include_deleted {
+        kept.push(("include_deleted", "true".to_string()));
+    }
+    kept.push(("per_page", page.per_page.to_string()));
+
+    let href = |page_number: u32, sort: Option<&str>| {
+        let mut params = kept.clone();
+        if let Some(sort) = sort {
+            params.push(("sort", sort.to_string()));
+        }
+        params.push(("page", page_number.to_string()));
+        format!("/users?{}", query_string(&params))
+    };
+
+    // Clicking a column sorts by it, clicking it again reverses the order
+    let headers: String = COLUMNS
+        .iter()
+        .map(|(field, label)| {
+            let descending = format!("-{}", field);
+            let (next, arrow) = match sort {
+                Some(current) if current == *field => (descending.as_str(), " &#9650;"),
+                Some(current) if current == descending => (*field, " &#9660;"),
+                _ => (*field, ""),
+            };
+            format!(r#"<th><a href="{}">{}</a>{}</th>"#, escape(&href(1, Some(next))), label, arrow)
+        })
+        .collect();
+
+    let rows: String = page
+        .items
+        .iter()
+        .map(|user| {
+            format!(
+                r#"<tr><td><a href="/users/{id}">{name}</a></td><td>{email}</td><td>{age}</td><td>{created_at}</td><td>{username}</td></tr>"#,
+                id = public_id::encode(&user.id),
+                name = escape(&user.name),
+                email = escape(&user.email),
+                age = user.age.map(|age| age.to_string()).unwrap_or_else(|| "-".to_string()),
+                created_at = user.created_at.format("%Y-%m-%d"),
+                username = user.username.as_deref().map(escape).unwrap_or_else(|| "-".to_string()),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    // A size from a hand-edited URL is offered too, so filtering keeps it
+    let mut per_page_choices = PER_PAGE_CHOICES.to_vec();
+    if !per_page_choices.contains(&page.per_page) {
+        per_page_choices.push(page.per_page);
+        per_page_choices.sort_unstable();
+    }
+    let per_page_options: String = per_page_choices
+        .iter()
+        .map(|&n| {
+            let selected = if n == page.per_page { " selected" } else { "" };
+            format!(r#"<option value="{n}"{selected}>{n}</option>"#)
+        })
+        .collect();
+    let sort_input = match sort {
+        Some(sort) => format!(r#"<input type="hidden" name="sort" value="{}">"#, escape(sort)),
+        None => String::new(),
+    };
+
+    let last_page = ((page.total.max(1) - 1) / page.per_page as i64 + 1) as u32;
+    let mut links = Vec::new();
+    let mut link = |label: String, target: u32, enabled: bool| {
+        links.push(if enabled {
+            format!(r#"<a href="{}">{}</a>"#, escape(&href(target, sort)), label)
+        } else {
+            format!("<span>{}</span>", label)
+        });
+    };
+    link("&laquo; First".to_string(), 1, page.page > 1);
+    link("&lsaquo; Prev".to_string(), page.page.saturating_sub(1).max(1), page.page > 1);
+    for n in page.page.saturating_sub(2).max(1)..=(page.page + 2).min(last_page) {
+        let label = if n == page.page { format!("<strong>{}</strong>", n) } else { n.to_string() };
+        link(label, n, n != page.page);
+    }
+    link("Next &rsaquo;".to_string(), page.page + 1, page.page < last_page);
+    link("Last &raquo;".to_string(), last_page, page.page < last_page);
+
+    let offset = (page.page as i64 - 1) * page.per_page as i64;
+    let (first_shown, last_shown) = match page.items.len() as i64 {
+        0 => (0, 0),
+        shown => (offset + 1, offset + shown),
+    };
+
+    let body = format!(
+        r#"<h1>Users</h1>
+<form class="filters" method="get" action="/users">
+<label>Name contains <input name="name_contains" value="{name_contains}"></label>
+<label>Email <input name="email" type="email" value="{email}"></label>
+<label>Min age <input name="min_age" type="number" min="0" max="150" value="{min_age}"></label>
+<label>Max age <input name="max_age" type="number" min="0" max="150" value="{max_age}"></label>
+<label>Per page <select name="per_page">{per_page_options}</select></label>
+{sort_input}<button type="submit">Filter</button> <a href="/users">Clear</a>
+</form>
+<p>Showing {first_shown}&ndash;{last_shown} of {total}</p>
+<table class="list">
+<tr>{headers}<th>Username</th></tr>
+{rows}
+</table>
+<nav class="pages">{links}</nav>"#,
+        name_contains = escape(filter.name_contains.as_deref().unwrap_or("")),
+        email = escape(filter.email.as_deref().unwrap_or("")),
+        min_age = filter.min_age.map(|age| age.to_string()).unwrap_or_default(),
+        max_age = filter.max_age.map(|age| age.to_string()).unwrap_or_default(),
+        per_page_options = per_page_options,
+        sort_input = sort_input,
+        first_shown = first_shown,
+        last_shown = last_shown,
+        total = page.total,
+        headers = headers,
+        rows = rows,
+        links = links.join(" "),
+    );
+
+    layout("Users", &body, branding)
+}
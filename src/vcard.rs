@@ -0,0 +1,96 @@
+use crate::models::public_id::{self, IdFormat};
+use crate::models::user::User;
+
+// RFC 6350 limits content lines to 75 octets, longer lines are folded
+const MAX_LINE_OCTETS: usize = 75;
+
+// Minimal vCard 4.0 serializer for user profiles
+pub struct VCard {
+    lines: Vec<String>,
+}
+
+impl VCard {
+    pub fn new() -> Self {
+        Self {
+            lines: vec!["BEGIN:VCARD".to_string(), "VERSION:4.0".to_string()],
+        }
+    }
+
+    // Add a property whose value is already escaped (e.g. structured values)
+    pub fn raw(mut self, name: &str, value: &str) -> Self {
+        self.lines.push(format!("{}:{}", name, value));
+        self
+    }
+
+    // Add a property with a single text value
+    pub fn text(self, name: &str, value: &str) -> Self {
+        let value = escape(value);
+        self.raw(name, &value)
+    }
+
+    pub fn build(mut self) -> String {
+        self.lines.push("END:VCARD".to_string());
+        self.lines
+            .iter()
+            .map(|line| fold(line))
+            .collect::<Vec<_>>()
+            .concat()
+    }
+}
+
+// Card for a user. The model has no phone or organisation data yet, so only
+// name, email and a stable UID are exported.
+pub fn from_user(user: &User) -> String {
+    let (given, family) = match user.name.trim().rsplit_once(' ') {
+        Some((given, family)) => (given.trim(), family),
+        None => (user.name.trim(), ""),
+    };
+
+    // urn:uuid would give the raw id away when public ids are encoded
+    let uid = match public_id::format() {
+        IdFormat::Uuid => format!("urn:uuid:{}", user.id),
+        IdFormat::Prefixed => public_id::encode(&user.id),
+    };
+
+    VCard::new()
+        .text("FN", &user.name)
+        .raw("N", &format!("{};{};;;", escape(family), escape(given)))
+        .text("EMAIL;TYPE=work", &user.email)
+        .raw("UID", &uid)
+        .build()
+}
+
+// Escape a text value per RFC 6350 section 3.4
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ',' => escaped.push_str("\\,"),
+            ';' => escaped.push_str("\\;"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Fold a content line into CRLF-terminated chunks of at most 75 octets, never
+// splitting a UTF-8 character. Continuation lines start with a single space.
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 8);
+    let mut octets = 0;
+
+    for c in line.chars() {
+        if octets + c.len_utf8() > MAX_LINE_OCTETS {
+            folded.push_str("\r\n ");
+            octets = 1;
+        }
+        folded.push(c);
+        octets += c.len_utf8();
+    }
+
+    folded.push_str("\r\n");
+    folded
+}